@@ -0,0 +1,659 @@
+#![no_std]
+
+//! Versioned, CRC checked records on NOR flash.
+//!
+//! Two erase sectors are used as an append-only log. Every write appends a
+//! record to the active sector:
+//!
+//! ```text
+//! | magic u32 | key u8 | version u8 | len u16 | seq u32 | crc u32 | payload, 0xFF padded |
+//! ```
+//!
+//! The record with the highest sequence number for a key wins. When the
+//! active sector is full, the latest record of every key is copied into the
+//! other sector, which then becomes active. Erases alternate between the two
+//! sectors and only happen once a sector is full.
+//!
+//! A record whose payload was not completely written fails its CRC check and
+//! is ignored, so the previous record for that key is used instead.
+//!
+//! ```ignore
+//! let mut store = ConfigStore::new(flash, [0x4_0000, 0x6_0000], 128 * 1024)?;
+//! store.write(0, 1, b"{\"dhcp\":true}")?;
+//! let mut buf = [0u8; 512];
+//! if let Some(record) = store.read(0, &mut buf)? {
+//!     assert_eq!(record.version, 1);
+//! }
+//! ```
+
+use embedded_storage::nor_flash::NorFlash;
+
+/// Number of distinct record keys, keys are `0..MAX_KEYS`
+pub const MAX_KEYS: usize = 8;
+/// Largest payload a single record can hold
+pub const MAX_RECORD_LEN: usize = 2048;
+
+const MAGIC: u32 = 0x5443_4647; // "TCFG"
+const HEADER_LEN: usize = 16;
+const COPY_CHUNK: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StoreError {
+    Flash,
+    Alignment,
+    InvalidKey,
+    TooLarge,
+    BufferTooSmall,
+    Corrupt,
+}
+impl core::fmt::Display for StoreError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            StoreError::Flash => write!(f, "Flash access error"),
+            StoreError::Alignment => write!(f, "Unsupported flash alignment"),
+            StoreError::InvalidKey => write!(f, "Invalid record key"),
+            StoreError::TooLarge => write!(f, "Record too large"),
+            StoreError::BufferTooSmall => write!(f, "Buffer too small for record"),
+            StoreError::Corrupt => write!(f, "Record failed CRC check"),
+        }
+    }
+}
+
+/// Version and payload length of a record returned by [`ConfigStore::read`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RecordInfo {
+    pub version: u8,
+    pub len: usize,
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    offset: u32,
+    seq: u32,
+    version: u8,
+    len: u16,
+    crc: u32,
+}
+
+struct Header {
+    key: u8,
+    version: u8,
+    len: u16,
+    seq: u32,
+    crc: u32,
+}
+
+impl Header {
+    fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut buf = [0u8; HEADER_LEN];
+        buf[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        buf[4] = self.key;
+        buf[5] = self.version;
+        buf[6..8].copy_from_slice(&self.len.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.seq.to_le_bytes());
+        buf[12..16].copy_from_slice(&self.crc.to_le_bytes());
+        buf
+    }
+
+    fn decode(buf: &[u8; HEADER_LEN]) -> Option<Self> {
+        let word = |i: usize| u32::from_le_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]);
+        if word(0) != MAGIC {
+            return None;
+        }
+        Some(Self {
+            key: buf[4],
+            version: buf[5],
+            len: u16::from_le_bytes([buf[6], buf[7]]),
+            seq: word(8),
+            crc: word(12),
+        })
+    }
+}
+
+pub struct ConfigStore<F: NorFlash> {
+    flash: F,
+    sectors: [u32; 2],
+    sector_size: u32,
+    active: usize,
+    head: u32,
+    seq: u32,
+    latest: [Option<Entry>; MAX_KEYS],
+}
+
+impl<F: NorFlash> ConfigStore<F> {
+    /// Mounts the store on two erase sectors at the given flash offsets and
+    /// scans them for the latest record of each key.
+    pub fn new(flash: F, sectors: [u32; 2], sector_size: u32) -> Result<Self, StoreError> {
+        let align = Self::align();
+        if F::READ_SIZE != 1 || !align.is_power_of_two() || align > HEADER_LEN {
+            return Err(StoreError::Alignment);
+        }
+        let mut store = Self {
+            flash,
+            sectors,
+            sector_size,
+            active: 0,
+            head: sectors[0],
+            seq: 0,
+            latest: [None; MAX_KEYS],
+        };
+
+        let (tail0, seq0) = store.scan(0)?;
+        let (tail1, seq1) = store.scan(1)?;
+        (store.active, store.head) = match (seq0, seq1) {
+            (Some(s0), Some(s1)) if s1 > s0 => (1, tail1),
+            (None, Some(_)) => (1, tail1),
+            _ => (0, tail0),
+        };
+        store.seq = seq0.max(seq1).map_or(0, |s| s.wrapping_add(1));
+
+        // Finish a compaction that was interrupted before every key was copied
+        for key in 0..MAX_KEYS {
+            if let Some(entry) = store.latest[key] {
+                if !store.in_sector(store.active, entry.offset) {
+                    if store.head + Self::record_len(entry.len as usize) > store.sector_end() {
+                        break;
+                    }
+                    store.copy_record(key as u8, entry)?;
+                }
+            }
+        }
+        Ok(store)
+    }
+
+    /// Releases the underlying flash
+    pub fn release(self) -> F {
+        self.flash
+    }
+
+    /// Reads the latest record for `key` into `buf`.
+    /// Returns `None` if no valid record has been written for this key.
+    pub fn read(&mut self, key: u8, buf: &mut [u8]) -> Result<Option<RecordInfo>, StoreError> {
+        let entry = match self.latest.get(key as usize).ok_or(StoreError::InvalidKey)? {
+            Some(entry) => *entry,
+            None => return Ok(None),
+        };
+        let len = entry.len as usize;
+        if buf.len() < len {
+            return Err(StoreError::BufferTooSmall);
+        }
+        self.flash
+            .read(entry.offset + HEADER_LEN as u32, &mut buf[..len])
+            .map_err(|_| StoreError::Flash)?;
+        if checksum(key, entry.version, &buf[..len]) != entry.crc {
+            return Err(StoreError::Corrupt);
+        }
+        Ok(Some(RecordInfo {
+            version: entry.version,
+            len,
+        }))
+    }
+
+    /// Appends a new record for `key`, compacting into the other sector if the
+    /// active one is full.
+    pub fn write(&mut self, key: u8, version: u8, data: &[u8]) -> Result<(), StoreError> {
+        if key as usize >= MAX_KEYS {
+            return Err(StoreError::InvalidKey);
+        }
+        let record_len = Self::record_len(data.len());
+        if data.len() > MAX_RECORD_LEN || record_len > self.sector_size {
+            return Err(StoreError::TooLarge);
+        }
+        if self.head + record_len <= self.sector_end() {
+            return self.append(key, version, data);
+        }
+
+        let result = self.compact(key).and_then(|_| {
+            if self.head + record_len > self.sector_end() {
+                return Err(StoreError::TooLarge);
+            }
+            self.append(key, version, data)
+        });
+        if result.is_err() {
+            // The compaction skipped this key, its last record is left in the sector
+            // that was given up and the next compaction erases it
+            if let Some(entry) = self.latest[key as usize] {
+                if !self.in_sector(self.active, entry.offset) {
+                    self.latest[key as usize] = None;
+                }
+            }
+        }
+        result
+    }
+
+    /// Writes a record at the head of the active sector
+    fn append(&mut self, key: u8, version: u8, data: &[u8]) -> Result<(), StoreError> {
+        let record_len = Self::record_len(data.len());
+        let offset = self.head;
+        let header = Header {
+            key,
+            version,
+            len: data.len() as u16,
+            seq: self.seq,
+            crc: checksum(key, version, data),
+        };
+        // Reserve the space first, a failed write must not be written over
+        self.head += record_len;
+        self.write_bytes(offset, &header.encode())?;
+
+        let align = Self::align();
+        let full = data.len() - data.len() % align;
+        let payload = offset + HEADER_LEN as u32;
+        if full > 0 {
+            self.write_bytes(payload, &data[..full])?;
+        }
+        if full < data.len() {
+            let mut tail = [0xFF; HEADER_LEN];
+            tail[..data.len() - full].copy_from_slice(&data[full..]);
+            self.write_bytes(payload + full as u32, &tail[..align])?;
+        }
+
+        self.latest[key as usize] = Some(Entry {
+            offset,
+            seq: header.seq,
+            version,
+            len: header.len,
+            crc: header.crc,
+        });
+        self.seq = self.seq.wrapping_add(1);
+        Ok(())
+    }
+
+    fn align() -> usize {
+        F::WRITE_SIZE.max(4)
+    }
+
+    fn record_len(len: usize) -> u32 {
+        let align = Self::align();
+        (HEADER_LEN + len.next_multiple_of(align)) as u32
+    }
+
+    fn sector_end(&self) -> u32 {
+        self.sectors[self.active] + self.sector_size
+    }
+
+    fn in_sector(&self, sector: usize, offset: u32) -> bool {
+        (self.sectors[sector]..self.sectors[sector] + self.sector_size).contains(&offset)
+    }
+
+    fn write_bytes(&mut self, offset: u32, bytes: &[u8]) -> Result<(), StoreError> {
+        self.flash
+            .write(offset, bytes)
+            .map_err(|_| StoreError::Flash)
+    }
+
+    /// Walks the records of one sector, returning the first free offset and
+    /// the highest valid sequence number found.
+    fn scan(&mut self, sector: usize) -> Result<(u32, Option<u32>), StoreError> {
+        let base = self.sectors[sector];
+        let end = base + self.sector_size;
+        let mut offset = base;
+        let mut max_seq: Option<u32> = None;
+
+        while offset + HEADER_LEN as u32 <= end {
+            let mut raw = [0u8; HEADER_LEN];
+            self.flash
+                .read(offset, &mut raw)
+                .map_err(|_| StoreError::Flash)?;
+            if raw.iter().all(|&b| b == 0xFF) {
+                return Ok((offset, max_seq));
+            }
+            let header = match Header::decode(&raw) {
+                Some(h) if (h.len as usize) <= MAX_RECORD_LEN => h,
+                // Unknown data, nothing more can be appended to this sector
+                _ => return Ok((end, max_seq)),
+            };
+            let record_len = Self::record_len(header.len as usize);
+            if offset + record_len > end {
+                return Ok((end, max_seq));
+            }
+
+            let crc = self.payload_crc(&header, offset)?;
+            if crc == header.crc && (header.key as usize) < MAX_KEYS {
+                let newer = match self.latest[header.key as usize] {
+                    Some(entry) => header.seq > entry.seq,
+                    None => true,
+                };
+                if newer {
+                    self.latest[header.key as usize] = Some(Entry {
+                        offset,
+                        seq: header.seq,
+                        version: header.version,
+                        len: header.len,
+                        crc: header.crc,
+                    });
+                }
+                max_seq = Some(max_seq.map_or(header.seq, |s| s.max(header.seq)));
+            }
+            offset += record_len;
+        }
+        Ok((end, max_seq))
+    }
+
+    fn payload_crc(&mut self, header: &Header, offset: u32) -> Result<u32, StoreError> {
+        let mut crc = checksum_start(header.key, header.version, header.len);
+        let mut chunk = [0u8; COPY_CHUNK];
+        let mut pos = 0usize;
+        let len = header.len as usize;
+        while pos < len {
+            let n = (len - pos).min(COPY_CHUNK);
+            self.flash
+                .read(offset + (HEADER_LEN + pos) as u32, &mut chunk[..n])
+                .map_err(|_| StoreError::Flash)?;
+            crc = crc32_update(crc, &chunk[..n]);
+            pos += n;
+        }
+        Ok(!crc)
+    }
+
+    /// Erases the inactive sector and moves the latest record of every key,
+    /// except `skip` which is about to be rewritten, into it.
+    fn compact(&mut self, skip: u8) -> Result<(), StoreError> {
+        let target = 1 - self.active;
+        let base = self.sectors[target];
+        self.flash
+            .erase(base, base + self.sector_size)
+            .map_err(|_| StoreError::Flash)?;
+        self.active = target;
+        self.head = base;
+
+        for key in 0..MAX_KEYS {
+            if key == skip as usize {
+                continue;
+            }
+            if let Some(entry) = self.latest[key] {
+                self.copy_record(key as u8, entry)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies a record to the head of the active sector with a new sequence number
+    fn copy_record(&mut self, key: u8, entry: Entry) -> Result<(), StoreError> {
+        let offset = self.head;
+        let header = Header {
+            key,
+            version: entry.version,
+            len: entry.len,
+            seq: self.seq,
+            crc: entry.crc,
+        };
+        let record_len = Self::record_len(entry.len as usize);
+        self.head += record_len;
+        self.write_bytes(offset, &header.encode())?;
+
+        let mut chunk = [0u8; COPY_CHUNK];
+        let mut pos = HEADER_LEN as u32;
+        while pos < record_len {
+            let n = (record_len - pos).min(COPY_CHUNK as u32);
+            self.flash
+                .read(entry.offset + pos, &mut chunk[..n as usize])
+                .map_err(|_| StoreError::Flash)?;
+            self.write_bytes(offset + pos, &chunk[..n as usize])?;
+            pos += n;
+        }
+
+        self.latest[key as usize] = Some(Entry {
+            offset,
+            seq: header.seq,
+            ..entry
+        });
+        self.seq = self.seq.wrapping_add(1);
+        Ok(())
+    }
+}
+
+fn checksum_start(key: u8, version: u8, len: u16) -> u32 {
+    let len = len.to_le_bytes();
+    crc32_update(0xFFFF_FFFF, &[key, version, len[0], len[1]])
+}
+
+fn checksum(key: u8, version: u8, data: &[u8]) -> u32 {
+    !crc32_update(checksum_start(key, version, data.len() as u16), data)
+}
+
+/// CRC-32 (IEEE 802.3), bitwise to keep it out of flash tables
+fn crc32_update(mut crc: u32, bytes: &[u8]) -> u32 {
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_storage::nor_flash::{ErrorType, NorFlashErrorKind, ReadNorFlash};
+
+    const SECTOR: usize = 512;
+    const SECTORS: [u32; 2] = [0, SECTOR as u32];
+
+    /// RAM backed NOR flash, writes can only clear bits of erased words
+    struct MemFlash {
+        mem: [u8; SECTOR * 2],
+        writes_left: Option<usize>,
+    }
+
+    impl MemFlash {
+        fn new() -> Self {
+            Self {
+                mem: [0xFF; SECTOR * 2],
+                writes_left: None,
+            }
+        }
+    }
+
+    impl ErrorType for MemFlash {
+        type Error = NorFlashErrorKind;
+    }
+
+    impl ReadNorFlash for MemFlash {
+        const READ_SIZE: usize = 1;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            bytes.copy_from_slice(&self.mem[offset..offset + bytes.len()]);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.mem.len()
+        }
+    }
+
+    impl NorFlash for MemFlash {
+        const WRITE_SIZE: usize = 4;
+        const ERASE_SIZE: usize = SECTOR;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            embedded_storage::nor_flash::check_erase(self, from, to)?;
+            self.mem[from as usize..to as usize].fill(0xFF);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            embedded_storage::nor_flash::check_write(self, offset, bytes.len())?;
+            if let Some(left) = self.writes_left.as_mut() {
+                if *left == 0 {
+                    return Err(NorFlashErrorKind::Other);
+                }
+                *left -= 1;
+            }
+            let target = &mut self.mem[offset as usize..offset as usize + bytes.len()];
+            assert!(target.iter().all(|&b| b == 0xFF), "write to unerased flash");
+            target.copy_from_slice(bytes);
+            Ok(())
+        }
+    }
+
+    fn read_back<'a>(
+        store: &mut ConfigStore<MemFlash>,
+        key: u8,
+        buf: &'a mut [u8],
+    ) -> Option<(u8, &'a [u8])> {
+        let info = store.read(key, buf).unwrap()?;
+        Some((info.version, &buf[..info.len]))
+    }
+
+    #[test]
+    fn empty_store_has_no_records() {
+        let mut store = ConfigStore::new(MemFlash::new(), SECTORS, SECTOR as u32).unwrap();
+        let mut buf = [0u8; 64];
+        assert_eq!(store.read(0, &mut buf), Ok(None));
+        assert_eq!(store.read(MAX_KEYS as u8, &mut buf), Err(StoreError::InvalidKey));
+    }
+
+    #[test]
+    fn latest_record_survives_remount() {
+        let mut store = ConfigStore::new(MemFlash::new(), SECTORS, SECTOR as u32).unwrap();
+        store.write(0, 1, b"{\"dhcp\":true}").unwrap();
+        store.write(1, 3, b"abc").unwrap();
+        store.write(0, 2, b"{\"dhcp\":false}").unwrap();
+
+        let mut store = ConfigStore::new(store.release(), SECTORS, SECTOR as u32).unwrap();
+        let mut buf = [0u8; 64];
+        assert_eq!(
+            read_back(&mut store, 0, &mut buf),
+            Some((2, &b"{\"dhcp\":false}"[..]))
+        );
+        assert_eq!(read_back(&mut store, 1, &mut buf), Some((3, &b"abc"[..])));
+        assert_eq!(read_back(&mut store, 2, &mut buf), None);
+    }
+
+    #[test]
+    fn compaction_alternates_sectors_and_keeps_all_keys() {
+        let mut store = ConfigStore::new(MemFlash::new(), SECTORS, SECTOR as u32).unwrap();
+        store.write(2, 1, b"constant record").unwrap();
+        let mut seen = [false; 2];
+        for i in 0u8..100 {
+            store.write(0, 1, &[i; 37]).unwrap();
+            seen[store.active] = true;
+        }
+        assert_eq!(seen, [true, true]);
+
+        let mut store = ConfigStore::new(store.release(), SECTORS, SECTOR as u32).unwrap();
+        let mut buf = [0u8; 64];
+        assert_eq!(read_back(&mut store, 0, &mut buf), Some((1, &[99u8; 37][..])));
+        assert_eq!(
+            read_back(&mut store, 2, &mut buf),
+            Some((1, &b"constant record"[..]))
+        );
+    }
+
+    #[test]
+    fn corrupt_record_falls_back_to_previous() {
+        let mut store = ConfigStore::new(MemFlash::new(), SECTORS, SECTOR as u32).unwrap();
+        store.write(0, 1, b"first").unwrap();
+        let second = store.head;
+        store.write(0, 1, b"second").unwrap();
+
+        let mut flash = store.release();
+        flash.mem[second as usize + HEADER_LEN] &= 0x0F;
+        let mut store = ConfigStore::new(flash, SECTORS, SECTOR as u32).unwrap();
+        let mut buf = [0u8; 64];
+        assert_eq!(read_back(&mut store, 0, &mut buf), Some((1, &b"first"[..])));
+    }
+
+    #[test]
+    fn interrupted_write_is_ignored() {
+        let mut store = ConfigStore::new(MemFlash::new(), SECTORS, SECTOR as u32).unwrap();
+        store.write(0, 1, b"good value").unwrap();
+
+        let mut flash = store.release();
+        // Header lands, payload does not
+        flash.writes_left = Some(1);
+        let mut store = ConfigStore::new(flash, SECTORS, SECTOR as u32).unwrap();
+        assert_eq!(store.write(0, 1, b"lost value"), Err(StoreError::Flash));
+
+        let mut flash = store.release();
+        flash.writes_left = None;
+        let mut store = ConfigStore::new(flash, SECTORS, SECTOR as u32).unwrap();
+        let mut buf = [0u8; 64];
+        assert_eq!(read_back(&mut store, 0, &mut buf), Some((1, &b"good value"[..])));
+        store.write(0, 2, b"next value").unwrap();
+        assert_eq!(read_back(&mut store, 0, &mut buf), Some((2, &b"next value"[..])));
+    }
+
+    #[test]
+    fn interrupted_compaction_is_completed_on_mount() {
+        let mut store = ConfigStore::new(MemFlash::new(), SECTORS, SECTOR as u32).unwrap();
+        store.write(1, 1, b"key one").unwrap();
+        store.write(2, 1, b"key two").unwrap();
+        while store.head + ConfigStore::<MemFlash>::record_len(40) <= store.sector_end() {
+            store.write(0, 1, &[7; 40]).unwrap();
+        }
+
+        // Erase succeeds, key one is copied, key two only gets its header
+        let mut flash = store.release();
+        flash.writes_left = Some(4);
+        let mut store = ConfigStore::new(flash, SECTORS, SECTOR as u32).unwrap();
+        assert_eq!(store.write(0, 1, &[8; 40]), Err(StoreError::Flash));
+
+        let mut flash = store.release();
+        flash.writes_left = None;
+        let mut store = ConfigStore::new(flash, SECTORS, SECTOR as u32).unwrap();
+        let mut buf = [0u8; 64];
+        assert_eq!(read_back(&mut store, 0, &mut buf), Some((1, &[7u8; 40][..])));
+        assert_eq!(read_back(&mut store, 1, &mut buf), Some((1, &b"key one"[..])));
+        assert_eq!(read_back(&mut store, 2, &mut buf), Some((1, &b"key two"[..])));
+
+        // Everything now lives in the active sector, the next compaction is safe
+        for i in 0u8..20 {
+            store.write(0, 1, &[i; 40]).unwrap();
+        }
+        let mut store = ConfigStore::new(store.release(), SECTORS, SECTOR as u32).unwrap();
+        assert_eq!(read_back(&mut store, 1, &mut buf), Some((1, &b"key one"[..])));
+        assert_eq!(read_back(&mut store, 2, &mut buf), Some((1, &b"key two"[..])));
+    }
+
+    #[test]
+    fn failed_write_after_compaction_drops_the_stale_record() {
+        let mut store = ConfigStore::new(MemFlash::new(), SECTORS, SECTOR as u32).unwrap();
+        store.write(1, 1, b"key one").unwrap();
+        while store.head + ConfigStore::<MemFlash>::record_len(40) <= store.sector_end() {
+            store.write(0, 1, &[7; 40]).unwrap();
+        }
+
+        // Key one is copied, the new record for key zero fails on its header
+        let mut flash = store.release();
+        flash.writes_left = Some(2);
+        let mut store = ConfigStore::new(flash, SECTORS, SECTOR as u32).unwrap();
+        assert_eq!(store.write(0, 1, &[8; 40]), Err(StoreError::Flash));
+        let mut buf = [0u8; 64];
+        assert_eq!(store.read(0, &mut buf), Ok(None));
+
+        // The next compaction erases the old sector and must not copy from it
+        store.flash.writes_left = None;
+        let active = store.active;
+        while store.active == active {
+            store.write(1, 1, b"key one").unwrap();
+        }
+        assert_eq!(store.read(0, &mut buf), Ok(None));
+        store.write(0, 2, &[9; 40]).unwrap();
+
+        let mut store = ConfigStore::new(store.release(), SECTORS, SECTOR as u32).unwrap();
+        assert_eq!(read_back(&mut store, 0, &mut buf), Some((2, &[9u8; 40][..])));
+        assert_eq!(read_back(&mut store, 1, &mut buf), Some((1, &b"key one"[..])));
+    }
+
+    #[test]
+    fn oversized_record_is_rejected() {
+        let mut store = ConfigStore::new(MemFlash::new(), SECTORS, SECTOR as u32).unwrap();
+        assert_eq!(store.write(0, 1, &[0; SECTOR]), Err(StoreError::TooLarge));
+        assert_eq!(store.write(MAX_KEYS as u8, 1, b"x"), Err(StoreError::InvalidKey));
+        let mut small = [0u8; 2];
+        store.write(0, 1, b"abc").unwrap();
+        assert_eq!(store.read(0, &mut small), Err(StoreError::BufferTooSmall));
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(!crc32_update(0xFFFF_FFFF, b"123456789"), 0xCBF4_3926);
+    }
+}
@@ -0,0 +1,81 @@
+/*
+ * MIT License
+ *
+ * Copyright (c) [2022] [Ondrej Babec <ond.babec@gmail.com>]
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_mqtt::encoding::variable_byte_integer::VariableByteIntegerDecoder;
+use rust_mqtt::packet::v3::mqtt_packet::Packet as _;
+use rust_mqtt::packet::v5::mqtt_packet::Packet as _;
+use rust_mqtt::packet::v5::property::Property;
+use rust_mqtt::packet::{v3, v5};
+use rust_mqtt::utils::buffer_reader::BuffReader;
+
+const DECODERS: u8 = 21;
+
+/// Any result but a panic is fine
+macro_rules! decode {
+    ($packet:ty, $data:expr) => {{
+        let mut packet = <$packet>::new();
+        let _ = packet.decode(&mut BuffReader::new($data, $data.len()));
+    }};
+}
+
+// The first byte picks the decoder, the rest is its input
+fuzz_target!(|input: &[u8]| {
+    let Some((&selector, data)) = input.split_first() else {
+        return;
+    };
+    match selector % DECODERS {
+        0 => decode!(v3::connack_packet::ConnackPacket, data),
+        1 => decode!(v3::puback_packet::PubackPacket, data),
+        2 => decode!(v3::pubcomp_packet::PubcompPacket, data),
+        3 => decode!(v3::publish_packet::PublishPacket, data),
+        4 => decode!(v3::pubrec_packet::PubrecPacket, data),
+        5 => decode!(v3::pubrel_packet::PubrelPacket, data),
+        6 => decode!(v3::suback_packet::SubackPacket<8>, data),
+        7 => decode!(v3::unsuback_packet::UnsubackPacket, data),
+        8 => decode!(v5::auth_packet::AuthPacket<'_, 8>, data),
+        9 => decode!(v5::connack_packet::ConnackPacket<'_, 8>, data),
+        10 => decode!(v5::disconnect_packet::DisconnectPacket<'_, 8>, data),
+        11 => decode!(v5::pingresp_packet::PingrespPacket, data),
+        12 => decode!(v5::puback_packet::PubackPacket<'_, 8>, data),
+        13 => decode!(v5::pubcomp_packet::PubcompPacket<'_, 8>, data),
+        14 => decode!(v5::publish_packet::PublishPacket<'_, 8>, data),
+        15 => decode!(v5::pubrec_packet::PubrecPacket<'_, 8>, data),
+        16 => decode!(v5::pubrel_packet::PubrelPacket<'_, 8>, data),
+        17 => decode!(v5::suback_packet::SubackPacket<'_, 8, 8>, data),
+        18 => decode!(v5::unsuback_packet::UnsubackPacket<'_, 8, 8>, data),
+        19 => {
+            let mut reader = BuffReader::new(data, data.len());
+            while reader.position < data.len() && Property::decode(&mut reader).is_ok() {}
+        }
+        _ => {
+            if let Ok(encoded) = <[u8; 4]>::try_from(data) {
+                let _ = VariableByteIntegerDecoder::decode(encoded);
+            }
+            let _ = BuffReader::new(data, data.len()).read_variable_byte_int();
+        }
+    }
+});
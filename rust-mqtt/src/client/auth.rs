@@ -0,0 +1,50 @@
+/*
+ * MIT License
+ *
+ * Copyright (c) [2022] [Ondrej Babec <ond.babec@gmail.com>]
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use crate::packet::v5::reason_codes::ReasonCode;
+
+/// MQTTv5 enhanced authentication, a challenge / response exchange (e.g. SCRAM) in place of
+/// a plaintext password. The client sends `method` and the data from `start` with CONNECT,
+/// answers every AUTH the broker sends with Continue Authentication through `challenge` and
+/// hands the data of the CONNACK (or the AUTH ending a re-authentication) to `complete`.
+///
+/// The returned data borrows the authenticator, which keeps its own buffers and state
+/// between the rounds. Ignored for MQTTv3, which has no AUTH packet.
+pub trait Authenticator {
+    /// Authentication Method, e.g. `SCRAM-SHA-256`.
+    fn method(&self) -> &'static str;
+
+    /// Starts a new exchange and returns the initial Authentication Data, possibly empty.
+    /// Called for every connect and re-authentication.
+    fn start(&mut self) -> Result<&[u8], ReasonCode>;
+
+    /// Response to the Authentication Data of a broker challenge.
+    fn challenge(&mut self, data: &[u8]) -> Result<&[u8], ReasonCode>;
+
+    /// The broker accepted the exchange, `data` is its final Authentication Data (empty if
+    /// none was sent), e.g. a server signature to verify. An error drops the connection.
+    fn complete(&mut self, _data: &[u8]) -> Result<(), ReasonCode> {
+        Ok(())
+    }
+}
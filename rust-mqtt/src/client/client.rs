@@ -27,9 +27,13 @@ use embedded_io_async::{Read, Write};
 use heapless::Vec;
 use rand_core::RngCore;
 
+use crate::client::auth::Authenticator;
 use crate::client::client_config::ClientConfig;
-use crate::packet::v5::publish_packet::QualityOfService::{self, QoS1};
+use crate::client::payload::Payload;
+use crate::packet::v5::publish_packet::PublishOptions;
+use crate::packet::v5::publish_packet::QualityOfService::{self, QoS1, QoS2};
 use crate::packet::v5::reason_codes::ReasonCode;
+use crate::packet::v5::subscription_packet::SubscriptionOptions;
 
 use super::raw_client::{Event, RawMqttClient};
 
@@ -65,19 +69,33 @@ where
         }
     }
 
+    /// MQTTv5 enhanced authentication for the following connects, see `Authenticator`.
+    /// The AUTH packets carry two properties, so `MAX_PROPERTIES` has to be at least 2.
+    pub fn set_authenticator(&mut self, authenticator: Option<&'a mut dyn Authenticator>) {
+        self.raw.set_authenticator(authenticator);
+    }
+
     /// Method allows client connect to server. Client is connecting to the specified broker
     /// in the `ClientConfig`. Method selects proper implementation of the MQTT version based on the config.
     /// If the connection to the broker fails, method returns Err variable that contains
-    /// Reason codes returned from the broker.
+    /// Reason codes returned from the broker. The birth message from the `ClientConfig`
+    /// is published once the broker accepts the connection.
     pub async fn connect_to_broker<'b>(&'b mut self) -> Result<(), ReasonCode> {
         self.raw.connect_to_broker().await?;
 
         match self.raw.poll::<0>().await? {
-            Event::Connack => Ok(()),
-            Event::Disconnect(reason) => Err(reason),
+            Event::Connack => (),
+            Event::Disconnect(reason) => return Err(reason),
             // If an application message comes at this moment, it is lost.
-            _ => Err(ReasonCode::ImplementationSpecificError),
+            _ => return Err(ReasonCode::ImplementationSpecificError),
         }
+
+        if let Some((topic, payload)) = self.raw.birth() {
+            self.raw
+                .send_message(topic, payload, QualityOfService::QoS0, true)
+                .await?;
+        }
+        Ok(())
     }
 
     /// Method allows client disconnect from the server. Client disconnects from the specified broker
@@ -92,17 +110,47 @@ where
     /// Method allows sending message to broker specified from the ClientConfig. Client sends the
     /// message from the parameter `message` to the topic `topic_name` on the broker
     /// specified in the ClientConfig. If the send fails method returns Err with reason code
-    /// received by broker.
+    /// received by broker. QoS1 returns once the PUBACK arrives, QoS2 once the
+    /// PUBREC / PUBREL / PUBCOMP exchange has completed.
     pub async fn send_message<'b>(
         &'b mut self,
         topic_name: &'b str,
         message: &'b [u8],
         qos: QualityOfService,
         retain: bool,
+    ) -> Result<(), ReasonCode> {
+        self.send_message_with_options(topic_name, message, qos, retain, PublishOptions::default())
+            .await
+    }
+
+    /// `send_message` with MQTTv5 message expiry and content type, MQTTv3 leaves them out.
+    pub async fn send_message_with_options<'b>(
+        &'b mut self,
+        topic_name: &'b str,
+        message: &'b [u8],
+        qos: QualityOfService,
+        retain: bool,
+        options: PublishOptions<'b>,
+    ) -> Result<(), ReasonCode> {
+        let mut message = message;
+        self.send_message_streamed(topic_name, &mut message, qos, retain, options)
+            .await
+    }
+
+    /// `send_message_with_options` for a payload written in chunks, so it can be larger than
+    /// the write buffer. A payload that ends before `Payload::remaining` bytes fails with
+    /// `MalformedPacket` and the connection has to be dropped.
+    pub async fn send_message_streamed<'b>(
+        &'b mut self,
+        topic_name: &'b str,
+        payload: &mut dyn Payload,
+        qos: QualityOfService,
+        retain: bool,
+        options: PublishOptions<'b>,
     ) -> Result<(), ReasonCode> {
         let identifier = self
             .raw
-            .send_message(topic_name, message, qos, retain)
+            .send_message_streamed(topic_name, payload, qos, retain, options)
             .await?;
 
         // QoS1
@@ -119,6 +167,29 @@ where
                 // If an application message comes at this moment, it is lost.
                 _ => Err(ReasonCode::ImplementationSpecificError),
             }
+        } else if qos == QoS2 {
+            match self.raw.poll::<0>().await? {
+                Event::Pubrec(ack_identifier) => {
+                    if identifier != ack_identifier {
+                        return Err(ReasonCode::PacketIdentifierNotFound);
+                    }
+                }
+                Event::Disconnect(reason) => return Err(reason),
+                // If an application message comes at this moment, it is lost.
+                _ => return Err(ReasonCode::ImplementationSpecificError),
+            }
+            match self.raw.poll::<0>().await? {
+                Event::Pubcomp(ack_identifier) => {
+                    if identifier == ack_identifier {
+                        Ok(())
+                    } else {
+                        Err(ReasonCode::PacketIdentifierNotFound)
+                    }
+                }
+                Event::Disconnect(reason) => Err(reason),
+                // If an application message comes at this moment, it is lost.
+                _ => Err(ReasonCode::ImplementationSpecificError),
+            }
         } else {
             Ok(())
         }
@@ -197,6 +268,35 @@ where
         }
     }
 
+    /// Same as `subscribe_to_topic` with MQTTv5 subscription options (no local,
+    /// retain as published, retain handling), MQTTv3 leaves them out.
+    pub async fn subscribe_to_topic_with_options<'b>(
+        &'b mut self,
+        topic_name: &'b str,
+        options: SubscriptionOptions,
+    ) -> Result<(), ReasonCode> {
+        let mut topic_names = Vec::<&'b str, 1>::new();
+        topic_names.push(topic_name).unwrap();
+
+        let identifier = self
+            .raw
+            .subscribe_to_topics_with_options(&topic_names, options)
+            .await?;
+
+        match self.raw.poll::<1>().await? {
+            Event::Suback(ack_identifier) => {
+                if identifier == ack_identifier {
+                    Ok(())
+                } else {
+                    Err(ReasonCode::PacketIdentifierNotFound)
+                }
+            }
+            Event::Disconnect(reason) => Err(reason),
+            // If an application message comes at this moment, it is lost.
+            _ => Err(ReasonCode::ImplementationSpecificError),
+        }
+    }
+
     /// Method allows client receive a message. The work of this method strictly depends on the
     /// network implementation passed in the `ClientConfig`. It expects the PUBLISH packet
     /// from the broker.
@@ -209,6 +309,19 @@ where
         }
     }
 
+    /// Method runs an MQTTv5 re-authentication with the `Authenticator` and returns once
+    /// the broker has accepted it, challenges in between are answered.
+    pub async fn reauthenticate(&mut self) -> Result<(), ReasonCode> {
+        self.raw.reauthenticate().await?;
+
+        match self.raw.poll::<0>().await? {
+            Event::Auth => Ok(()),
+            Event::Disconnect(reason) => Err(reason),
+            // If an application message comes at this moment, it is lost.
+            _ => Err(ReasonCode::ImplementationSpecificError),
+        }
+    }
+
     /// Method allows client send PING message to the broker specified in the `ClientConfig`.
     /// If there is expectation for long running connection. Method should be executed
     /// regularly by the timer that counts down the session expiry interval.
@@ -47,6 +47,8 @@ pub enum MqttVersion {
 #[derive(Clone)]
 pub struct ClientConfig<'a, const MAX_PROPERTIES: usize, T: RngCore> {
     pub max_subscribe_qos: QualityOfService,
+    /// Seconds, the broker drops the connection after one and a half times this without
+    /// a packet from the client. 0 turns the keep alive off.
     pub keep_alive: u16,
     pub username_flag: bool,
     pub username: EncodedString<'a>,
@@ -60,6 +62,9 @@ pub struct ClientConfig<'a, const MAX_PROPERTIES: usize, T: RngCore> {
     pub will_topic: EncodedString<'a>,
     pub will_payload: BinaryData<'a>,
     pub will_retain: bool,
+    pub birth_flag: bool,
+    pub birth_topic: &'a str,
+    pub birth_payload: &'a [u8],
     pub client_id: EncodedString<'a>,
 }
 
@@ -80,6 +85,9 @@ impl<'a, const MAX_PROPERTIES: usize, T: RngCore> ClientConfig<'a, MAX_PROPERTIE
             will_topic: EncodedString::new(),
             will_payload: BinaryData::new(),
             will_retain: false,
+            birth_flag: false,
+            birth_topic: "",
+            birth_payload: &[],
             client_id: EncodedString::new(),
         }
     }
@@ -103,6 +111,15 @@ impl<'a, const MAX_PROPERTIES: usize, T: RngCore> ClientConfig<'a, MAX_PROPERTIE
         self.will_payload = payload_d;
     }
 
+    /// Method sets the birth message that is published with QoS0 and the retain flag after
+    /// every successful connect. Together with a will on the same topic it forms an
+    /// availability topic, e.g. will `offline` and birth `online`.
+    pub fn add_birth(&mut self, topic: &'a str, payload: &'a [u8]) {
+        self.birth_flag = true;
+        self.birth_topic = topic;
+        self.birth_payload = payload;
+    }
+
     /// Method adds the username array and also sets the username flag so client
     /// will use it for the authentication
     pub fn add_username(&mut self, username: &'a str) {
@@ -0,0 +1,632 @@
+/*
+ * MIT License
+ *
+ * Copyright (c) [2022] [Ondrej Babec <ond.babec@gmail.com>]
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use core::convert::Infallible;
+use core::future::{pending, Future};
+
+use embassy_futures::select::{select, select3, Either, Either3};
+use embedded_io_async::{Read, Write};
+use heapless::Vec;
+use rand_core::RngCore;
+
+use crate::client::auth::Authenticator;
+use crate::client::client_config::ClientConfig;
+use crate::client::payload::Payload;
+use crate::client::raw_client::{Event, RawMqttClient};
+use crate::packet::v5::publish_packet::PublishOptions;
+use crate::packet::v5::publish_packet::QualityOfService::{self, QoS0, QoS1, QoS2};
+use crate::packet::v5::reason_codes::ReasonCode;
+use crate::packet::v5::subscription_packet::SubscriptionOptions;
+
+/// Opens the network connection to the broker, for the first connect and every reconnect.
+#[allow(async_fn_in_trait)]
+pub trait Connector {
+    type Connection: Read + Write;
+
+    /// Establishes a new transport (TCP, TLS, ...). The connection returned by the previous
+    /// call has always been dropped before this is called again, so a connector can hand
+    /// the same buffers to every connection.
+    async fn connect(&mut self) -> Result<Self::Connection, ReasonCode>;
+}
+
+/// Time source for the keep alive schedule and the reconnect backoff.
+#[allow(async_fn_in_trait)]
+pub trait Clock {
+    /// Milliseconds since an arbitrary fixed point.
+    fn now_ms(&self) -> u64;
+
+    /// Completes once `now_ms` has reached `deadline_ms`.
+    async fn sleep_until(&mut self, deadline_ms: u64);
+}
+
+/// What woke `ConnectionManager::poll`.
+pub enum ManagerEvent<'b> {
+    /// Connected to the broker and subscribed to every topic again.
+    Connected,
+    /// The connection was lost or could not be established, the next poll retries
+    /// after the backoff delay.
+    Disconnected(ReasonCode),
+    /// Messages that arrived while `publish` or `subscribe` waited for the broker are
+    /// returned first, in order.
+    Message(&'b str, &'b [u8]),
+    /// Keep alive housekeeping only.
+    Idle,
+}
+
+enum State {
+    Disconnected { retry_at: u64 },
+    Connected,
+}
+
+/// Acknowledgement `wait_for` expects from the broker.
+#[derive(Clone, Copy)]
+enum Ack {
+    Puback(u16),
+    Pubcomp(u16),
+    Suback(u16),
+    Auth,
+}
+
+/// Messages that arrived while `wait_for` waited for an acknowledgement, until `poll` hands
+/// them out. Each entry is the topic length (u16) and payload length (u32) followed by both.
+struct Inbox<'a> {
+    buffer: &'a mut [u8],
+    start: usize,
+    end: usize,
+    /// Length of the entry handed out last, it is released on the next call
+    delivered: usize,
+}
+
+const INBOX_HEADER_LEN: usize = 6;
+
+impl<'a> Inbox<'a> {
+    fn new(buffer: &'a mut [u8]) -> Self {
+        Self {
+            buffer,
+            start: 0,
+            end: 0,
+            delivered: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.start + self.delivered == self.end
+    }
+
+    fn release(&mut self) {
+        self.start += self.delivered;
+        self.delivered = 0;
+        if self.start == self.end {
+            self.start = 0;
+            self.end = 0;
+        }
+    }
+
+    /// Returns false if the message does not fit.
+    fn push(&mut self, topic: &str, payload: &[u8]) -> bool {
+        self.release();
+        let len = INBOX_HEADER_LEN + topic.len() + payload.len();
+        if len > self.buffer.len() - (self.end - self.start) {
+            return false;
+        }
+        if self.end + len > self.buffer.len() {
+            self.buffer.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+        }
+        let entry = &mut self.buffer[self.end..self.end + len];
+        // MQTT limits topics to 65535 bytes and packets to 256MiB
+        entry[0..2].copy_from_slice(&(topic.len() as u16).to_be_bytes());
+        entry[2..6].copy_from_slice(&(payload.len() as u32).to_be_bytes());
+        entry[INBOX_HEADER_LEN..INBOX_HEADER_LEN + topic.len()].copy_from_slice(topic.as_bytes());
+        entry[INBOX_HEADER_LEN + topic.len()..].copy_from_slice(payload);
+        self.end += len;
+        true
+    }
+
+    fn pop(&mut self) -> Option<(&str, &[u8])> {
+        self.release();
+        if self.start == self.end {
+            return None;
+        }
+        let entry = &self.buffer[self.start..self.end];
+        let topic_len = usize::from(u16::from_be_bytes([entry[0], entry[1]]));
+        let payload_len = u32::from_be_bytes([entry[2], entry[3], entry[4], entry[5]]) as usize;
+        let (topic, rest) = entry[INBOX_HEADER_LEN..].split_at(topic_len);
+        self.delivered = INBOX_HEADER_LEN + topic_len + payload_len;
+        // the topic was a str when it was queued
+        let topic = core::str::from_utf8(topic).unwrap_or_default();
+        Some((topic, &rest[..payload_len]))
+    }
+}
+
+const DEFAULT_BACKOFF_MIN_MS: u64 = 1_000;
+const DEFAULT_BACKOFF_MAX_MS: u64 = 60_000;
+
+/// Keeps a client connected to the broker. PINGREQ is sent once `keep_alive` seconds pass
+/// without a packet from the client, a missing PINGRESP or acknowledgement drops the
+/// connection. Lost connections are re-established with exponential backoff and the
+/// subscriptions are sent again, so `publish` and `poll` can be used across broker restarts.
+///
+/// `poll` has to be called continuously as it drives the keep alive and the reconnects.
+/// It must not be dropped before it returns, a `select` would restart a reconnect or lose
+/// part of a packet; `poll_or` waits for the application's own events instead.
+pub struct ConnectionManager<
+    'a,
+    C,
+    K,
+    const MAX_PROPERTIES: usize,
+    R,
+    const MAX_SUBSCRIPTIONS: usize,
+> where
+    C: Connector,
+    K: Clock,
+    R: RngCore,
+{
+    raw: RawMqttClient<'a, C::Connection, MAX_PROPERTIES, R>,
+    connector: C,
+    clock: K,
+    subscriptions: Vec<(&'a str, SubscriptionOptions), MAX_SUBSCRIPTIONS>,
+    inbox: Inbox<'a>,
+    state: State,
+    /// Time of the last packet sent to the broker
+    last_sent: u64,
+    /// Time of the outstanding PINGREQ
+    ping_sent: Option<u64>,
+    backoff_min: u64,
+    backoff_max: u64,
+    backoff: u64,
+}
+
+impl<'a, C, K, const MAX_PROPERTIES: usize, R, const MAX_SUBSCRIPTIONS: usize>
+    ConnectionManager<'a, C, K, MAX_PROPERTIES, R, MAX_SUBSCRIPTIONS>
+where
+    C: Connector,
+    K: Clock,
+    R: RngCore,
+{
+    /// The first `poll` connects. `config.keep_alive` is in seconds.
+    /// `inbox` holds the messages that arrive while `publish` or `subscribe` wait for the
+    /// broker, each takes 6 bytes plus its topic and payload. Messages that do not fit are
+    /// dropped with a warning, QoS1 and QoS2 ones have already been acknowledged by then.
+    // the buffers and lengths of `RawMqttClient::new`, plus the connector, clock and inbox
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        connector: C,
+        clock: K,
+        buffer: &'a mut [u8],
+        buffer_len: usize,
+        recv_buffer: &'a mut [u8],
+        recv_buffer_len: usize,
+        inbox: &'a mut [u8],
+        config: ClientConfig<'a, MAX_PROPERTIES, R>,
+    ) -> Self {
+        Self {
+            raw: RawMqttClient::without_connection(
+                buffer,
+                buffer_len,
+                recv_buffer,
+                recv_buffer_len,
+                config,
+            ),
+            connector,
+            clock,
+            subscriptions: Vec::new(),
+            inbox: Inbox::new(inbox),
+            state: State::Disconnected { retry_at: 0 },
+            last_sent: 0,
+            ping_sent: None,
+            backoff_min: DEFAULT_BACKOFF_MIN_MS,
+            backoff_max: DEFAULT_BACKOFF_MAX_MS,
+            backoff: DEFAULT_BACKOFF_MIN_MS,
+        }
+    }
+
+    /// Delay before the first retry, doubled after every failed attempt up to `max_ms`.
+    pub fn set_backoff(&mut self, min_ms: u64, max_ms: u64) {
+        self.backoff_min = min_ms;
+        self.backoff_max = max_ms.max(min_ms);
+        self.backoff = min_ms;
+    }
+
+    /// MQTTv5 enhanced authentication for every connect, see `Authenticator`.
+    pub fn set_authenticator(&mut self, authenticator: Option<&'a mut dyn Authenticator>) {
+        self.raw.set_authenticator(authenticator);
+    }
+
+    pub fn is_connected(&self) -> bool {
+        matches!(self.state, State::Connected)
+    }
+
+    /// Subscribes now if connected and again after every reconnect.
+    /// Fails with `BuffError` once `MAX_SUBSCRIPTIONS` topics are registered.
+    pub async fn subscribe(&mut self, topic: &'a str) -> Result<(), ReasonCode> {
+        self.subscribe_with_options(topic, SubscriptionOptions::default())
+            .await
+    }
+
+    /// `subscribe` with MQTTv5 subscription options, they replace the options of a topic
+    /// that is already registered.
+    pub async fn subscribe_with_options(
+        &mut self,
+        topic: &'a str,
+        options: SubscriptionOptions,
+    ) -> Result<(), ReasonCode> {
+        match self.subscriptions.iter_mut().find(|(t, _)| *t == topic) {
+            Some(subscription) => subscription.1 = options,
+            None => self
+                .subscriptions
+                .push((topic, options))
+                .map_err(|_| ReasonCode::BuffError)?,
+        }
+        if !self.is_connected() {
+            return Ok(());
+        }
+        let result = self.send_subscribe(topic, options).await;
+        self.check(result)
+    }
+
+    /// Publishes and, for QoS1/2, waits for the broker to acknowledge. Fails with
+    /// `NetworkError` while disconnected, the message is not queued.
+    pub async fn publish(
+        &mut self,
+        topic: &str,
+        payload: &[u8],
+        qos: QualityOfService,
+        retain: bool,
+    ) -> Result<(), ReasonCode> {
+        self.publish_with_options(topic, payload, qos, retain, PublishOptions::default())
+            .await
+    }
+
+    /// `publish` with MQTTv5 message expiry and content type, MQTTv3 leaves them out.
+    pub async fn publish_with_options(
+        &mut self,
+        topic: &str,
+        payload: &[u8],
+        qos: QualityOfService,
+        retain: bool,
+        options: PublishOptions<'_>,
+    ) -> Result<(), ReasonCode> {
+        let mut payload = payload;
+        self.publish_streamed(topic, &mut payload, qos, retain, options)
+            .await
+    }
+
+    /// `publish_with_options` for a payload written in chunks, so it can be larger than the
+    /// write buffer. A payload that ends early drops the connection.
+    pub async fn publish_streamed(
+        &mut self,
+        topic: &str,
+        payload: &mut dyn Payload,
+        qos: QualityOfService,
+        retain: bool,
+        options: PublishOptions<'_>,
+    ) -> Result<(), ReasonCode> {
+        if !self.is_connected() {
+            return Err(ReasonCode::NetworkError);
+        }
+        let result = self
+            .send_publish(topic, payload, qos, retain, options)
+            .await;
+        self.check(result)
+    }
+
+    /// Runs an MQTTv5 re-authentication and waits for the broker to accept it. Fails with
+    /// `NetworkError` while disconnected, a failed exchange drops the connection.
+    pub async fn reauthenticate(&mut self) -> Result<(), ReasonCode> {
+        if !self.is_connected() {
+            return Err(ReasonCode::NetworkError);
+        }
+        let result = self.send_reauthenticate().await;
+        self.check(result)
+    }
+
+    /// Sends DISCONNECT and closes the connection, the next `poll` connects again.
+    pub async fn disconnect(&mut self) -> Result<(), ReasonCode> {
+        let result = if self.is_connected() {
+            self.raw.disconnect().await
+        } else {
+            Ok(())
+        };
+        self.raw.set_connection(None);
+        self.state = State::Disconnected {
+            retry_at: self.clock.now_ms(),
+        };
+        result
+    }
+
+    /// Connects, keeps the connection alive and waits for the next message from the broker.
+    pub async fn poll<'b>(&'b mut self) -> ManagerEvent<'b> {
+        match self.poll_or(pending::<Infallible>()).await {
+            Either::First(event) => event,
+            Either::Second(never) => match never {},
+        }
+    }
+
+    /// `poll` that also returns once `wake` completes, e.g. the timer of the next publish.
+    /// `wake` only ever interrupts the backoff delay, the keep alive wait and the wait for
+    /// the next packet, never a connect (which gives up after the response timeout), and a
+    /// partly received packet is kept for the next call. With `wake` racing the reads the transport's read has to be cancel-safe,
+    /// which embassy-net TCP is.
+    pub async fn poll_or<'b, W: Future>(
+        &'b mut self,
+        wake: W,
+    ) -> Either<ManagerEvent<'b>, W::Output> {
+        if !self.inbox.is_empty() {
+            // cannot fail, the inbox is not empty
+            let (topic, payload) = self.inbox.pop().unwrap_or_default();
+            return Either::First(ManagerEvent::Message(topic, payload));
+        }
+
+        if let State::Disconnected { retry_at } = self.state {
+            self.raw.set_connection(None);
+            return match select(self.clock.sleep_until(retry_at), wake).await {
+                Either::First(_) => Either::First(self.reconnect().await),
+                Either::Second(output) => Either::Second(output),
+            };
+        }
+
+        let now = self.clock.now_ms();
+        let deadline = match self.ping_sent {
+            Some(sent) => {
+                let timeout = sent + self.response_timeout();
+                if now >= timeout {
+                    warn!("No PINGRESP from the broker");
+                    self.lose_connection();
+                    return Either::First(ManagerEvent::Disconnected(ReasonCode::KeepAliveTimeout));
+                }
+                Some(timeout)
+            }
+            None => match self.keep_alive() {
+                Some(keep_alive) if now >= self.last_sent + keep_alive => {
+                    if let Err(reason) = self.raw.send_ping().await {
+                        self.lose_connection();
+                        return Either::First(ManagerEvent::Disconnected(reason));
+                    }
+                    self.last_sent = now;
+                    self.ping_sent = Some(now);
+                    Some(now + self.response_timeout())
+                }
+                Some(keep_alive) => Some(self.last_sent + keep_alive),
+                None => None,
+            },
+        };
+
+        let clock = &mut self.clock;
+        let sleep = async move {
+            match deadline {
+                Some(deadline) => clock.sleep_until(deadline).await,
+                None => pending().await,
+            }
+        };
+        match select3(sleep, self.raw.receive(), wake).await {
+            Either3::First(_) => return Either::First(ManagerEvent::Idle),
+            Either3::Second(Ok(())) => (),
+            Either3::Second(Err(reason)) => {
+                self.lose_connection();
+                return Either::First(ManagerEvent::Disconnected(reason));
+            }
+            Either3::Third(output) => return Either::Second(output),
+        }
+
+        // Nothing below may use the client again, the message borrows it
+        Either::First(match self.raw.poll_received::<0>().await {
+            Ok(Some(Event::Message(topic, payload))) => ManagerEvent::Message(topic, payload),
+            Ok(Some(Event::Pingresp)) => {
+                self.ping_sent = None;
+                ManagerEvent::Idle
+            }
+            Ok(Some(Event::Disconnect(reason))) | Err(reason) => {
+                // the connection itself is dropped before the next connect
+                self.state = State::Disconnected {
+                    retry_at: self.clock.now_ms(),
+                };
+                ManagerEvent::Disconnected(reason)
+            }
+            // answered by the client, or a late acknowledgement of a publish that already
+            // timed out
+            Ok(_) => ManagerEvent::Idle,
+        })
+    }
+
+    async fn reconnect<'b>(&'b mut self) -> ManagerEvent<'b> {
+        match self.connect().await {
+            Ok(()) => {
+                self.state = State::Connected;
+                self.backoff = self.backoff_min;
+                ManagerEvent::Connected
+            }
+            Err(reason) => {
+                self.raw.set_connection(None);
+                self.state = State::Disconnected {
+                    retry_at: self.clock.now_ms() + self.backoff,
+                };
+                self.backoff = (self.backoff * 2).min(self.backoff_max);
+                ManagerEvent::Disconnected(reason)
+            }
+        }
+    }
+
+    /// The transport, the CONNACK and the birth message share one response timeout, a
+    /// broker that accepts the connection but never answers fails with `KeepAliveTimeout`.
+    /// Every subscription gets its own, as in `wait_for`.
+    async fn connect(&mut self) -> Result<(), ReasonCode> {
+        let deadline = self.response_deadline();
+        let connection = until(&mut self.clock, deadline, self.connector.connect()).await?;
+        self.raw.set_connection(Some(connection));
+        self.last_sent = self.clock.now_ms();
+        self.ping_sent = None;
+        let raw = &mut self.raw;
+        let connack = async move {
+            raw.connect_to_broker().await?;
+            match raw.poll::<0>().await? {
+                Event::Connack => Ok(()),
+                Event::Disconnect(reason) => Err(reason),
+                _ => Err(ReasonCode::ImplementationSpecificError),
+            }
+        };
+        until(&mut self.clock, deadline, connack).await?;
+        if let Some((topic, payload)) = self.raw.birth() {
+            let birth = self.raw.send_message(topic, payload, QoS0, true);
+            until(&mut self.clock, deadline, birth).await?;
+        }
+        for i in 0..self.subscriptions.len() {
+            let (topic, options) = self.subscriptions[i];
+            self.send_subscribe(topic, options).await?;
+        }
+        Ok(())
+    }
+
+    async fn send_subscribe(
+        &mut self,
+        topic: &str,
+        options: SubscriptionOptions,
+    ) -> Result<(), ReasonCode> {
+        let mut topics = Vec::<&str, 1>::new();
+        // cannot fail, there is room for one topic
+        let _ = topics.push(topic);
+        let identifier = self
+            .raw
+            .subscribe_to_topics_with_options(&topics, options)
+            .await?;
+        self.last_sent = self.clock.now_ms();
+        self.wait_for(Ack::Suback(identifier)).await
+    }
+
+    async fn send_publish(
+        &mut self,
+        topic: &str,
+        payload: &mut dyn Payload,
+        qos: QualityOfService,
+        retain: bool,
+        options: PublishOptions<'_>,
+    ) -> Result<(), ReasonCode> {
+        let identifier = self
+            .raw
+            .send_message_streamed(topic, payload, qos, retain, options)
+            .await?;
+        self.last_sent = self.clock.now_ms();
+        match qos {
+            QoS1 => self.wait_for(Ack::Puback(identifier)).await,
+            // PUBREL is sent by the client when the PUBREC arrives
+            QoS2 => self.wait_for(Ack::Pubcomp(identifier)).await,
+            _ => Ok(()),
+        }
+    }
+
+    async fn send_reauthenticate(&mut self) -> Result<(), ReasonCode> {
+        self.raw.reauthenticate().await?;
+        self.last_sent = self.clock.now_ms();
+        self.wait_for(Ack::Auth).await
+    }
+
+    /// Waits for `ack`, a PINGRESP in between is accepted and messages are queued in the inbox.
+    async fn wait_for(&mut self, ack: Ack) -> Result<(), ReasonCode> {
+        let deadline = self.response_deadline();
+        loop {
+            until(&mut self.clock, deadline, self.raw.receive()).await?;
+            let Some(event) = self.raw.poll_received::<1>().await? else {
+                continue;
+            };
+            match (ack, event) {
+                (Ack::Puback(id), Event::Puback(ack_id))
+                | (Ack::Pubcomp(id), Event::Pubcomp(ack_id))
+                | (Ack::Suback(id), Event::Suback(ack_id)) => {
+                    return if id == ack_id {
+                        Ok(())
+                    } else {
+                        Err(ReasonCode::PacketIdentifierNotFound)
+                    };
+                }
+                (Ack::Auth, Event::Auth) => return Ok(()),
+                (Ack::Pubcomp(id), Event::Pubrec(ack_id)) if id != ack_id => {
+                    return Err(ReasonCode::PacketIdentifierNotFound);
+                }
+                (_, Event::Pingresp) => self.ping_sent = None,
+                (_, Event::Message(topic, payload)) => {
+                    if !self.inbox.push(topic, payload) {
+                        warn!("No room in the inbox, a message on {} is dropped", topic);
+                    }
+                }
+                (_, Event::Disconnect(reason)) => return Err(reason),
+                _ => (),
+            }
+        }
+    }
+
+    /// Drops the connection on any error so the next `poll` reconnects.
+    fn check(&mut self, result: Result<(), ReasonCode>) -> Result<(), ReasonCode> {
+        if result.is_err() {
+            self.lose_connection();
+        }
+        result
+    }
+
+    fn lose_connection(&mut self) {
+        self.raw.set_connection(None);
+        self.state = State::Disconnected {
+            retry_at: self.clock.now_ms(),
+        };
+    }
+
+    fn keep_alive(&self) -> Option<u64> {
+        match self.raw.keep_alive() {
+            0 => None,
+            seconds => Some(u64::from(seconds) * 1000),
+        }
+    }
+
+    /// When an answer the broker owes from now on is overdue, `None` without keep alive.
+    fn response_deadline(&self) -> Option<u64> {
+        self.keep_alive()
+            .map(|_| self.clock.now_ms() + self.response_timeout())
+    }
+
+    /// Time the broker gets to answer a CONNECT, PINGREQ, PUBLISH or SUBSCRIBE: half the keep alive,
+    /// so a dead connection is noticed within one and a half keep alive periods.
+    fn response_timeout(&self) -> u64 {
+        self.keep_alive()
+            .map_or(0, |keep_alive| (keep_alive / 2).max(1000))
+    }
+}
+
+/// Runs `future` until `deadline`, past it the future is dropped and `KeepAliveTimeout`
+/// returned. Without a deadline it runs to completion.
+async fn until<K: Clock, T>(
+    clock: &mut K,
+    deadline: Option<u64>,
+    future: impl Future<Output = Result<T, ReasonCode>>,
+) -> Result<T, ReasonCode> {
+    let sleep = async move {
+        match deadline {
+            Some(deadline) => clock.sleep_until(deadline).await,
+            None => pending().await,
+        }
+    };
+    match select(sleep, future).await {
+        Either::First(_) => Err(ReasonCode::KeepAliveTimeout),
+        Either::Second(result) => result,
+    }
+}
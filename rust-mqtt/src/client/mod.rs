@@ -22,8 +22,12 @@
  * SOFTWARE.
  */
 
+pub mod auth;
 #[allow(clippy::module_inception)]
 pub mod client;
 #[allow(unused_must_use)]
 pub mod client_config;
+pub mod manager;
+pub mod payload;
 pub mod raw_client;
+pub mod topic_alias;
@@ -0,0 +1,181 @@
+/*
+ * MIT License
+ *
+ * Copyright (c) [2022] [Ondrej Babec <ond.babec@gmail.com>]
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use core::fmt;
+
+/// Message payload that is copied into the client's write buffer a chunk at a time, so a
+/// publish does not need a buffer as large as the message. The length has to be known up
+/// front, it goes into the PUBLISH remaining length before the first chunk is written.
+pub trait Payload {
+    /// Bytes still to be written
+    fn remaining(&self) -> usize;
+
+    /// Copies the next bytes to the start of `buf` and returns how many, at most `buf.len()`.
+    /// Returning 0 before `remaining` bytes have been written fails the publish.
+    fn write_chunk(&mut self, buf: &mut [u8]) -> usize;
+}
+
+impl Payload for &[u8] {
+    fn remaining(&self) -> usize {
+        self.len()
+    }
+
+    fn write_chunk(&mut self, buf: &mut [u8]) -> usize {
+        let len = buf.len().min(self.len());
+        let (chunk, rest) = self.split_at(len);
+        buf[..len].copy_from_slice(chunk);
+        *self = rest;
+        len
+    }
+}
+
+/// Payload made of consecutive slices, e.g. a header and records from a ring buffer.
+/// `len` is the total of all slices.
+pub struct Chunks<'c, I>
+where
+    I: Iterator<Item = &'c [u8]>,
+{
+    remaining: usize,
+    chunks: I,
+    current: &'c [u8],
+}
+
+impl<'c, I> Chunks<'c, I>
+where
+    I: Iterator<Item = &'c [u8]>,
+{
+    pub fn new(len: usize, chunks: I) -> Self {
+        Self {
+            remaining: len,
+            chunks,
+            current: &[],
+        }
+    }
+}
+
+impl<'c, I> Payload for Chunks<'c, I>
+where
+    I: Iterator<Item = &'c [u8]>,
+{
+    fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    fn write_chunk(&mut self, buf: &mut [u8]) -> usize {
+        let room = buf.len().min(self.remaining);
+        let mut len = 0;
+        while len < room {
+            if self.current.is_empty() {
+                match self.chunks.next() {
+                    Some(chunk) => self.current = chunk,
+                    None => break,
+                }
+            }
+            len += self.current.write_chunk(&mut buf[len..room]);
+        }
+        self.remaining -= len;
+        len
+    }
+}
+
+/// Payload rendered by a `core::fmt` writer, e.g. a JSON document built with `write!`.
+/// `render` runs once to count the bytes and again for every chunk, skipping what has
+/// already been written, so it has to produce the same output every time.
+pub struct Formatted<F>
+where
+    F: Fn(&mut dyn fmt::Write) -> fmt::Result,
+{
+    render: F,
+    len: usize,
+    written: usize,
+}
+
+impl<F> Formatted<F>
+where
+    F: Fn(&mut dyn fmt::Write) -> fmt::Result,
+{
+    /// Fails if `render` does
+    pub fn new(render: F) -> Result<Self, fmt::Error> {
+        let mut counter = Window {
+            skip: 0,
+            buf: &mut [],
+            len: 0,
+        };
+        render(&mut counter)?;
+        Ok(Self {
+            render,
+            len: counter.skip,
+            written: 0,
+        })
+    }
+}
+
+impl<F> Payload for Formatted<F>
+where
+    F: Fn(&mut dyn fmt::Write) -> fmt::Result,
+{
+    fn remaining(&self) -> usize {
+        self.len - self.written
+    }
+
+    fn write_chunk(&mut self, buf: &mut [u8]) -> usize {
+        let room = buf.len().min(self.remaining());
+        let mut window = Window {
+            skip: self.written,
+            buf: &mut buf[..room],
+            len: 0,
+        };
+        // the window stops the rendering with an error once it is full
+        let _ = (self.render)(&mut window);
+        self.written += window.len;
+        window.len
+    }
+}
+
+/// Keeps the rendered bytes after the first `skip` until `buf` is full. With an empty
+/// `buf` it only counts, `skip` ends up as the total length.
+struct Window<'w> {
+    skip: usize,
+    buf: &'w mut [u8],
+    len: usize,
+}
+
+impl fmt::Write for Window<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut bytes = s.as_bytes();
+        if self.buf.is_empty() {
+            self.skip += bytes.len();
+            return Ok(());
+        }
+        let skipped = self.skip.min(bytes.len());
+        self.skip -= skipped;
+        bytes = &bytes[skipped..];
+        let len = bytes.write_chunk(&mut self.buf[self.len..]);
+        self.len += len;
+        if self.len == self.buf.len() {
+            return Err(fmt::Error);
+        }
+        Ok(())
+    }
+}
@@ -3,9 +3,10 @@ use heapless::Vec;
 use rand_core::RngCore;
 
 use crate::{
-    encoding::variable_byte_integer::{VariableByteInteger, VariableByteIntegerDecoder},
     network::NetworkConnection,
+    packet::v3,
     packet::v5::{
+        auth_packet::AuthPacket,
         connack_packet::ConnackPacket,
         connect_packet::ConnectPacket,
         disconnect_packet::DisconnectPacket,
@@ -13,25 +14,47 @@ use crate::{
         packet_type::PacketType,
         pingreq_packet::PingreqPacket,
         pingresp_packet::PingrespPacket,
+        property::Property,
         puback_packet::PubackPacket,
-        publish_packet::{PublishPacket, QualityOfService},
+        pubcomp_packet::PubcompPacket,
+        publish_packet::{PublishOptions, PublishPacket, QualityOfService},
+        pubrec_packet::PubrecPacket,
+        pubrel_packet::PubrelPacket,
         reason_codes::ReasonCode,
         suback_packet::SubackPacket,
-        subscription_packet::SubscriptionPacket,
+        subscription_packet::{SubscriptionOptions, SubscriptionPacket},
         unsuback_packet::UnsubackPacket,
         unsubscription_packet::UnsubscriptionPacket,
     },
-    utils::{buffer_reader::BuffReader, buffer_writer::BuffWriter, types::BufferError},
+    utils::{
+        buffer_reader::BuffReader,
+        types::{BinaryData, BufferError, EncodedString},
+    },
 };
 
+use super::auth::Authenticator;
 use super::client_config::{ClientConfig, MqttVersion};
+use super::payload::Payload;
+use super::topic_alias::{Alias, TopicAliases};
+
+/// Incoming QoS2 messages that can be awaiting their PUBREL at the same time. The client should
+/// announce it to MQTTv5 brokers with `Property::ReceiveMaximum`, more fail with
+/// `ReceiveMaximumExceeded`.
+pub const MAX_INBOUND_QOS2: usize = 16;
 
 pub enum Event<'a> {
     Connack,
     Puback(u16),
+    /// PUBREC of an outgoing QoS2 message, the PUBREL reply has already been sent
+    Pubrec(u16),
+    Pubcomp(u16),
     Suback(u16),
     Unsuback(u16),
     Pingresp,
+    /// AUTH with Success, the broker accepted a re-authentication
+    Auth,
+    /// QoS1 and QoS2 messages are acknowledged before they are returned. A QoS2 message is
+    /// returned once, the broker's retransmissions until its PUBREL are answered but not returned.
     Message(&'a str, &'a [u8]),
     Disconnect(ReasonCode),
 }
@@ -45,7 +68,15 @@ where
     buffer_len: usize,
     recv_buffer: &'a mut [u8],
     recv_buffer_len: usize,
+    /// Bytes of the next packet already in `recv_buffer`
+    rx_len: usize,
+    /// Length of the next packet once its fixed header is complete
+    rx_total: Option<usize>,
     config: ClientConfig<'a, MAX_PROPERTIES, R>,
+    /// Packet identifiers of delivered QoS2 messages still waiting for their PUBREL
+    inbound_qos2: Vec<u16, MAX_INBOUND_QOS2>,
+    authenticator: Option<&'a mut dyn Authenticator>,
+    topic_aliases: TopicAliases,
 }
 
 impl<'a, T, const MAX_PROPERTIES: usize, R> RawMqttClient<'a, T, MAX_PROPERTIES, R>
@@ -67,7 +98,63 @@ where
             buffer_len,
             recv_buffer,
             recv_buffer_len,
+            rx_len: 0,
+            rx_total: None,
+            config,
+            inbound_qos2: Vec::new(),
+            authenticator: None,
+            topic_aliases: TopicAliases::new(),
+        }
+    }
+
+    /// Client without a network connection, one has to be attached with `set_connection`
+    /// before connecting to the broker.
+    pub fn without_connection(
+        buffer: &'a mut [u8],
+        buffer_len: usize,
+        recv_buffer: &'a mut [u8],
+        recv_buffer_len: usize,
+        config: ClientConfig<'a, MAX_PROPERTIES, R>,
+    ) -> Self {
+        Self {
+            connection: None,
+            buffer,
+            buffer_len,
+            recv_buffer,
+            recv_buffer_len,
+            rx_len: 0,
+            rx_total: None,
             config,
+            inbound_qos2: Vec::new(),
+            authenticator: None,
+            topic_aliases: TopicAliases::new(),
+        }
+    }
+
+    /// Replaces the network connection, the previous one is dropped.
+    pub fn set_connection(&mut self, network_driver: Option<T>) {
+        self.connection = network_driver.map(NetworkConnection::new);
+        self.rx_len = 0;
+        self.rx_total = None;
+    }
+
+    /// MQTTv5 enhanced authentication for the following connects, see `Authenticator`.
+    /// The AUTH packets carry two properties, so `MAX_PROPERTIES` has to be at least 2.
+    pub fn set_authenticator(&mut self, authenticator: Option<&'a mut dyn Authenticator>) {
+        self.authenticator = authenticator;
+    }
+
+    /// Keep alive from the `ClientConfig` in seconds.
+    pub fn keep_alive(&self) -> u16 {
+        self.config.keep_alive
+    }
+
+    /// Birth topic and payload from the `ClientConfig`, if set
+    pub fn birth(&self) -> Option<(&'a str, &'a [u8])> {
+        if self.config.birth_flag {
+            Some((self.config.birth_topic, self.config.birth_payload))
+        } else {
+            None
         }
     }
 
@@ -80,6 +167,52 @@ where
             connect.keep_alive = self.config.keep_alive;
             self.config.add_max_packet_size_as_prop();
             connect.property_len = connect.add_properties(&self.config.properties);
+            if let Some(authenticator) = self.authenticator.as_deref_mut() {
+                let method = authenticator.method();
+                for property in auth_properties(method, authenticator.start()?) {
+                    connect.property_len += property.encoded_len() as u32 + 1;
+                    if connect.properties.push(property).is_err() {
+                        error!("No room for the authentication properties");
+                        return Err(ReasonCode::BuffError);
+                    }
+                }
+            }
+            if self.config.username_flag {
+                connect.add_username(&self.config.username);
+            }
+            if self.config.password_flag {
+                connect.add_password(&self.config.password)
+            }
+            if self.config.will_flag {
+                connect.add_will(
+                    &self.config.will_topic,
+                    &self.config.will_payload,
+                    self.config.will_retain,
+                )
+            }
+            connect.add_client_id(&self.config.client_id);
+            connect.encode(self.buffer, self.buffer_len)
+        };
+
+        if let Err(err) = len {
+            error!("[DECODE ERR]: {}", err);
+            return Err(ReasonCode::BuffError);
+        }
+        let conn = self.connection.as_mut().unwrap();
+        trace!("Sending connect");
+        conn.send(&self.buffer[0..len.unwrap()]).await?;
+
+        Ok(())
+    }
+
+    async fn connect_to_broker_v3<'b>(&'b mut self) -> Result<(), ReasonCode> {
+        use v3::mqtt_packet::Packet as _;
+        if self.connection.is_none() {
+            return Err(ReasonCode::NetworkError);
+        }
+        let len = {
+            let mut connect = v3::connect_packet::ConnectPacket::<'b>::new();
+            connect.keep_alive = self.config.keep_alive;
             if self.config.username_flag {
                 connect.add_username(&self.config.username);
             }
@@ -113,8 +246,12 @@ where
     /// If the connection to the broker fails, method returns Err variable that contains
     /// Reason codes returned from the broker.
     pub async fn connect_to_broker<'b>(&'b mut self) -> Result<(), ReasonCode> {
+        // the session always starts clean, the broker does not resend unfinished QoS2 messages
+        self.inbound_qos2.clear();
+        // aliases are per connection, the CONNACK sets how many the broker accepts
+        self.topic_aliases.reset(0);
         match self.config.mqtt_version {
-            MqttVersion::MQTTv3 => Err(ReasonCode::UnsupportedProtocolVersion),
+            MqttVersion::MQTTv3 => self.connect_to_broker_v3().await,
             MqttVersion::MQTTv5 => self.connect_to_broker_v5().await,
         }
     }
@@ -142,13 +279,37 @@ where
         Ok(())
     }
 
+    async fn disconnect_v3<'b>(&'b mut self) -> Result<(), ReasonCode> {
+        use v3::mqtt_packet::Packet as _;
+        if self.connection.is_none() {
+            return Err(ReasonCode::NetworkError);
+        }
+        let conn = self.connection.as_mut().unwrap();
+        trace!("Creating disconnect packet!");
+        let mut disconnect = v3::disconnect_packet::DisconnectPacket::new();
+        let len = disconnect.encode(self.buffer, self.buffer_len);
+        if let Err(err) = len {
+            warn!("[DECODE ERR]: {}", err);
+            let _ = self.connection.take();
+            return Err(ReasonCode::BuffError);
+        }
+
+        if let Err(_e) = conn.send(&self.buffer[0..len.unwrap()]).await {
+            warn!("Could not send DISCONNECT packet");
+        }
+
+        // Drop connection
+        let _ = self.connection.take();
+        Ok(())
+    }
+
     /// Method allows client disconnect from the server. Client disconnects from the specified broker
     /// in the `ClientConfig`. Method selects proper implementation of the MQTT version based on the config.
     /// If the disconnect from the broker fails, method returns Err variable that contains
     /// Reason codes returned from the broker.
     pub async fn disconnect<'b>(&'b mut self) -> Result<(), ReasonCode> {
         match self.config.mqtt_version {
-            MqttVersion::MQTTv3 => Err(ReasonCode::UnsupportedProtocolVersion),
+            MqttVersion::MQTTv3 => self.disconnect_v3().await,
             MqttVersion::MQTTv5 => self.disconnect_v5().await,
         }
     }
@@ -156,24 +317,93 @@ where
     async fn send_message_v5<'b>(
         &'b mut self,
         topic_name: &'b str,
-        message: &'b [u8],
+        payload: &mut dyn Payload,
         qos: QualityOfService,
         retain: bool,
+        options: PublishOptions<'b>,
     ) -> Result<u16, ReasonCode> {
         if self.connection.is_none() {
             return Err(ReasonCode::NetworkError);
         }
-        let conn = self.connection.as_mut().unwrap();
+        let payload_len = payload_len(payload)?;
         let identifier: u16 = self.config.rng.next_u32() as u16;
         //self.rng.next_u32() as u16;
         let len = {
             let mut packet = PublishPacket::<'b, MAX_PROPERTIES>::new();
+            packet.add_qos(qos);
+            packet.add_identifier(identifier);
+            packet.add_retain(retain);
+            let content_type = options.content_type.map(|content_type| {
+                Property::ContentType(EncodedString {
+                    string: content_type,
+                    len: content_type.len() as u16,
+                })
+            });
+            let properties = options
+                .message_expiry
+                .map(Property::MessageExpiryInterval)
+                .into_iter()
+                .chain(content_type);
+            for property in properties {
+                if packet.add_property(property).is_err() {
+                    error!("No room for the publish properties");
+                    return Err(ReasonCode::BuffError);
+                }
+            }
+            // without room for the alias property the topic is sent in full
+            let alias = if packet.properties.len() < MAX_PROPERTIES {
+                self.topic_aliases.alias(topic_name)
+            } else {
+                Alias::None
+            };
+            match alias {
+                Alias::None => packet.add_topic_name(topic_name),
+                Alias::New(alias) => {
+                    packet.add_topic_name(topic_name);
+                    // cannot fail, there is room for one more property
+                    let _ = packet.add_property(Property::TopicAlias(alias));
+                }
+                Alias::Known(alias) => {
+                    packet.add_topic_name("");
+                    let _ = packet.add_property(Property::TopicAlias(alias));
+                }
+            }
+            let len = packet.encode_header(self.buffer, self.buffer_len, payload_len);
+            if let (Err(_), Alias::New(alias)) = (&len, alias) {
+                self.topic_aliases.forget(alias);
+            }
+            len
+        };
+
+        if let Err(err) = len {
+            error!("[DECODE ERR]: {}", err);
+            return Err(ReasonCode::BuffError);
+        }
+        trace!("Sending message");
+        self.send_payload(len.unwrap(), payload).await?;
+
+        Ok(identifier)
+    }
+    async fn send_message_v3<'b>(
+        &'b mut self,
+        topic_name: &'b str,
+        payload: &mut dyn Payload,
+        qos: QualityOfService,
+        retain: bool,
+    ) -> Result<u16, ReasonCode> {
+        use v3::mqtt_packet::Packet as _;
+        if self.connection.is_none() {
+            return Err(ReasonCode::NetworkError);
+        }
+        let payload_len = payload_len(payload)?;
+        let identifier: u16 = self.config.rng.next_u32() as u16;
+        let len = {
+            let mut packet = v3::publish_packet::PublishPacket::<'b>::new();
             packet.add_topic_name(topic_name);
             packet.add_qos(qos);
             packet.add_identifier(identifier);
-            packet.add_message(message);
             packet.add_retain(retain);
-            packet.encode(self.buffer, self.buffer_len)
+            packet.encode_header(self.buffer, self.buffer_len, payload_len)
         };
 
         if let Err(err) = len {
@@ -181,30 +411,95 @@ where
             return Err(ReasonCode::BuffError);
         }
         trace!("Sending message");
-        conn.send(&self.buffer[0..len.unwrap()]).await?;
+        self.send_payload(len.unwrap(), payload).await?;
 
         Ok(identifier)
     }
+
+    /// Sends the PUBLISH header already encoded in the first `header_len` bytes of the write
+    /// buffer followed by the payload, filling the buffer with payload chunks and writing it
+    /// out whenever it is full. A payload that ends early leaves a partial packet on the
+    /// connection, which then has to be dropped.
+    async fn send_payload(
+        &mut self,
+        header_len: usize,
+        payload: &mut dyn Payload,
+    ) -> Result<(), ReasonCode> {
+        let conn = self.connection.as_mut().ok_or(ReasonCode::NetworkError)?;
+        let mut left = payload.remaining();
+        let mut filled = header_len;
+        while left > 0 {
+            if filled == self.buffer_len {
+                conn.write(&self.buffer[0..filled]).await?;
+                filled = 0;
+            }
+            let room = (self.buffer_len - filled).min(left);
+            let len = payload.write_chunk(&mut self.buffer[filled..filled + room]);
+            if len == 0 || len > room {
+                error!("Payload ended {} bytes early", left);
+                return Err(ReasonCode::MalformedPacket);
+            }
+            filled += len;
+            left -= len;
+        }
+        conn.send(&self.buffer[0..filled]).await
+    }
+
     /// Method allows sending message to broker specified from the ClientConfig. Client sends the
     /// message from the parameter `message` to the topic `topic_name` on the broker
     /// specified in the ClientConfig. If the send fails method returns Err with reason code
-    /// received by broker.
+    /// received by broker. With MQTTv5 repeated topics are replaced by topic aliases when
+    /// the broker allows them, see `TopicAliases`.
     pub async fn send_message<'b>(
         &'b mut self,
         topic_name: &'b str,
         message: &'b [u8],
         qos: QualityOfService,
         retain: bool,
+    ) -> Result<u16, ReasonCode> {
+        self.send_message_with_options(topic_name, message, qos, retain, PublishOptions::default())
+            .await
+    }
+
+    /// `send_message` with MQTTv5 message expiry and content type, MQTTv3 leaves them out.
+    pub async fn send_message_with_options<'b>(
+        &'b mut self,
+        topic_name: &'b str,
+        message: &'b [u8],
+        qos: QualityOfService,
+        retain: bool,
+        options: PublishOptions<'b>,
+    ) -> Result<u16, ReasonCode> {
+        let mut message = message;
+        self.send_message_streamed(topic_name, &mut message, qos, retain, options)
+            .await
+    }
+
+    /// `send_message_with_options` for a payload that is written in chunks, so it can be larger
+    /// than the write buffer. The remaining length is taken from `Payload::remaining` before
+    /// the first chunk, a payload that then ends early fails with `MalformedPacket` and leaves
+    /// the connection unusable.
+    pub async fn send_message_streamed<'b>(
+        &'b mut self,
+        topic_name: &'b str,
+        payload: &mut dyn Payload,
+        qos: QualityOfService,
+        retain: bool,
+        options: PublishOptions<'b>,
     ) -> Result<u16, ReasonCode> {
         match self.config.mqtt_version {
-            MqttVersion::MQTTv3 => Err(ReasonCode::UnsupportedProtocolVersion),
-            MqttVersion::MQTTv5 => self.send_message_v5(topic_name, message, qos, retain).await,
+            MqttVersion::MQTTv3 => self.send_message_v3(topic_name, payload, qos, retain).await,
+            MqttVersion::MQTTv5 => {
+                self.send_message_v5(topic_name, payload, qos, retain, options)
+                    .await
+            }
         }
     }
 
     async fn subscribe_to_topics_v5<'b, const TOPICS: usize>(
         &'b mut self,
         topic_names: &'b Vec<&'b str, TOPICS>,
+        options: SubscriptionOptions,
     ) -> Result<u16, ReasonCode> {
         if self.connection.is_none() {
             return Err(ReasonCode::NetworkError);
@@ -214,6 +509,36 @@ where
         let len = {
             let mut subs = SubscriptionPacket::<'b, TOPICS, MAX_PROPERTIES>::new();
             subs.packet_identifier = identifier;
+            for topic_name in topic_names.iter() {
+                subs.add_filter_with_options(topic_name, self.config.max_subscribe_qos, options);
+            }
+            subs.encode(self.buffer, self.buffer_len)
+        };
+
+        if let Err(err) = len {
+            error!("[DECODE ERR]: {}", err);
+            return Err(ReasonCode::BuffError);
+        }
+
+        conn.send(&self.buffer[0..len.unwrap()]).await?;
+
+        Ok(identifier)
+    }
+
+    async fn subscribe_to_topics_v3<'b, const TOPICS: usize>(
+        &'b mut self,
+        topic_names: &'b Vec<&'b str, TOPICS>,
+        _options: SubscriptionOptions,
+    ) -> Result<u16, ReasonCode> {
+        use v3::mqtt_packet::Packet as _;
+        if self.connection.is_none() {
+            return Err(ReasonCode::NetworkError);
+        }
+        let conn = self.connection.as_mut().unwrap();
+        let identifier: u16 = self.config.rng.next_u32() as u16;
+        let len = {
+            let mut subs = v3::subscription_packet::SubscriptionPacket::<'b, TOPICS>::new();
+            subs.packet_identifier = identifier;
             for topic_name in topic_names.iter() {
                 subs.add_new_filter(topic_name, self.config.max_subscribe_qos);
             }
@@ -237,10 +562,22 @@ where
     pub async fn subscribe_to_topics<'b, const TOPICS: usize>(
         &'b mut self,
         topic_names: &'b Vec<&'b str, TOPICS>,
+    ) -> Result<u16, ReasonCode> {
+        self.subscribe_to_topics_with_options(topic_names, SubscriptionOptions::default())
+            .await
+    }
+
+    /// Same as `subscribe_to_topics` with the MQTTv5 subscription options (no local,
+    /// retain as published, retain handling) applied to every topic. MQTTv3 has no
+    /// subscription options, they are left out.
+    pub async fn subscribe_to_topics_with_options<'b, const TOPICS: usize>(
+        &'b mut self,
+        topic_names: &'b Vec<&'b str, TOPICS>,
+        options: SubscriptionOptions,
     ) -> Result<u16, ReasonCode> {
         match self.config.mqtt_version {
-            MqttVersion::MQTTv3 => Err(ReasonCode::UnsupportedProtocolVersion),
-            MqttVersion::MQTTv5 => self.subscribe_to_topics_v5(topic_names).await,
+            MqttVersion::MQTTv3 => self.subscribe_to_topics_v3(topic_names, options).await,
+            MqttVersion::MQTTv5 => self.subscribe_to_topics_v5(topic_names, options).await,
         }
     }
 
@@ -252,11 +589,38 @@ where
         topic_name: &'b str,
     ) -> Result<u16, ReasonCode> {
         match self.config.mqtt_version {
-            MqttVersion::MQTTv3 => Err(ReasonCode::UnsupportedProtocolVersion),
+            MqttVersion::MQTTv3 => self.unsubscribe_from_topic_v3(topic_name).await,
             MqttVersion::MQTTv5 => self.unsubscribe_from_topic_v5(topic_name).await,
         }
     }
 
+    async fn unsubscribe_from_topic_v3<'b>(
+        &'b mut self,
+        topic_name: &'b str,
+    ) -> Result<u16, ReasonCode> {
+        use v3::mqtt_packet::Packet as _;
+        if self.connection.is_none() {
+            return Err(ReasonCode::NetworkError);
+        }
+        let conn = self.connection.as_mut().unwrap();
+        let identifier = self.config.rng.next_u32() as u16;
+
+        let len = {
+            let mut unsub = v3::unsubscription_packet::UnsubscriptionPacket::<'b, 1>::new();
+            unsub.packet_identifier = identifier;
+            unsub.add_new_filter(topic_name);
+            unsub.encode(self.buffer, self.buffer_len)
+        };
+
+        if let Err(err) = len {
+            error!("[DECODE ERR]: {}", err);
+            return Err(ReasonCode::BuffError);
+        }
+        conn.send(&self.buffer[0..len.unwrap()]).await?;
+
+        Ok(identifier)
+    }
+
     async fn unsubscribe_from_topic_v5<'b>(
         &'b mut self,
         topic_name: &'b str,
@@ -308,21 +672,270 @@ where
     /// regularly by the timer that counts down the session expiry interval.
     pub async fn send_ping<'b>(&'b mut self) -> Result<(), ReasonCode> {
         match self.config.mqtt_version {
-            MqttVersion::MQTTv3 => Err(ReasonCode::UnsupportedProtocolVersion),
-            MqttVersion::MQTTv5 => self.send_ping_v5().await,
+            // PINGREQ is the same in both versions
+            MqttVersion::MQTTv3 | MqttVersion::MQTTv5 => self.send_ping_v5().await,
         }
     }
 
+    /// Waits for the next packet from the broker and maps it to an `Event`. Acknowledgements
+    /// the client owes the broker (PUBACK for QoS1 messages, PUBREC and PUBCOMP for QoS2
+    /// messages, PUBREL for PUBREC) are sent here.
+    /// MQTT protocol implementation is selected automatically.
     pub async fn poll<'b, const MAX_TOPICS: usize>(&'b mut self) -> Result<Event<'b>, ReasonCode> {
         if self.connection.is_none() {
             return Err(ReasonCode::NetworkError);
         }
 
-        let conn = self.connection.as_mut().unwrap();
-
         trace!("Waiting for a packet");
 
-        let read = { receive_packet(self.buffer, self.buffer_len, self.recv_buffer, conn).await? };
+        let read = loop {
+            self.receive().await?;
+            let read = self.take_received();
+            if !self.answer(read).await? {
+                break read;
+            }
+        };
+        self.decode::<MAX_TOPICS>(read).await
+    }
+
+    /// Reads from the connection until a whole packet is in the receive buffer, returns at
+    /// once if one is already there. The bytes read so far are kept in the client, so this
+    /// is cancel-safe if the transport's read is, and can be raced against other futures.
+    /// `poll_received` then handles the packet.
+    pub async fn receive(&mut self) -> Result<(), ReasonCode> {
+        let conn = self.connection.as_mut().ok_or(ReasonCode::NetworkError)?;
+        loop {
+            let want = match self.rx_total {
+                Some(total) if self.rx_len == total => return Ok(()),
+                Some(total) => total,
+                // the fixed header is read a byte at a time, nothing of the next packet is taken
+                None => self.rx_len + 1,
+            };
+            let len = conn
+                .receive(&mut self.recv_buffer[self.rx_len..want])
+                .await?;
+            if len == 0 {
+                trace!("Connection closed by the broker.");
+                return Err(ReasonCode::NetworkError);
+            }
+            self.rx_len += len;
+            if self.rx_total.is_none() {
+                if let Some(total) = packet_len(&self.recv_buffer[..self.rx_len])? {
+                    if total > self.buffer_len.min(self.recv_buffer.len()) {
+                        error!("Packet of {} bytes does not fit the buffers", total);
+                        return Err(ReasonCode::PacketTooLarge);
+                    }
+                    self.rx_total = Some(total);
+                }
+            }
+        }
+    }
+
+    /// The packet `receive` completed, if it has not been handled yet.
+    pub fn received_packet(&self) -> Option<&[u8]> {
+        match self.rx_total {
+            Some(total) if self.rx_len == total => Some(&self.recv_buffer[..total]),
+            _ => None,
+        }
+    }
+
+    /// Handles the packet `receive` completed like `poll` does. Returns `None` if there is
+    /// none yet or the packet was answered here without anything for the application
+    /// (a PUBREL, a retransmitted QoS2 message or an authentication challenge).
+    pub async fn poll_received<'b, const MAX_TOPICS: usize>(
+        &'b mut self,
+    ) -> Result<Option<Event<'b>>, ReasonCode> {
+        if self.received_packet().is_none() {
+            return Ok(None);
+        }
+        let read = self.take_received();
+        if self.answer(read).await? {
+            return Ok(None);
+        }
+        self.decode::<MAX_TOPICS>(read).await.map(Some)
+    }
+
+    /// Moves the received packet to `buffer` for decoding, `recv_buffer` is free again for
+    /// the acknowledgements and the next packet. Returns its length.
+    fn take_received(&mut self) -> usize {
+        let read = self.rx_len;
+        self.buffer[..read].copy_from_slice(&self.recv_buffer[..read]);
+        self.rx_len = 0;
+        self.rx_total = None;
+        read
+    }
+
+    /// Answers the packets that carry nothing for the application, returns false if the
+    /// packet is left to `decode`.
+    async fn answer(&mut self, read: usize) -> Result<bool, ReasonCode> {
+        Ok(self.answer_qos2(read).await?
+            || (self.config.mqtt_version == MqttVersion::MQTTv5 && self.answer_auth(read).await?))
+    }
+
+    async fn decode<'b, const MAX_TOPICS: usize>(
+        &'b mut self,
+        read: usize,
+    ) -> Result<Event<'b>, ReasonCode> {
+        match self.config.mqtt_version {
+            MqttVersion::MQTTv3 => self.decode_v3::<MAX_TOPICS>(read).await,
+            MqttVersion::MQTTv5 => self.decode_v5::<MAX_TOPICS>(read).await,
+        }
+    }
+
+    async fn decode_v3<'b, const MAX_TOPICS: usize>(
+        &'b mut self,
+        read: usize,
+    ) -> Result<Event<'b>, ReasonCode> {
+        use v3::mqtt_packet::Packet as _;
+        let conn = self.connection.as_mut().ok_or(ReasonCode::NetworkError)?;
+
+        let buf_reader = BuffReader::new(self.buffer, read);
+
+        match PacketType::from(buf_reader.peek_u8().map_err(|_| ReasonCode::BuffError)?) {
+            // The server closes the connection instead of sending DISCONNECT, AUTH is v5 only
+            PacketType::Reserved
+            | PacketType::Connect
+            | PacketType::Subscribe
+            | PacketType::Unsubscribe
+            | PacketType::Pingreq
+            | PacketType::Disconnect
+            | PacketType::Auth => Err(ReasonCode::ProtocolError),
+            // answered by answer_qos2
+            PacketType::Pubrel => Err(ReasonCode::ImplementationSpecificError),
+            PacketType::Connack => {
+                let mut packet = v3::connack_packet::ConnackPacket::new();
+                if let Err(err) = packet.decode(&mut BuffReader::new(self.buffer, read)) {
+                    error!("[DECODE ERR]: {}", err);
+                    Err(ReasonCode::BuffError)
+                } else if packet.return_code != 0x00 {
+                    Err(packet.reason_code())
+                } else {
+                    Ok(Event::Connack)
+                }
+            }
+            PacketType::Puback => {
+                let mut packet = v3::puback_packet::PubackPacket::new();
+                if let Err(err) = packet.decode(&mut BuffReader::new(self.buffer, read)) {
+                    error!("[DECODE ERR]: {}", err);
+                    return Err(ReasonCode::BuffError);
+                }
+                Ok(Event::Puback(packet.packet_identifier))
+            }
+            PacketType::Pubrec => {
+                let mut packet = v3::pubrec_packet::PubrecPacket::new();
+                if let Err(err) = packet.decode(&mut BuffReader::new(self.buffer, read)) {
+                    error!("[DECODE ERR]: {}", err);
+                    return Err(ReasonCode::BuffError);
+                }
+
+                let mut pubrel = v3::pubrel_packet::PubrelPacket::new();
+                pubrel.packet_identifier = packet.packet_identifier;
+                {
+                    let len = { pubrel.encode(self.recv_buffer, self.recv_buffer_len) };
+                    if let Err(err) = len {
+                        error!("[DECODE ERR]: {}", err);
+                        return Err(ReasonCode::BuffError);
+                    }
+                    conn.send(&self.recv_buffer[0..len.unwrap()]).await?;
+                }
+
+                Ok(Event::Pubrec(packet.packet_identifier))
+            }
+            PacketType::Pubcomp => {
+                let mut packet = v3::pubcomp_packet::PubcompPacket::new();
+                if let Err(err) = packet.decode(&mut BuffReader::new(self.buffer, read)) {
+                    error!("[DECODE ERR]: {}", err);
+                    return Err(ReasonCode::BuffError);
+                }
+                Ok(Event::Pubcomp(packet.packet_identifier))
+            }
+            PacketType::Suback => {
+                let mut packet = v3::suback_packet::SubackPacket::<MAX_TOPICS>::new();
+                if let Err(err) = packet.decode(&mut BuffReader::new(self.buffer, read)) {
+                    error!("[DECODE ERR]: {}", err);
+                    return Err(ReasonCode::BuffError);
+                }
+                // 0x80 (failure) maps to UnspecifiedError
+                for return_code in &packet.return_codes {
+                    if *return_code
+                        != (<QualityOfService as Into<u8>>::into(self.config.max_subscribe_qos)
+                            >> 1)
+                    {
+                        return Err(ReasonCode::from(*return_code));
+                    }
+                }
+                Ok(Event::Suback(packet.packet_identifier))
+            }
+            PacketType::Unsuback => {
+                let mut packet = v3::unsuback_packet::UnsubackPacket::new();
+                if let Err(err) = packet.decode(&mut BuffReader::new(self.buffer, read)) {
+                    error!("[DECODE ERR]: {}", err);
+                    Err(ReasonCode::BuffError)
+                } else {
+                    Ok(Event::Unsuback(packet.packet_identifier))
+                }
+            }
+            PacketType::Pingresp => {
+                let mut packet = PingrespPacket::new();
+                if let Err(err) = packet.decode(&mut BuffReader::new(self.buffer, read)) {
+                    error!("[DECODE ERR]: {}", err);
+                    Err(ReasonCode::BuffError)
+                } else {
+                    Ok(Event::Pingresp)
+                }
+            }
+            PacketType::Publish => {
+                let mut packet = v3::publish_packet::PublishPacket::<'b>::new();
+                if let Err(err) = { packet.decode(&mut BuffReader::new(self.buffer, read)) } {
+                    error!("[DECODE ERR]: {}", err);
+                    return Err(ReasonCode::BuffError);
+                }
+
+                if (packet.fixed_header & 0x06)
+                    == <QualityOfService as Into<u8>>::into(QualityOfService::QoS1)
+                {
+                    let mut puback = v3::puback_packet::PubackPacket::new();
+                    puback.packet_identifier = packet.packet_identifier;
+                    {
+                        let len = { puback.encode(self.recv_buffer, self.recv_buffer_len) };
+                        if let Err(err) = len {
+                            error!("[DECODE ERR]: {}", err);
+                            return Err(ReasonCode::BuffError);
+                        }
+                        conn.send(&self.recv_buffer[0..len.unwrap()]).await?;
+                    }
+                } else if (packet.fixed_header & 0x06)
+                    == <QualityOfService as Into<u8>>::into(QualityOfService::QoS2)
+                {
+                    // retransmissions of a delivered message are answered by answer_qos2
+                    if self.inbound_qos2.push(packet.packet_identifier).is_err() {
+                        return Err(ReasonCode::ReceiveMaximumExceeded);
+                    }
+                    let mut pubrec = v3::pubrec_packet::PubrecPacket::new();
+                    pubrec.packet_identifier = packet.packet_identifier;
+                    {
+                        let len = { pubrec.encode(self.recv_buffer, self.recv_buffer_len) };
+                        if let Err(err) = len {
+                            error!("[DECODE ERR]: {}", err);
+                            return Err(ReasonCode::BuffError);
+                        }
+                        conn.send(&self.recv_buffer[0..len.unwrap()]).await?;
+                    }
+                }
+
+                Ok(Event::Message(
+                    packet.topic_name.string,
+                    packet.message.unwrap(),
+                ))
+            }
+        }
+    }
+
+    async fn decode_v5<'b, const MAX_TOPICS: usize>(
+        &'b mut self,
+        read: usize,
+    ) -> Result<Event<'b>, ReasonCode> {
+        let conn = self.connection.as_mut().ok_or(ReasonCode::NetworkError)?;
 
         let buf_reader = BuffReader::new(self.buffer, read);
 
@@ -332,8 +945,24 @@ where
             | PacketType::Subscribe
             | PacketType::Unsubscribe
             | PacketType::Pingreq => Err(ReasonCode::ProtocolError),
-            PacketType::Pubrec | PacketType::Pubrel | PacketType::Pubcomp | PacketType::Auth => {
-                Err(ReasonCode::ImplementationSpecificError)
+            // PUBREL is answered by answer_qos2
+            PacketType::Pubrel => Err(ReasonCode::ImplementationSpecificError),
+            // challenges are answered by answer_auth, this ends a re-authentication
+            PacketType::Auth => {
+                let mut packet = AuthPacket::<'b, MAX_PROPERTIES>::new();
+                if let Err(err) = packet.decode(&mut BuffReader::new(self.buffer, read)) {
+                    error!("[DECODE ERR]: {}", err);
+                    return Err(ReasonCode::BuffError);
+                }
+                if packet.auth_reason != ReasonCode::Success.into() {
+                    return Err(ReasonCode::ProtocolError);
+                }
+                let authenticator = self
+                    .authenticator
+                    .as_deref_mut()
+                    .ok_or(ReasonCode::ProtocolError)?;
+                authenticator.complete(auth_data(&packet.properties))?;
+                Ok(Event::Auth)
             }
             PacketType::Connack => {
                 let mut packet = ConnackPacket::<'b, MAX_PROPERTIES>::new();
@@ -350,6 +979,18 @@ where
                 } else if packet.connect_reason_code != 0x00 {
                     Err(ReasonCode::from(packet.connect_reason_code))
                 } else {
+                    if let Some(authenticator) = self.authenticator.as_deref_mut() {
+                        authenticator.complete(auth_data(&packet.properties))?;
+                    }
+                    let topic_alias_maximum =
+                        packet
+                            .properties
+                            .iter()
+                            .find_map(|property| match property {
+                                Property::TopicAliasMaximum(maximum) => Some(*maximum),
+                                _ => None,
+                            });
+                    self.topic_aliases.reset(topic_alias_maximum.unwrap_or(0));
                     Ok(Event::Connack)
                 }
             }
@@ -374,6 +1015,62 @@ where
 
                 Ok(Event::Puback(res[0]))
             }
+            PacketType::Pubrec => {
+                let reason: Result<[u16; 2], BufferError> = {
+                    let mut packet = PubrecPacket::<'b, MAX_PROPERTIES>::new();
+                    packet
+                        .decode(&mut BuffReader::new(self.buffer, read))
+                        .map(|_| [packet.packet_identifier, packet.reason_code as u16])
+                };
+
+                if let Err(err) = reason {
+                    error!("[DECODE ERR]: {}", err);
+                    return Err(ReasonCode::BuffError);
+                }
+
+                let res = reason.unwrap();
+
+                // 0x10 No matching subscribers is still a success
+                if res[1] >= 0x80 {
+                    return Err(ReasonCode::from(res[1] as u8));
+                }
+
+                let mut pubrel = PubrelPacket::<'b, MAX_PROPERTIES>::new();
+                pubrel.fixed_header = PacketType::Pubrel.into();
+                pubrel.packet_identifier = res[0];
+                pubrel.reason_code = 0x00;
+                {
+                    let len = { pubrel.encode(self.recv_buffer, self.recv_buffer_len) };
+                    if let Err(err) = len {
+                        error!("[DECODE ERR]: {}", err);
+                        return Err(ReasonCode::BuffError);
+                    }
+                    conn.send(&self.recv_buffer[0..len.unwrap()]).await?;
+                }
+
+                Ok(Event::Pubrec(res[0]))
+            }
+            PacketType::Pubcomp => {
+                let reason: Result<[u16; 2], BufferError> = {
+                    let mut packet = PubcompPacket::<'b, MAX_PROPERTIES>::new();
+                    packet
+                        .decode(&mut BuffReader::new(self.buffer, read))
+                        .map(|_| [packet.packet_identifier, packet.reason_code as u16])
+                };
+
+                if let Err(err) = reason {
+                    error!("[DECODE ERR]: {}", err);
+                    return Err(ReasonCode::BuffError);
+                }
+
+                let res = reason.unwrap();
+
+                if res[1] != 0 {
+                    return Err(ReasonCode::from(res[1] as u8));
+                }
+
+                Ok(Event::Pubcomp(res[0]))
+            }
             PacketType::Suback => {
                 let reason: Result<(u16, Vec<u8, MAX_TOPICS>), BufferError> = {
                     let mut packet = SubackPacket::<'b, MAX_TOPICS, MAX_PROPERTIES>::new();
@@ -449,6 +1146,24 @@ where
                         }
                         conn.send(&self.recv_buffer[0..len.unwrap()]).await?;
                     }
+                } else if (packet.fixed_header & 0x06)
+                    == <QualityOfService as Into<u8>>::into(QualityOfService::QoS2)
+                {
+                    // retransmissions of a delivered message are answered by answer_qos2
+                    if self.inbound_qos2.push(packet.packet_identifier).is_err() {
+                        return Err(ReasonCode::ReceiveMaximumExceeded);
+                    }
+                    let mut pubrec = PubrecPacket::<'b, MAX_PROPERTIES>::new();
+                    pubrec.packet_identifier = packet.packet_identifier;
+                    pubrec.reason_code = 0x00;
+                    {
+                        let len = { pubrec.encode(self.recv_buffer, self.recv_buffer_len) };
+                        if let Err(err) = len {
+                            error!("[DECODE ERR]: {}", err);
+                            return Err(ReasonCode::BuffError);
+                        }
+                        conn.send(&self.recv_buffer[0..len.unwrap()]).await?;
+                    }
                 }
 
                 Ok(Event::Message(
@@ -470,93 +1185,244 @@ where
             }
         }
     }
-}
 
-#[cfg(not(feature = "tls"))]
-async fn receive_packet<'c, T: Read + Write>(
-    buffer: &mut [u8],
-    buffer_len: usize,
-    recv_buffer: &mut [u8],
-    conn: &'c mut NetworkConnection<T>,
-) -> Result<usize, ReasonCode> {
-    use crate::utils::buffer_writer::RemLenError;
-
-    let target_len: usize;
-    let mut rem_len: Result<VariableByteInteger, RemLenError>;
-    let mut writer = BuffWriter::new(buffer, buffer_len);
-    let mut i = 0;
-
-    // Get len of packet
-    trace!("Reading lenght of packet");
-    loop {
-        trace!("    Reading in loop!");
-        let len: usize = conn
-            .receive(&mut recv_buffer[writer.position..(writer.position + 1)])
-            .await?;
-        trace!("    Received data!");
-        if len == 0 {
-            trace!("Zero byte len packet received, dropping connection.");
-            return Err(ReasonCode::NetworkError);
-        }
-        i += len;
-        if let Err(_e) = writer.insert_ref(len, &recv_buffer[writer.position..i]) {
-            error!("Error occurred during write to buffer!");
-            return Err(ReasonCode::BuffError);
-        }
-        if i > 1 {
-            rem_len = writer.get_rem_len();
-            if rem_len.is_ok() {
-                break;
+    /// Answers the packets of an incoming QoS2 exchange that carry nothing for the application:
+    /// PUBREL gets its PUBCOMP, and a retransmitted PUBLISH that was already delivered gets
+    /// its PUBREC again. Returns false if the packet is left to `poll`.
+    async fn answer_qos2(&mut self, read: usize) -> Result<bool, ReasonCode> {
+        use v3::mqtt_packet::Packet as _;
+        let header = self.buffer[0];
+        let v3 = self.config.mqtt_version == MqttVersion::MQTTv3;
+        match PacketType::from(header) {
+            PacketType::Pubrel => {
+                let identifier = if v3 {
+                    let mut packet = v3::pubrel_packet::PubrelPacket::new();
+                    packet
+                        .decode(&mut BuffReader::new(self.buffer, read))
+                        .map(|_| packet.packet_identifier)
+                } else {
+                    let mut packet = PubrelPacket::<MAX_PROPERTIES>::new();
+                    packet
+                        .decode(&mut BuffReader::new(self.buffer, read))
+                        .map(|_| packet.packet_identifier)
+                };
+                let identifier = identifier.map_err(|err| {
+                    error!("[DECODE ERR]: {}", err);
+                    ReasonCode::BuffError
+                })?;
+                let reason_code = match self.inbound_qos2.iter().position(|id| *id == identifier) {
+                    Some(i) => {
+                        self.inbound_qos2.swap_remove(i);
+                        ReasonCode::Success
+                    }
+                    None => ReasonCode::PacketIdentifierNotFound,
+                };
+                self.send_qos2_ack(PacketType::Pubcomp, identifier, reason_code)
+                    .await?;
+                Ok(true)
             }
-            if i >= 5 {
-                error!("Could not read len of packet!");
-                return Err(ReasonCode::NetworkError);
+            PacketType::Publish
+                if (header & 0x06)
+                    == <QualityOfService as Into<u8>>::into(QualityOfService::QoS2) =>
+            {
+                let identifier = if v3 {
+                    let mut packet = v3::publish_packet::PublishPacket::new();
+                    packet
+                        .decode(&mut BuffReader::new(self.buffer, read))
+                        .map(|_| packet.packet_identifier)
+                } else {
+                    let mut packet = PublishPacket::<5>::new();
+                    packet
+                        .decode(&mut BuffReader::new(self.buffer, read))
+                        .map(|_| packet.packet_identifier)
+                };
+                let identifier = identifier.map_err(|err| {
+                    error!("[DECODE ERR]: {}", err);
+                    ReasonCode::BuffError
+                })?;
+                if !self.inbound_qos2.contains(&identifier) {
+                    return Ok(false);
+                }
+                self.send_qos2_ack(PacketType::Pubrec, identifier, ReasonCode::Success)
+                    .await?;
+                Ok(true)
             }
+            _ => Ok(false),
         }
     }
-    trace!("Lenght done!");
-    let rem_len_len = i;
-    i = 0;
-    if let Ok(l) = VariableByteIntegerDecoder::decode(rem_len.unwrap()) {
-        trace!("Reading packet with target len {}", l);
-        target_len = l as usize;
-    } else {
-        error!("Could not decode len of packet!");
-        return Err(ReasonCode::BuffError);
-    }
 
-    loop {
-        if writer.position == target_len + rem_len_len {
-            trace!("Received packet with len: {}", (target_len + rem_len_len));
-            return Ok(target_len + rem_len_len);
+    /// Answers a broker challenge, an AUTH with Continue Authentication, with the next round
+    /// of the `Authenticator`. Returns false if the packet is left to `poll`.
+    async fn answer_auth(&mut self, read: usize) -> Result<bool, ReasonCode> {
+        if PacketType::from(self.buffer[0]) != PacketType::Auth {
+            return Ok(false);
         }
-        let len: usize = conn
-            .receive(&mut recv_buffer[writer.position..writer.position + (target_len - i)])
-            .await?;
-        i += len;
-        if let Err(_e) =
-            writer.insert_ref(len, &recv_buffer[writer.position..(writer.position + i)])
-        {
-            error!("Error occurred during write to buffer!");
+        let mut packet = AuthPacket::<MAX_PROPERTIES>::new();
+        if let Err(err) = packet.decode(&mut BuffReader::new(self.buffer, read)) {
+            error!("[DECODE ERR]: {}", err);
             return Err(ReasonCode::BuffError);
         }
+        if packet.auth_reason != ReasonCode::ContinueAuth.into() {
+            return Ok(false);
+        }
+        let authenticator = self
+            .authenticator
+            .as_deref_mut()
+            .ok_or(ReasonCode::ProtocolError)?;
+        let method = authenticator.method();
+        let response = authenticator.challenge(auth_data(&packet.properties))?;
+        let len = encode_auth::<MAX_PROPERTIES>(
+            self.recv_buffer,
+            self.recv_buffer_len,
+            ReasonCode::ContinueAuth,
+            method,
+            response,
+        )?;
+        let conn = self.connection.as_mut().ok_or(ReasonCode::NetworkError)?;
+        conn.send(&self.recv_buffer[0..len]).await?;
+        Ok(true)
     }
+
+    /// Starts an MQTTv5 re-authentication with a new exchange of the `Authenticator`. The
+    /// broker's challenges are answered by `poll`, which returns `Event::Auth` once the broker
+    /// accepted it. Fails with `BadAuthMethod` without an authenticator or for MQTTv3.
+    pub async fn reauthenticate(&mut self) -> Result<(), ReasonCode> {
+        let conn = self.connection.as_mut().ok_or(ReasonCode::NetworkError)?;
+        let authenticator = match self.config.mqtt_version {
+            MqttVersion::MQTTv3 => None,
+            MqttVersion::MQTTv5 => self.authenticator.as_deref_mut(),
+        }
+        .ok_or(ReasonCode::BadAuthMethod)?;
+        let method = authenticator.method();
+        let len = encode_auth::<MAX_PROPERTIES>(
+            self.buffer,
+            self.buffer_len,
+            ReasonCode::ReAuthenticate,
+            method,
+            authenticator.start()?,
+        )?;
+        trace!("Sending re-authentication");
+        conn.send(&self.buffer[0..len]).await
+    }
+
+    /// Sends the PUBREC or PUBCOMP of an incoming QoS2 message, MQTTv3 has no reason code.
+    async fn send_qos2_ack(
+        &mut self,
+        packet_type: PacketType,
+        identifier: u16,
+        reason_code: ReasonCode,
+    ) -> Result<(), ReasonCode> {
+        use v3::mqtt_packet::Packet as _;
+        let v3 = self.config.mqtt_version == MqttVersion::MQTTv3;
+        let len = match (v3, packet_type) {
+            (true, PacketType::Pubrec) => {
+                let mut packet = v3::pubrec_packet::PubrecPacket::new();
+                packet.packet_identifier = identifier;
+                packet.encode(self.recv_buffer, self.recv_buffer_len)
+            }
+            (true, _) => {
+                let mut packet = v3::pubcomp_packet::PubcompPacket::new();
+                packet.packet_identifier = identifier;
+                packet.encode(self.recv_buffer, self.recv_buffer_len)
+            }
+            (false, PacketType::Pubrec) => {
+                let mut packet = PubrecPacket::<MAX_PROPERTIES>::new();
+                packet.packet_identifier = identifier;
+                packet.reason_code = reason_code.into();
+                packet.encode(self.recv_buffer, self.recv_buffer_len)
+            }
+            (false, _) => {
+                let mut packet = PubcompPacket::<MAX_PROPERTIES>::new();
+                packet.packet_identifier = identifier;
+                packet.reason_code = reason_code.into();
+                packet.encode(self.recv_buffer, self.recv_buffer_len)
+            }
+        };
+        let len = len.map_err(|err| {
+            error!("[DECODE ERR]: {}", err);
+            ReasonCode::BuffError
+        })?;
+        let conn = self.connection.as_mut().ok_or(ReasonCode::NetworkError)?;
+        conn.send(&self.recv_buffer[0..len]).await
+    }
+}
+
+/// Authentication Method and, unless empty, Authentication Data properties
+fn auth_properties<'p>(method: &'p str, data: &'p [u8]) -> Vec<Property<'p>, 2> {
+    let mut properties = Vec::new();
+    // cannot fail, there is room for both
+    let _ = properties.push(Property::AuthenticationMethod(EncodedString {
+        string: method,
+        len: method.len() as u16,
+    }));
+    if !data.is_empty() {
+        let _ = properties.push(Property::AuthenticationData(BinaryData {
+            bin: data,
+            len: data.len() as u16,
+        }));
+    }
+    properties
+}
+
+/// Authentication Data of a CONNACK or AUTH, empty if the broker sent none
+fn auth_data<'p>(properties: &[Property<'p>]) -> &'p [u8] {
+    properties
+        .iter()
+        .find_map(|property| match property {
+            Property::AuthenticationData(data) => Some(data.bin),
+            _ => None,
+        })
+        .unwrap_or(&[])
 }
 
-#[cfg(feature = "tls")]
-async fn receive_packet<'c, T: Read + Write>(
+/// Encodes an AUTH packet with `reason_code` for the `Authenticator` data
+fn encode_auth<const MAX_PROPERTIES: usize>(
     buffer: &mut [u8],
     buffer_len: usize,
-    recv_buffer: &mut [u8],
-    conn: &'c mut NetworkConnection<T>,
+    reason_code: ReasonCode,
+    method: &str,
+    data: &[u8],
 ) -> Result<usize, ReasonCode> {
-    trace!("Reading packet");
-    let mut writer = BuffWriter::new(buffer, buffer_len);
-    let len = conn.receive(recv_buffer).await?;
-    if let Err(_e) = writer.insert_ref(len, &recv_buffer[writer.position..(writer.position + len)])
-    {
-        error!("Error occurred during write to buffer!");
-        return Err(ReasonCode::BuffError);
-    }
-    Ok(len)
+    let mut packet = AuthPacket::<MAX_PROPERTIES>::new();
+    packet.add_reason_code(reason_code.into());
+    for property in auth_properties(method, data) {
+        packet.property_len += property.encoded_len() as u32 + 1;
+        if packet.properties.push(property).is_err() {
+            error!("No room for the authentication properties");
+            return Err(ReasonCode::BuffError);
+        }
+    }
+    packet.encode(buffer, buffer_len).map_err(|err| {
+        error!("[DECODE ERR]: {}", err);
+        ReasonCode::BuffError
+    })
+}
+
+/// Length of the payload for the remaining length, which is limited to 268,435,455 bytes
+/// including the rest of the packet
+fn payload_len(payload: &dyn Payload) -> Result<u32, ReasonCode> {
+    match u32::try_from(payload.remaining()) {
+        Ok(len) if len < 268_435_455 => Ok(len),
+        _ => {
+            error!("Payload too large for a PUBLISH");
+            Err(ReasonCode::PacketTooLarge)
+        }
+    }
+}
+
+/// Length of the packet starting with `header`, `None` until its remaining length is complete.
+fn packet_len(header: &[u8]) -> Result<Option<usize>, ReasonCode> {
+    let mut rem_len = 0;
+    for (i, byte) in header.iter().skip(1).enumerate() {
+        rem_len |= usize::from(byte & 0x7F) << (7 * i);
+        if byte & 0x80 == 0 {
+            // packet type, the length bytes and the rest
+            return Ok(Some(1 + i + 1 + rem_len));
+        }
+        if i == 3 {
+            error!("Could not read len of packet!");
+            return Err(ReasonCode::NetworkError);
+        }
+    }
+    Ok(None)
 }
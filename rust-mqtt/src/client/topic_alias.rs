@@ -0,0 +1,123 @@
+/*
+ * MIT License
+ *
+ * Copyright (c) [2022] [Ondrej Babec <ond.babec@gmail.com>]
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use heapless::{String, Vec};
+
+/// Topics that hold an alias at the same time, enough for the topics a device publishes
+/// periodically
+pub const MAX_TOPIC_ALIASES: usize = 8;
+/// Longer topics are always sent in full
+pub const MAX_ALIAS_TOPIC_LEN: usize = 64;
+
+/// Topic to send with an outgoing PUBLISH
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Alias {
+    /// No alias, the topic is sent in full
+    None,
+    /// Topic and alias, the broker maps the alias to the topic
+    New(u16),
+    /// Empty topic and the alias the broker already knows
+    Known(u16),
+}
+
+struct Entry {
+    topic: String<MAX_ALIAS_TOPIC_LEN>,
+    last_used: u32,
+}
+
+/// MQTTv5 topic aliases for outgoing messages. An alias is allocated the first time a topic is
+/// published and replaces the topic afterwards, within the broker's Topic Alias Maximum. Once
+/// every alias is taken the least recently used one is mapped to the new topic. Aliases only
+/// live as long as the connection.
+pub struct TopicAliases {
+    maximum: u16,
+    entries: Vec<Entry, MAX_TOPIC_ALIASES>,
+    uses: u32,
+}
+
+impl TopicAliases {
+    pub const fn new() -> Self {
+        Self {
+            maximum: 0,
+            entries: Vec::new(),
+            uses: 0,
+        }
+    }
+
+    /// Forgets every alias, `maximum` is the Topic Alias Maximum from the CONNACK,
+    /// 0 turns aliases off.
+    pub fn reset(&mut self, maximum: u16) {
+        self.maximum = maximum;
+        self.entries.clear();
+        self.uses = 0;
+    }
+
+    pub fn alias(&mut self, topic: &str) -> Alias {
+        let limit = MAX_TOPIC_ALIASES.min(usize::from(self.maximum));
+        if limit == 0 || topic.is_empty() || topic.len() > MAX_ALIAS_TOPIC_LEN {
+            return Alias::None;
+        }
+        self.uses = self.uses.wrapping_add(1);
+        if let Some(i) = self.entries.iter().position(|e| e.topic == topic) {
+            self.entries[i].last_used = self.uses;
+            return Alias::Known(i as u16 + 1);
+        }
+        let mut entry = Entry {
+            topic: String::new(),
+            last_used: self.uses,
+        };
+        // cannot fail, the length was checked above
+        let _ = entry.topic.push_str(topic);
+        let i = if self.entries.len() < limit {
+            // cannot fail, limit is at most the capacity
+            let _ = self.entries.push(entry);
+            self.entries.len() - 1
+        } else {
+            let i = self
+                .entries
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, e)| e.last_used)
+                .map_or(0, |(i, _)| i);
+            self.entries[i] = entry;
+            i
+        };
+        Alias::New(i as u16 + 1)
+    }
+
+    /// Frees a `New` alias that never reached the broker, its slot is reused first once every
+    /// alias is taken
+    pub fn forget(&mut self, alias: u16) {
+        if let Some(entry) = self.entries.get_mut(usize::from(alias).wrapping_sub(1)) {
+            entry.topic.clear();
+            entry.last_used = 0;
+        }
+    }
+}
+
+impl Default for TopicAliases {
+    fn default() -> Self {
+        Self::new()
+    }
+}
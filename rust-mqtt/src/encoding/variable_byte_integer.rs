@@ -71,16 +71,13 @@ impl VariableByteIntegerEncoder {
         Ok(res)
     }
 
+    /// Number of bytes used by the encoded integer, at most 4 even if the last one
+    /// has its continuation bit set
     pub fn len(var_int: VariableByteInteger) -> usize {
-        let mut i: usize = 0;
-        loop {
-            let encoded_byte = var_int[i];
-            i += 1;
-            if (encoded_byte & 128) == 0 {
-                break;
-            }
-        }
-        i
+        var_int
+            .iter()
+            .position(|encoded_byte| (encoded_byte & 128) == 0)
+            .map_or(var_int.len(), |i| i + 1)
     }
 }
 
@@ -96,22 +93,14 @@ impl VariableByteIntegerDecoder {
         let mut multiplier: u32 = 1;
         let mut ret: u32 = 0;
 
-        let mut encoded_byte: u8;
-        let mut i: usize = 0;
-
-        loop {
-            encoded_byte = encoded[i];
-            i += 1;
+        for encoded_byte in encoded {
             ret += (encoded_byte & 127) as u32 * multiplier;
-            if multiplier > 128 * 128 * 128 {
-                return Err(BufferError::DecodingError);
-            }
-            multiplier *= 128;
             if (encoded_byte & 128) == 0 {
-                break;
+                return Ok(ret);
             }
+            multiplier *= 128;
         }
-
-        Ok(ret)
+        // the fourth byte still has the continuation bit set
+        Err(BufferError::DecodingError)
     }
 }
@@ -22,7 +22,7 @@
  * SOFTWARE.
  */
 
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![macro_use]
 // #![cfg_attr(not(feature = "std"), no_std)]
 #![allow(dead_code)]
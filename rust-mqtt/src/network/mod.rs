@@ -25,6 +25,11 @@
 use crate::packet::v5::reason_codes::ReasonCode;
 use embedded_io_async::{Read, Write};
 
+#[cfg(feature = "tls")]
+pub mod tls;
+#[cfg(feature = "std")]
+pub mod tokio_net;
+
 pub struct NetworkConnection<T>
 where
     T: Read + Write,
@@ -43,13 +48,22 @@ where
     }
 
     /// Send the data from `buffer` via TCP connection.
+    /// Flushed so transports that buffer writes (TLS records) put the whole packet on the wire.
     pub async fn send(&mut self, buffer: &[u8]) -> Result<(), ReasonCode> {
-        let _ = self
-            .io
-            .write(buffer)
+        self.io
+            .write_all(buffer)
             .await
             .map_err(|_| ReasonCode::NetworkError)?;
-        Ok(())
+        self.io.flush().await.map_err(|_| ReasonCode::NetworkError)
+    }
+
+    /// Writes the data from `buffer` without flushing, for a packet sent in several parts
+    /// that ends with `send`.
+    pub async fn write(&mut self, buffer: &[u8]) -> Result<(), ReasonCode> {
+        self.io
+            .write_all(buffer)
+            .await
+            .map_err(|_| ReasonCode::NetworkError)
     }
 
     /// Receive data to the `buffer` from TCP connection.
@@ -0,0 +1,248 @@
+/*
+ * MIT License
+ *
+ * Copyright (c) [2022] [Ondrej Babec <ond.babec@gmail.com>]
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+//! TLS 1.3 transport over any `embedded-io-async` socket, built on embedded-tls.
+//!
+//! The opened connection is itself `Read + Write`, so it is passed to `MqttClient::new`
+//! in place of the TCP connection. Only the TLS_AES_128_GCM_SHA256 cipher suite is offered,
+//! which every TLS 1.3 server must support. Reads are cancel-safe if the socket's are, so the
+//! connection can be used with `ConnectionManager::poll_or`.
+
+use embedded_io_async::{Error, ErrorKind, ErrorType, Read, Write};
+use embedded_tls::webpki::CertVerifier;
+use embedded_tls::{
+    Aes128GcmSha256, Certificate, NoVerify, TlsConfig, TlsConnection, TlsContext, TlsError,
+};
+use rand_core::{CryptoRng, RngCore};
+
+pub use embedded_tls::TlsClock;
+
+/// The read buffer and the record buffer have to hold a whole record, servers may send up to
+/// 16KiB of plaintext plus the record overhead.
+pub const TLS_READ_BUFFER_LEN: usize = 16640;
+/// Outgoing records are split to fit, this only has to hold the ClientHello
+/// and should be larger than the biggest MQTT packet sent to avoid fragmenting it.
+pub const TLS_WRITE_BUFFER_LEN: usize = 4096;
+/// Largest DER certificate accepted from the server.
+const CERT_SIZE: usize = 4096;
+/// Content type, legacy version and length.
+const RECORD_HEADER_LEN: usize = 5;
+
+pub type TlsTransport<'a, T> = TlsConnection<'a, RecordGate<'a, T>, Aes128GcmSha256>;
+
+/// How the broker is authenticated.
+#[derive(Clone, Copy)]
+pub enum TlsAuth<'a> {
+    /// The broker certificate has to be issued by this DER encoded CA certificate,
+    /// intermediate certificates are not supported.
+    Ca(&'a [u8]),
+    /// TLS 1.3 external pre-shared key, no certificates are exchanged.
+    Psk { identity: &'a [u8], key: &'a [u8] },
+}
+
+pub struct TlsOptions<'a> {
+    /// Sent as SNI and checked against the broker certificate.
+    pub server_name: &'a str,
+    pub auth: TlsAuth<'a>,
+}
+
+/// Clock for devices without wall clock time, only for `TlsAuth::Psk`: every certificate
+/// is checked against the Unix epoch and fails its validity period.
+pub struct NoClock;
+
+impl TlsClock for NoClock {
+    fn now() -> Option<u64> {
+        None
+    }
+}
+
+/// Performs the TLS handshake over an established `socket`.
+/// `Clock` supplies the time certificates are checked against, it is unused with a PSK.
+/// `record_buffer` is the `RecordGate` between the socket and embedded-tls.
+pub async fn open_tls<'a, T, RNG, Clock>(
+    socket: T,
+    read_buffer: &'a mut [u8],
+    record_buffer: &'a mut [u8],
+    write_buffer: &'a mut [u8],
+    options: &TlsOptions<'_>,
+    rng: &mut RNG,
+) -> Result<TlsTransport<'a, T>, TlsError>
+where
+    T: Read + Write + 'a,
+    RNG: CryptoRng + RngCore,
+    Clock: TlsClock + 'static,
+{
+    let socket = RecordGate::new(socket, record_buffer);
+    let mut tls = TlsConnection::new(socket, read_buffer, write_buffer);
+    let config = TlsConfig::new().with_server_name(options.server_name);
+    match options.auth {
+        TlsAuth::Ca(ca) => {
+            let config = config.with_ca(Certificate::X509(ca));
+            tls.open::<RNG, CertVerifier<Aes128GcmSha256, Clock, CERT_SIZE>>(TlsContext::new(
+                &config, rng,
+            ))
+            .await?;
+        }
+        TlsAuth::Psk { identity, key } => {
+            // the PSK binder authenticates the server
+            let config = config.with_psk(key, &[identity]);
+            tls.open::<RNG, NoVerify>(TlsContext::new(&config, rng))
+                .await?;
+        }
+    }
+    Ok(tls)
+}
+
+/// Hands the socket's bytes to embedded-tls a whole record at a time. embedded-tls takes
+/// a record header before it waits for the body, a read cancelled in between loses the record;
+/// behind the gate its reads only wait between records, which makes them cancel-safe.
+pub struct RecordGate<'a, T> {
+    socket: T,
+    buffer: &'a mut [u8],
+    /// Bytes read from the socket
+    filled: usize,
+    /// Bytes of the complete record at the front of the buffer
+    ready: usize,
+    /// Bytes of that record already handed out
+    served: usize,
+}
+
+impl<'a, T> RecordGate<'a, T> {
+    /// `buffer` has to hold a whole record, see `TLS_READ_BUFFER_LEN`.
+    pub fn new(socket: T, buffer: &'a mut [u8]) -> Self {
+        Self {
+            socket,
+            buffer,
+            filled: 0,
+            ready: 0,
+            served: 0,
+        }
+    }
+
+    /// Length of the record at the front of the buffer once its header is there
+    fn record_len(&self) -> Option<usize> {
+        (self.filled >= RECORD_HEADER_LEN).then(|| {
+            RECORD_HEADER_LEN + usize::from(u16::from_be_bytes([self.buffer[3], self.buffer[4]]))
+        })
+    }
+}
+
+impl<'a, T> ErrorType for RecordGate<'a, T>
+where
+    T: Read + Write,
+{
+    type Error = ErrorKind;
+}
+
+impl<'a, T> Read for RecordGate<'a, T>
+where
+    T: Read + Write,
+{
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        while self.served == self.ready {
+            match self.record_len() {
+                Some(len) if len > self.buffer.len() => return Err(ErrorKind::InvalidData),
+                Some(len) if len <= self.filled => self.ready = len,
+                // the only await, nothing has been handed out of the record it waits for
+                _ => match self.socket.read(&mut self.buffer[self.filled..]).await {
+                    Ok(0) => return Ok(0),
+                    Ok(read) => self.filled += read,
+                    Err(e) => return Err(e.kind()),
+                },
+            }
+        }
+        let len = buf.len().min(self.ready - self.served);
+        buf[..len].copy_from_slice(&self.buffer[self.served..self.served + len]);
+        self.served += len;
+        if self.served == self.ready {
+            self.buffer.copy_within(self.ready..self.filled, 0);
+            self.filled -= self.ready;
+            self.ready = 0;
+            self.served = 0;
+        }
+        Ok(len)
+    }
+}
+
+impl<'a, T> Write for RecordGate<'a, T>
+where
+    T: Read + Write,
+{
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.socket.write(buf).await.map_err(|e| e.kind())
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.socket.flush().await.map_err(|e| e.kind())
+    }
+}
+
+/// Connection that is either plain TCP or TLS, for clients where TLS is chosen at runtime.
+// only one connection exists at a time and the crate has no allocator to box the TLS state
+#[allow(clippy::large_enum_variant)]
+pub enum MaybeTls<'a, T>
+where
+    T: Read + Write + 'a,
+{
+    Plain(T),
+    Tls(TlsTransport<'a, T>),
+}
+
+impl<'a, T> ErrorType for MaybeTls<'a, T>
+where
+    T: Read + Write + 'a,
+{
+    type Error = ErrorKind;
+}
+
+impl<'a, T> Read for MaybeTls<'a, T>
+where
+    T: Read + Write + 'a,
+{
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        match self {
+            MaybeTls::Plain(io) => io.read(buf).await.map_err(|e| e.kind()),
+            MaybeTls::Tls(io) => io.read(buf).await.map_err(|e| e.kind()),
+        }
+    }
+}
+
+impl<'a, T> Write for MaybeTls<'a, T>
+where
+    T: Read + Write + 'a,
+{
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        match self {
+            MaybeTls::Plain(io) => io.write(buf).await.map_err(|e| e.kind()),
+            MaybeTls::Tls(io) => io.write(buf).await.map_err(|e| e.kind()),
+        }
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        match self {
+            MaybeTls::Plain(io) => io.flush().await.map_err(|e| e.kind()),
+            MaybeTls::Tls(io) => io.flush().await.map_err(|e| e.kind()),
+        }
+    }
+}
@@ -0,0 +1,90 @@
+/*
+ * MIT License
+ *
+ * Copyright (c) [2022] [Ondrej Babec <ond.babec@gmail.com>]
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use crate::packet::v3::mqtt_packet::Packet;
+use crate::packet::v5::packet_type::PacketType;
+use crate::packet::v5::reason_codes::ReasonCode;
+use crate::utils::buffer_reader::BuffReader;
+use crate::utils::buffer_writer::BuffWriter;
+use crate::utils::types::BufferError;
+
+pub struct ConnackPacket {
+    pub fixed_header: u8,
+    pub remain_len: u32,
+    pub ack_flags: u8,
+    pub return_code: u8,
+}
+
+impl ConnackPacket {
+    /// Maps the 3.1.1 connect return code to the v5 reason code with the same meaning
+    pub fn reason_code(&self) -> ReasonCode {
+        match self.return_code {
+            0x00 => ReasonCode::Success,
+            0x01 => ReasonCode::UnsupportedProtocolVersion,
+            0x02 => ReasonCode::ClientIdNotValid,
+            0x03 => ReasonCode::ServerUnavailable,
+            0x04 => ReasonCode::BadUserNameOrPassword,
+            0x05 => ReasonCode::NotAuthorized,
+            _ => ReasonCode::ProtocolError,
+        }
+    }
+}
+
+impl<'a> Packet<'a> for ConnackPacket {
+    fn new() -> Self {
+        Self {
+            fixed_header: PacketType::Connack.into(),
+            remain_len: 0,
+            ack_flags: 0,
+            return_code: 0,
+        }
+    }
+
+    fn encode(&mut self, buffer: &mut [u8], buffer_len: usize) -> Result<usize, BufferError> {
+        let mut buff_writer = BuffWriter::new(buffer, buffer_len);
+        buff_writer.write_u8(self.fixed_header)?;
+        buff_writer.write_variable_byte_int(2)?;
+        buff_writer.write_u8(self.ack_flags)?;
+        buff_writer.write_u8(self.return_code)?;
+        Ok(buff_writer.position)
+    }
+
+    fn decode(&mut self, buff_reader: &mut BuffReader<'a>) -> Result<(), BufferError> {
+        if self.decode_fixed_header(buff_reader)? != PacketType::Connack {
+            error!("Packet you are trying to decode is not CONNACK packet!");
+            return Err(BufferError::PacketTypeMismatch);
+        }
+        self.ack_flags = buff_reader.read_u8()?;
+        self.return_code = buff_reader.read_u8()?;
+        Ok(())
+    }
+
+    fn set_fixed_header(&mut self, header: u8) {
+        self.fixed_header = header;
+    }
+
+    fn set_remaining_len(&mut self, remaining_len: u32) {
+        self.remain_len = remaining_len;
+    }
+}
@@ -0,0 +1,40 @@
+/*
+ * MIT License
+ *
+ * Copyright (c) [2022] [Ondrej Babec <ond.babec@gmail.com>]
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+// MQTT 3.1.1 (protocol level 4) packets. They share the fixed header and packet types
+// with v5 but carry no properties and no reason codes on the acknowledgements.
+// PINGREQ and PINGRESP are identical in both versions, use the v5 packets.
+pub mod connack_packet;
+pub mod connect_packet;
+pub mod disconnect_packet;
+pub mod mqtt_packet;
+pub mod puback_packet;
+pub mod pubcomp_packet;
+pub mod publish_packet;
+pub mod pubrec_packet;
+pub mod pubrel_packet;
+pub mod suback_packet;
+pub mod subscription_packet;
+pub mod unsuback_packet;
+pub mod unsubscription_packet;
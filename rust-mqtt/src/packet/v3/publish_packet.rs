@@ -0,0 +1,135 @@
+/*
+ * MIT License
+ *
+ * Copyright (c) [2022] [Ondrej Babec <ond.babec@gmail.com>]
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use crate::encoding::variable_byte_integer::VariableByteIntegerEncoder;
+use crate::packet::v3::mqtt_packet::Packet;
+use crate::packet::v5::packet_type::PacketType;
+use crate::packet::v5::publish_packet::QualityOfService;
+use crate::utils::buffer_reader::BuffReader;
+use crate::utils::buffer_writer::BuffWriter;
+use crate::utils::types::{BufferError, EncodedString};
+
+pub struct PublishPacket<'a> {
+    pub fixed_header: u8,
+    pub remain_len: u32,
+    pub topic_name: EncodedString<'a>,
+    pub packet_identifier: u16,
+    pub message: Option<&'a [u8]>,
+}
+
+impl<'a> PublishPacket<'a> {
+    pub fn add_topic_name(&mut self, topic_name: &'a str) {
+        self.topic_name.string = topic_name;
+        self.topic_name.len = topic_name.len() as u16;
+    }
+
+    pub fn add_message(&mut self, message: &'a [u8]) {
+        self.message = Some(message);
+    }
+
+    pub fn add_qos(&mut self, qos: QualityOfService) {
+        self.fixed_header |= <QualityOfService as Into<u8>>::into(qos);
+    }
+
+    pub fn add_retain(&mut self, retain: bool) {
+        self.fixed_header |= retain as u8
+    }
+
+    pub fn add_identifier(&mut self, identifier: u16) {
+        self.packet_identifier = identifier;
+    }
+
+    /// Encodes the packet up to the payload, with a remaining length that counts `payload_len`
+    /// more bytes. The payload is written after it, `message` is not used.
+    pub fn encode_header(
+        &mut self,
+        buffer: &mut [u8],
+        buffer_len: usize,
+        payload_len: u32,
+    ) -> Result<usize, BufferError> {
+        let mut buff_writer = BuffWriter::new(buffer, buffer_len);
+
+        let mut rm_ln = payload_len + self.topic_name.len as u32 + 2;
+        let qos = self.fixed_header & 0x06;
+        if qos != 0 {
+            rm_ln += 2;
+        }
+
+        buff_writer.write_u8(self.fixed_header)?;
+        buff_writer.write_variable_byte_int(rm_ln)?;
+        buff_writer.write_string_ref(&self.topic_name)?;
+
+        if qos != 0 {
+            buff_writer.write_u16(self.packet_identifier)?;
+        }
+        Ok(buff_writer.position)
+    }
+}
+
+impl<'a> Packet<'a> for PublishPacket<'a> {
+    fn new() -> Self {
+        Self {
+            fixed_header: PacketType::Publish.into(),
+            remain_len: 0,
+            topic_name: EncodedString::new(),
+            packet_identifier: 1,
+            message: None,
+        }
+    }
+
+    fn encode(&mut self, buffer: &mut [u8], buffer_len: usize) -> Result<usize, BufferError> {
+        let message = self.message.unwrap();
+        let position = self.encode_header(buffer, buffer_len, message.len() as u32)?;
+        let mut buff_writer = BuffWriter::new(buffer, buffer_len);
+        buff_writer.position = position;
+        buff_writer.insert_ref(message.len(), message)?;
+        Ok(buff_writer.position)
+    }
+
+    fn decode(&mut self, buff_reader: &mut BuffReader<'a>) -> Result<(), BufferError> {
+        if self.decode_fixed_header(buff_reader)? != PacketType::Publish {
+            error!("Packet you are trying to decode is not PUBLISH packet!");
+            return Err(BufferError::PacketTypeMismatch);
+        }
+        self.topic_name = buff_reader.read_string()?;
+        let qos = self.fixed_header & 0x06;
+        if qos != 0 {
+            // Decode only for QoS 1 / 2
+            self.packet_identifier = buff_reader.read_u16()?;
+        }
+        let mut total_len =
+            VariableByteIntegerEncoder::len(VariableByteIntegerEncoder::encode(self.remain_len)?);
+        total_len = total_len + 1 + self.remain_len as usize;
+        self.message = Some(buff_reader.read_message(total_len));
+        Ok(())
+    }
+
+    fn set_fixed_header(&mut self, header: u8) {
+        self.fixed_header = header;
+    }
+
+    fn set_remaining_len(&mut self, remaining_len: u32) {
+        self.remain_len = remaining_len;
+    }
+}
@@ -0,0 +1,80 @@
+/*
+ * MIT License
+ *
+ * Copyright (c) [2022] [Ondrej Babec <ond.babec@gmail.com>]
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use heapless::Vec;
+
+use crate::encoding::variable_byte_integer::VariableByteIntegerEncoder;
+use crate::packet::v3::mqtt_packet::Packet;
+use crate::packet::v5::packet_type::PacketType;
+use crate::utils::buffer_reader::BuffReader;
+use crate::utils::types::BufferError;
+
+/// Return codes are the granted QoS per filter, or 0x80 for a refused subscription
+pub struct SubackPacket<const MAX_REASONS: usize> {
+    pub fixed_header: u8,
+    pub remain_len: u32,
+    pub packet_identifier: u16,
+    pub return_codes: Vec<u8, MAX_REASONS>,
+}
+
+impl<'a, const MAX_REASONS: usize> Packet<'a> for SubackPacket<MAX_REASONS> {
+    fn new() -> Self {
+        Self {
+            fixed_header: PacketType::Suback.into(),
+            remain_len: 0,
+            packet_identifier: 0,
+            return_codes: Vec::<u8, MAX_REASONS>::new(),
+        }
+    }
+
+    fn encode(&mut self, _buffer: &mut [u8], _buffer_len: usize) -> Result<usize, BufferError> {
+        error!("SUBACK packet does not support encoding!");
+        Err(BufferError::WrongPacketToEncode)
+    }
+
+    fn decode(&mut self, buff_reader: &mut BuffReader<'a>) -> Result<(), BufferError> {
+        if self.decode_fixed_header(buff_reader)? != PacketType::Suback {
+            error!("Packet you are trying to decode is not SUBACK packet!");
+            return Err(BufferError::PacketTypeMismatch);
+        }
+        self.packet_identifier = buff_reader.read_u16()?;
+        let rm_ln_ln =
+            VariableByteIntegerEncoder::len(VariableByteIntegerEncoder::encode(self.remain_len)?);
+        let max = self.remain_len as usize + rm_ln_ln + 1;
+        while buff_reader.position < max {
+            self.return_codes
+                .push(buff_reader.read_u8()?)
+                .map_err(|_| BufferError::InsufficientBufferSize)?;
+        }
+        Ok(())
+    }
+
+    fn set_fixed_header(&mut self, header: u8) {
+        self.fixed_header = header;
+    }
+
+    fn set_remaining_len(&mut self, remaining_len: u32) {
+        self.remain_len = remaining_len;
+    }
+}
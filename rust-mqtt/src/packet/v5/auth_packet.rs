@@ -33,8 +33,8 @@ use crate::utils::types::BufferError;
 use super::packet_type::PacketType;
 use super::property::Property;
 
-/// Auth packets serves MQTTv5 extended authentication. This packet is not currently supported
-/// by rust-mqtt client but decoding and encoding of packet is prepared for future development.
+/// Auth packets serves MQTTv5 extended authentication, the challenge / response rounds of the
+/// `Authenticator` after CONNECT and for re-authentication.
 pub struct AuthPacket<'a, const MAX_PROPERTIES: usize> {
     pub fixed_header: u8,
     pub remain_len: u32,
@@ -90,8 +90,20 @@ impl<'a, const MAX_PROPERTIES: usize> Packet<'a> for AuthPacket<'a, MAX_PROPERTI
     }
 
     fn decode(&mut self, buff_reader: &mut BuffReader<'a>) -> Result<(), BufferError> {
-        self.decode_fixed_header(buff_reader)?;
-        self.auth_reason = buff_reader.read_u8()?;
+        if self.decode_fixed_header(buff_reader)? != PacketType::Auth {
+            error!("Packet you are trying to decode is not AUTH packet!");
+            return Err(BufferError::PacketTypeMismatch);
+        }
+        // success without properties may leave out the reason code and the property length
+        if self.remain_len == 0 {
+            self.auth_reason = 0x00;
+        } else {
+            self.auth_reason = buff_reader.read_u8()?;
+        }
+        if self.remain_len < 2 {
+            self.property_len = 0;
+            return Ok(());
+        }
         self.decode_properties(buff_reader)
     }
 
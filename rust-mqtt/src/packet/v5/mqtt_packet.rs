@@ -68,22 +68,19 @@ pub trait Packet<'a> {
     fn set_remaining_len(&mut self, remaining_len: u32);
 
     /// Method is decoding Byte array pointing to properties into heapless Vec
-    /// in packet. If decoding goes wrong method is returning Error
+    /// in packet. If decoding goes wrong method is returning Error, also when the last
+    /// property runs past the property length.
     fn decode_properties(&mut self, buff_reader: &mut BuffReader<'a>) -> Result<(), BufferError> {
         self.set_property_len(buff_reader.read_variable_byte_int()?);
-        let mut x: u32 = 0;
-        let mut prop: Property;
-        if self.get_property_len() != 0 {
-            loop {
-                prop = Property::decode(buff_reader)?;
-                //debug!("Parsed property {:?}", prop);
-                x = x + prop.encoded_len() as u32 + 1;
-                self.push_to_properties(prop);
-
-                if x == self.get_property_len() {
-                    break;
-                }
-            }
+        let end = buff_reader.position + self.get_property_len() as usize;
+        while buff_reader.position < end {
+            let prop = Property::decode(buff_reader)?;
+            //debug!("Parsed property {:?}", prop);
+            self.push_to_properties(prop);
+        }
+        if buff_reader.position != end {
+            error!("Properties do not match the property length");
+            return Err(BufferError::DecodingError);
         }
         Ok(())
     }
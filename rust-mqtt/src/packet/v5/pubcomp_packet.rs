@@ -79,8 +79,15 @@ impl<'a, const MAX_PROPERTIES: usize> Packet<'a> for PubcompPacket<'a, MAX_PROPE
             return Err(BufferError::PacketTypeMismatch);
         }
         self.packet_identifier = buff_reader.read_u16()?;
-        self.reason_code = buff_reader.read_u8()?;
-        self.decode_properties(buff_reader)?;
+        // reason code and properties may be omitted on success
+        if self.remain_len != 2 {
+            self.reason_code = buff_reader.read_u8()?;
+        }
+        if self.remain_len < 4 {
+            self.property_len = 0;
+        } else {
+            self.decode_properties(buff_reader)?;
+        }
         Ok(())
     }
 
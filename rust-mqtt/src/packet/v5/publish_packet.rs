@@ -64,6 +64,16 @@ impl From<QualityOfService> for u8 {
     }
 }
 
+/// MQTTv5 properties of an outgoing message, MQTTv3 has no properties and leaves them out.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct PublishOptions<'a> {
+    /// Seconds the broker keeps the message for subscribers, a retained message is dropped
+    /// once it has expired
+    pub message_expiry: Option<u32>,
+    /// MIME type of the payload, e.g. `application/json`
+    pub content_type: Option<&'a str>,
+}
+
 pub struct PublishPacket<'a, const MAX_PROPERTIES: usize> {
     pub fixed_header: u8,
     pub remain_len: u32,
@@ -95,29 +105,21 @@ impl<'a, const MAX_PROPERTIES: usize> PublishPacket<'a, MAX_PROPERTIES> {
     pub fn add_identifier(&mut self, identifier: u16) {
         self.packet_identifier = identifier;
     }
-}
-
-impl<'a, const MAX_PROPERTIES: usize> Packet<'a> for PublishPacket<'a, MAX_PROPERTIES> {
-    fn new() -> Self {
-        Self {
-            fixed_header: PacketType::Publish.into(),
-            remain_len: 0,
-            topic_name: EncodedString::new(),
-            packet_identifier: 1,
-            property_len: 0,
-            properties: Vec::<Property<'a>, MAX_PROPERTIES>::new(),
-            message: None,
-        }
-    }
 
-    fn encode(&mut self, buffer: &mut [u8], buffer_len: usize) -> Result<usize, BufferError> {
+    /// Encodes the packet up to the payload, with a remaining length that counts `payload_len`
+    /// more bytes. The payload is written after it, `message` is not used.
+    pub fn encode_header(
+        &mut self,
+        buffer: &mut [u8],
+        buffer_len: usize,
+        payload_len: u32,
+    ) -> Result<usize, BufferError> {
         let mut buff_writer = BuffWriter::new(buffer, buffer_len);
 
         let mut rm_ln = self.property_len;
         let property_len_enc: [u8; 4] = VariableByteIntegerEncoder::encode(self.property_len)?;
         let property_len_len = VariableByteIntegerEncoder::len(property_len_enc);
-        let msg_len = self.message.unwrap().len() as u32;
-        rm_ln = rm_ln + property_len_len as u32 + msg_len + self.topic_name.len as u32 + 2;
+        rm_ln = rm_ln + property_len_len as u32 + payload_len + self.topic_name.len as u32 + 2;
 
         buff_writer.write_u8(self.fixed_header)?;
         let qos = self.fixed_header & 0x06;
@@ -134,7 +136,39 @@ impl<'a, const MAX_PROPERTIES: usize> Packet<'a> for PublishPacket<'a, MAX_PROPE
 
         buff_writer.write_variable_byte_int(self.property_len)?;
         buff_writer.write_properties::<MAX_PROPERTIES>(&self.properties)?;
-        buff_writer.insert_ref(msg_len as usize, self.message.unwrap())?;
+        Ok(buff_writer.position)
+    }
+
+    /// Adds the property and its length, fails once `MAX_PROPERTIES` are set
+    pub fn add_property(&mut self, property: Property<'a>) -> Result<(), BufferError> {
+        let len = property.encoded_len() as u32 + 1;
+        self.properties
+            .push(property)
+            .map_err(|_| BufferError::InsufficientBufferSize)?;
+        self.property_len += len;
+        Ok(())
+    }
+}
+
+impl<'a, const MAX_PROPERTIES: usize> Packet<'a> for PublishPacket<'a, MAX_PROPERTIES> {
+    fn new() -> Self {
+        Self {
+            fixed_header: PacketType::Publish.into(),
+            remain_len: 0,
+            topic_name: EncodedString::new(),
+            packet_identifier: 1,
+            property_len: 0,
+            properties: Vec::<Property<'a>, MAX_PROPERTIES>::new(),
+            message: None,
+        }
+    }
+
+    fn encode(&mut self, buffer: &mut [u8], buffer_len: usize) -> Result<usize, BufferError> {
+        let message = self.message.unwrap();
+        let position = self.encode_header(buffer, buffer_len, message.len() as u32)?;
+        let mut buff_writer = BuffWriter::new(buffer, buffer_len);
+        buff_writer.position = position;
+        buff_writer.insert_ref(message.len(), message)?;
         Ok(buff_writer.position)
     }
 
@@ -47,7 +47,7 @@ impl<'a, const MAX_PROPERTIES: usize> PubrelPacket<'a, MAX_PROPERTIES> {}
 impl<'a, const MAX_PROPERTIES: usize> Packet<'a> for PubrelPacket<'a, MAX_PROPERTIES> {
     fn new() -> Self {
         Self {
-            fixed_header: 0,
+            fixed_header: PacketType::Pubrel.into(),
             remain_len: 0,
             packet_identifier: 0,
             reason_code: 0,
@@ -79,8 +79,16 @@ impl<'a, const MAX_PROPERTIES: usize> Packet<'a> for PubrelPacket<'a, MAX_PROPER
             return Err(BufferError::PacketTypeMismatch);
         }
         self.packet_identifier = buff_reader.read_u16()?;
-        self.reason_code = buff_reader.read_u8()?;
-        self.decode_properties(buff_reader)
+        // reason code and properties may be omitted on success
+        if self.remain_len != 2 {
+            self.reason_code = buff_reader.read_u8()?;
+        }
+        if self.remain_len < 4 {
+            self.property_len = 0;
+        } else {
+            self.decode_properties(buff_reader)?;
+        }
+        Ok(())
     }
 
     fn set_property_len(&mut self, value: u32) {
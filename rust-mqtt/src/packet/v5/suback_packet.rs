@@ -48,9 +48,8 @@ impl<'a, const MAX_REASONS: usize, const MAX_PROPERTIES: usize>
         &mut self,
         buff_reader: &mut BuffReader<'a>,
     ) -> Result<(), BufferError> {
-        let rm_ln_ln = VariableByteIntegerEncoder::len(
-            VariableByteIntegerEncoder::encode(self.remain_len).unwrap(),
-        );
+        let rm_ln_ln =
+            VariableByteIntegerEncoder::len(VariableByteIntegerEncoder::encode(self.remain_len)?);
         let max = self.remain_len as usize + rm_ln_ln + 1;
         if buff_reader.position >= max {
             return Ok(());
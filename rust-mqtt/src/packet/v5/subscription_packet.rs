@@ -34,6 +34,40 @@ use crate::utils::types::{BufferError, TopicFilter};
 use super::packet_type::PacketType;
 use super::property::Property;
 
+/// Whether the broker sends retained messages when the subscription is made.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub enum RetainHandling {
+    #[default]
+    SendAtSubscribe = 0,
+    /// Only if the subscription did not exist yet
+    SendAtNewSubscribe = 1,
+    DoNotSend = 2,
+}
+
+/// MQTTv5 subscription options besides the QoS, MQTTv3 brokers ignore them.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct SubscriptionOptions {
+    /// Messages published by this client are not sent back to it
+    pub no_local: bool,
+    /// Forwarded messages keep the retain flag they were published with
+    pub retain_as_published: bool,
+    pub retain_handling: RetainHandling,
+}
+
+impl SubscriptionOptions {
+    /// Subscription options byte without the QoS bits
+    pub fn bits(&self) -> u8 {
+        let mut bits = (self.retain_handling as u8) << 4;
+        if self.no_local {
+            bits |= 0x04;
+        }
+        if self.retain_as_published {
+            bits |= 0x08;
+        }
+        bits
+    }
+}
+
 pub struct SubscriptionPacket<'a, const MAX_FILTERS: usize, const MAX_PROPERTIES: usize> {
     pub fixed_header: u8,
     pub remain_len: u32,
@@ -48,11 +82,21 @@ impl<'a, const MAX_FILTERS: usize, const MAX_PROPERTIES: usize>
     SubscriptionPacket<'a, MAX_FILTERS, MAX_PROPERTIES>
 {
     pub fn add_new_filter(&mut self, topic_name: &'a str, qos: QualityOfService) {
+        self.add_filter_with_options(topic_name, qos, SubscriptionOptions::default());
+    }
+
+    pub fn add_filter_with_options(
+        &mut self,
+        topic_name: &'a str,
+        qos: QualityOfService,
+        options: SubscriptionOptions,
+    ) {
         let len = topic_name.len();
         let mut new_filter = TopicFilter::new();
         new_filter.filter.string = topic_name;
         new_filter.filter.len = len as u16;
         new_filter.sub_options |= <QualityOfService as Into<u8>>::into(qos) >> 1;
+        new_filter.sub_options |= options.bits();
         self.topic_filters.push(new_filter);
         self.topic_filter_len += 1;
     }
@@ -24,6 +24,7 @@
 
 use heapless::Vec;
 
+use crate::encoding::variable_byte_integer::VariableByteIntegerEncoder;
 use crate::packet::v5::mqtt_packet::Packet;
 use crate::utils::buffer_reader::BuffReader;
 use crate::utils::types::BufferError;
@@ -47,13 +48,14 @@ impl<'a, const MAX_REASONS: usize, const MAX_PROPERTIES: usize>
         &mut self,
         buff_reader: &mut BuffReader<'a>,
     ) -> Result<(), BufferError> {
-        let mut i = 0;
-        loop {
-            self.reason_codes.push(buff_reader.read_u8()?);
-            i += 1;
-            if i == MAX_REASONS {
-                break;
-            }
+        // one reason code per topic until the end of the packet
+        let rm_ln_ln =
+            VariableByteIntegerEncoder::len(VariableByteIntegerEncoder::encode(self.remain_len)?);
+        let max = self.remain_len as usize + rm_ln_ln + 1;
+        while buff_reader.position < max {
+            self.reason_codes
+                .push(buff_reader.read_u8()?)
+                .map_err(|_| BufferError::InsufficientBufferSize)?;
         }
         Ok(())
     }
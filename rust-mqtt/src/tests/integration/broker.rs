@@ -0,0 +1,539 @@
+/*
+ * MIT License
+ *
+ * Copyright (c) [2022] [Ondrej Babec <ond.babec@gmail.com>]
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+extern crate std;
+
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::string::String;
+use std::sync::{Arc, Mutex};
+use std::vec::Vec;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::OwnedReadHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio::task::JoinHandle;
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 3;
+const PUBACK: u8 = 4;
+const PUBREC: u8 = 5;
+const PUBREL: u8 = 6;
+const PUBCOMP: u8 = 7;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 0x90;
+const UNSUBSCRIBE: u8 = 10;
+const UNSUBACK: u8 = 0xB0;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 0xD0;
+const DISCONNECT: u8 = 14;
+
+const TOPIC_ALIAS_MAXIMUM: u16 = 4;
+
+const SUCCESS: u8 = 0x00;
+const NO_SUBSCRIPTION_EXISTED: u8 = 0x11;
+const UNSUPPORTED_PROTOCOL_VERSION: u8 = 0x84;
+const BAD_USER_NAME_OR_PASSWORD: u8 = 0x86;
+
+#[derive(Clone, Copy, Default)]
+pub struct BrokerOptions {
+    /// Username and password every client has to present
+    pub credentials: Option<(&'static str, &'static str)>,
+    /// Leaves PINGREQ unanswered, for keep alive timeouts
+    pub ignore_pings: bool,
+    /// Leaves CONNECT unanswered and the connection open, for connect timeouts
+    pub ignore_connects: bool,
+}
+
+/// MQTT v5 broker on a local port, just enough of the protocol for the integration tests:
+/// QoS 0/1/2 in both directions, `+`/`#` filters, retained messages, no local and retain
+/// handling, and topic aliases from the client. Sessions are never persisted and every packet id is trusted.
+pub struct TestBroker {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    accept: JoinHandle<()>,
+}
+
+enum Outbound {
+    Packet(Vec<u8>),
+    /// Closes the connection once the packets before it are written
+    Close,
+}
+
+struct Subscription {
+    filter: String,
+    qos: u8,
+    no_local: bool,
+}
+
+struct Session {
+    id: usize,
+    tx: UnboundedSender<Outbound>,
+    subscriptions: Vec<Subscription>,
+    next_packet_id: u16,
+}
+
+#[derive(Default)]
+struct State {
+    sessions: Vec<Session>,
+    retained: HashMap<String, (Vec<u8>, u8)>,
+    connects: usize,
+}
+
+impl TestBroker {
+    pub async fn start() -> Self {
+        Self::start_with(BrokerOptions::default()).await
+    }
+
+    pub async fn start_with(options: BrokerOptions) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(State::default()));
+        let shared = state.clone();
+        let accept = tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(serve(socket, shared.clone(), options));
+            }
+        });
+        Self {
+            addr,
+            state,
+            accept,
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// CONNECTs received so far, refused ones included
+    pub fn connects(&self) -> usize {
+        self.state.lock().unwrap().connects
+    }
+
+    /// Sends DISCONNECT with `reason` to every client and closes the connections
+    pub fn disconnect_all(&self, reason: u8) {
+        let sessions = std::mem::take(&mut self.state.lock().unwrap().sessions);
+        for session in sessions {
+            let _ = session
+                .tx
+                .send(Outbound::Packet(packet(DISCONNECT << 4, &[reason, 0])));
+            let _ = session.tx.send(Outbound::Close);
+        }
+    }
+}
+
+impl Drop for TestBroker {
+    fn drop(&mut self) {
+        self.accept.abort();
+    }
+}
+
+async fn serve(socket: TcpStream, state: Arc<Mutex<State>>, options: BrokerOptions) {
+    let _ = socket.set_nodelay(true);
+    let (mut reader, mut writer) = socket.into_split();
+    let (tx, mut rx) = unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(Outbound::Packet(packet)) = rx.recv().await {
+            if writer.write_all(&packet).await.is_err() {
+                break;
+            }
+        }
+        let _ = writer.shutdown().await;
+    });
+
+    let Some((CONNECT, _, body)) = read_packet(&mut reader).await else {
+        return;
+    };
+    let reason = check_connect(&body, &options);
+    let id = {
+        let mut state = state.lock().unwrap();
+        state.connects += 1;
+        let id = state.connects;
+        // registered before the CONNACK so nothing published after the connect is missed
+        if reason == SUCCESS && !options.ignore_connects {
+            state.sessions.push(Session {
+                id,
+                tx: tx.clone(),
+                subscriptions: Vec::new(),
+                next_packet_id: 1,
+            });
+        }
+        id
+    };
+    if options.ignore_connects {
+        while read_packet(&mut reader).await.is_some() {}
+        return;
+    }
+    if reason != SUCCESS {
+        let _ = tx.send(Outbound::Packet(packet(CONNACK, &[0, reason, 0])));
+        return;
+    }
+    let [msb, lsb] = TOPIC_ALIAS_MAXIMUM.to_be_bytes();
+    let _ = tx.send(Outbound::Packet(packet(
+        CONNACK,
+        &[0, reason, 3, 0x22, msb, lsb],
+    )));
+
+    let mut inbound_qos2 = HashSet::new();
+    let mut topic_aliases = HashMap::new();
+    while let Some((kind, flags, body)) = read_packet(&mut reader).await {
+        let mut reader = Reader::new(&body);
+        match kind {
+            PUBLISH => {
+                let qos = (flags >> 1) & 0x03;
+                let retain = flags & 0x01 != 0;
+                let mut topic = reader.string();
+                let packet_id = if qos > 0 { reader.u16() } else { 0 };
+                if let Some(alias) = reader.topic_alias() {
+                    if topic.is_empty() {
+                        // an unknown alias is a protocol error
+                        let Some(known) = topic_aliases.get(&alias) else {
+                            break;
+                        };
+                        topic = String::clone(known);
+                    } else {
+                        topic_aliases.insert(alias, topic.clone());
+                    }
+                }
+                let payload = reader.rest();
+                match qos {
+                    1 => send_ack(&tx, PUBACK << 4, packet_id),
+                    2 => send_ack(&tx, PUBREC << 4, packet_id),
+                    _ => (),
+                }
+                // a duplicate QoS2 PUBLISH only gets the PUBREC again
+                if qos < 2 || inbound_qos2.insert(packet_id) {
+                    state
+                        .lock()
+                        .unwrap()
+                        .publish(id, &topic, payload, qos, retain);
+                }
+            }
+            PUBREL => {
+                let packet_id = reader.u16();
+                inbound_qos2.remove(&packet_id);
+                send_ack(&tx, PUBCOMP << 4, packet_id);
+            }
+            // second half of a QoS2 delivery to the client
+            PUBREC => send_ack(&tx, (PUBREL << 4) | 0x02, reader.u16()),
+            PUBACK | PUBCOMP => (),
+            SUBSCRIBE => {
+                let packet_id = reader.u16();
+                reader.properties();
+                let mut state = state.lock().unwrap();
+                state.subscribe(id, packet_id, &mut reader);
+            }
+            UNSUBSCRIBE => {
+                let packet_id = reader.u16();
+                reader.properties();
+                let mut state = state.lock().unwrap();
+                let Some(session) = state.session(id) else {
+                    break;
+                };
+                let mut body = Vec::from(packet_id.to_be_bytes());
+                body.push(0);
+                while !reader.is_empty() {
+                    let filter = reader.string();
+                    let before = session.subscriptions.len();
+                    session.subscriptions.retain(|s| s.filter != filter);
+                    body.push(if session.subscriptions.len() < before {
+                        SUCCESS
+                    } else {
+                        NO_SUBSCRIPTION_EXISTED
+                    });
+                }
+                let _ = tx.send(Outbound::Packet(packet(UNSUBACK, &body)));
+            }
+            PINGREQ if !options.ignore_pings => {
+                let _ = tx.send(Outbound::Packet(packet(PINGRESP, &[])));
+            }
+            PINGREQ => (),
+            _ => break,
+        }
+    }
+    state.lock().unwrap().sessions.retain(|s| s.id != id);
+}
+
+/// CONNACK reason code for a CONNECT body
+fn check_connect(body: &[u8], options: &BrokerOptions) -> u8 {
+    let mut reader = Reader::new(body);
+    let _protocol_name = reader.string();
+    if reader.u8() != 5 {
+        return UNSUPPORTED_PROTOCOL_VERSION;
+    }
+    let flags = reader.u8();
+    let _keep_alive = reader.u16();
+    reader.properties();
+    let _client_id = reader.string();
+    if flags & 0x04 != 0 {
+        reader.properties();
+        let _will_topic = reader.string();
+        let _will_payload = reader.binary();
+    }
+    let username = (flags & 0x80 != 0).then(|| reader.string());
+    let password = (flags & 0x40 != 0).then(|| reader.binary());
+    match options.credentials {
+        Some((user, pass))
+            if username.as_deref() != Some(user)
+                || password.as_deref() != Some(pass.as_bytes()) =>
+        {
+            BAD_USER_NAME_OR_PASSWORD
+        }
+        _ => SUCCESS,
+    }
+}
+
+impl State {
+    fn session(&mut self, id: usize) -> Option<&mut Session> {
+        self.sessions.iter_mut().find(|s| s.id == id)
+    }
+
+    fn publish(&mut self, from: usize, topic: &str, payload: &[u8], qos: u8, retain: bool) {
+        if retain {
+            if payload.is_empty() {
+                self.retained.remove(topic);
+            } else {
+                self.retained.insert(topic.into(), (payload.into(), qos));
+            }
+        }
+        for session in self.sessions.iter_mut() {
+            let granted = session
+                .subscriptions
+                .iter()
+                .find(|s| matches(&s.filter, topic) && !(s.no_local && session.id == from))
+                .map(|s| s.qos);
+            if let Some(granted) = granted {
+                session.deliver(topic, payload, qos.min(granted), false);
+            }
+        }
+    }
+
+    fn subscribe(&mut self, id: usize, packet_id: u16, reader: &mut Reader) {
+        let Some(session) = self.sessions.iter_mut().find(|s| s.id == id) else {
+            return;
+        };
+        let mut body = Vec::from(packet_id.to_be_bytes());
+        body.push(0);
+        let mut retained = Vec::new();
+        while !reader.is_empty() {
+            let filter = reader.string();
+            let options = reader.u8();
+            let qos = options & 0x03;
+            let retain_handling = (options >> 4) & 0x03;
+            let existed = session.subscriptions.iter().any(|s| s.filter == filter);
+            session.subscriptions.retain(|s| s.filter != filter);
+            if retain_handling == 0 || (retain_handling == 1 && !existed) {
+                retained.extend(
+                    self.retained
+                        .iter()
+                        .filter(|(topic, _)| matches(&filter, topic))
+                        .map(|(topic, (payload, stored))| {
+                            (topic.clone(), payload.clone(), qos.min(*stored))
+                        }),
+                );
+            }
+            session.subscriptions.push(Subscription {
+                filter,
+                qos,
+                no_local: options & 0x04 != 0,
+            });
+            body.push(qos);
+        }
+        let _ = session.tx.send(Outbound::Packet(packet(SUBACK, &body)));
+        for (topic, payload, qos) in retained {
+            session.deliver(&topic, &payload, qos, true);
+        }
+    }
+}
+
+impl Session {
+    fn deliver(&mut self, topic: &str, payload: &[u8], qos: u8, retain: bool) {
+        let mut body = Vec::new();
+        push_string(&mut body, topic);
+        if qos > 0 {
+            body.extend(self.next_packet_id.to_be_bytes());
+            self.next_packet_id = self.next_packet_id.checked_add(1).unwrap_or(1);
+        }
+        // no properties
+        body.push(0);
+        body.extend_from_slice(payload);
+        let header = (PUBLISH << 4) | (qos << 1) | u8::from(retain);
+        let _ = self.tx.send(Outbound::Packet(packet(header, &body)));
+    }
+}
+
+/// `+` matches one topic level, `#` the remaining levels including none
+fn matches(filter: &str, topic: &str) -> bool {
+    let mut levels = topic.split('/');
+    for part in filter.split('/') {
+        match (part, levels.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => (),
+            (part, Some(level)) if part == level => (),
+            _ => return false,
+        }
+    }
+    levels.next().is_none()
+}
+
+fn send_ack(tx: &UnboundedSender<Outbound>, header: u8, packet_id: u16) {
+    let _ = tx.send(Outbound::Packet(packet(header, &packet_id.to_be_bytes())));
+}
+
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = std::vec![header];
+    let mut len = body.len();
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+fn push_string(body: &mut Vec<u8>, value: &str) {
+    body.extend((value.len() as u16).to_be_bytes());
+    body.extend_from_slice(value.as_bytes());
+}
+
+/// Packet type, header flags and body, `None` once the client is gone
+async fn read_packet(reader: &mut OwnedReadHalf) -> Option<(u8, u8, Vec<u8>)> {
+    let header = reader.read_u8().await.ok()?;
+    let mut len = 0usize;
+    for shift in (0..28).step_by(7) {
+        let byte = reader.read_u8().await.ok()?;
+        len |= usize::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            let mut body = std::vec![0; len];
+            reader.read_exact(&mut body).await.ok()?;
+            return Some((header >> 4, header & 0x0F, body));
+        }
+    }
+    None
+}
+
+/// Cursor over a packet body, the client under test is trusted to send well-formed packets
+struct Reader<'a> {
+    body: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(body: &'a [u8]) -> Self {
+        Self { body }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.body.is_empty()
+    }
+
+    fn take(&mut self, len: usize) -> &'a [u8] {
+        let (head, tail) = self.body.split_at(len);
+        self.body = tail;
+        head
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.take(1)[0]
+    }
+
+    fn u16(&mut self) -> u16 {
+        u16::from_be_bytes([self.u8(), self.u8()])
+    }
+
+    fn varint(&mut self) -> usize {
+        let mut value = 0;
+        for shift in (0..28).step_by(7) {
+            let byte = self.u8();
+            value |= usize::from(byte & 0x7F) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        value
+    }
+
+    fn binary(&mut self) -> Vec<u8> {
+        let len = self.u16();
+        self.take(usize::from(len)).into()
+    }
+
+    fn string(&mut self) -> String {
+        String::from_utf8(self.binary()).unwrap()
+    }
+
+    /// Skips the property block, properties besides the topic alias are ignored
+    fn properties(&mut self) {
+        let len = self.varint();
+        self.take(len);
+    }
+
+    /// Topic Alias from the PUBLISH properties, the others are skipped
+    fn topic_alias(&mut self) -> Option<u16> {
+        let len = self.varint();
+        let mut properties = Reader::new(self.take(len));
+        let mut alias = None;
+        while !properties.is_empty() {
+            match properties.varint() {
+                // payload format indicator
+                0x01 => {
+                    properties.take(1);
+                }
+                // message expiry interval
+                0x02 => {
+                    properties.take(4);
+                }
+                // content type, response topic, correlation data
+                0x03 | 0x08 | 0x09 => {
+                    properties.binary();
+                }
+                // subscription identifier
+                0x0B => {
+                    properties.varint();
+                }
+                0x23 => alias = Some(properties.u16()),
+                // user property
+                0x26 => {
+                    properties.binary();
+                    properties.binary();
+                }
+                _ => panic!("unexpected PUBLISH property"),
+            }
+        }
+        alias
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        self.take(self.body.len())
+    }
+}
@@ -0,0 +1,329 @@
+/*
+ * MIT License
+ *
+ * Copyright (c) [2022] [Ondrej Babec <ond.babec@gmail.com>]
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+extern crate std;
+
+use core::fmt::Write;
+use std::string::String;
+
+use tokio_test::assert_ok;
+
+use crate::client::client::MqttClient;
+use crate::client::client_config::{ClientConfig, MqttVersion};
+use crate::client::payload::Formatted;
+use crate::network::tokio_net::TokioNetwork;
+use crate::packet::v5::publish_packet::PublishOptions;
+use crate::packet::v5::publish_packet::QualityOfService::{self, QoS0, QoS1, QoS2};
+use crate::packet::v5::reason_codes::ReasonCode;
+use crate::packet::v5::subscription_packet::SubscriptionOptions;
+use crate::tests::integration::broker::{BrokerOptions, TestBroker};
+use crate::utils::rng_generator::CountingRng;
+
+const BUFFER_LEN: usize = 512;
+// SERVER_SHUTTING_DOWN
+const SHUTTING_DOWN: u8 = 0x8B;
+
+type Client<'a> = MqttClient<'a, TokioNetwork, 5, CountingRng>;
+
+fn config<'a>() -> ClientConfig<'a, 5, CountingRng> {
+    let mut config = ClientConfig::new(MqttVersion::MQTTv5, CountingRng(20000));
+    config.add_max_subscribe_qos(QoS2);
+    config
+}
+
+/// Opens the TCP connection, `connect_to_broker` is left to the test
+async fn client<'a>(
+    broker: &TestBroker,
+    config: ClientConfig<'a, 5, CountingRng>,
+    write_buffer: &'a mut [u8],
+    recv_buffer: &'a mut [u8],
+) -> Client<'a> {
+    let network = TokioNetwork::connect(broker.addr()).await.unwrap();
+    MqttClient::new(
+        network,
+        write_buffer,
+        BUFFER_LEN,
+        recv_buffer,
+        BUFFER_LEN,
+        config,
+    )
+}
+
+/// Publishes at `qos` from one client and returns what a second, subscribed client receives
+async fn publish_received(qos: QualityOfService, retain: bool) {
+    let broker = TestBroker::start().await;
+    let (mut sub_write, mut sub_recv) = ([0; BUFFER_LEN], [0; BUFFER_LEN]);
+    let mut subscriber = client(&broker, config(), &mut sub_write, &mut sub_recv).await;
+    assert_ok!(subscriber.connect_to_broker().await);
+    assert_ok!(subscriber.subscribe_to_topic("test/+").await);
+
+    let (mut pub_write, mut pub_recv) = ([0; BUFFER_LEN], [0; BUFFER_LEN]);
+    let mut publisher = client(&broker, config(), &mut pub_write, &mut pub_recv).await;
+    assert_ok!(publisher.connect_to_broker().await);
+    assert_ok!(
+        publisher
+            .send_message("test/qos", b"payload", qos, retain)
+            .await
+    );
+
+    let (topic, payload) = assert_ok!(subscriber.receive_message().await);
+    assert_eq!(topic, "test/qos");
+    assert_eq!(payload, b"payload");
+    assert_ok!(publisher.disconnect().await);
+    assert_ok!(subscriber.disconnect().await);
+}
+
+#[tokio::test]
+async fn test_connect_disconnect() {
+    let broker = TestBroker::start().await;
+    let (mut write_buffer, mut recv_buffer) = ([0; BUFFER_LEN], [0; BUFFER_LEN]);
+    let mut client = client(&broker, config(), &mut write_buffer, &mut recv_buffer).await;
+    assert_ok!(client.connect_to_broker().await);
+    assert_ok!(client.disconnect().await);
+    assert_eq!(broker.connects(), 1);
+}
+
+#[tokio::test]
+async fn test_connect_credentials() {
+    let broker = TestBroker::start_with(BrokerOptions {
+        credentials: Some(("toucan", "secret")),
+        ..Default::default()
+    })
+    .await;
+    let mut config = config();
+    config.add_username("toucan");
+    config.add_password("secret");
+    let (mut write_buffer, mut recv_buffer) = ([0; BUFFER_LEN], [0; BUFFER_LEN]);
+    let mut client = client(&broker, config, &mut write_buffer, &mut recv_buffer).await;
+    assert_ok!(client.connect_to_broker().await);
+    assert_ok!(client.disconnect().await);
+}
+
+#[tokio::test]
+async fn test_connect_bad_password() {
+    let broker = TestBroker::start_with(BrokerOptions {
+        credentials: Some(("toucan", "secret")),
+        ..Default::default()
+    })
+    .await;
+    let mut config = config();
+    config.add_username("toucan");
+    config.add_password("guess");
+    let (mut write_buffer, mut recv_buffer) = ([0; BUFFER_LEN], [0; BUFFER_LEN]);
+    let mut client = client(&broker, config, &mut write_buffer, &mut recv_buffer).await;
+    assert_eq!(
+        client.connect_to_broker().await,
+        Err(ReasonCode::BadUserNameOrPassword)
+    );
+}
+
+#[tokio::test]
+async fn test_connect_without_credentials() {
+    let broker = TestBroker::start_with(BrokerOptions {
+        credentials: Some(("toucan", "secret")),
+        ..Default::default()
+    })
+    .await;
+    let (mut write_buffer, mut recv_buffer) = ([0; BUFFER_LEN], [0; BUFFER_LEN]);
+    let mut client = client(&broker, config(), &mut write_buffer, &mut recv_buffer).await;
+    assert_eq!(
+        client.connect_to_broker().await,
+        Err(ReasonCode::BadUserNameOrPassword)
+    );
+}
+
+#[tokio::test]
+async fn test_publish_qos0() {
+    publish_received(QoS0, false).await;
+}
+
+#[tokio::test]
+async fn test_publish_qos1() {
+    publish_received(QoS1, false).await;
+}
+
+#[tokio::test]
+async fn test_publish_qos2() {
+    publish_received(QoS2, false).await;
+}
+
+#[tokio::test]
+async fn test_receive_retained() {
+    let broker = TestBroker::start().await;
+    let (mut pub_write, mut pub_recv) = ([0; BUFFER_LEN], [0; BUFFER_LEN]);
+    let mut publisher = client(&broker, config(), &mut pub_write, &mut pub_recv).await;
+    assert_ok!(publisher.connect_to_broker().await);
+    assert_ok!(
+        publisher
+            .send_message("test/retained", b"kept", QoS1, true)
+            .await
+    );
+
+    let (mut sub_write, mut sub_recv) = ([0; BUFFER_LEN], [0; BUFFER_LEN]);
+    let mut subscriber = client(&broker, config(), &mut sub_write, &mut sub_recv).await;
+    assert_ok!(subscriber.connect_to_broker().await);
+    assert_ok!(subscriber.subscribe_to_topic("test/#").await);
+    let (topic, payload) = assert_ok!(subscriber.receive_message().await);
+    assert_eq!(topic, "test/retained");
+    assert_eq!(payload, b"kept");
+}
+
+#[tokio::test]
+async fn test_no_local() {
+    let broker = TestBroker::start().await;
+    let (mut write_buffer, mut recv_buffer) = ([0; BUFFER_LEN], [0; BUFFER_LEN]);
+    let mut client = client(&broker, config(), &mut write_buffer, &mut recv_buffer).await;
+    assert_ok!(client.connect_to_broker().await);
+    let options = SubscriptionOptions {
+        no_local: true,
+        ..Default::default()
+    };
+    assert_ok!(
+        client
+            .subscribe_to_topic_with_options("test/a", options)
+            .await
+    );
+    assert_ok!(client.subscribe_to_topic("test/b").await);
+    // the own message on test/a is not sent back, the one on test/b is
+    assert_ok!(client.send_message("test/a", b"a", QoS0, false).await);
+    assert_ok!(client.send_message("test/b", b"b", QoS0, false).await);
+    let (topic, payload) = assert_ok!(client.receive_message().await);
+    assert_eq!(topic, "test/b");
+    assert_eq!(payload, b"b");
+}
+
+#[tokio::test]
+async fn test_unsubscribe() {
+    let broker = TestBroker::start().await;
+    let (mut write_buffer, mut recv_buffer) = ([0; BUFFER_LEN], [0; BUFFER_LEN]);
+    let mut client = client(&broker, config(), &mut write_buffer, &mut recv_buffer).await;
+    assert_ok!(client.connect_to_broker().await);
+    assert_ok!(client.subscribe_to_topic("test/a").await);
+    assert_ok!(client.subscribe_to_topic("test/b").await);
+    assert_ok!(client.unsubscribe_from_topic("test/a").await);
+    assert_ok!(client.send_message("test/a", b"a", QoS0, false).await);
+    assert_ok!(client.send_message("test/b", b"b", QoS0, false).await);
+    let (topic, _) = assert_ok!(client.receive_message().await);
+    assert_eq!(topic, "test/b");
+}
+
+#[tokio::test]
+async fn test_ping() {
+    let broker = TestBroker::start().await;
+    let (mut write_buffer, mut recv_buffer) = ([0; BUFFER_LEN], [0; BUFFER_LEN]);
+    let mut client = client(&broker, config(), &mut write_buffer, &mut recv_buffer).await;
+    assert_ok!(client.connect_to_broker().await);
+    assert_ok!(client.send_ping().await);
+    assert_ok!(client.send_ping().await);
+}
+
+#[tokio::test]
+async fn test_broker_disconnect() {
+    let broker = TestBroker::start().await;
+    let (mut write_buffer, mut recv_buffer) = ([0; BUFFER_LEN], [0; BUFFER_LEN]);
+    let mut client = client(&broker, config(), &mut write_buffer, &mut recv_buffer).await;
+    assert_ok!(client.connect_to_broker().await);
+    assert_ok!(client.subscribe_to_topic("test/#").await);
+    broker.disconnect_all(SHUTTING_DOWN);
+    assert_eq!(
+        client.receive_message().await,
+        Err(ReasonCode::ServerShuttingDown)
+    );
+    // the broker closed the connection after the DISCONNECT
+    assert_eq!(
+        client.receive_message().await,
+        Err(ReasonCode::NetworkError)
+    );
+}
+
+#[tokio::test]
+async fn test_topic_alias() {
+    let broker = TestBroker::start().await;
+    let (mut sub_write, mut sub_recv) = ([0; BUFFER_LEN], [0; BUFFER_LEN]);
+    let mut subscriber = client(&broker, config(), &mut sub_write, &mut sub_recv).await;
+    assert_ok!(subscriber.connect_to_broker().await);
+    assert_ok!(subscriber.subscribe_to_topic("test/#").await);
+
+    let (mut pub_write, mut pub_recv) = ([0; BUFFER_LEN], [0; BUFFER_LEN]);
+    let mut publisher = client(&broker, config(), &mut pub_write, &mut pub_recv).await;
+    assert_ok!(publisher.connect_to_broker().await);
+    // the second and third message only carry the alias
+    for payload in [b"1", b"2", b"3"] {
+        assert_ok!(
+            publisher
+                .send_message("test/alias", payload, QoS1, false)
+                .await
+        );
+        let (topic, received) = assert_ok!(subscriber.receive_message().await);
+        assert_eq!(topic, "test/alias");
+        assert_eq!(received, payload);
+    }
+}
+
+#[tokio::test]
+async fn test_streamed_publish() {
+    const LARGE_LEN: usize = 4096;
+    let broker = TestBroker::start().await;
+    // receiving still needs the whole packet in both buffers
+    let (mut sub_write, mut sub_recv) = ([0; LARGE_LEN], [0; LARGE_LEN]);
+    let network = TokioNetwork::connect(broker.addr()).await.unwrap();
+    let mut subscriber = MqttClient::new(
+        network,
+        &mut sub_write,
+        LARGE_LEN,
+        &mut sub_recv,
+        LARGE_LEN,
+        config(),
+    );
+    assert_ok!(subscriber.connect_to_broker().await);
+    assert_ok!(subscriber.subscribe_to_topic("test/#").await);
+
+    // the payload is several times the publisher's write buffer
+    let render = |w: &mut dyn Write| {
+        for cell in 0..300 {
+            write!(w, "{},", 3000 + cell)?;
+        }
+        Ok(())
+    };
+    let mut expected = String::new();
+    render(&mut expected).unwrap();
+    let (mut pub_write, mut pub_recv) = ([0; BUFFER_LEN], [0; BUFFER_LEN]);
+    let mut publisher = client(&broker, config(), &mut pub_write, &mut pub_recv).await;
+    assert_ok!(publisher.connect_to_broker().await);
+    let mut payload = Formatted::new(render).unwrap();
+    assert_ok!(
+        publisher
+            .send_message_streamed(
+                "test/cells",
+                &mut payload,
+                QoS1,
+                false,
+                PublishOptions::default()
+            )
+            .await
+    );
+    let (topic, received) = assert_ok!(subscriber.receive_message().await);
+    assert_eq!(topic, "test/cells");
+    assert_eq!(received, expected.as_bytes());
+}
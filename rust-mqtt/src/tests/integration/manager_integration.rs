@@ -0,0 +1,225 @@
+/*
+ * MIT License
+ *
+ * Copyright (c) [2022] [Ondrej Babec <ond.babec@gmail.com>]
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+extern crate std;
+
+use std::net::SocketAddr;
+use std::string::String;
+use std::time::Duration;
+use std::vec::Vec;
+
+use tokio::time::timeout;
+use tokio_test::assert_ok;
+
+use crate::client::client_config::{ClientConfig, MqttVersion};
+use crate::client::manager::{ConnectionManager, Connector, ManagerEvent};
+use crate::network::tokio_net::{TokioClock, TokioNetwork};
+use crate::packet::v5::publish_packet::QualityOfService::{QoS1, QoS2};
+use crate::packet::v5::reason_codes::ReasonCode;
+use crate::tests::integration::broker::{BrokerOptions, TestBroker};
+use crate::utils::rng_generator::CountingRng;
+
+const BUFFER_LEN: usize = 512;
+// SERVER_SHUTTING_DOWN
+const SHUTTING_DOWN: u8 = 0x8B;
+
+struct TcpConnector {
+    addr: SocketAddr,
+}
+
+impl Connector for TcpConnector {
+    type Connection = TokioNetwork;
+
+    async fn connect(&mut self) -> Result<TokioNetwork, ReasonCode> {
+        TokioNetwork::connect(self.addr)
+            .await
+            .map_err(|_| ReasonCode::NetworkError)
+    }
+}
+
+type Manager<'a> = ConnectionManager<'a, TcpConnector, TokioClock, 5, CountingRng, 2>;
+
+fn manager<'a>(
+    broker: &TestBroker,
+    keep_alive: u16,
+    write_buffer: &'a mut [u8],
+    recv_buffer: &'a mut [u8],
+    inbox: &'a mut [u8],
+) -> Manager<'a> {
+    let mut config = ClientConfig::new(MqttVersion::MQTTv5, CountingRng(20000));
+    config.add_max_subscribe_qos(QoS2);
+    config.keep_alive = keep_alive;
+    let connector = TcpConnector {
+        addr: broker.addr(),
+    };
+    let mut manager = ConnectionManager::new(
+        connector,
+        TokioClock::new(),
+        write_buffer,
+        BUFFER_LEN,
+        recv_buffer,
+        BUFFER_LEN,
+        inbox,
+        config,
+    );
+    manager.set_backoff(10, 100);
+    manager
+}
+
+/// Owned copy of a `ManagerEvent`, so the tests can poll in a loop
+#[derive(Debug, PartialEq)]
+enum Event {
+    Connected,
+    Disconnected(ReasonCode),
+    Message(String, Vec<u8>),
+}
+
+/// Polls past the keep alive housekeeping, fails the test if nothing else happens in time
+async fn next_event(manager: &mut Manager<'_>) -> Event {
+    let event = async {
+        loop {
+            match manager.poll().await {
+                ManagerEvent::Idle => (),
+                ManagerEvent::Connected => return Event::Connected,
+                ManagerEvent::Disconnected(reason) => return Event::Disconnected(reason),
+                ManagerEvent::Message(topic, payload) => {
+                    return Event::Message(topic.into(), payload.into())
+                }
+            }
+        }
+    };
+    timeout(Duration::from_secs(5), event)
+        .await
+        .expect("no event from the connection manager")
+}
+
+fn message(topic: &str, payload: &[u8]) -> Event {
+    Event::Message(topic.into(), payload.into())
+}
+
+#[tokio::test]
+async fn test_manager_subscribes_on_connect() {
+    let broker = TestBroker::start().await;
+    let (mut write_buffer, mut recv_buffer, mut inbox) =
+        ([0; BUFFER_LEN], [0; BUFFER_LEN], [0; BUFFER_LEN]);
+    let mut manager = manager(&broker, 60, &mut write_buffer, &mut recv_buffer, &mut inbox);
+    assert_ok!(manager.subscribe("cmd/#").await);
+    assert_eq!(next_event(&mut manager).await, Event::Connected);
+    assert_ok!(manager.publish("cmd/x", b"on", QoS1, false).await);
+    assert_eq!(next_event(&mut manager).await, message("cmd/x", b"on"));
+    // outside the subscription, a message arriving while publish waits for the PUBCOMP is lost
+    assert_ok!(manager.publish("state", b"{}", QoS2, false).await);
+    assert!(manager.is_connected());
+}
+
+#[tokio::test]
+async fn test_manager_refused() {
+    let broker = TestBroker::start_with(BrokerOptions {
+        credentials: Some(("toucan", "secret")),
+        ..Default::default()
+    })
+    .await;
+    let (mut write_buffer, mut recv_buffer, mut inbox) =
+        ([0; BUFFER_LEN], [0; BUFFER_LEN], [0; BUFFER_LEN]);
+    let mut manager = manager(&broker, 60, &mut write_buffer, &mut recv_buffer, &mut inbox);
+    assert_eq!(
+        next_event(&mut manager).await,
+        Event::Disconnected(ReasonCode::BadUserNameOrPassword)
+    );
+    assert!(!manager.is_connected());
+}
+
+#[tokio::test]
+async fn test_manager_keep_alive() {
+    let broker = TestBroker::start().await;
+    let (mut write_buffer, mut recv_buffer, mut inbox) =
+        ([0; BUFFER_LEN], [0; BUFFER_LEN], [0; BUFFER_LEN]);
+    let mut manager = manager(&broker, 1, &mut write_buffer, &mut recv_buffer, &mut inbox);
+    assert_eq!(next_event(&mut manager).await, Event::Connected);
+    // two keep alive periods, every PINGREQ is answered
+    let idle = timeout(Duration::from_millis(2500), next_event(&mut manager)).await;
+    assert!(idle.is_err(), "unexpected {:?}", idle);
+    assert!(manager.is_connected());
+    assert_eq!(broker.connects(), 1);
+}
+
+#[tokio::test]
+async fn test_manager_ping_timeout() {
+    let broker = TestBroker::start_with(BrokerOptions {
+        ignore_pings: true,
+        ..Default::default()
+    })
+    .await;
+    let (mut write_buffer, mut recv_buffer, mut inbox) =
+        ([0; BUFFER_LEN], [0; BUFFER_LEN], [0; BUFFER_LEN]);
+    let mut manager = manager(&broker, 1, &mut write_buffer, &mut recv_buffer, &mut inbox);
+    assert_eq!(next_event(&mut manager).await, Event::Connected);
+    assert_eq!(
+        next_event(&mut manager).await,
+        Event::Disconnected(ReasonCode::KeepAliveTimeout)
+    );
+    assert_eq!(next_event(&mut manager).await, Event::Connected);
+    assert_eq!(broker.connects(), 2);
+}
+
+#[tokio::test]
+async fn test_manager_connack_timeout() {
+    let broker = TestBroker::start_with(BrokerOptions {
+        ignore_connects: true,
+        ..Default::default()
+    })
+    .await;
+    let (mut write_buffer, mut recv_buffer, mut inbox) =
+        ([0; BUFFER_LEN], [0; BUFFER_LEN], [0; BUFFER_LEN]);
+    let mut manager = manager(&broker, 1, &mut write_buffer, &mut recv_buffer, &mut inbox);
+    // the connection stays open, the manager gives up and retries after the backoff
+    for _ in 0..2 {
+        assert_eq!(
+            next_event(&mut manager).await,
+            Event::Disconnected(ReasonCode::KeepAliveTimeout)
+        );
+    }
+    assert!(!manager.is_connected());
+    assert_eq!(broker.connects(), 2);
+}
+
+#[tokio::test]
+async fn test_manager_broker_disconnect() {
+    let broker = TestBroker::start().await;
+    let (mut write_buffer, mut recv_buffer, mut inbox) =
+        ([0; BUFFER_LEN], [0; BUFFER_LEN], [0; BUFFER_LEN]);
+    let mut manager = manager(&broker, 60, &mut write_buffer, &mut recv_buffer, &mut inbox);
+    assert_ok!(manager.subscribe("cmd/#").await);
+    assert_eq!(next_event(&mut manager).await, Event::Connected);
+    broker.disconnect_all(SHUTTING_DOWN);
+    assert_eq!(
+        next_event(&mut manager).await,
+        Event::Disconnected(ReasonCode::ServerShuttingDown)
+    );
+    assert_eq!(next_event(&mut manager).await, Event::Connected);
+    assert_eq!(broker.connects(), 2);
+    // the new session only gets the message if the subscription was sent again
+    assert_ok!(manager.publish("cmd/x", b"on", QoS1, false).await);
+    assert_eq!(next_event(&mut manager).await, message("cmd/x", b"on"));
+}
@@ -22,6 +22,8 @@
  * SOFTWARE.
  */
 
+#[cfg(all(test, feature = "std"))]
+pub mod integration;
 #[cfg(test)]
 #[allow(unused_must_use)]
 pub mod unit;
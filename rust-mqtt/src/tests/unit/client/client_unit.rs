@@ -0,0 +1,750 @@
+/*
+ * MIT License
+ *
+ * Copyright (c) [2022] [Ondrej Babec <ond.babec@gmail.com>]
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+extern crate std;
+
+use std::vec::Vec;
+
+use proptest::prelude::*;
+use tokio_test::{assert_err, assert_ok};
+
+use crate::client::auth::Authenticator;
+use crate::client::client::MqttClient;
+use crate::client::client_config::{ClientConfig, MqttVersion};
+use crate::client::payload::Chunks;
+use crate::packet::v5::publish_packet::PublishOptions;
+use crate::packet::v5::publish_packet::QualityOfService::{self, QoS0, QoS1, QoS2};
+use crate::packet::v5::reason_codes::ReasonCode;
+use crate::packet::v5::subscription_packet::{RetainHandling, SubscriptionOptions};
+use crate::tests::unit::client::mock_broker::{ack, connack, suback, MockBroker, SentLog};
+use crate::utils::rng_generator::CountingRng;
+
+const CONNECT_FLAGS: usize = 9;
+const WILL_FLAG: u8 = 0x04;
+const WILL_RETAIN: u8 = 0x20;
+const PASSWORD_FLAG: u8 = 0x40;
+const USERNAME_FLAG: u8 = 0x80;
+const PUBACK: u8 = 0x40;
+const PUBREC: u8 = 0x50;
+const PUBREL: u8 = 0x62;
+const PUBCOMP: u8 = 0x70;
+const DUP: u8 = 0x08;
+const CONTINUE_AUTH: u8 = 0x18;
+const REAUTHENTICATE: u8 = 0x19;
+// CountingRng(0) hands out 1 as the first packet identifier
+const FIRST_ID: u16 = 1;
+
+/// Runs `connect_to_broker` against the broker and returns what the client sent
+fn connect(
+    broker: MockBroker,
+    config: ClientConfig<5, CountingRng>,
+) -> (Result<(), ReasonCode>, SentLog) {
+    let sent = broker.sent();
+    let mut write_buffer = [0; 256];
+    let mut recv_buffer = [0; 256];
+    let mut client = MqttClient::new(
+        broker,
+        &mut write_buffer,
+        256,
+        &mut recv_buffer,
+        256,
+        config,
+    );
+    let res = tokio_test::block_on(client.connect_to_broker());
+    (res, sent)
+}
+
+fn publish(broker: MockBroker, qos: QualityOfService) -> (Result<(), ReasonCode>, SentLog) {
+    publish_with(broker, MqttVersion::MQTTv5, qos)
+}
+
+/// Publishes one message without connecting first and returns what the client sent
+fn publish_with(
+    broker: MockBroker,
+    version: MqttVersion,
+    qos: QualityOfService,
+) -> (Result<(), ReasonCode>, SentLog) {
+    let sent = broker.sent();
+    let mut write_buffer = [0; 256];
+    let mut recv_buffer = [0; 256];
+    let config = ClientConfig::<5, _>::new(version, CountingRng(0));
+    let mut client = MqttClient::new(
+        broker,
+        &mut write_buffer,
+        256,
+        &mut recv_buffer,
+        256,
+        config,
+    );
+    let res = tokio_test::block_on(client.send_message("toucan", b"{}", qos, false));
+    (res, sent)
+}
+
+/// Publishes `{}` to "toucan" at QoS0 without connecting first and returns what the client sent
+fn publish_options(version: MqttVersion, options: PublishOptions) -> SentLog {
+    let broker = MockBroker::new();
+    let sent = broker.sent();
+    let mut write_buffer = [0; 256];
+    let mut recv_buffer = [0; 256];
+    let mut client = client_with(broker, version, &mut write_buffer, &mut recv_buffer);
+    assert_ok!(tokio_test::block_on(
+        client.send_message_with_options("toucan", b"{}", QoS0, false, options)
+    ));
+    sent
+}
+
+fn client_with<'a>(
+    broker: MockBroker,
+    version: MqttVersion,
+    write_buffer: &'a mut [u8],
+    recv_buffer: &'a mut [u8],
+) -> MqttClient<'a, MockBroker, 5, CountingRng> {
+    let config = ClientConfig::<5, _>::new(version, CountingRng(0));
+    MqttClient::new(broker, write_buffer, 256, recv_buffer, 256, config)
+}
+
+/// PUBLISH of `payload` to "cmd" as the broker sends it, `header` carries QoS and DUP
+fn incoming(header: u8, identifier: u16, payload: u8, version: &MqttVersion) -> Vec<u8> {
+    let mut packet = std::vec![header, 0x00, 0x00, 0x03, b'c', b'm', b'd'];
+    if header & 0x06 != 0 {
+        packet.extend(identifier.to_be_bytes());
+    }
+    if *version == MqttVersion::MQTTv5 {
+        // no properties
+        packet.push(0x00);
+    }
+    packet.push(payload);
+    packet[1] = (packet.len() - 2) as u8;
+    packet
+}
+
+/// Receives two messages and returns their payloads
+fn receive_two(broker: MockBroker, version: MqttVersion) -> (u8, u8) {
+    let mut write_buffer = [0; 256];
+    let mut recv_buffer = [0; 256];
+    let mut client = client_with(broker, version, &mut write_buffer, &mut recv_buffer);
+    let (topic, payload) = tokio_test::block_on(client.receive_message()).unwrap();
+    assert_eq!(topic, "cmd");
+    let first = payload[0];
+    let (_, payload) = tokio_test::block_on(client.receive_message()).unwrap();
+    (first, payload[0])
+}
+
+/// Answers the broker's "challenge" with "final" and accepts the exchange once the broker
+/// sends its "signature"
+#[derive(Default)]
+struct ScriptedAuthenticator {
+    completed: bool,
+}
+
+impl Authenticator for ScriptedAuthenticator {
+    fn method(&self) -> &'static str {
+        "TEST"
+    }
+
+    fn start(&mut self) -> Result<&[u8], ReasonCode> {
+        self.completed = false;
+        Ok(b"first")
+    }
+
+    fn challenge(&mut self, data: &[u8]) -> Result<&[u8], ReasonCode> {
+        match data {
+            b"challenge" => Ok(b"final"),
+            _ => Err(ReasonCode::NotAuthorized),
+        }
+    }
+
+    fn complete(&mut self, data: &[u8]) -> Result<(), ReasonCode> {
+        self.completed = data == b"signature";
+        if self.completed {
+            Ok(())
+        } else {
+            Err(ReasonCode::NotAuthorized)
+        }
+    }
+}
+
+/// Property length, then Authentication Method "TEST" and Authentication Data
+fn auth_properties(data: &[u8]) -> Vec<u8> {
+    let mut properties = std::vec![0x00, 0x15, 0x00, 0x04, b'T', b'E', b'S', b'T'];
+    properties.extend([0x16, 0x00, data.len() as u8]);
+    properties.extend_from_slice(data);
+    properties[0] = (properties.len() - 1) as u8;
+    properties
+}
+
+fn auth(reason_code: u8, data: &[u8]) -> Vec<u8> {
+    let mut packet = std::vec![0xF0, 0x00, reason_code];
+    packet.extend(auth_properties(data));
+    packet[1] = (packet.len() - 2) as u8;
+    packet
+}
+
+fn connack_with_auth(data: &[u8]) -> Vec<u8> {
+    let mut packet = std::vec![0x20, 0x00, 0x00, 0x00];
+    packet.extend(auth_properties(data));
+    packet[1] = (packet.len() - 2) as u8;
+    packet
+}
+
+/// Connects with the `ScriptedAuthenticator`, returns whether it completed
+fn connect_authenticated(broker: MockBroker) -> (Result<(), ReasonCode>, bool) {
+    let mut authenticator = ScriptedAuthenticator::default();
+    let mut write_buffer = [0; 256];
+    let mut recv_buffer = [0; 256];
+    let mut client = client_with(
+        broker,
+        MqttVersion::MQTTv5,
+        &mut write_buffer,
+        &mut recv_buffer,
+    );
+    client.set_authenticator(Some(&mut authenticator));
+    let res = tokio_test::block_on(client.connect_to_broker());
+    drop(client);
+    (res, authenticator.completed)
+}
+
+fn availability_config<'a>() -> ClientConfig<'a, 5, CountingRng> {
+    let mut config = ClientConfig::new(MqttVersion::MQTTv5, CountingRng(0));
+    config.add_will("toucan/availability", b"offline", true);
+    config.add_birth("toucan/availability", b"online");
+    config
+}
+
+#[test]
+fn will_is_retained_offline() {
+    let broker = MockBroker::new().reply(&connack(0x00));
+    let (res, sent) = connect(broker, availability_config());
+    assert_ok!(res);
+
+    let packets = sent.packets();
+    let connect = &packets[0];
+    assert_eq!(connect[0], 0x10);
+    assert_eq!(
+        connect[CONNECT_FLAGS] & (WILL_FLAG | WILL_RETAIN),
+        WILL_FLAG | WILL_RETAIN
+    );
+    assert!(connect.ends_with(b"\x00\x13toucan/availability\x00\x07offline"));
+}
+
+#[test]
+fn birth_published_retained_after_connack() {
+    let broker = MockBroker::new().reply(&connack(0x00));
+    let (res, sent) = connect(broker, availability_config());
+    assert_ok!(res);
+
+    let packets = sent.packets();
+    assert_eq!(packets.len(), 2);
+    let birth = &packets[1];
+    // PUBLISH, QoS0, retain
+    assert_eq!(birth[0], 0x31);
+    assert_eq!(&birth[2..23], b"\x00\x13toucan/availability");
+    assert!(birth.ends_with(b"online"));
+}
+
+#[test]
+fn no_birth_when_connection_refused() {
+    // Not authorized
+    let broker = MockBroker::new().reply(&connack(0x87));
+    let (res, sent) = connect(broker, availability_config());
+    assert_err!(res);
+    assert_eq!(sent.packets().len(), 1);
+}
+
+#[test]
+fn credentials_omitted_when_not_set() {
+    let broker = MockBroker::new().reply(&connack(0x00));
+    let config = ClientConfig::new(MqttVersion::MQTTv5, CountingRng(0));
+    let (res, sent) = connect(broker, config);
+    assert_ok!(res);
+
+    let connect = &sent.packets()[0];
+    assert_eq!(connect[CONNECT_FLAGS] & (USERNAME_FLAG | PASSWORD_FLAG), 0);
+}
+
+#[test]
+fn credentials_sent_when_set() {
+    let broker = MockBroker::new().reply(&connack(0x00));
+    let mut config = ClientConfig::new(MqttVersion::MQTTv5, CountingRng(0));
+    config.add_username("user");
+    config.add_password("secret");
+    let (res, sent) = connect(broker, config);
+    assert_ok!(res);
+
+    let connect = &sent.packets()[0];
+    assert_eq!(
+        connect[CONNECT_FLAGS] & (USERNAME_FLAG | PASSWORD_FLAG),
+        USERNAME_FLAG | PASSWORD_FLAG
+    );
+    assert!(connect.ends_with(b"\x00\x04user\x00\x06secret"));
+}
+
+#[test]
+fn qos1_completes_on_puback() {
+    let broker = MockBroker::new().reply(&ack(PUBACK, FIRST_ID));
+    let (res, sent) = publish(broker, QoS1);
+    assert_ok!(res);
+    assert_eq!(sent.packets().len(), 1);
+}
+
+#[test]
+fn qos1_puback_for_other_identifier() {
+    let broker = MockBroker::new().reply(&ack(PUBACK, FIRST_ID + 1));
+    let (res, _) = publish(broker, QoS1);
+    assert_eq!(res, Err(ReasonCode::PacketIdentifierNotFound));
+}
+
+#[test]
+fn qos1_without_puback_fails() {
+    let (res, _) = publish(MockBroker::new(), QoS1);
+    assert_eq!(res, Err(ReasonCode::NetworkError));
+}
+
+#[test]
+fn qos2_answers_pubrec_with_pubrel() {
+    let broker = MockBroker::new()
+        .reply(&ack(PUBREC, FIRST_ID))
+        .reply(&ack(PUBCOMP, FIRST_ID));
+    let (res, sent) = publish(broker, QoS2);
+    assert_ok!(res);
+
+    let packets = sent.packets();
+    assert_eq!(packets.len(), 2);
+    // PUBLISH, QoS2
+    assert_eq!(packets[0][0], 0x34);
+    assert_eq!(packets[1], [0x62, 0x04, 0x00, 0x01, 0x00, 0x00]);
+}
+
+#[test]
+fn qos2_without_pubcomp_fails() {
+    let broker = MockBroker::new().reply(&ack(PUBREC, FIRST_ID));
+    let (res, sent) = publish(broker, QoS2);
+    assert_err!(res);
+    // PUBREL was still sent
+    assert_eq!(sent.packets().len(), 2);
+}
+
+#[test]
+fn qos2_rejected_pubrec() {
+    // Not authorized
+    let pubrec = [PUBREC, 0x03, 0x00, 0x01, 0x87];
+    let broker = MockBroker::new().reply(&pubrec);
+    let (res, sent) = publish(broker, QoS2);
+    assert_eq!(res, Err(ReasonCode::NotAuthorized));
+    assert_eq!(sent.packets().len(), 1);
+}
+
+#[test]
+fn v3_connect_is_protocol_level_4() {
+    // 3.1.1 CONNACK has no properties
+    let broker = MockBroker::new().reply(&[0x20, 0x02, 0x00, 0x00]);
+    let mut config = ClientConfig::new(MqttVersion::MQTTv3, CountingRng(0));
+    config.add_will("toucan/availability", b"offline", true);
+    config.add_birth("toucan/availability", b"online");
+    let (res, sent) = connect(broker, config);
+    assert_ok!(res);
+
+    let packets = sent.packets();
+    let connect = &packets[0];
+    assert_eq!(&connect[2..9], b"\x00\x04MQTT\x04");
+    // client id, then the will without a property length
+    assert!(connect.ends_with(b"\x00\x00\x00\x13toucan/availability\x00\x07offline"));
+    // birth PUBLISH has no property length between topic and payload
+    assert_eq!(packets[1][2..], *b"\x00\x13toucan/availabilityonline");
+}
+
+#[test]
+fn v3_connect_refused() {
+    // 0x05 Not authorized
+    let broker = MockBroker::new().reply(&[0x20, 0x02, 0x00, 0x05]);
+    let config = ClientConfig::new(MqttVersion::MQTTv3, CountingRng(0));
+    let (res, sent) = connect(broker, config);
+    assert_eq!(res, Err(ReasonCode::NotAuthorized));
+    assert_eq!(sent.packets().len(), 1);
+}
+
+#[test]
+fn v3_qos1_completes_on_puback() {
+    let broker = MockBroker::new().reply(&ack(PUBACK, FIRST_ID));
+    let (res, sent) = publish_with(broker, MqttVersion::MQTTv3, QoS1);
+    assert_ok!(res);
+    assert_eq!(
+        sent.packets()[0],
+        [0x32, 0x0C, 0x00, 0x06, b't', b'o', b'u', b'c', b'a', b'n', 0x00, 0x01, b'{', b'}']
+    );
+}
+
+#[test]
+fn v3_qos2_answers_pubrec_with_pubrel() {
+    let broker = MockBroker::new()
+        .reply(&ack(PUBREC, FIRST_ID))
+        .reply(&ack(PUBCOMP, FIRST_ID));
+    let (res, sent) = publish_with(broker, MqttVersion::MQTTv3, QoS2);
+    assert_ok!(res);
+    assert_eq!(sent.packets()[1], [0x62, 0x02, 0x00, 0x01]);
+}
+
+#[test]
+fn qos2_message_delivered_once() {
+    let version = MqttVersion::MQTTv5;
+    let broker = MockBroker::new()
+        .reply(&incoming(0x34, 7, b'a', &version))
+        // the broker missed the PUBREC and sends it again
+        .reply(&incoming(0x34 | DUP, 7, b'a', &version))
+        .reply(&ack(PUBREL, 7))
+        .reply(&incoming(0x30, 0, b'b', &version));
+    let sent = broker.sent();
+    assert_eq!(receive_two(broker, version), (b'a', b'b'));
+
+    let packets = sent.packets();
+    assert_eq!(packets.len(), 3);
+    assert_eq!(packets[0], [PUBREC, 0x04, 0x00, 0x07, 0x00, 0x00]);
+    assert_eq!(packets[1], packets[0]);
+    assert_eq!(packets[2], [PUBCOMP, 0x04, 0x00, 0x07, 0x00, 0x00]);
+}
+
+#[test]
+fn qos2_identifier_reused_after_pubcomp() {
+    let version = MqttVersion::MQTTv5;
+    let broker = MockBroker::new()
+        .reply(&incoming(0x34, 7, b'a', &version))
+        .reply(&ack(PUBREL, 7))
+        .reply(&incoming(0x34, 7, b'b', &version));
+    assert_eq!(receive_two(broker, version), (b'a', b'b'));
+}
+
+#[test]
+fn pubrel_for_unknown_identifier() {
+    let version = MqttVersion::MQTTv5;
+    let broker = MockBroker::new()
+        .reply(&incoming(0x30, 0, b'a', &version))
+        .reply(&ack(PUBREL, 9))
+        .reply(&incoming(0x30, 0, b'b', &version));
+    let sent = broker.sent();
+    assert_eq!(receive_two(broker, version), (b'a', b'b'));
+    // Packet identifier not found
+    assert_eq!(sent.packets()[0], [PUBCOMP, 0x04, 0x00, 0x09, 0x92, 0x00]);
+}
+
+#[test]
+fn v3_qos2_message_delivered_once() {
+    let version = MqttVersion::MQTTv3;
+    let broker = MockBroker::new()
+        .reply(&incoming(0x34, 7, b'a', &version))
+        .reply(&incoming(0x34 | DUP, 7, b'a', &version))
+        .reply(&ack(PUBREL, 7))
+        .reply(&incoming(0x30, 0, b'b', &version));
+    let sent = broker.sent();
+    assert_eq!(receive_two(broker, version), (b'a', b'b'));
+
+    let packets = sent.packets();
+    assert_eq!(packets.len(), 3);
+    assert_eq!(packets[0], [PUBREC, 0x02, 0x00, 0x07]);
+    assert_eq!(packets[1], packets[0]);
+    assert_eq!(packets[2], [PUBCOMP, 0x02, 0x00, 0x07]);
+}
+
+#[test]
+fn subscription_options_encoded() {
+    let broker = MockBroker::new().reply(&suback(FIRST_ID));
+    let sent = broker.sent();
+    let mut write_buffer = [0; 256];
+    let mut recv_buffer = [0; 256];
+    let mut client = client_with(
+        broker,
+        MqttVersion::MQTTv5,
+        &mut write_buffer,
+        &mut recv_buffer,
+    );
+    let options = SubscriptionOptions {
+        no_local: true,
+        retain_as_published: true,
+        retain_handling: RetainHandling::DoNotSend,
+    };
+    assert_ok!(tokio_test::block_on(
+        client.subscribe_to_topic_with_options("cmd/#", options)
+    ));
+    // QoS0, no local 0x04, retain as published 0x08, do not send 0x20
+    assert_eq!(sent.packets()[0].last(), Some(&0x2C));
+}
+
+#[test]
+fn v3_subscription_options_left_out() {
+    let broker = MockBroker::new().reply(&[0x90, 0x03, 0x00, 0x01, 0x00]);
+    let sent = broker.sent();
+    let mut write_buffer = [0; 256];
+    let mut recv_buffer = [0; 256];
+    let mut client = client_with(
+        broker,
+        MqttVersion::MQTTv3,
+        &mut write_buffer,
+        &mut recv_buffer,
+    );
+    let options = SubscriptionOptions {
+        no_local: true,
+        ..Default::default()
+    };
+    assert_ok!(tokio_test::block_on(
+        client.subscribe_to_topic_with_options("cmd/#", options)
+    ));
+    assert_eq!(sent.packets()[0].last(), Some(&0x00));
+}
+
+#[test]
+fn auth_exchange_on_connect() {
+    let broker = MockBroker::new()
+        .reply(&auth(CONTINUE_AUTH, b"challenge"))
+        .reply(&connack_with_auth(b"signature"));
+    let sent = broker.sent();
+    let (res, completed) = connect_authenticated(broker);
+    assert_ok!(res);
+    assert!(completed);
+
+    let packets = sent.packets();
+    assert_eq!(packets.len(), 2);
+    // CONNECT carries the method and the initial data
+    let initial = b"\x15\x00\x04TEST\x16\x00\x05first";
+    assert!(packets[0].windows(initial.len()).any(|w| w == initial));
+    assert_eq!(packets[1], auth(CONTINUE_AUTH, b"final"));
+}
+
+#[test]
+fn auth_server_signature_rejected() {
+    let broker = MockBroker::new()
+        .reply(&auth(CONTINUE_AUTH, b"challenge"))
+        .reply(&connack_with_auth(b"forged"));
+    let (res, completed) = connect_authenticated(broker);
+    assert_eq!(res, Err(ReasonCode::NotAuthorized));
+    assert!(!completed);
+}
+
+#[test]
+fn auth_challenge_without_authenticator() {
+    let broker = MockBroker::new().reply(&auth(CONTINUE_AUTH, b"challenge"));
+    let config = ClientConfig::new(MqttVersion::MQTTv5, CountingRng(0));
+    let (res, sent) = connect(broker, config);
+    assert_eq!(res, Err(ReasonCode::ProtocolError));
+    assert_eq!(sent.packets().len(), 1);
+}
+
+#[test]
+fn reauthenticate() {
+    let broker = MockBroker::new()
+        .reply(&auth(CONTINUE_AUTH, b"challenge"))
+        .reply(&auth(0x00, b"signature"));
+    let sent = broker.sent();
+    let mut authenticator = ScriptedAuthenticator::default();
+    let mut write_buffer = [0; 256];
+    let mut recv_buffer = [0; 256];
+    let mut client = client_with(
+        broker,
+        MqttVersion::MQTTv5,
+        &mut write_buffer,
+        &mut recv_buffer,
+    );
+    client.set_authenticator(Some(&mut authenticator));
+    assert_ok!(tokio_test::block_on(client.reauthenticate()));
+    drop(client);
+    assert!(authenticator.completed);
+
+    let packets = sent.packets();
+    assert_eq!(packets.len(), 2);
+    assert_eq!(packets[0], auth(REAUTHENTICATE, b"first"));
+    assert_eq!(packets[1], auth(CONTINUE_AUTH, b"final"));
+}
+
+#[test]
+fn v3_reauthenticate_unsupported() {
+    let mut authenticator = ScriptedAuthenticator::default();
+    let mut write_buffer = [0; 256];
+    let mut recv_buffer = [0; 256];
+    let mut client = client_with(
+        MockBroker::new(),
+        MqttVersion::MQTTv3,
+        &mut write_buffer,
+        &mut recv_buffer,
+    );
+    client.set_authenticator(Some(&mut authenticator));
+    assert_eq!(
+        tokio_test::block_on(client.reauthenticate()),
+        Err(ReasonCode::BadAuthMethod)
+    );
+}
+
+#[test]
+fn topic_alias_replaces_repeated_topic() {
+    // CONNACK with Topic Alias Maximum 2
+    let broker = MockBroker::new().reply(&[0x20, 0x06, 0x00, 0x00, 0x03, 0x22, 0x00, 0x02]);
+    let sent = broker.sent();
+    let mut write_buffer = [0; 256];
+    let mut recv_buffer = [0; 256];
+    let mut client = client_with(
+        broker,
+        MqttVersion::MQTTv5,
+        &mut write_buffer,
+        &mut recv_buffer,
+    );
+    assert_ok!(tokio_test::block_on(client.connect_to_broker()));
+    for _ in 0..2 {
+        assert_ok!(tokio_test::block_on(
+            client.send_message("toucan", b"{}", QoS0, false)
+        ));
+    }
+
+    let packets = sent.packets();
+    // topic and Topic Alias 1, then the alias with an empty topic
+    assert_eq!(packets[1], b"\x30\x0E\x00\x06toucan\x03\x23\x00\x01{}");
+    assert_eq!(packets[2], b"\x30\x08\x00\x00\x03\x23\x00\x01{}");
+}
+
+#[test]
+fn no_topic_alias_without_broker_maximum() {
+    let broker = MockBroker::new().reply(&connack(0x00));
+    let sent = broker.sent();
+    let mut write_buffer = [0; 256];
+    let mut recv_buffer = [0; 256];
+    let mut client = client_with(
+        broker,
+        MqttVersion::MQTTv5,
+        &mut write_buffer,
+        &mut recv_buffer,
+    );
+    assert_ok!(tokio_test::block_on(client.connect_to_broker()));
+    for _ in 0..2 {
+        assert_ok!(tokio_test::block_on(
+            client.send_message("toucan", b"{}", QoS0, false)
+        ));
+    }
+
+    let packets = sent.packets();
+    assert_eq!(packets[1], b"\x30\x0B\x00\x06toucan\x00{}");
+    assert_eq!(packets[2], packets[1]);
+}
+
+#[test]
+fn publish_options_encoded() {
+    let options = PublishOptions {
+        message_expiry: Some(600),
+        content_type: Some("application/json"),
+    };
+    let sent = publish_options(MqttVersion::MQTTv5, options);
+    assert_eq!(
+        sent.packets()[0],
+        b"\x30\x23\x00\x06toucan\x18\x02\x00\x00\x02\x58\x03\x00\x10application/json{}"
+    );
+}
+
+#[test]
+fn v3_publish_options_left_out() {
+    let options = PublishOptions {
+        message_expiry: Some(600),
+        content_type: Some("application/json"),
+    };
+    let sent = publish_options(MqttVersion::MQTTv3, options);
+    assert_eq!(sent.packets()[0], b"\x30\x0A\x00\x06toucan{}");
+}
+
+#[test]
+fn streamed_publish_larger_than_buffer() {
+    // remaining length 1000 + topic (+ no properties) takes two bytes
+    let cases: [(MqttVersion, &[u8]); 2] = [
+        (MqttVersion::MQTTv5, b"\x30\xF1\x07\x00\x06toucan\x00"),
+        (MqttVersion::MQTTv3, b"\x30\xF0\x07\x00\x06toucan"),
+    ];
+    for (version, header) in cases {
+        let broker = MockBroker::new();
+        let sent = broker.sent();
+        let mut write_buffer = [0; 256];
+        let mut recv_buffer = [0; 256];
+        let mut client = client_with(broker, version, &mut write_buffer, &mut recv_buffer);
+        let message: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let mut payload = message.as_slice();
+        assert_ok!(tokio_test::block_on(client.send_message_streamed(
+            "toucan",
+            &mut payload,
+            QoS0,
+            false,
+            PublishOptions::default()
+        )));
+
+        let packets = sent.packets();
+        assert_eq!(packets.len(), 1);
+        let (sent_header, body) = packets[0].split_at(header.len());
+        assert_eq!(sent_header, header);
+        assert_eq!(body, message.as_slice());
+    }
+}
+
+#[test]
+fn streamed_publish_ending_early() {
+    let mut write_buffer = [0; 256];
+    let mut recv_buffer = [0; 256];
+    let mut client = client_with(
+        MockBroker::new(),
+        MqttVersion::MQTTv5,
+        &mut write_buffer,
+        &mut recv_buffer,
+    );
+    let parts: [&[u8]; 1] = [&[0; 300]];
+    let mut payload = Chunks::new(600, parts.iter().copied());
+    assert_eq!(
+        tokio_test::block_on(client.send_message_streamed(
+            "toucan",
+            &mut payload,
+            QoS0,
+            false,
+            PublishOptions::default()
+        )),
+        Err(ReasonCode::MalformedPacket)
+    );
+}
+
+/// Hands `bytes` to a client waiting for messages until it fails, which it has to do
+/// with a reason code once the script runs out
+fn receive_all(version: MqttVersion, bytes: &[u8]) {
+    let broker = MockBroker::new().reply(bytes);
+    let mut write_buffer = [0; 256];
+    let mut recv_buffer = [0; 256];
+    let mut client = client_with(broker, version, &mut write_buffer, &mut recv_buffer);
+    while tokio_test::block_on(client.receive_message()).is_ok() {}
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(1024))]
+
+    #[test]
+    fn malformed_broker_bytes(bytes in prop::collection::vec(any::<u8>(), 0..128)) {
+        receive_all(MqttVersion::MQTTv5, &bytes);
+        receive_all(MqttVersion::MQTTv3, &bytes);
+    }
+
+    /// A PUBLISH with a random body and a remaining length that matches it
+    #[test]
+    fn malformed_publish(body in prop::collection::vec(any::<u8>(), 0..100)) {
+        let mut bytes = std::vec![0x32, body.len() as u8];
+        bytes.extend(body);
+        receive_all(MqttVersion::MQTTv5, &bytes);
+        receive_all(MqttVersion::MQTTv3, &bytes);
+    }
+}
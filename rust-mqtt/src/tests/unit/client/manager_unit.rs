@@ -0,0 +1,433 @@
+/*
+ * MIT License
+ *
+ * Copyright (c) [2022] [Ondrej Babec <ond.babec@gmail.com>]
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+extern crate std;
+
+use core::cell::Cell;
+use core::future::{poll_fn, ready};
+use core::task::Poll;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use embassy_futures::select::Either;
+
+use crate::client::client_config::{ClientConfig, MqttVersion};
+use crate::client::manager::{Clock, ConnectionManager, Connector, ManagerEvent};
+use crate::packet::v5::publish_packet::QualityOfService::{QoS0, QoS1, QoS2};
+use crate::packet::v5::reason_codes::ReasonCode;
+use crate::packet::v5::subscription_packet::{RetainHandling, SubscriptionOptions};
+use crate::tests::unit::client::mock_broker::{
+    ack, connack, suback, MockBroker, SentLog, PINGREQ, PINGRESP,
+};
+use crate::utils::rng_generator::CountingRng;
+
+const CONNECT: u8 = 0x10;
+const SUBSCRIBE: u8 = 0x82;
+const PUBACK: u8 = 0x40;
+const PUBREC: u8 = 0x50;
+const PUBREL: u8 = 0x62;
+const PUBCOMP: u8 = 0x70;
+// "cmd/x" with payload "hi" at QoS0
+const PUBLISH: [u8; 12] = [
+    0x30, 0x0A, 0x00, 0x05, b'c', b'm', b'd', b'/', b'x', 0x00, b'h', b'i',
+];
+// the same at QoS1 and QoS2 with packet identifier 7
+const PUBLISH_QOS1: [u8; 14] = [
+    0x32, 0x0C, 0x00, 0x05, b'c', b'm', b'd', b'/', b'x', 0x00, 0x07, 0x00, b'h', b'i',
+];
+const PUBLISH_QOS2: [u8; 14] = [
+    0x34, 0x0C, 0x00, 0x05, b'c', b'm', b'd', b'/', b'x', 0x00, 0x07, 0x00, b'h', b'i',
+];
+
+/// Hands out the scripted brokers in order, `None` is a refused connection
+struct MockConnector {
+    brokers: VecDeque<Option<MockBroker>>,
+    attempts: Rc<Cell<usize>>,
+}
+
+impl Connector for MockConnector {
+    type Connection = MockBroker;
+
+    async fn connect(&mut self) -> Result<MockBroker, ReasonCode> {
+        self.attempts.set(self.attempts.get() + 1);
+        self.brokers
+            .pop_front()
+            .flatten()
+            .ok_or(ReasonCode::NetworkError)
+    }
+}
+
+/// Virtual time that jumps to the deadline when a sleep is polled a second time,
+/// so a ready broker read in the same select wins
+#[derive(Clone, Default)]
+struct MockClock(Rc<Cell<u64>>);
+
+impl Clock for MockClock {
+    fn now_ms(&self) -> u64 {
+        self.0.get()
+    }
+
+    async fn sleep_until(&mut self, deadline_ms: u64) {
+        let mut yielded = false;
+        poll_fn(|cx| {
+            if yielded || self.0.get() >= deadline_ms {
+                self.0.set(self.0.get().max(deadline_ms));
+                Poll::Ready(())
+            } else {
+                yielded = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+/// Runs `test` against a manager with the given brokers and keep alive in seconds
+fn with_manager<F>(brokers: Vec<Option<MockBroker>>, keep_alive: u16, test: F)
+where
+    F: FnOnce(
+        &mut ConnectionManager<'_, MockConnector, MockClock, 5, CountingRng, 2>,
+        &MockClock,
+        &Rc<Cell<usize>>,
+    ),
+{
+    let attempts = Rc::new(Cell::new(0));
+    let clock = MockClock::default();
+    let connector = MockConnector {
+        brokers: brokers.into(),
+        attempts: attempts.clone(),
+    };
+    let mut write_buffer = [0; 256];
+    let mut recv_buffer = [0; 256];
+    let mut inbox = [0; 64];
+    let mut config = ClientConfig::<5, _>::new(MqttVersion::MQTTv5, CountingRng(0));
+    config.keep_alive = keep_alive;
+    let mut manager = ConnectionManager::new(
+        connector,
+        clock.clone(),
+        &mut write_buffer,
+        256,
+        &mut recv_buffer,
+        256,
+        &mut inbox,
+        config,
+    );
+    test(&mut manager, &clock, &attempts);
+}
+
+fn poll(
+    manager: &mut ConnectionManager<'_, MockConnector, MockClock, 5, CountingRng, 2>,
+) -> Result<(), ReasonCode> {
+    match tokio_test::block_on(manager.poll()) {
+        ManagerEvent::Connected | ManagerEvent::Idle => Ok(()),
+        ManagerEvent::Disconnected(reason) => Err(reason),
+        ManagerEvent::Message(_, _) => Ok(()),
+    }
+}
+
+fn expect_message(
+    manager: &mut ConnectionManager<'_, MockConnector, MockClock, 5, CountingRng, 2>,
+) {
+    match tokio_test::block_on(manager.poll()) {
+        ManagerEvent::Message(topic, payload) => {
+            assert_eq!(topic, "cmd/x");
+            assert_eq!(payload, b"hi");
+        }
+        _ => panic!("expected the message"),
+    }
+}
+
+/// Whether the client sent an acknowledgement of `packet_type` for `identifier`
+fn acknowledged(sent: &SentLog, packet_type: u8, identifier: u16) -> bool {
+    sent.packets()
+        .iter()
+        .any(|p| p[0] == packet_type && p[2..4] == identifier.to_be_bytes())
+}
+
+fn count(sent: &SentLog, packet: &[u8]) -> usize {
+    sent.packets()
+        .iter()
+        .filter(|p| p.as_slice() == packet)
+        .count()
+}
+
+#[test]
+fn test_manager_resubscribes_after_broker_restart() {
+    let first = MockBroker::new().reply(&connack(0)).reply(&suback(1));
+    let second = MockBroker::new()
+        .reply(&connack(0))
+        .reply(&suback(2))
+        .reply(&PUBLISH)
+        .stay_open();
+    let sent = second.sent();
+    with_manager(
+        vec![Some(first), Some(second)],
+        60,
+        |manager, _, attempts| {
+            assert_eq!(tokio_test::block_on(manager.subscribe("cmd/#")), Ok(()));
+            assert!(matches!(
+                tokio_test::block_on(manager.poll()),
+                ManagerEvent::Connected
+            ));
+            // the first broker goes away
+            assert_eq!(poll(manager), Err(ReasonCode::NetworkError));
+            assert!(matches!(
+                tokio_test::block_on(manager.poll()),
+                ManagerEvent::Connected
+            ));
+            match tokio_test::block_on(manager.poll()) {
+                ManagerEvent::Message(topic, payload) => {
+                    assert_eq!(topic, "cmd/x");
+                    assert_eq!(payload, b"hi");
+                }
+                _ => panic!("expected the message"),
+            }
+            assert_eq!(attempts.get(), 2);
+        },
+    );
+    let packets = sent.packets();
+    assert_eq!(packets[0][0], CONNECT);
+    assert_eq!(packets[1][0], SUBSCRIBE);
+}
+
+#[test]
+fn test_manager_resubscribes_with_options() {
+    let first = MockBroker::new().reply(&connack(0)).reply(&suback(1));
+    let second = MockBroker::new()
+        .reply(&connack(0))
+        .reply(&suback(2))
+        .stay_open();
+    let sent = second.sent();
+    with_manager(vec![Some(first), Some(second)], 60, |manager, _, _| {
+        let options = SubscriptionOptions {
+            no_local: true,
+            retain_handling: RetainHandling::DoNotSend,
+            ..Default::default()
+        };
+        assert_eq!(
+            tokio_test::block_on(manager.subscribe_with_options("cmd/#", options)),
+            Ok(())
+        );
+        assert_eq!(poll(manager), Ok(()));
+        assert_eq!(poll(manager), Err(ReasonCode::NetworkError));
+        assert_eq!(poll(manager), Ok(()));
+    });
+    // no local 0x04, do not send 0x20
+    assert_eq!(sent.packets()[1].last(), Some(&0x24));
+}
+
+#[test]
+fn test_manager_backoff_doubles() {
+    let broker = MockBroker::new().reply(&connack(0)).stay_open();
+    with_manager(
+        vec![None, None, None, Some(broker)],
+        60,
+        |manager, clock, _| {
+            manager.set_backoff(1_000, 60_000);
+            // retries at 0, 1s, 3s and 7s
+            for _ in 0..3 {
+                assert_eq!(poll(manager), Err(ReasonCode::NetworkError));
+            }
+            assert_eq!(clock.now_ms(), 3_000);
+            assert_eq!(poll(manager), Ok(()));
+            assert!(manager.is_connected());
+            assert_eq!(clock.now_ms(), 7_000);
+        },
+    );
+}
+
+#[test]
+fn test_manager_backoff_capped() {
+    let broker = MockBroker::new().reply(&connack(0)).stay_open();
+    with_manager(
+        vec![None, None, None, Some(broker)],
+        60,
+        |manager, clock, _| {
+            manager.set_backoff(1_000, 1_500);
+            for _ in 0..3 {
+                assert_eq!(poll(manager), Err(ReasonCode::NetworkError));
+            }
+            assert_eq!(poll(manager), Ok(()));
+            assert_eq!(clock.now_ms(), 4_000);
+        },
+    );
+}
+
+#[test]
+fn test_manager_pings_on_keep_alive() {
+    let broker = MockBroker::new()
+        .reply(&connack(0))
+        .stay_open()
+        .answer_pings();
+    let sent = broker.sent();
+    with_manager(vec![Some(broker)], 10, |manager, clock, attempts| {
+        assert_eq!(poll(manager), Ok(()));
+        // wait for the keep alive, ping, PINGRESP, twice
+        for _ in 0..4 {
+            assert_eq!(poll(manager), Ok(()));
+        }
+        assert_eq!(clock.now_ms(), 20_000);
+        assert!(manager.is_connected());
+        assert_eq!(attempts.get(), 1);
+    });
+    assert_eq!(count(&sent, &PINGREQ), 2);
+}
+
+#[test]
+fn test_manager_drops_connection_without_pingresp() {
+    let broker = MockBroker::new().reply(&connack(0)).stay_open();
+    with_manager(vec![Some(broker), None], 10, |manager, clock, _| {
+        assert_eq!(poll(manager), Ok(()));
+        // keep alive expires, PINGREQ goes out, half the keep alive passes without an answer
+        assert_eq!(poll(manager), Ok(()));
+        assert_eq!(poll(manager), Ok(()));
+        assert_eq!(poll(manager), Err(ReasonCode::KeepAliveTimeout));
+        assert_eq!(clock.now_ms(), 15_000);
+        assert!(!manager.is_connected());
+    });
+}
+
+#[test]
+fn test_manager_publish_accepts_pingresp_before_puback() {
+    let broker = MockBroker::new()
+        .reply(&connack(0))
+        .reply(&PINGRESP)
+        .reply(&ack(PUBACK, 1))
+        .stay_open();
+    with_manager(vec![Some(broker)], 60, |manager, _, _| {
+        assert_eq!(poll(manager), Ok(()));
+        let result = tokio_test::block_on(manager.publish("state", b"{}", QoS1, false));
+        assert_eq!(result, Ok(()));
+        assert!(manager.is_connected());
+    });
+}
+
+#[test]
+fn test_manager_publish_while_disconnected() {
+    with_manager(vec![None], 60, |manager, _, _| {
+        let result = tokio_test::block_on(manager.publish("state", b"{}", QoS0, false));
+        assert_eq!(result, Err(ReasonCode::NetworkError));
+    });
+}
+
+#[test]
+fn test_manager_poll_or_keeps_a_partial_packet() {
+    let broker = MockBroker::new()
+        .reply(&connack(0))
+        .reply(&PUBLISH[..5])
+        .stay_open();
+    let replies = broker.replies();
+    with_manager(vec![Some(broker)], 60, |manager, _, _| {
+        assert_eq!(poll(manager), Ok(()));
+        // the first part of the message is read before the wake interrupts the wait
+        assert!(matches!(
+            tokio_test::block_on(manager.poll_or(ready(()))),
+            Either::Second(())
+        ));
+        replies.push(&PUBLISH[5..]);
+        match tokio_test::block_on(manager.poll()) {
+            ManagerEvent::Message(topic, payload) => {
+                assert_eq!(topic, "cmd/x");
+                assert_eq!(payload, b"hi");
+            }
+            _ => panic!("expected the message"),
+        }
+        assert!(manager.is_connected());
+    });
+}
+
+#[test]
+fn test_manager_queues_messages_until_the_inbox_is_full() {
+    // 13 bytes each in the inbox, four fit
+    let mut broker = MockBroker::new().reply(&connack(0));
+    for _ in 0..5 {
+        broker = broker.reply(&PUBLISH);
+    }
+    let broker = broker.reply(&ack(PUBACK, 1)).stay_open();
+    with_manager(vec![Some(broker)], 60, |manager, _, _| {
+        assert_eq!(poll(manager), Ok(()));
+        let result = tokio_test::block_on(manager.publish("state", b"{}", QoS1, false));
+        assert_eq!(result, Ok(()));
+        for _ in 0..4 {
+            match tokio_test::block_on(manager.poll()) {
+                ManagerEvent::Message(topic, payload) => {
+                    assert_eq!(topic, "cmd/x");
+                    assert_eq!(payload, b"hi");
+                }
+                _ => panic!("expected a queued message"),
+            }
+        }
+        assert!(matches!(
+            tokio_test::block_on(manager.poll()),
+            ManagerEvent::Idle
+        ));
+    });
+}
+
+#[test]
+fn test_manager_keeps_a_qos1_command_sent_during_a_publish() {
+    let broker = MockBroker::new()
+        .reply(&connack(0))
+        .reply(&PUBLISH_QOS1)
+        .reply(&ack(PUBACK, 1))
+        .stay_open();
+    let sent = broker.sent();
+    with_manager(vec![Some(broker)], 60, |manager, _, _| {
+        assert_eq!(poll(manager), Ok(()));
+        let result = tokio_test::block_on(manager.publish("state", b"{}", QoS1, false));
+        assert_eq!(result, Ok(()));
+        expect_message(manager);
+    });
+    assert!(acknowledged(&sent, PUBACK, 7));
+}
+
+#[test]
+fn test_manager_keeps_a_qos2_command_sent_during_a_publish() {
+    let broker = MockBroker::new()
+        .reply(&connack(0))
+        .reply(&ack(PUBREC, 1))
+        .reply(&PUBLISH_QOS2)
+        .reply(&ack(PUBCOMP, 1))
+        .stay_open();
+    let sent = broker.sent();
+    let replies = broker.replies();
+    with_manager(vec![Some(broker)], 60, |manager, _, _| {
+        assert_eq!(poll(manager), Ok(()));
+        let result = tokio_test::block_on(manager.publish("state", b"{}", QoS2, false));
+        assert_eq!(result, Ok(()));
+        expect_message(manager);
+        // the broker completes the exchange, the command is not returned again
+        replies.push(&PUBLISH_QOS2);
+        replies.push(&ack(PUBREL, 7));
+        assert_eq!(poll(manager), Ok(()));
+        assert_eq!(poll(manager), Ok(()));
+        assert!(matches!(
+            tokio_test::block_on(manager.poll()),
+            ManagerEvent::Idle
+        ));
+    });
+    assert!(acknowledged(&sent, PUBREC, 7));
+    assert!(acknowledged(&sent, PUBCOMP, 7));
+}
@@ -0,0 +1,180 @@
+/*
+ * MIT License
+ *
+ * Copyright (c) [2022] [Ondrej Babec <ond.babec@gmail.com>]
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+extern crate std;
+
+use core::cell::RefCell;
+use core::convert::Infallible;
+use core::future::poll_fn;
+use core::task::Poll;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::vec::Vec;
+
+use embedded_io_async::{ErrorType, Read, Write};
+
+/// In-memory stand-in for a broker connection. Replies are scripted up front and handed
+/// to the client in order, everything the client writes is recorded in a shared `SentLog`.
+/// Once the script runs out reads return 0, which the client treats as a dropped connection,
+/// unless the broker was told to `stay_open`.
+#[derive(Default)]
+pub struct MockBroker {
+    replies: Replies,
+    sent: SentLog,
+    stay_open: bool,
+    answer_pings: bool,
+}
+
+/// Bytes written by the client, readable while the client still owns the `MockBroker`
+#[derive(Clone, Default)]
+pub struct SentLog(Rc<RefCell<Vec<u8>>>);
+
+/// Bytes the broker has yet to send, more can be queued while the client owns the `MockBroker`
+#[derive(Clone, Default)]
+pub struct Replies(Rc<RefCell<VecDeque<u8>>>);
+
+impl MockBroker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues raw packet bytes the broker will send to the client
+    pub fn reply(self, packet: &[u8]) -> Self {
+        self.replies.push(packet);
+        self
+    }
+
+    /// Reads wait instead of returning 0 once the script has run out
+    pub fn stay_open(mut self) -> Self {
+        self.stay_open = true;
+        self
+    }
+
+    /// Every PINGREQ the client writes queues a PINGRESP
+    pub fn answer_pings(mut self) -> Self {
+        self.answer_pings = true;
+        self
+    }
+
+    pub fn sent(&self) -> SentLog {
+        self.sent.clone()
+    }
+
+    pub fn replies(&self) -> Replies {
+        self.replies.clone()
+    }
+}
+
+impl Replies {
+    /// Queues raw bytes after the ones already scripted
+    pub fn push(&self, bytes: &[u8]) {
+        self.0.borrow_mut().extend(bytes);
+    }
+}
+
+impl SentLog {
+    /// Splits the recorded client output into packets using the fixed header
+    pub fn packets(&self) -> Vec<Vec<u8>> {
+        let sent = self.0.borrow();
+        let mut packets = Vec::new();
+        let mut pos = 0;
+        while pos < sent.len() {
+            let mut len: usize = 0;
+            let mut shift = 0;
+            let mut i = pos + 1;
+            loop {
+                let byte = sent[i];
+                len |= ((byte & 0x7F) as usize) << shift;
+                shift += 7;
+                i += 1;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+            }
+            packets.push(sent[pos..i + len].to_vec());
+            pos = i + len;
+        }
+        packets
+    }
+}
+
+impl ErrorType for MockBroker {
+    type Error = Infallible;
+}
+
+impl Read for MockBroker {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if self.stay_open {
+            // nothing wakes this, the test relies on the other branch of a select
+            poll_fn(|_| {
+                if self.replies.0.borrow().is_empty() {
+                    Poll::Pending
+                } else {
+                    Poll::Ready(())
+                }
+            })
+            .await;
+        }
+        let mut replies = self.replies.0.borrow_mut();
+        let mut len = 0;
+        while len < buf.len() {
+            match replies.pop_front() {
+                Some(byte) => buf[len] = byte,
+                None => break,
+            }
+            len += 1;
+        }
+        Ok(len)
+    }
+}
+
+impl Write for MockBroker {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.sent.0.borrow_mut().extend_from_slice(buf);
+        if self.answer_pings && buf == PINGREQ {
+            self.replies.push(&PINGRESP);
+        }
+        Ok(buf.len())
+    }
+}
+
+pub const PINGREQ: [u8; 2] = [0xC0, 0x00];
+pub const PINGRESP: [u8; 2] = [0xD0, 0x00];
+
+/// CONNACK with the given reason code and no properties
+pub fn connack(reason_code: u8) -> [u8; 5] {
+    [0x20, 0x03, 0x00, reason_code, 0x00]
+}
+
+/// PUBACK, PUBREC or PUBCOMP in the short form brokers send on success
+pub fn ack(packet_type: u8, identifier: u16) -> [u8; 4] {
+    let [msb, lsb] = identifier.to_be_bytes();
+    [packet_type, 0x02, msb, lsb]
+}
+
+/// SUBACK granting QoS0 to a single topic
+pub fn suback(identifier: u16) -> [u8; 6] {
+    let [msb, lsb] = identifier.to_be_bytes();
+    [0x90, 0x04, msb, lsb, 0x00, 0x00]
+}
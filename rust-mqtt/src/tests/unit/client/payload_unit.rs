@@ -0,0 +1,96 @@
+/*
+ * MIT License
+ *
+ * Copyright (c) [2022] [Ondrej Babec <ond.babec@gmail.com>]
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+extern crate std;
+
+use core::fmt::Write;
+use std::vec::Vec;
+
+use crate::client::payload::{Chunks, Formatted, Payload};
+
+/// Drains the payload through a buffer of `chunk_len` bytes
+fn drain(payload: &mut dyn Payload, chunk_len: usize) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut buf = std::vec![0; chunk_len];
+    while payload.remaining() > 0 {
+        let len = payload.write_chunk(&mut buf);
+        assert!(len > 0);
+        out.extend_from_slice(&buf[..len]);
+    }
+    out
+}
+
+#[test]
+fn test_slice() {
+    let mut payload: &[u8] = b"0123456789";
+    assert_eq!(payload.remaining(), 10);
+    assert_eq!(drain(&mut payload, 3), b"0123456789");
+    assert_eq!(payload.remaining(), 0);
+}
+
+#[test]
+fn test_chunks_across_buffers() {
+    let parts: [&[u8]; 4] = [b"{\"mv\":[", b"", b"3301,3302", b"]}"];
+    for chunk_len in [1, 4, 7, 64] {
+        let mut payload = Chunks::new(18, parts.iter().copied());
+        assert_eq!(drain(&mut payload, chunk_len), b"{\"mv\":[3301,3302]}");
+    }
+}
+
+#[test]
+fn test_chunks_shorter_than_len() {
+    let parts: [&[u8]; 1] = [b"abc"];
+    let mut payload = Chunks::new(5, parts.iter().copied());
+    let mut buf = [0; 8];
+    assert_eq!(payload.write_chunk(&mut buf), 3);
+    assert_eq!(payload.write_chunk(&mut buf), 0);
+    assert_eq!(payload.remaining(), 2);
+}
+
+#[test]
+fn test_formatted_length() {
+    let payload = Formatted::new(|w| write!(w, "{{\"soc\":{}}}", 87)).unwrap();
+    assert_eq!(payload.remaining(), 10);
+}
+
+#[test]
+fn test_formatted_chunks() {
+    let render = |w: &mut dyn Write| {
+        w.write_str("[")?;
+        for cell in 0..96 {
+            if cell > 0 {
+                w.write_str(",")?;
+            }
+            write!(w, "{}", 3300 + cell)?;
+        }
+        w.write_str("]")
+    };
+    let mut expected = std::string::String::new();
+    render(&mut expected).unwrap();
+    for chunk_len in [1, 5, 64, 1024] {
+        let mut payload = Formatted::new(render).unwrap();
+        assert_eq!(payload.remaining(), expected.len());
+        assert_eq!(drain(&mut payload, chunk_len), expected.as_bytes());
+    }
+}
@@ -0,0 +1,97 @@
+/*
+ * MIT License
+ *
+ * Copyright (c) [2022] [Ondrej Babec <ond.babec@gmail.com>]
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use crate::client::topic_alias::{Alias, TopicAliases, MAX_ALIAS_TOPIC_LEN, MAX_TOPIC_ALIASES};
+
+#[test]
+fn test_alias_after_first_use() {
+    let mut aliases = TopicAliases::new();
+    aliases.reset(10);
+    assert_eq!(aliases.alias("toucan"), Alias::New(1));
+    assert_eq!(aliases.alias("toucan"), Alias::Known(1));
+    assert_eq!(aliases.alias("toucan/cells"), Alias::New(2));
+    assert_eq!(aliases.alias("toucan"), Alias::Known(1));
+}
+
+#[test]
+fn test_disabled_by_default() {
+    let mut aliases = TopicAliases::new();
+    assert_eq!(aliases.alias("toucan"), Alias::None);
+    assert_eq!(aliases.alias("toucan"), Alias::None);
+}
+
+#[test]
+fn test_broker_maximum_reuses_least_recent() {
+    let mut aliases = TopicAliases::new();
+    aliases.reset(2);
+    assert_eq!(aliases.alias("a"), Alias::New(1));
+    assert_eq!(aliases.alias("b"), Alias::New(2));
+    assert_eq!(aliases.alias("a"), Alias::Known(1));
+    // b was used least recently
+    assert_eq!(aliases.alias("c"), Alias::New(2));
+    assert_eq!(aliases.alias("b"), Alias::New(1));
+    assert_eq!(aliases.alias("c"), Alias::Known(2));
+}
+
+#[test]
+fn test_table_limit() {
+    let mut aliases = TopicAliases::new();
+    aliases.reset(u16::MAX);
+    let topics = ["0", "1", "2", "3", "4", "5", "6", "7", "8"];
+    for (i, topic) in topics.iter().enumerate().take(MAX_TOPIC_ALIASES) {
+        assert_eq!(aliases.alias(topic), Alias::New(i as u16 + 1));
+    }
+    assert_eq!(aliases.alias(topics[MAX_TOPIC_ALIASES]), Alias::New(1));
+}
+
+#[test]
+fn test_long_topic_sent_in_full() {
+    let mut aliases = TopicAliases::new();
+    aliases.reset(10);
+    let topic = "t".repeat(MAX_ALIAS_TOPIC_LEN + 1);
+    assert_eq!(aliases.alias(&topic), Alias::None);
+    assert_eq!(aliases.alias(""), Alias::None);
+}
+
+#[test]
+fn test_reset_forgets() {
+    let mut aliases = TopicAliases::new();
+    aliases.reset(10);
+    assert_eq!(aliases.alias("toucan"), Alias::New(1));
+    aliases.reset(10);
+    assert_eq!(aliases.alias("toucan"), Alias::New(1));
+}
+
+#[test]
+fn test_forget() {
+    let mut aliases = TopicAliases::new();
+    aliases.reset(10);
+    assert_eq!(aliases.alias("a"), Alias::New(1));
+    assert_eq!(aliases.alias("b"), Alias::New(2));
+    aliases.forget(2);
+    assert_eq!(aliases.alias("b"), Alias::New(3));
+    aliases.forget(0);
+    aliases.forget(42);
+    assert_eq!(aliases.alias("a"), Alias::Known(1));
+}
@@ -27,6 +27,8 @@ use crate::encoding::variable_byte_integer::{
 };
 use crate::utils::types::BufferError;
 
+use proptest::prelude::*;
+
 #[test]
 fn test_decode() {
     static BUFFER: VariableByteInteger = [0x81, 0x81, 0x81, 0x01];
@@ -78,3 +80,23 @@ fn test_encode_max() {
     assert!(encoded.is_err());
     assert_eq!(encoded.unwrap_err(), BufferError::EncodingError);
 }
+
+proptest! {
+    #[test]
+    fn test_roundtrip(value in 0..=268_435_455u32) {
+        let encoded = VariableByteIntegerEncoder::encode(value).unwrap();
+        let len = VariableByteIntegerEncoder::len(encoded);
+        prop_assert!(encoded[len..].iter().all(|byte| *byte == 0));
+        prop_assert_eq!(VariableByteIntegerDecoder::decode(encoded), Ok(value));
+    }
+
+    #[test]
+    fn test_decode_any(encoded in any::<[u8; 4]>()) {
+        // Overlong encodings like 0x80 0x00 decode too, encoding again is never longer
+        if let Ok(value) = VariableByteIntegerDecoder::decode(encoded) {
+            let len = VariableByteIntegerEncoder::len(encoded);
+            let reencoded = VariableByteIntegerEncoder::encode(value).unwrap();
+            prop_assert!(VariableByteIntegerEncoder::len(reencoded) <= len);
+        }
+    }
+}
@@ -22,6 +22,8 @@
  * SOFTWARE.
  */
 
+pub mod client;
 pub mod encoding;
+pub mod network;
 pub mod packet;
 pub mod utils;
@@ -0,0 +1,272 @@
+/*
+ * MIT License
+ *
+ * Copyright (c) [2022] [Ondrej Babec <ond.babec@gmail.com>]
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::task::Poll;
+use std::collections::VecDeque;
+use std::io::{Read as _, Write as _};
+use std::net::{TcpListener, TcpStream};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use embedded_io_async::{ErrorKind, ErrorType, Read, Write};
+use rand_core::{CryptoRng, RngCore};
+use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa, KeyPair};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+
+use crate::client::client::MqttClient;
+use crate::client::client_config::{ClientConfig, MqttVersion};
+use crate::network::tls::{
+    open_tls, RecordGate, TlsAuth, TlsClock, TlsOptions, TLS_READ_BUFFER_LEN, TLS_WRITE_BUFFER_LEN,
+};
+use crate::utils::rng_generator::CountingRng;
+
+const CONNECT: u8 = 0x10;
+const CONNACK: [u8; 5] = [0x20, 0x03, 0x00, 0x00, 0x00];
+
+/// Blocking std socket, the broker runs on its own thread so blocking the test is fine
+struct HostTcp(TcpStream);
+
+impl ErrorType for HostTcp {
+    type Error = ErrorKind;
+}
+
+impl Read for HostTcp {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.0.read(buf).map_err(|_| ErrorKind::Other)
+    }
+}
+
+impl Write for HostTcp {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.0.write(buf).map_err(|_| ErrorKind::Other)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.0.flush().map_err(|_| ErrorKind::Other)
+    }
+}
+
+/// Socket that hands out the queued segments and waits once they run out
+#[derive(Clone, Default)]
+struct Segments(Rc<RefCell<VecDeque<Vec<u8>>>>);
+
+impl ErrorType for Segments {
+    type Error = ErrorKind;
+}
+
+impl Read for Segments {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        // nothing wakes this, the test polls by hand
+        let segment = poll_fn(|_| match self.0.borrow_mut().pop_front() {
+            Some(segment) => Poll::Ready(segment),
+            None => Poll::Pending,
+        })
+        .await;
+        buf[..segment.len()].copy_from_slice(&segment);
+        Ok(segment.len())
+    }
+}
+
+impl Write for Segments {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        Ok(buf.len())
+    }
+}
+
+/// Deterministic xorshift, only good enough for a handshake under test
+struct TestRng(u64);
+
+impl RngCore for TestRng {
+    fn next_u32(&mut self) -> u32 {
+        self.next_u64() as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        rand_core::impls::fill_bytes_via_next(self, dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl CryptoRng for TestRng {}
+
+struct HostClock;
+
+impl TlsClock for HostClock {
+    fn now() -> Option<u64> {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()
+            .map(|now| now.as_secs())
+    }
+}
+
+struct Ca {
+    cert: Certificate,
+    key: KeyPair,
+}
+
+fn ca() -> Ca {
+    let key = KeyPair::generate().unwrap();
+    let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let cert = params.self_signed(&key).unwrap();
+    Ca { cert, key }
+}
+
+/// TLS 1.3 broker on localhost with a certificate for "localhost" issued by `ca`.
+/// Answers the CONNECT with a CONNACK and returns the first byte the client sent.
+fn tls_broker(ca: &Ca) -> (u16, JoinHandle<Option<u8>>) {
+    let key = KeyPair::generate().unwrap();
+    let cert = CertificateParams::new(vec!["localhost".into()])
+        .unwrap()
+        .signed_by(&key, &ca.cert, &ca.key)
+        .unwrap();
+    let config =
+        ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![CertificateDer::from(cert.der().to_vec())],
+                PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der())),
+            )
+            .unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let broker = thread::spawn(move || {
+        let (tcp, _) = listener.accept().ok()?;
+        let conn = ServerConnection::new(Arc::new(config)).ok()?;
+        let mut tls = StreamOwned::new(conn, tcp);
+        // fixed header and a one byte remaining length, the CONNECT is short
+        let mut header = [0; 2];
+        tls.read_exact(&mut header).ok()?;
+        let mut rest = vec![0; header[1] as usize];
+        tls.read_exact(&mut rest).ok()?;
+        tls.write_all(&CONNACK).ok()?;
+        tls.flush().ok()?;
+        Some(header[0])
+    });
+    (port, broker)
+}
+
+/// Opens TLS to the broker and runs `connect_to_broker` over it
+fn connect_over_tls(port: u16, ca_der: &[u8], server_name: &str) -> bool {
+    let tcp = HostTcp(TcpStream::connect(("127.0.0.1", port)).unwrap());
+    let mut read_buffer = vec![0; TLS_READ_BUFFER_LEN];
+    let mut record_buffer = vec![0; TLS_READ_BUFFER_LEN];
+    let mut write_buffer = vec![0; TLS_WRITE_BUFFER_LEN];
+    let options = TlsOptions {
+        server_name,
+        auth: TlsAuth::Ca(ca_der),
+    };
+    let mut rng = TestRng(0x2545_f491_4f6c_dd1d);
+    tokio_test::block_on(async {
+        let tls = match open_tls::<_, _, HostClock>(
+            tcp,
+            &mut read_buffer,
+            &mut record_buffer,
+            &mut write_buffer,
+            &options,
+            &mut rng,
+        )
+        .await
+        {
+            Ok(tls) => tls,
+            Err(_) => return false,
+        };
+        let mut mqtt_write = [0; 256];
+        let mut mqtt_recv = [0; 256];
+        let config = ClientConfig::<5, _>::new(MqttVersion::MQTTv5, CountingRng(0));
+        let mut client = MqttClient::new(tls, &mut mqtt_write, 256, &mut mqtt_recv, 256, config);
+        client.connect_to_broker().await.is_ok()
+    })
+}
+
+#[test]
+fn test_tls_connect_with_ca() {
+    let ca = ca();
+    let (port, broker) = tls_broker(&ca);
+    assert!(connect_over_tls(port, ca.cert.der(), "localhost"));
+    assert_eq!(broker.join().unwrap(), Some(CONNECT));
+}
+
+#[test]
+fn test_tls_unknown_ca_rejected() {
+    let (port, broker) = tls_broker(&ca());
+    let other = ca();
+    assert!(!connect_over_tls(port, other.cert.der(), "localhost"));
+    assert_eq!(broker.join().unwrap(), None);
+}
+
+#[test]
+fn test_tls_wrong_server_name_rejected() {
+    let ca = ca();
+    let (port, broker) = tls_broker(&ca);
+    assert!(!connect_over_tls(port, ca.cert.der(), "broker.example"));
+    assert_eq!(broker.join().unwrap(), None);
+}
+
+#[test]
+fn test_record_gate_holds_back_a_partial_record() {
+    // application data record with a 4 byte body
+    let record = [0x17, 0x03, 0x03, 0x00, 0x04, 1, 2, 3, 4];
+    let segments = Segments::default();
+    let mut buffer = [0; 64];
+    let mut gate = RecordGate::new(segments.clone(), &mut buffer);
+    let mut out = [0; 64];
+
+    segments.0.borrow_mut().push_back(record[..7].to_vec());
+    // cancelled while the body is incomplete
+    assert!(tokio_test::task::spawn(gate.read(&mut out))
+        .poll()
+        .is_pending());
+
+    // the rest of the record and the start of the next one
+    segments
+        .0
+        .borrow_mut()
+        .push_back([&record[7..], &record[..2]].concat());
+    let read = tokio_test::task::spawn(gate.read(&mut out)).poll();
+    assert!(matches!(read, Poll::Ready(Ok(9))));
+    assert_eq!(out[..9], record);
+    assert!(tokio_test::task::spawn(gate.read(&mut out))
+        .poll()
+        .is_pending());
+}
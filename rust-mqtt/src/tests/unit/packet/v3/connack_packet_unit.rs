@@ -0,0 +1,69 @@
+/*
+ * MIT License
+ *
+ * Copyright (c) [2022] [Ondrej Babec <ond.babec@gmail.com>]
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use crate::packet::v3::connack_packet::ConnackPacket;
+use crate::packet::v3::mqtt_packet::Packet;
+use crate::packet::v5::packet_type::PacketType;
+use crate::packet::v5::reason_codes::ReasonCode;
+use crate::utils::buffer_reader::BuffReader;
+
+#[test]
+fn test_encode() {
+    let mut buffer: [u8; 4] = [0; 4];
+    let mut connack = ConnackPacket::new();
+    connack.ack_flags = 0x01;
+    connack.return_code = 0x00;
+    let res = connack.encode(&mut buffer, 4);
+    assert!(res.is_ok());
+    assert_eq!(buffer, [0x20, 0x02, 0x01, 0x00])
+}
+
+#[test]
+fn test_decode() {
+    let buffer: [u8; 4] = [0x20, 0x02, 0x01, 0x00];
+    let mut connack = ConnackPacket::new();
+    let res = connack.decode(&mut BuffReader::new(&buffer, 4));
+    assert!(res.is_ok());
+    assert_eq!(connack.fixed_header, PacketType::Connack.into());
+    assert_eq!(connack.remain_len, 2);
+    assert_eq!(connack.ack_flags, 0x01);
+    assert_eq!(connack.reason_code(), ReasonCode::Success);
+}
+
+#[test]
+fn test_return_codes() {
+    let expected = [
+        ReasonCode::UnsupportedProtocolVersion,
+        ReasonCode::ClientIdNotValid,
+        ReasonCode::ServerUnavailable,
+        ReasonCode::BadUserNameOrPassword,
+        ReasonCode::NotAuthorized,
+    ];
+    for (return_code, reason) in (1..=5).zip(expected) {
+        let buffer: [u8; 4] = [0x20, 0x02, 0x00, return_code];
+        let mut connack = ConnackPacket::new();
+        assert!(connack.decode(&mut BuffReader::new(&buffer, 4)).is_ok());
+        assert_eq!(connack.reason_code(), reason);
+    }
+}
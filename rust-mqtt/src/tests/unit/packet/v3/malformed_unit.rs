@@ -0,0 +1,99 @@
+/*
+ * MIT License
+ *
+ * Copyright (c) [2022] [Ondrej Babec <ond.babec@gmail.com>]
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+extern crate std;
+
+use std::vec::Vec;
+
+use proptest::prelude::*;
+
+use crate::packet::v3::connack_packet::ConnackPacket;
+use crate::packet::v3::mqtt_packet::Packet;
+use crate::packet::v3::puback_packet::PubackPacket;
+use crate::packet::v3::pubcomp_packet::PubcompPacket;
+use crate::packet::v3::publish_packet::PublishPacket;
+use crate::packet::v3::pubrec_packet::PubrecPacket;
+use crate::packet::v3::pubrel_packet::PubrelPacket;
+use crate::packet::v3::suback_packet::SubackPacket;
+use crate::packet::v3::unsuback_packet::UnsubackPacket;
+use crate::utils::buffer_reader::BuffReader;
+use crate::utils::types::BufferError;
+
+/// Fixed header bytes of the packets a broker sends, so decoding gets past the type check
+const BROKER_HEADERS: [u8; 9] = [0x20, 0x30, 0x3B, 0x40, 0x50, 0x62, 0x70, 0x90, 0xB0];
+
+fn decode<'a, P: Packet<'a>>(bytes: &'a [u8]) -> Result<(), BufferError> {
+    let mut packet = P::new();
+    packet.decode(&mut BuffReader::new(bytes, bytes.len()))
+}
+
+/// Runs every MQTTv3 decoder the client uses on `bytes`, none of them may panic
+fn decode_all(bytes: &[u8]) {
+    let _ = decode::<ConnackPacket>(bytes);
+    let _ = decode::<PubackPacket>(bytes);
+    let _ = decode::<PubcompPacket>(bytes);
+    let _ = decode::<PublishPacket>(bytes);
+    let _ = decode::<PubrecPacket>(bytes);
+    let _ = decode::<PubrelPacket>(bytes);
+    let _ = decode::<SubackPacket<4>>(bytes);
+    let _ = decode::<UnsubackPacket>(bytes);
+}
+
+/// A broker packet type followed by anything
+fn broker_packet() -> impl Strategy<Value = Vec<u8>> {
+    (
+        prop::sample::select(&BROKER_HEADERS[..]),
+        prop::collection::vec(any::<u8>(), 0..64),
+    )
+        .prop_map(|(header, mut rest)| {
+            rest.insert(0, header);
+            rest
+        })
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(2048))]
+
+    #[test]
+    fn test_arbitrary_bytes(bytes in prop::collection::vec(any::<u8>(), 0..64)) {
+        decode_all(&bytes);
+    }
+
+    /// Every prefix of a packet, as left by a connection that drops mid-packet
+    #[test]
+    fn test_truncated_broker_packet(bytes in broker_packet()) {
+        for len in 0..=bytes.len() {
+            decode_all(&bytes[..len]);
+        }
+    }
+}
+
+#[test]
+fn test_suback_more_codes_than_room() {
+    let bytes = [0x90, 0x05, 0x00, 0x01, 0x00, 0x01, 0x02];
+    assert_eq!(
+        decode::<SubackPacket<2>>(&bytes),
+        Err(BufferError::InsufficientBufferSize)
+    );
+}
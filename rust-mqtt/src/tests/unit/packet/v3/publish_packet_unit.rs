@@ -0,0 +1,64 @@
+/*
+ * MIT License
+ *
+ * Copyright (c) [2022] [Ondrej Babec <ond.babec@gmail.com>]
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use crate::packet::v3::mqtt_packet::Packet;
+use crate::packet::v3::publish_packet::PublishPacket;
+use crate::packet::v5::packet_type::PacketType;
+use crate::packet::v5::publish_packet::QualityOfService;
+use crate::utils::buffer_reader::BuffReader;
+
+#[test]
+fn test_encode() {
+    let mut buffer: [u8; 15] = [0; 15];
+    let mut packet = PublishPacket::new();
+    packet.add_qos(QualityOfService::QoS1);
+    packet.add_topic_name("test");
+    packet.add_identifier(23432);
+    packet.add_message(b"Hello");
+    let res = packet.encode(&mut buffer, 15);
+    assert!(res.is_ok());
+    assert_eq!(res.unwrap(), 15);
+    // no property length between the identifier and the payload
+    assert_eq!(
+        buffer,
+        [
+            0x32, 0x0D, 0x00, 0x04, 0x74, 0x65, 0x73, 0x74, 0x5B, 0x88, 0x48, 0x65, 0x6c, 0x6c,
+            0x6f
+        ]
+    )
+}
+
+#[test]
+fn test_decode() {
+    let buffer: [u8; 10] = [0x31, 0x08, 0x00, 0x04, 0x74, 0x65, 0x73, 0x74, 0x48, 0x69];
+    let mut packet = PublishPacket::new();
+    let res = packet.decode(&mut BuffReader::new(&buffer, 10));
+    assert!(res.is_ok());
+    assert_eq!(packet.fixed_header, 0x31);
+    assert_eq!(packet.fixed_header & 0xF0, PacketType::Publish.into());
+    assert_eq!(packet.remain_len, 8);
+    assert_eq!(packet.topic_name.len, 4);
+    assert_eq!(packet.topic_name.string, "test");
+    assert_eq!(packet.message.unwrap(), b"Hi");
+}
@@ -0,0 +1,50 @@
+/*
+ * MIT License
+ *
+ * Copyright (c) [2022] [Ondrej Babec <ond.babec@gmail.com>]
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use crate::packet::v3::mqtt_packet::Packet;
+use crate::packet::v3::pubrel_packet::PubrelPacket;
+use crate::packet::v5::packet_type::PacketType;
+use crate::utils::buffer_reader::BuffReader;
+
+#[test]
+fn test_encode() {
+    let mut buffer: [u8; 4] = [0; 4];
+    let mut packet = PubrelPacket::new();
+    packet.packet_identifier = 35420;
+    let res = packet.encode(&mut buffer, 4);
+    assert!(res.is_ok());
+    assert_eq!(res.unwrap(), 4);
+    assert_eq!(buffer, [0x62, 0x02, 0x8A, 0x5C])
+}
+
+#[test]
+fn test_decode() {
+    let buffer: [u8; 4] = [0x62, 0x02, 0x8A, 0x5C];
+    let mut packet = PubrelPacket::new();
+    let res = packet.decode(&mut BuffReader::new(&buffer, 4));
+    assert!(res.is_ok());
+    assert_eq!(packet.fixed_header, PacketType::Pubrel.into());
+    assert_eq!(packet.remain_len, 2);
+    assert_eq!(packet.packet_identifier, 35420);
+}
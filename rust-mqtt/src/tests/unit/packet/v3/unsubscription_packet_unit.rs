@@ -0,0 +1,41 @@
+/*
+ * MIT License
+ *
+ * Copyright (c) [2022] [Ondrej Babec <ond.babec@gmail.com>]
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use crate::packet::v3::mqtt_packet::Packet;
+use crate::packet::v3::unsubscription_packet::UnsubscriptionPacket;
+
+#[test]
+fn test_encode() {
+    let mut buffer: [u8; 12] = [0; 12];
+    let mut packet = UnsubscriptionPacket::<1>::new();
+    packet.packet_identifier = 5432;
+    packet.add_new_filter("hehe/#");
+    let res = packet.encode(&mut buffer, 12);
+    assert!(res.is_ok());
+    assert_eq!(res.unwrap(), 12);
+    assert_eq!(
+        buffer,
+        [0xA2, 0x0A, 0x15, 0x38, 0x00, 0x06, 0x68, 0x65, 0x68, 0x65, 0x2F, 0x23]
+    );
+}
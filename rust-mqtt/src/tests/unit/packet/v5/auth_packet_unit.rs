@@ -0,0 +1,103 @@
+/*
+ * MIT License
+ *
+ * Copyright (c) [2022] [Ondrej Babec <ond.babec@gmail.com>]
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use heapless::Vec;
+
+use crate::packet::v5::auth_packet::AuthPacket;
+use crate::packet::v5::mqtt_packet::Packet;
+use crate::packet::v5::packet_type::PacketType;
+use crate::packet::v5::property::Property;
+use crate::utils::buffer_reader::BuffReader;
+use crate::utils::types::{BinaryData, EncodedString};
+
+#[test]
+fn test_encode() {
+    let mut buffer: [u8; 17] = [0; 17];
+    let mut packet = AuthPacket::<2>::new();
+    packet.add_reason_code(0x18);
+    let mut method = EncodedString::new();
+    method.string = "SCRAM";
+    method.len = 5;
+    let mut data = BinaryData::new();
+    data.bin = b"hi";
+    data.len = 2;
+    let mut props = Vec::<Property, 2>::new();
+    props.push(Property::AuthenticationMethod(method));
+    props.push(Property::AuthenticationData(data));
+    packet.property_len = packet.add_properties(&props);
+    let res = packet.encode(&mut buffer, 17);
+    assert!(res.is_ok());
+    assert_eq!(res.unwrap(), 17);
+    assert_eq!(
+        buffer,
+        [
+            0xF0, 0x0F, 0x18, 0x0D, 0x15, 0x00, 0x05, b'S', b'C', b'R', b'A', b'M', 0x16, 0x00,
+            0x02, b'h', b'i'
+        ]
+    )
+}
+
+#[test]
+fn test_decode() {
+    let buffer: [u8; 17] = [
+        0xF0, 0x0F, 0x18, 0x0D, 0x15, 0x00, 0x05, b'S', b'C', b'R', b'A', b'M', 0x16, 0x00, 0x02,
+        b'h', b'i',
+    ];
+    let mut packet = AuthPacket::<2>::new();
+    let res = packet.decode(&mut BuffReader::new(&buffer, 17));
+    assert!(res.is_ok());
+    assert_eq!(packet.fixed_header, PacketType::Auth.into());
+    assert_eq!(packet.remain_len, 15);
+    assert_eq!(packet.auth_reason, 0x18);
+    assert_eq!(packet.property_len, 13);
+    assert_eq!(packet.properties.len(), 2);
+    if let Property::AuthenticationMethod(method) = &packet.properties[0] {
+        assert_eq!(method.string, "SCRAM");
+    } else {
+        panic!("authentication method expected");
+    }
+    if let Property::AuthenticationData(data) = &packet.properties[1] {
+        assert_eq!(data.bin, b"hi");
+    } else {
+        panic!("authentication data expected");
+    }
+}
+
+#[test]
+fn test_decode_short() {
+    // success without properties
+    let buffer: [u8; 2] = [0xF0, 0x00];
+    let mut packet = AuthPacket::<2>::new();
+    let res = packet.decode(&mut BuffReader::new(&buffer, 2));
+    assert!(res.is_ok());
+    assert_eq!(packet.auth_reason, 0x00);
+    assert_eq!(packet.property_len, 0);
+}
+
+#[test]
+fn test_decode_other_packet() {
+    let buffer: [u8; 2] = [0xE0, 0x00];
+    let mut packet = AuthPacket::<2>::new();
+    assert!(packet.decode(&mut BuffReader::new(&buffer, 2)).is_err());
+}
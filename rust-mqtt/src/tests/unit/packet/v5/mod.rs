@@ -22,9 +22,11 @@
  * SOFTWARE.
  */
 
+pub mod auth_packet_unit;
 pub mod connack_packet_unit;
 pub mod connect_packet_unit;
 pub mod disconnect_packet_unit;
+pub mod malformed_unit;
 pub mod pingreq_packet_unit;
 pub mod pingresp_packet_unit;
 pub mod puback_packet_unit;
@@ -32,6 +34,7 @@ pub mod pubcomp_packet_unit;
 pub mod publish_packet_unit;
 pub mod pubrec_packet_unit;
 pub mod pubrel_packet_unit;
+pub mod roundtrip_unit;
 pub mod suback_packet_unit;
 pub mod subscription_packet_unit;
 pub mod unsuback_packet_unit;
@@ -72,3 +72,16 @@ fn test_decode() {
         assert_eq!(u.string, "Wheel");
     }
 }
+
+#[test]
+fn test_decode_short() {
+    // success with reason code and properties omitted
+    let buffer: [u8; 4] = [0x70, 0x02, 0x8A, 0x5C];
+    let mut packet = PubcompPacket::<1>::new();
+    let res = packet.decode(&mut BuffReader::new(&buffer, 4));
+    assert!(res.is_ok());
+    assert_eq!(packet.remain_len, 2);
+    assert_eq!(packet.packet_identifier, 35420);
+    assert_eq!(packet.reason_code, 0x00);
+    assert_eq!(packet.property_len, 0);
+}
@@ -86,3 +86,15 @@ fn test_decode() {
         assert_eq!(u.value.string, "hehe89");
     }
 }
+
+#[test]
+fn test_decode_short() {
+    // reason code and properties left out on success
+    let buffer: [u8; 4] = [0x62, 0x02, 0x30, 0x39];
+    let mut packet = PubrelPacket::<1>::new();
+    let res = packet.decode(&mut BuffReader::new(&buffer, 4));
+    assert!(res.is_ok());
+    assert_eq!(packet.packet_identifier, 12345);
+    assert_eq!(packet.reason_code, 0x00);
+    assert_eq!(packet.property_len, 0);
+}
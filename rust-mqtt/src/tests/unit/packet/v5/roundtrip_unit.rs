@@ -0,0 +1,410 @@
+/*
+ * MIT License
+ *
+ * Copyright (c) [2022] [Ondrej Babec <ond.babec@gmail.com>]
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+extern crate std;
+
+use std::string::String;
+use std::vec::Vec;
+
+use proptest::prelude::*;
+
+use crate::encoding::variable_byte_integer::{
+    VariableByteIntegerDecoder, VariableByteIntegerEncoder,
+};
+use crate::packet::v5::auth_packet::AuthPacket;
+use crate::packet::v5::connack_packet::ConnackPacket;
+use crate::packet::v5::connect_packet::ConnectPacket;
+use crate::packet::v5::disconnect_packet::DisconnectPacket;
+use crate::packet::v5::mqtt_packet::Packet;
+use crate::packet::v5::pingreq_packet::PingreqPacket;
+use crate::packet::v5::pingresp_packet::PingrespPacket;
+use crate::packet::v5::property::Property;
+use crate::packet::v5::puback_packet::PubackPacket;
+use crate::packet::v5::pubcomp_packet::PubcompPacket;
+use crate::packet::v5::publish_packet::PublishPacket;
+use crate::packet::v5::publish_packet::QualityOfService::{QoS0, QoS1, QoS2};
+use crate::packet::v5::pubrec_packet::PubrecPacket;
+use crate::packet::v5::pubrel_packet::PubrelPacket;
+use crate::packet::v5::suback_packet::SubackPacket;
+use crate::packet::v5::subscription_packet::SubscriptionPacket;
+use crate::packet::v5::unsuback_packet::UnsubackPacket;
+use crate::packet::v5::unsubscription_packet::UnsubscriptionPacket;
+use crate::utils::buffer_reader::BuffReader;
+use crate::utils::buffer_writer::BuffWriter;
+use crate::utils::types::{BinaryData, EncodedString, StringPair};
+
+const BUFFER_LEN: usize = 1024;
+const MAX_PROPERTIES: usize = 8;
+// Property::decode knows 27 property identifiers
+const PROPERTY_KINDS: u8 = 27;
+
+/// Values for one property, `kind` picks the property and the rest fills it
+#[derive(Debug, Clone)]
+struct PropertyValues {
+    kind: u8,
+    number: u32,
+    name: String,
+    value: String,
+    bin: Vec<u8>,
+}
+
+fn property_values() -> impl Strategy<Value = PropertyValues> {
+    (
+        0..PROPERTY_KINDS,
+        any::<u32>(),
+        "\\PC{0,12}",
+        "\\PC{0,12}",
+        prop::collection::vec(any::<u8>(), 0..12),
+    )
+        .prop_map(|(kind, number, name, value, bin)| PropertyValues {
+            kind,
+            number,
+            name,
+            value,
+            bin,
+        })
+}
+
+fn string(s: &str) -> EncodedString<'_> {
+    EncodedString {
+        string: s,
+        len: s.len() as u16,
+    }
+}
+
+fn binary(bin: &[u8]) -> BinaryData<'_> {
+    BinaryData {
+        bin,
+        len: bin.len() as u16,
+    }
+}
+
+fn property(values: &PropertyValues) -> Property<'_> {
+    let number = values.number;
+    let name = string(&values.name);
+    match values.kind {
+        0 => Property::PayloadFormat(number as u8),
+        1 => Property::MessageExpiryInterval(number),
+        2 => Property::ContentType(name),
+        3 => Property::ResponseTopic(name),
+        4 => Property::CorrelationData(binary(&values.bin)),
+        // largest variable byte integer
+        5 => Property::SubscriptionIdentifier(number % 268_435_456),
+        6 => Property::SessionExpiryInterval(number),
+        7 => Property::AssignedClientIdentifier(name),
+        8 => Property::ServerKeepAlive(number as u16),
+        9 => Property::AuthenticationMethod(name),
+        10 => Property::AuthenticationData(binary(&values.bin)),
+        11 => Property::RequestProblemInformation(number as u8),
+        12 => Property::WillDelayInterval(number),
+        13 => Property::RequestResponseInformation(number as u8),
+        14 => Property::ResponseInformation(name),
+        15 => Property::ServerReference(name),
+        16 => Property::ReasonString(name),
+        17 => Property::ReceiveMaximum(number as u16),
+        18 => Property::TopicAliasMaximum(number as u16),
+        19 => Property::TopicAlias(number as u16),
+        20 => Property::MaximumQoS(number as u8),
+        21 => Property::RetainAvailable(number as u8),
+        22 => Property::UserProperty(StringPair {
+            name,
+            value: string(&values.value),
+        }),
+        23 => Property::MaximumPacketSize(number),
+        24 => Property::WildcardSubscriptionAvailable(number as u8),
+        25 => Property::SubscriptionIdentifierAvailable(number as u8),
+        _ => Property::SharedSubscriptionAvailable(number as u8),
+    }
+}
+
+fn properties(values: &[PropertyValues]) -> heapless::Vec<Property<'_>, MAX_PROPERTIES> {
+    values.iter().map(property).collect()
+}
+
+fn properties_values() -> impl Strategy<Value = Vec<PropertyValues>> {
+    prop::collection::vec(property_values(), 0..MAX_PROPERTIES)
+}
+
+/// Remaining length from the fixed header and the length of the header itself
+fn remaining_len(buffer: &[u8]) -> (usize, usize) {
+    let mut encoded = [0; 4];
+    let len = VariableByteIntegerEncoder::len([buffer[1], buffer[2], buffer[3], buffer[4]]);
+    encoded[..len].copy_from_slice(&buffer[1..1 + len]);
+    let remaining = VariableByteIntegerDecoder::decode(encoded).unwrap();
+    (remaining as usize, 1 + len)
+}
+
+/// The remaining length in the fixed header covers exactly the rest of the packet
+fn assert_remaining_len(buffer: &[u8], len: usize) {
+    let (remaining, header_len) = remaining_len(buffer);
+    assert_eq!(header_len + remaining, len);
+}
+
+proptest! {
+    #[test]
+    fn test_property(values in property_values()) {
+        let property = property(&values);
+        let mut buffer = [0; 64];
+        let mut writer = BuffWriter::new(&mut buffer, 64);
+        let mut single = heapless::Vec::<Property<'_>, 1>::new();
+        single.push(property.clone()).unwrap();
+        writer.write_properties(&single).unwrap();
+        let len = writer.position;
+        prop_assert_eq!(len, property.encoded_len() as usize + 1);
+
+        let mut reader = BuffReader::new(&buffer, len);
+        prop_assert_eq!(Property::decode(&mut reader).unwrap(), property);
+        prop_assert_eq!(reader.position, len);
+    }
+
+    #[test]
+    fn test_auth(reason in any::<u8>(), values in properties_values()) {
+        let props = properties(&values);
+        let mut packet = AuthPacket::<MAX_PROPERTIES>::new();
+        packet.auth_reason = reason;
+        packet.property_len = packet.add_properties(&props);
+        let mut buffer = [0; BUFFER_LEN];
+        let len = packet.encode(&mut buffer, BUFFER_LEN).unwrap();
+        assert_remaining_len(&buffer, len);
+
+        let mut decoded = AuthPacket::<MAX_PROPERTIES>::new();
+        decoded.decode(&mut BuffReader::new(&buffer, len)).unwrap();
+        prop_assert_eq!(decoded.auth_reason, reason);
+        prop_assert_eq!(decoded.properties, packet.properties);
+    }
+
+    #[test]
+    fn test_connack(
+        flags in any::<u8>(),
+        reason in any::<u8>(),
+        values in properties_values(),
+    ) {
+        let props = properties(&values);
+        let mut packet = ConnackPacket::<MAX_PROPERTIES>::new();
+        packet.ack_flags = flags;
+        packet.connect_reason_code = reason;
+        packet.property_len = packet.add_properties(&props);
+        let mut buffer = [0; BUFFER_LEN];
+        let len = packet.encode(&mut buffer, BUFFER_LEN).unwrap();
+        assert_remaining_len(&buffer, len);
+
+        let mut decoded = ConnackPacket::<MAX_PROPERTIES>::new();
+        decoded.decode(&mut BuffReader::new(&buffer, len)).unwrap();
+        prop_assert_eq!(decoded.ack_flags, flags);
+        prop_assert_eq!(decoded.connect_reason_code, reason);
+        prop_assert_eq!(decoded.properties, packet.properties);
+    }
+
+    #[test]
+    fn test_disconnect(reason in any::<u8>(), values in properties_values()) {
+        let props = properties(&values);
+        let mut packet = DisconnectPacket::<MAX_PROPERTIES>::new();
+        packet.disconnect_reason = reason;
+        packet.property_len = packet.add_properties(&props);
+        let mut buffer = [0; BUFFER_LEN];
+        let len = packet.encode(&mut buffer, BUFFER_LEN).unwrap();
+        assert_remaining_len(&buffer, len);
+
+        let mut decoded = DisconnectPacket::<MAX_PROPERTIES>::new();
+        decoded.decode(&mut BuffReader::new(&buffer, len)).unwrap();
+        prop_assert_eq!(decoded.disconnect_reason, reason);
+        prop_assert_eq!(decoded.properties, packet.properties);
+    }
+
+    #[test]
+    fn test_publish_acks(
+        identifier in any::<u16>(),
+        reason in any::<u8>(),
+        values in properties_values(),
+    ) {
+        let props = properties(&values);
+
+        let mut buffer = [0; BUFFER_LEN];
+        let mut puback = PubackPacket::<MAX_PROPERTIES>::new();
+        puback.packet_identifier = identifier;
+        puback.reason_code = reason;
+        puback.property_len = puback.add_properties(&props);
+        let len = puback.encode(&mut buffer, BUFFER_LEN).unwrap();
+        assert_remaining_len(&buffer, len);
+        let mut decoded = PubackPacket::<MAX_PROPERTIES>::new();
+        decoded.decode(&mut BuffReader::new(&buffer, len)).unwrap();
+        prop_assert_eq!(decoded.packet_identifier, identifier);
+        prop_assert_eq!(decoded.reason_code, reason);
+        prop_assert_eq!(decoded.properties, puback.properties);
+
+        let mut buffer = [0; BUFFER_LEN];
+        let mut pubrec = PubrecPacket::<MAX_PROPERTIES>::new();
+        pubrec.packet_identifier = identifier;
+        pubrec.reason_code = reason;
+        pubrec.property_len = pubrec.add_properties(&props);
+        let len = pubrec.encode(&mut buffer, BUFFER_LEN).unwrap();
+        assert_remaining_len(&buffer, len);
+        let mut decoded = PubrecPacket::<MAX_PROPERTIES>::new();
+        decoded.decode(&mut BuffReader::new(&buffer, len)).unwrap();
+        prop_assert_eq!(decoded.packet_identifier, identifier);
+        prop_assert_eq!(decoded.reason_code, reason);
+        prop_assert_eq!(decoded.properties, pubrec.properties);
+
+        let mut buffer = [0; BUFFER_LEN];
+        let mut pubrel = PubrelPacket::<MAX_PROPERTIES>::new();
+        pubrel.packet_identifier = identifier;
+        pubrel.reason_code = reason;
+        pubrel.property_len = pubrel.add_properties(&props);
+        let len = pubrel.encode(&mut buffer, BUFFER_LEN).unwrap();
+        assert_remaining_len(&buffer, len);
+        let mut decoded = PubrelPacket::<MAX_PROPERTIES>::new();
+        decoded.decode(&mut BuffReader::new(&buffer, len)).unwrap();
+        prop_assert_eq!(decoded.packet_identifier, identifier);
+        prop_assert_eq!(decoded.reason_code, reason);
+        prop_assert_eq!(decoded.properties, pubrel.properties);
+
+        let mut buffer = [0; BUFFER_LEN];
+        let mut pubcomp = PubcompPacket::<MAX_PROPERTIES>::new();
+        pubcomp.packet_identifier = identifier;
+        pubcomp.reason_code = reason;
+        pubcomp.property_len = pubcomp.add_properties(&props);
+        let len = pubcomp.encode(&mut buffer, BUFFER_LEN).unwrap();
+        assert_remaining_len(&buffer, len);
+        let mut decoded = PubcompPacket::<MAX_PROPERTIES>::new();
+        decoded.decode(&mut BuffReader::new(&buffer, len)).unwrap();
+        prop_assert_eq!(decoded.packet_identifier, identifier);
+        prop_assert_eq!(decoded.reason_code, reason);
+        prop_assert_eq!(decoded.properties, pubcomp.properties);
+    }
+
+    #[test]
+    fn test_publish(
+        topic in "\\PC{0,32}",
+        message in prop::collection::vec(any::<u8>(), 0..256),
+        qos in prop::sample::select(&[QoS0, QoS1, QoS2][..]),
+        retain in any::<bool>(),
+        identifier in any::<u16>(),
+        values in properties_values(),
+    ) {
+        let props = properties(&values);
+        let mut packet = PublishPacket::<MAX_PROPERTIES>::new();
+        packet.add_topic_name(&topic);
+        packet.add_message(&message);
+        packet.add_qos(qos);
+        packet.add_retain(retain);
+        packet.add_identifier(identifier);
+        packet.property_len = packet.add_properties(&props);
+        let mut buffer = [0; BUFFER_LEN];
+        let len = packet.encode(&mut buffer, BUFFER_LEN).unwrap();
+        assert_remaining_len(&buffer, len);
+
+        let mut decoded = PublishPacket::<MAX_PROPERTIES>::new();
+        decoded.decode(&mut BuffReader::new(&buffer, len)).unwrap();
+        prop_assert_eq!(decoded.fixed_header, packet.fixed_header);
+        prop_assert_eq!(decoded.topic_name, packet.topic_name);
+        if qos != QoS0 {
+            prop_assert_eq!(decoded.packet_identifier, identifier);
+        }
+        prop_assert_eq!(decoded.properties, packet.properties);
+        prop_assert_eq!(decoded.message, Some(message.as_slice()));
+    }
+
+    /// SUBACK and UNSUBACK are only decoded by the client, the bytes are built here
+    #[test]
+    fn test_subscribe_acks(
+        identifier in any::<u16>(),
+        reasons in prop::collection::vec(any::<u8>(), 1..8),
+        values in properties_values(),
+    ) {
+        let props = properties(&values);
+        let mut properties_buffer = [0; BUFFER_LEN];
+        let mut writer = BuffWriter::new(&mut properties_buffer, BUFFER_LEN);
+        writer.write_properties(&props).unwrap();
+        let properties_len = writer.position;
+        let property_len = VariableByteIntegerEncoder::encode(properties_len as u32).unwrap();
+        let property_len = &property_len[..VariableByteIntegerEncoder::len(property_len)];
+
+        let mut body = Vec::new();
+        body.extend(identifier.to_be_bytes());
+        body.extend(property_len);
+        body.extend(&properties_buffer[..properties_len]);
+        body.extend(&reasons);
+        let remaining = VariableByteIntegerEncoder::encode(body.len() as u32).unwrap();
+        for header in [0x90, 0xB0] {
+            let mut bytes = std::vec![header];
+            bytes.extend(&remaining[..VariableByteIntegerEncoder::len(remaining)]);
+            bytes.extend(&body);
+
+            let mut reader = BuffReader::new(&bytes, bytes.len());
+            let (decoded_identifier, decoded_properties, decoded_reasons) = if header == 0x90 {
+                let mut decoded = SubackPacket::<8, MAX_PROPERTIES>::new();
+                decoded.decode(&mut reader).unwrap();
+                (decoded.packet_identifier, decoded.properties, decoded.reason_codes)
+            } else {
+                let mut decoded = UnsubackPacket::<8, MAX_PROPERTIES>::new();
+                decoded.decode(&mut reader).unwrap();
+                (decoded.packet_identifier, decoded.properties, decoded.reason_codes)
+            };
+            prop_assert_eq!(decoded_identifier, identifier);
+            prop_assert_eq!(&decoded_properties, &props);
+            prop_assert_eq!(decoded_reasons.as_slice(), reasons.as_slice());
+        }
+    }
+
+    /// CONNECT, SUBSCRIBE and UNSUBSCRIBE are only encoded by the client, their remaining
+    /// length has to match what is written
+    #[test]
+    fn test_client_packets_length(
+        client_id in "\\PC{0,23}",
+        topic in "\\PC{0,32}",
+        values in properties_values(),
+    ) {
+        let props = properties(&values);
+        let mut buffer = [0; BUFFER_LEN];
+
+        let mut connect = ConnectPacket::<MAX_PROPERTIES, 0>::new();
+        connect.add_client_id(&string(&client_id));
+        connect.property_len = connect.add_properties(&props);
+        let len = connect.encode(&mut buffer, BUFFER_LEN).unwrap();
+        assert_remaining_len(&buffer, len);
+
+        let mut subscribe = SubscriptionPacket::<1, MAX_PROPERTIES>::new();
+        subscribe.add_new_filter(&topic, QoS1);
+        subscribe.property_len = subscribe.add_properties(&props);
+        let len = subscribe.encode(&mut buffer, BUFFER_LEN).unwrap();
+        assert_remaining_len(&buffer, len);
+
+        let mut unsubscribe = UnsubscriptionPacket::<1, MAX_PROPERTIES>::new();
+        unsubscribe.add_new_filter(&topic);
+        unsubscribe.property_len = unsubscribe.add_properties(&props);
+        let len = unsubscribe.encode(&mut buffer, BUFFER_LEN).unwrap();
+        assert_remaining_len(&buffer, len);
+    }
+}
+
+#[test]
+fn test_pings() {
+    let mut buffer = [0; 8];
+    let len = PingreqPacket::new().encode(&mut buffer, 8).unwrap();
+    assert_eq!(&buffer[..len], [0xC0, 0x00]);
+
+    let len = PingrespPacket::new().encode(&mut buffer, 8).unwrap();
+    let mut decoded = PingrespPacket::new();
+    assert!(decoded.decode(&mut BuffReader::new(&buffer, len)).is_ok());
+}
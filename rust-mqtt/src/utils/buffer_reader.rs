@@ -41,50 +41,33 @@ impl<'a> BuffReader<'a> {
         self.position += increment;
     }
 
+    /// Reads at most `buff_len` bytes, a longer `buff_len` stops at the end of `buffer`
     pub fn new(buffer: &'a [u8], buff_len: usize) -> Self {
         Self {
             buffer,
             position: 0,
-            len: buff_len,
+            len: buff_len.min(buffer.len()),
         }
     }
 
-    /// Variable byte integer can be 1-4 Bytes long. Buffer reader takes all 4 Bytes at first and
-    /// than check what is true length of varbyteint and increment cursor by that
+    /// Variable byte integer can be 1-4 Bytes long. Bytes are taken until one without the
+    /// continuation bit, a fifth byte is a decoding error.
     pub fn read_variable_byte_int(&mut self) -> Result<u32, BufferError> {
         let mut variable_byte_integer: [u8; 4] = [0; 4];
-        let mut len: usize = 1;
 
-        // Everytime checking first bit of Byte which determines whenever there is continuous Byte
-        let mut x = 0;
-        loop {
-            if x >= 4 {
-                break;
-            }
+        for (x, byte) in variable_byte_integer.iter_mut().enumerate() {
             if self.position + x >= self.len {
                 return Err(BufferError::InsufficientBufferSize);
             }
-            if self.buffer[self.position + x] & 0x80 != 0 {
-                variable_byte_integer[x] = self.buffer[self.position + x];
-                len += 1
-            } else {
-                variable_byte_integer[x] = self.buffer[self.position + x];
-                x += 1;
-                if x != 4 {
-                    loop {
-                        variable_byte_integer[x] = 0;
-                        x += 1;
-                        if x == 4 {
-                            break;
-                        }
-                    }
-                    break;
-                }
+            *byte = self.buffer[self.position + x];
+            // Checking first bit of Byte which determines whenever there is continuous Byte
+            if *byte & 0x80 == 0 {
+                self.increment_position(x + 1);
+                return VariableByteIntegerDecoder::decode(variable_byte_integer);
             }
-            x += 1;
         }
-        self.increment_position(len);
-        VariableByteIntegerDecoder::decode(variable_byte_integer)
+        error!("Variable byte integer is longer than 4 bytes");
+        Err(BufferError::DecodingError)
     }
 
     /// Reading u32 from buffer as `Big endian`
@@ -148,6 +131,7 @@ impl<'a> BuffReader<'a> {
         }
 
         let res_bin = &(self.buffer[self.position..(self.position + len as usize)]);
+        self.increment_position(len as usize);
         Ok(BinaryData { bin: res_bin, len })
     }
 
@@ -158,12 +142,14 @@ impl<'a> BuffReader<'a> {
         Ok(StringPair { name, value })
     }
 
-    /// Read payload message from buffer
+    /// Read payload message from buffer, up to `total_len` (the end of the packet) or the
+    /// end of the buffer. Empty if the packet ended before the current position.
     pub fn read_message(&mut self, total_len: usize) -> &'a [u8] {
-        if total_len > self.len {
-            return &self.buffer[self.position..self.len];
+        let end = total_len.min(self.len);
+        if self.position >= end {
+            return &[];
         }
-        &self.buffer[self.position..total_len]
+        &self.buffer[self.position..end]
     }
 
     /// Peeking (without incremental internal pointer) one byte from buffer as `Big endian`
@@ -56,7 +56,7 @@ impl Display for BufferError {
     }
 }
 /// Encoded string provides structure representing UTF-8 encoded string in MQTTv5 packets
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct EncodedString<'a> {
     pub string: &'a str,
     pub len: u16,
@@ -74,7 +74,7 @@ impl EncodedString<'_> {
 }
 
 /// Binary data represents `Binary data` in MQTTv5 protocol
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct BinaryData<'a> {
     pub bin: &'a [u8],
     pub len: u16,
@@ -91,7 +91,7 @@ impl BinaryData<'_> {
 }
 
 /// String pair struct represents `String pair` in MQTTv5 (2 UTF-8 encoded strings name-value)
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct StringPair<'a> {
     pub name: EncodedString<'a>,
     pub value: EncodedString<'a>,
@@ -0,0 +1,531 @@
+#![no_std]
+
+//! SLCAN (Lawicel ASCII) command decoder and response encoder.
+//!
+//! Every message is a line of ASCII ending in `\r`. The host sends commands,
+//! the adapter answers `\r` for success or BELL (0x07) for an error, and while
+//! the channel is open it sends every bus frame as a line:
+//!
+//! ```text
+//! t1F42AABB\r          standard id 0x1F4, 2 bytes
+//! T18DAF1DB1FF\r       extended id, 1 byte
+//! r1F40\r              standard remote frame, dlc 0
+//! t1F42AABB1A2B\r      with timestamps on, milliseconds 0..60000
+//! ```
+//!
+//! ```ignore
+//! let mut decoder = Decoder::new();
+//! for &byte in b"S6\rO\rt1F42AABB\r" {
+//!     match decoder.push(byte) {
+//!         Some(Ok(Command::Transmit(frame))) => send(frame),
+//!         Some(Ok(_)) => reply(Response::Ok.encode().as_bytes()),
+//!         Some(Err(_)) => reply(Response::Error.encode().as_bytes()),
+//!         None => (),
+//!     }
+//! }
+//! ```
+
+/// Bitrates selected by `S0` to `S8`
+pub const BITRATES: [u32; 9] = [
+    10_000, 20_000, 50_000, 100_000, 125_000, 250_000, 500_000, 800_000, 1_000_000,
+];
+/// Longest line either way: extended frame, 8 bytes, timestamp and `\r`
+pub const MAX_LINE: usize = 32;
+
+const CR: u8 = b'\r';
+const BELL: u8 = 0x07;
+const MAX_STANDARD_ID: u32 = 0x7FF;
+const MAX_EXTENDED_ID: u32 = 0x1FFF_FFFF;
+const TIMESTAMP_WRAP_MS: u64 = 60_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Command letter not supported
+    Unknown,
+    /// Wrong length, bad hex digit or value out of range
+    Invalid,
+    /// Line longer than MAX_LINE
+    TooLong,
+}
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::Unknown => write!(f, "Unknown SLCAN command"),
+            Error::Invalid => write!(f, "Invalid SLCAN command"),
+            Error::TooLong => write!(f, "SLCAN line too long"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Id {
+    Standard(u16),
+    Extended(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Frame {
+    id: Id,
+    remote: bool,
+    dlc: u8,
+    data: [u8; 8],
+}
+
+impl Frame {
+    /// None if the id is out of range or there are more than 8 bytes
+    pub fn new(id: Id, data: &[u8]) -> Option<Self> {
+        if !id_valid(id) || data.len() > 8 {
+            return None;
+        }
+        let mut frame = Self {
+            id,
+            remote: false,
+            dlc: data.len() as u8,
+            data: [0; 8],
+        };
+        frame.data[..data.len()].copy_from_slice(data);
+        Some(frame)
+    }
+
+    pub fn new_remote(id: Id, dlc: u8) -> Option<Self> {
+        if !id_valid(id) || dlc > 8 {
+            return None;
+        }
+        Some(Self {
+            id,
+            remote: true,
+            dlc,
+            data: [0; 8],
+        })
+    }
+
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
+    pub fn is_remote(&self) -> bool {
+        self.remote
+    }
+
+    pub fn dlc(&self) -> u8 {
+        self.dlc
+    }
+
+    /// Empty for remote frames
+    pub fn data(&self) -> &[u8] {
+        match self.remote {
+            true => &[],
+            false => &self.data[..self.dlc as usize],
+        }
+    }
+}
+
+fn id_valid(id: Id) -> bool {
+    match id {
+        Id::Standard(id) => id as u32 <= MAX_STANDARD_ID,
+        Id::Extended(id) => id <= MAX_EXTENDED_ID,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Command {
+    /// `O`
+    Open,
+    /// `L`, open without transmitting
+    ListenOnly,
+    /// `C`
+    Close,
+    /// `S0` to `S8`, in bit/s
+    Bitrate(u32),
+    /// `t`, `T`, `r` and `R`
+    Transmit(Frame),
+    /// `Z0` / `Z1`
+    Timestamps(bool),
+    /// `V`
+    Version,
+    /// `N`
+    SerialNumber,
+    /// `F`
+    Status,
+    /// Accepted but with no effect: acceptance code and mask, auto poll
+    Ignored,
+}
+
+/// Decodes one line without its `\r`
+pub fn parse(line: &[u8]) -> Result<Command, Error> {
+    let (&cmd, args) = line.split_first().ok_or(Error::Unknown)?;
+    let no_args = |command| match args.is_empty() {
+        true => Ok(command),
+        false => Err(Error::Invalid),
+    };
+    match cmd {
+        b'O' => no_args(Command::Open),
+        b'L' => no_args(Command::ListenOnly),
+        b'C' => no_args(Command::Close),
+        b'V' => no_args(Command::Version),
+        b'N' => no_args(Command::SerialNumber),
+        b'F' => no_args(Command::Status),
+        b'S' => match args {
+            [n @ b'0'..=b'8'] => Ok(Command::Bitrate(BITRATES[(n - b'0') as usize])),
+            _ => Err(Error::Invalid),
+        },
+        b'Z' => match args {
+            [b'0'] => Ok(Command::Timestamps(false)),
+            [b'1'] => Ok(Command::Timestamps(true)),
+            _ => Err(Error::Invalid),
+        },
+        b'M' | b'm' if args.len() == 8 => hex(args).map(|_| Command::Ignored),
+        b'X' if matches!(args, [b'0' | b'1']) => Ok(Command::Ignored),
+        b'M' | b'm' | b'X' => Err(Error::Invalid),
+        b't' | b'T' | b'r' | b'R' => parse_frame(cmd, args).map(Command::Transmit),
+        _ => Err(Error::Unknown),
+    }
+}
+
+fn parse_frame(cmd: u8, args: &[u8]) -> Result<Frame, Error> {
+    let id_len = match cmd {
+        b't' | b'r' => 3,
+        _ => 8,
+    };
+    if args.len() <= id_len {
+        return Err(Error::Invalid);
+    }
+    let (id, args) = args.split_at(id_len);
+    let id = hex(id)?;
+    let id = match id_len {
+        3 => Id::Standard(id as u16),
+        _ => Id::Extended(id),
+    };
+    let (dlc, data) = args.split_first().ok_or(Error::Invalid)?;
+    let dlc = hex(core::slice::from_ref(dlc))? as u8;
+    let frame = match cmd {
+        b'r' | b'R' if data.is_empty() => Frame::new_remote(id, dlc),
+        b'r' | b'R' => None,
+        _ if dlc > 8 || data.len() != dlc as usize * 2 => None,
+        _ => {
+            let mut bytes = [0u8; 8];
+            for (byte, pair) in bytes.iter_mut().zip(data.chunks(2)) {
+                *byte = hex(pair)? as u8;
+            }
+            Frame::new(id, &bytes[..dlc as usize])
+        }
+    };
+    frame.ok_or(Error::Invalid)
+}
+
+fn hex(digits: &[u8]) -> Result<u32, Error> {
+    digits.iter().try_fold(0u32, |acc, &d| {
+        let nibble = (d as char).to_digit(16).ok_or(Error::Invalid)?;
+        Ok(acc << 4 | nibble)
+    })
+}
+
+/// Collects bytes into lines and decodes each one at its `\r`
+pub struct Decoder {
+    buf: [u8; MAX_LINE],
+    len: usize,
+    overflow: bool,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; MAX_LINE],
+            len: 0,
+            overflow: false,
+        }
+    }
+
+    /// Some once a line is complete, empty lines are skipped
+    pub fn push(&mut self, byte: u8) -> Option<Result<Command, Error>> {
+        match byte {
+            CR => {
+                let line = &self.buf[..self.len];
+                let result = match (self.overflow, line.is_empty()) {
+                    (true, _) => Some(Err(Error::TooLong)),
+                    (false, true) => None,
+                    (false, false) => Some(parse(line)),
+                };
+                self.len = 0;
+                self.overflow = false;
+                result
+            }
+            b'\n' => None,
+            _ if self.len == MAX_LINE => {
+                self.overflow = true;
+                None
+            }
+            _ => {
+                self.buf[self.len] = byte;
+                self.len += 1;
+                None
+            }
+        }
+    }
+}
+
+/// An encoded line, ready to write
+pub struct Line {
+    buf: [u8; MAX_LINE],
+    len: usize,
+}
+
+impl Line {
+    fn new() -> Self {
+        Self {
+            buf: [0; MAX_LINE],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.buf[self.len] = byte;
+        self.len += 1;
+    }
+
+    fn push_hex(&mut self, value: u32, digits: usize) {
+        const HEX: &[u8; 16] = b"0123456789ABCDEF";
+        for shift in (0..digits).rev() {
+            self.push(HEX[(value >> (shift * 4)) as usize & 0xF]);
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Response {
+    Ok,
+    Error,
+    /// Reply to a transmit, `z` or `Z` for an extended id
+    Sent(Id),
+    /// Reply to `V`, two digits each
+    Version {
+        hardware: u8,
+        software: u8,
+    },
+    /// Reply to `N`, four characters
+    SerialNumber([u8; 4]),
+    /// Reply to `F`, error flags
+    Status(u8),
+    /// A bus frame, `timestamp` from [`timestamp`] when enabled with `Z1`
+    Frame {
+        frame: Frame,
+        timestamp: Option<u16>,
+    },
+}
+
+impl Response {
+    pub fn encode(&self) -> Line {
+        let mut line = Line::new();
+        match self {
+            Response::Ok => (),
+            Response::Error => {
+                line.push(BELL);
+                return line;
+            }
+            Response::Sent(Id::Standard(_)) => line.push(b'z'),
+            Response::Sent(Id::Extended(_)) => line.push(b'Z'),
+            Response::Version { hardware, software } => {
+                line.push(b'V');
+                line.push_hex(*hardware as u32, 2);
+                line.push_hex(*software as u32, 2);
+            }
+            Response::SerialNumber(serial) => {
+                line.push(b'N');
+                serial.iter().for_each(|&c| line.push(c));
+            }
+            Response::Status(flags) => {
+                line.push(b'F');
+                line.push_hex(*flags as u32, 2);
+            }
+            Response::Frame { frame, timestamp } => {
+                match (frame.id, frame.remote) {
+                    (Id::Standard(id), false) => (line.push(b't'), line.push_hex(id as u32, 3)),
+                    (Id::Standard(id), true) => (line.push(b'r'), line.push_hex(id as u32, 3)),
+                    (Id::Extended(id), false) => (line.push(b'T'), line.push_hex(id, 8)),
+                    (Id::Extended(id), true) => (line.push(b'R'), line.push_hex(id, 8)),
+                };
+                line.push_hex(frame.dlc as u32, 1);
+                frame
+                    .data()
+                    .iter()
+                    .for_each(|&b| line.push_hex(b as u32, 2));
+                if let Some(ms) = timestamp {
+                    line.push_hex(*ms as u32, 4);
+                }
+            }
+        }
+        line.push(CR);
+        line
+    }
+}
+
+/// Lawicel timestamp from a millisecond clock, wraps at 60 s
+pub fn timestamp(millis: u64) -> u16 {
+    (millis % TIMESTAMP_WRAP_MS) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_all(input: &[u8]) -> ([Option<Result<Command, Error>>; 4], usize) {
+        let mut decoder = Decoder::new();
+        let mut out = [None; 4];
+        let mut n = 0;
+        for &byte in input {
+            if let Some(result) = decoder.push(byte) {
+                out[n] = Some(result);
+                n += 1;
+            }
+        }
+        (out, n)
+    }
+
+    #[test]
+    fn channel_commands() {
+        assert_eq!(parse(b"O"), Ok(Command::Open));
+        assert_eq!(parse(b"L"), Ok(Command::ListenOnly));
+        assert_eq!(parse(b"C"), Ok(Command::Close));
+        assert_eq!(parse(b"S6"), Ok(Command::Bitrate(500_000)));
+        assert_eq!(parse(b"S8"), Ok(Command::Bitrate(1_000_000)));
+        assert_eq!(parse(b"S9"), Err(Error::Invalid));
+        assert_eq!(parse(b"Z1"), Ok(Command::Timestamps(true)));
+        assert_eq!(parse(b"M00000000"), Ok(Command::Ignored));
+        assert_eq!(parse(b"mFFFFFFFF"), Ok(Command::Ignored));
+        assert_eq!(parse(b"O1"), Err(Error::Invalid));
+        assert_eq!(parse(b"s4037"), Err(Error::Unknown));
+    }
+
+    #[test]
+    fn transmit_standard_and_extended() {
+        let std = Frame::new(Id::Standard(0x1F4), &[0xAA, 0xBB]).unwrap();
+        assert_eq!(parse(b"t1F42AABB"), Ok(Command::Transmit(std)));
+        let ext = Frame::new(Id::Extended(0x18DAF1DB), &[0x02, 0x10, 0x03]).unwrap();
+        assert_eq!(parse(b"T18DAF1DB3021003"), Ok(Command::Transmit(ext)));
+        let empty = Frame::new(Id::Standard(0x7FF), &[]).unwrap();
+        assert_eq!(parse(b"t7FF0"), Ok(Command::Transmit(empty)));
+        let remote = Frame::new_remote(Id::Extended(0x1FFFFFFF), 8).unwrap();
+        assert_eq!(parse(b"R1FFFFFFF8"), Ok(Command::Transmit(remote)));
+    }
+
+    #[test]
+    fn transmit_rejects_bad_frames() {
+        assert_eq!(parse(b"t8000"), Err(Error::Invalid)); // id above 0x7FF
+        assert_eq!(parse(b"T200000000"), Err(Error::Invalid)); // id above 29 bits
+        assert_eq!(parse(b"t1F42AA"), Err(Error::Invalid)); // short data
+        assert_eq!(parse(b"t1F41AABB"), Err(Error::Invalid)); // long data
+        assert_eq!(parse(b"t1F49"), Err(Error::Invalid)); // dlc above 8
+        assert_eq!(parse(b"t1F49112233445566778899"), Err(Error::Invalid));
+        assert_eq!(parse(b"t1F41GG"), Err(Error::Invalid));
+        assert_eq!(parse(b"r1F41AA"), Err(Error::Invalid));
+        assert_eq!(parse(b"t1F"), Err(Error::Invalid));
+    }
+
+    #[test]
+    fn decoder_splits_lines() {
+        let (out, n) = decode_all(b"\r\rS6\rO\r\nt1230\rQ\r");
+        assert_eq!(n, 4);
+        assert_eq!(out[0], Some(Ok(Command::Bitrate(500_000))));
+        assert_eq!(out[1], Some(Ok(Command::Open)));
+        let frame = Frame::new(Id::Standard(0x123), &[]).unwrap();
+        assert_eq!(out[2], Some(Ok(Command::Transmit(frame))));
+        assert_eq!(out[3], Some(Err(Error::Unknown)));
+    }
+
+    #[test]
+    fn decoder_recovers_after_overflow() {
+        let mut input = [b'T'; MAX_LINE + 10];
+        input[MAX_LINE + 9] = CR;
+        let mut decoder = Decoder::new();
+        let mut last = None;
+        for &byte in input.iter().chain(b"C\r") {
+            if let Some(result) = decoder.push(byte) {
+                assert!(last.is_none() || result == Ok(Command::Close));
+                last = Some(result);
+            }
+        }
+        assert_eq!(last, Some(Ok(Command::Close)));
+        assert_eq!(decode_all(&input).0[0], Some(Err(Error::TooLong)));
+    }
+
+    #[test]
+    fn encode_responses() {
+        assert_eq!(Response::Ok.encode().as_bytes(), b"\r");
+        assert_eq!(Response::Error.encode().as_bytes(), b"\x07");
+        assert_eq!(Response::Sent(Id::Standard(1)).encode().as_bytes(), b"z\r");
+        assert_eq!(Response::Sent(Id::Extended(1)).encode().as_bytes(), b"Z\r");
+        let version = Response::Version {
+            hardware: 0x10,
+            software: 0x13,
+        };
+        assert_eq!(version.encode().as_bytes(), b"V1013\r");
+        assert_eq!(
+            Response::SerialNumber(*b"TC01").encode().as_bytes(),
+            b"NTC01\r"
+        );
+        assert_eq!(Response::Status(0x08).encode().as_bytes(), b"F08\r");
+    }
+
+    #[test]
+    fn encode_frames() {
+        let frame = |f, timestamp| {
+            Response::Frame {
+                frame: f,
+                timestamp,
+            }
+            .encode()
+        };
+        let std = Frame::new(Id::Standard(0x1F4), &[0xAA, 0xBB]).unwrap();
+        assert_eq!(frame(std, None).as_bytes(), b"t1F42AABB\r");
+        assert_eq!(frame(std, Some(0x1A2B)).as_bytes(), b"t1F42AABB1A2B\r");
+        let ext = Frame::new(Id::Extended(0x18DAF1DB), &[0xFF; 8]).unwrap();
+        assert_eq!(
+            frame(ext, Some(59_999)).as_bytes(),
+            b"T18DAF1DB8FFFFFFFFFFFFFFFFEA5F\r"
+        );
+        let remote = Frame::new_remote(Id::Standard(0x00A), 4).unwrap();
+        assert_eq!(frame(remote, None).as_bytes(), b"r00A4\r");
+    }
+
+    #[test]
+    fn frames_roundtrip() {
+        let frames = [
+            Frame::new(Id::Standard(0), &[]).unwrap(),
+            Frame::new(Id::Standard(0x7FF), &[1, 2, 3, 4, 5, 6, 7, 8]).unwrap(),
+            Frame::new(Id::Extended(0x1FFFFFFF), &[0x55]).unwrap(),
+            Frame::new_remote(Id::Extended(0x100), 2).unwrap(),
+        ];
+        for f in frames {
+            let line = Response::Frame {
+                frame: f,
+                timestamp: None,
+            }
+            .encode();
+            let bytes = line.as_bytes();
+            assert_eq!(parse(&bytes[..bytes.len() - 1]), Ok(Command::Transmit(f)));
+        }
+    }
+
+    #[test]
+    fn timestamp_wraps_each_minute() {
+        assert_eq!(timestamp(0), 0);
+        assert_eq!(timestamp(59_999), 59_999);
+        assert_eq!(timestamp(60_000), 0);
+        assert_eq!(timestamp(123_456), 3_456);
+    }
+}
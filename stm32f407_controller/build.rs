@@ -27,4 +27,28 @@ fn main() {
 
     f.write_all(format!("const TZ: chrono_tz::Tz = {};\n", tz_code).as_bytes())
         .unwrap();
+
+    // CA certificate (DER) the MQTT broker certificate must chain to with the mqtt_tls feature
+    let dest_path = Path::new(&env::var("OUT_DIR").unwrap()).join("mqtt_ca.rs");
+    let mut f = File::create(dest_path).unwrap();
+    let ca = match env::var("MQTT_CA_DER") {
+        Ok(path) if !path.is_empty() => {
+            let path = Path::new(&path)
+                .canonicalize()
+                .unwrap_or_else(|_| panic!("MQTT_CA_DER file not found: {}", path));
+            println!("cargo:rerun-if-changed={}", path.display());
+            format!("Some(include_bytes!({:?}))", path)
+        }
+        _ => "None".to_string(),
+    };
+    f.write_all(format!("pub const MQTT_CA: Option<&[u8]> = {};\n", ca).as_bytes())
+        .unwrap();
+    // Fails the link when the image grows into the config store sectors
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    println!("cargo:rustc-link-search={}", manifest_dir);
+    println!("cargo:rustc-link-arg-bins=-Tstore.x");
+    println!("cargo:rerun-if-changed=store.x");
+    println!("cargo:rerun-if-env-changed=MQTT_CA_DER");
+    println!("cargo:rerun-if-env-changed=TIMEZONE");
+    println!("cargo:rerun-if-changed=build.rs");
 }
@@ -0,0 +1,181 @@
+use super::{Bus, Direction};
+use core::cell::RefCell;
+use core::fmt::Write;
+use core::ops::Range;
+use defmt::{warn, Format};
+use embassy_stm32::can::bxcan::{Frame, Id};
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex as _Mutex, Mutex};
+use embassy_time::Instant;
+use heapless::Vec;
+use miniserde::{Deserialize, Serialize};
+
+/// Frames kept in RAM, about 32 bytes each
+pub const CAPTURE_FRAMES: usize = 512;
+/// Frames still recorded after a trigger, so the capture shows the lead up and the aftermath
+const POST_TRIGGER_FRAMES: u32 = CAPTURE_FRAMES as u32 / 4;
+
+static CAPTURE: Mutex<_Mutex, RefCell<Capture>> = Mutex::new(RefCell::new(Capture::new()));
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum State {
+    Recording,
+    Triggered { remaining: u32 },
+    Frozen,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Format)]
+pub enum Command {
+    /// Clears the capture and starts recording
+    Arm,
+    /// Records POST_TRIGGER_FRAMES more frames, then freezes
+    Trigger,
+    /// Stops recording now
+    Freeze,
+}
+
+#[derive(Clone)]
+pub struct Record {
+    pub bus: Bus,
+    pub direction: Direction,
+    pub frame: Frame,
+    pub at: Instant,
+}
+
+impl Record {
+    /// One `candump -l -x` line: `(seconds.micros) can0 1F4#0102 R`
+    pub fn write_candump<W: Write>(&self, w: &mut W) -> core::fmt::Result {
+        let micros = self.at.as_micros();
+        write!(
+            w,
+            "({:010}.{:06}) {} ",
+            micros / 1_000_000,
+            micros % 1_000_000,
+            self.bus.interface()
+        )?;
+        match self.frame.id() {
+            Id::Standard(id) => write!(w, "{:03X}#", id.as_raw())?,
+            Id::Extended(id) => write!(w, "{:08X}#", id.as_raw())?,
+        }
+        match self.frame.data() {
+            Some(data) => {
+                for byte in data.iter() {
+                    write!(w, "{:02X}", byte)?;
+                }
+            }
+            None if self.frame.dlc() > 0 => write!(w, "R{}", self.frame.dlc())?,
+            None => w.write_char('R')?,
+        }
+        match self.direction {
+            Direction::Rx => w.write_str(" R\n"),
+            Direction::Tx => w.write_str(" T\n"),
+        }
+    }
+}
+
+/// Ring of the last CAPTURE_FRAMES frames, frame `n` of the session lives at `n % CAPTURE_FRAMES`
+struct Capture {
+    frames: Vec<Record, CAPTURE_FRAMES>,
+    recorded: u32,
+    state: State,
+}
+
+impl Capture {
+    const fn new() -> Self {
+        Self {
+            frames: Vec::new(),
+            recorded: 0,
+            state: State::Recording,
+        }
+    }
+
+    fn push(&mut self, record: Record) {
+        match self.state {
+            State::Frozen => return,
+            State::Triggered { remaining: 0 } => {
+                self.state = State::Frozen;
+                warn!("CAN capture frozen with {} frames", self.frames.len());
+                return;
+            }
+            State::Triggered { remaining } => {
+                self.state = State::Triggered {
+                    remaining: remaining - 1,
+                }
+            }
+            State::Recording => (),
+        }
+        let slot = self.recorded as usize % CAPTURE_FRAMES;
+        match self.frames.get_mut(slot) {
+            Some(old) => *old = record,
+            None => {
+                let _ = self.frames.push(record);
+            }
+        }
+        self.recorded = self.recorded.wrapping_add(1);
+    }
+
+    /// Empties the ring in place, a new Capture would be built on the stack inside the lock
+    fn reset(&mut self) {
+        self.frames.clear();
+        self.recorded = 0;
+        self.state = State::Recording;
+    }
+
+    fn window(&self) -> Range<u32> {
+        self.recorded.wrapping_sub(self.frames.len() as u32)..self.recorded
+    }
+}
+
+/// Called by the CAN tasks for every frame read or written
+pub fn record(bus: Bus, direction: Direction, frame: &Frame, at: Instant) {
+    CAPTURE.lock(|c| {
+        c.borrow_mut().push(Record {
+            bus,
+            direction,
+            frame: frame.clone(),
+            at,
+        })
+    });
+}
+
+/// Freezes the capture shortly after a fault, ignored unless recording
+pub fn trigger() {
+    CAPTURE.lock(|c| {
+        let mut c = c.borrow_mut();
+        if c.state == State::Recording {
+            warn!("CAN capture triggered");
+            c.state = State::Triggered {
+                remaining: POST_TRIGGER_FRAMES,
+            };
+        }
+    });
+}
+
+pub fn command(command: Command) {
+    match command {
+        Command::Arm => CAPTURE.lock(|c| c.borrow_mut().reset()),
+        Command::Trigger => trigger(),
+        Command::Freeze => CAPTURE.lock(|c| c.borrow_mut().state = State::Frozen),
+    }
+}
+
+pub fn state() -> State {
+    CAPTURE.lock(|c| c.borrow().state)
+}
+
+/// Sequence numbers currently held, pass them to `get` one at a time
+pub fn window() -> Range<u32> {
+    CAPTURE.lock(|c| c.borrow().window())
+}
+
+/// Frame `n` of the session, None once it has been overwritten
+pub fn get(n: u32) -> Option<Record> {
+    CAPTURE.lock(|c| {
+        let c = c.borrow();
+        let window = c.window();
+        // wrapping distance from the oldest frame so the u32 rolling over is harmless
+        if n.wrapping_sub(window.start) >= window.end.wrapping_sub(window.start) {
+            return None;
+        }
+        c.frames.get(n as usize % CAPTURE_FRAMES).cloned()
+    })
+}
@@ -0,0 +1,150 @@
+pub mod capture;
+
+use crate::types::FRAME_BUFFER;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use defmt::{error, Format};
+use embassy_stm32::can::bxcan::Frame;
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex as _Mutex,
+    channel::{Channel, Receiver, Sender},
+    pubsub::{self, PubSubChannel, Subscriber},
+};
+use embassy_time::Instant;
+
+/// Lossless subscribers per bus, the protocol processors
+const PROCESSORS: usize = 2;
+/// Lossy subscribers per bus, debug, logging and recorders
+const TAPS: usize = 4;
+const TAP_BUFFER: usize = 32;
+
+pub type FrameSubscriber = Subscriber<'static, _Mutex, Frame, FRAME_BUFFER, PROCESSORS, 1>;
+pub type TapSubscriber = Subscriber<'static, _Mutex, TappedFrame, TAP_BUFFER, TAPS, 0>;
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum Bus {
+    /// CAN1, the battery
+    Bms,
+    /// CAN2, the inverter
+    Inverter,
+}
+
+impl Bus {
+    /// SocketCAN style interface name used in captures
+    pub fn interface(&self) -> &'static str {
+        match self {
+            Bus::Bms => "can0",
+            Bus::Inverter => "can1",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum Direction {
+    Rx,
+    Tx,
+}
+
+/// A frame seen on the bus, `at` is when the CAN task read or wrote it
+#[derive(Clone)]
+pub struct TappedFrame {
+    pub direction: Direction,
+    pub frame: Frame,
+    pub at: Instant,
+}
+
+/// Traffic of one CAN interface.
+///
+/// Received frames go to every processor subscriber, the CAN task waits until each of
+/// them has room. Every frame in both directions is also kept in the capture ring.
+/// Taps get a copy of both directions without back pressure: a tap that falls behind
+/// loses the oldest frames (`WaitResult::Lagged`) and never holds up the bus or the
+/// processors.
+pub struct CanBus {
+    bus: Bus,
+    bitrate: AtomicU32,
+    auto_baud_failed: AtomicBool,
+    rx: PubSubChannel<_Mutex, Frame, FRAME_BUFFER, PROCESSORS, 1>,
+    tx: Channel<_Mutex, Frame, FRAME_BUFFER>,
+    taps: PubSubChannel<_Mutex, TappedFrame, TAP_BUFFER, TAPS, 0>,
+}
+
+impl CanBus {
+    pub const fn new(bus: Bus) -> Self {
+        Self {
+            bus,
+            bitrate: AtomicU32::new(0),
+            auto_baud_failed: AtomicBool::new(false),
+            rx: PubSubChannel::new(),
+            tx: Channel::new(),
+            taps: PubSubChannel::new(),
+        }
+    }
+
+    pub fn bus(&self) -> Bus {
+        self.bus
+    }
+
+    /// Set by the CAN task once the interface is configured
+    pub fn set_bitrate(&self, bitrate: u32) {
+        self.bitrate.store(bitrate, Ordering::Relaxed)
+    }
+
+    pub fn bitrate(&self) -> u32 {
+        self.bitrate.load(Ordering::Relaxed)
+    }
+
+    /// Set when auto-baud saw no usable traffic and the bus runs at the fallback rate
+    pub fn set_auto_baud_failed(&self) {
+        self.auto_baud_failed.store(true, Ordering::Relaxed)
+    }
+
+    pub fn auto_baud_failed(&self) -> bool {
+        self.auto_baud_failed.load(Ordering::Relaxed)
+    }
+
+    /// Every received frame, for a protocol processor
+    pub fn subscribe(&self) -> Result<FrameSubscriber, pubsub::Error> {
+        self.rx.subscriber()
+    }
+
+    /// Copy of the traffic in both directions, frames are dropped when the tap lags
+    pub fn tap(&self) -> Result<TapSubscriber, pubsub::Error> {
+        self.taps.subscriber()
+    }
+
+    /// Queues a frame for the CAN task to write
+    pub fn sender(&self) -> Sender<'_, _Mutex, Frame, FRAME_BUFFER> {
+        self.tx.sender()
+    }
+
+    /// Frames waiting to be written, for the CAN task
+    pub fn outgoing(&self) -> Receiver<'_, _Mutex, Frame, FRAME_BUFFER> {
+        self.tx.receiver()
+    }
+
+    /// Publishes a frame read from the bus
+    pub async fn received(&self, frame: Frame) {
+        self.tap_frame(Direction::Rx, &frame);
+        match self.rx.publisher() {
+            Ok(publisher) => publisher.publish(frame).await,
+            Err(e) => error!("CAN bus publisher {}", e),
+        }
+    }
+
+    /// Records a frame the CAN task wrote to the bus
+    pub fn transmitted(&self, frame: &Frame) {
+        self.tap_frame(Direction::Tx, frame);
+    }
+
+    fn tap_frame(&self, direction: Direction, frame: &Frame) {
+        let at = Instant::now();
+        capture::record(self.bus, direction, frame, at);
+        self.taps
+            .immediate_publisher()
+            .publish_immediate(TappedFrame {
+                direction,
+                frame: frame.clone(),
+                at,
+            });
+    }
+}
@@ -2,12 +2,12 @@ use core::str::FromStr;
 
 use crate::errors::StmError;
 use alloc::string::ToString;
+use alloc::vec::Vec;
 // use crate::tasks::ntp::Time;
 use bms_standard::MinMax;
-use defmt::error;
+use defmt::{error, Format};
 use miniserde::__private::String;
 use miniserde::{json, Deserialize, Serialize};
-use no_std_net::{Ipv4Addr, SocketAddr, SocketAddrV4};
 
 #[derive(Default, Serialize)]
 pub struct GlobalState {
@@ -26,6 +26,9 @@ impl GlobalState {
         self.state = state;
     }
     pub fn set_fault(&mut self, fault: Fault) {
+        if self.fault == Fault::None && fault != Fault::None {
+            crate::can_bus::capture::trigger();
+        }
         self.fault = fault;
     }
 }
@@ -83,6 +86,23 @@ impl NetConfig {
             }
         }
     }
+    /// Static settings must parse, DHCP ignores them
+    pub fn validate(&self) -> Result<(), StmError> {
+        if self.dhcp {
+            return Ok(());
+        }
+        match (&self.ip, &self.netmask) {
+            (Some(_), Some(netmask)) => {
+                embassy_net::Ipv4Address::from_str(netmask)
+                    .map_err(|_| StmError::InvalidIpDetails)?;
+            }
+            _ => return Err(StmError::InvalidIpDetails),
+        }
+        if let Some(gw) = &self.gateway {
+            embassy_net::Ipv4Address::from_str(gw).map_err(|_| StmError::InvalidIpDetails)?;
+        }
+        self.create_config().map(|_| ())
+    }
     fn create_config(&self) -> Result<embassy_net::Config, StmError> {
         let ipaddress = embassy_net::Ipv4Address::from_str(self.ip.as_ref().unwrap())
             .map_err(|_| StmError::InvalidIpDetails)?;
@@ -111,6 +131,70 @@ impl NetConfig {
     }
 }
 
+/// Battery and inverter protocols the firmware drives, read once at boot
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct ProtocolConfig {
+    pub battery: Battery,
+    pub inverter: Inverter,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Format)]
+pub enum Battery {
+    #[default]
+    Ze40,
+    Ze50,
+    TeslaM3,
+}
+
+impl Battery {
+    /// The pack closes its own contactors, inverter faults must not open ours
+    pub fn integrated_contactors(&self) -> bool {
+        matches!(self, Battery::TeslaM3)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Format)]
+pub enum Inverter {
+    #[default]
+    Solax,
+    FoxEss,
+    Byd,
+    Pylontech,
+    GoodWe,
+    ForceH2,
+}
+
+/// CAN bitrate of each bus, read once at boot
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct CanConfig {
+    pub bms: Bitrate,
+    pub inverter: Bitrate,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Format)]
+pub enum Bitrate {
+    Kbit125,
+    Kbit250,
+    #[default]
+    Kbit500,
+    Kbit1000,
+    /// Detected at boot by listening in silent mode
+    Auto,
+}
+
+impl Bitrate {
+    /// bit/s, None for Auto
+    pub fn bps(&self) -> Option<u32> {
+        match self {
+            Bitrate::Kbit125 => Some(125_000),
+            Bitrate::Kbit250 => Some(250_000),
+            Bitrate::Kbit500 => Some(500_000),
+            Bitrate::Kbit1000 => Some(1_000_000),
+            Bitrate::Auto => None,
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct MqttConfigBuilder {
     // Fields for the builder
@@ -123,6 +207,12 @@ pub struct MqttConfigBuilder {
     qos: u8,
     retain: bool,
     interval: u16,
+    min_interval: Option<u16>,
+    deadband: Option<Deadband>,
+    version: Option<u8>,
+    tls: Option<bool>,
+    psk_identity: Option<String>,
+    psk_key: Option<String>,
 }
 
 impl MqttConfigBuilder {
@@ -180,6 +270,33 @@ impl MqttConfigBuilder {
         self
     }
 
+    pub fn min_interval(mut self, min_interval: u16) -> Self {
+        self.min_interval = Some(min_interval);
+        self
+    }
+
+    pub fn deadband(mut self, deadband: Deadband) -> Self {
+        self.deadband = Some(deadband);
+        self
+    }
+
+    pub fn version(mut self, version: u8) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    pub fn tls(mut self, tls: bool) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// `key` is hex encoded
+    pub fn psk(mut self, identity: &str, key: &str) -> Self {
+        self.psk_identity = Some(identity.to_string());
+        self.psk_key = Some(key.to_string());
+        self
+    }
+
     // Build the MqttConfig
     pub fn build(self) -> MqttConfig {
         MqttConfig {
@@ -192,6 +309,40 @@ impl MqttConfigBuilder {
             qos: self.qos,
             retain: self.retain,
             interval: self.interval,
+            min_interval: self.min_interval,
+            deadband: self.deadband,
+            version: self.version,
+            tls: self.tls,
+            psk_identity: self.psk_identity,
+            psk_key: self.psk_key,
+        }
+    }
+}
+
+/// Change since the last MQTT publish that triggers an early publish,
+/// `interval` still forces one when nothing moves
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Deadband {
+    pub soc: f32,
+    pub volts: f32,
+    pub amps: f32,
+    pub kwh: f32,
+    pub cell_mv: u16,
+    pub temp: f32,
+    /// charge and discharge current limits
+    pub limits: f32,
+}
+
+impl Default for Deadband {
+    fn default() -> Self {
+        Self {
+            soc: 0.5,
+            volts: 0.5,
+            amps: 1.0,
+            kwh: 0.1,
+            cell_mv: 5,
+            temp: 1.0,
+            limits: 1.0,
         }
     }
 }
@@ -206,7 +357,20 @@ pub struct MqttConfig {
     basetopic: Option<String>,
     pub qos: u8,
     pub retain: bool,
+    /// Maximum seconds between publishes
     pub interval: u16,
+    /// Minimum seconds between publishes, rate limits deadband triggered publishes
+    min_interval: Option<u16>,
+    deadband: Option<Deadband>,
+    /// 3 for brokers that only speak MQTT 3.1.1, 5 by default
+    version: Option<u8>,
+    /// TLS 1.3 to the broker (usually port 8883), needs the `mqtt_tls` feature
+    tls: Option<bool>,
+    /// With `psk_key` selects a pre-shared key, otherwise the broker certificate
+    /// must be issued by the CA built in from MQTT_CA_DER, checked once NTP has the time
+    psk_identity: Option<String>,
+    /// Hex encoded
+    psk_key: Option<String>,
 }
 
 impl MqttConfig {
@@ -214,14 +378,58 @@ impl MqttConfig {
         MqttConfigBuilder::new()
     }
 
+    /// The broker settings firmware before the stored MqttConfig was built with (MQTTHOST,
+    /// MQTTPORT, ... at compile time), None when MQTTHOST was not set
+    pub fn from_env() -> Option<MqttConfig> {
+        let env = |value: Option<&'static str>| value.filter(|v| !v.is_empty());
+        let mut builder = MqttConfig::builder()
+            .host(env(option_env!("MQTTHOST"))?)
+            .port(
+                env(option_env!("MQTTPORT"))
+                    .and_then(|port| port.parse().ok())
+                    .unwrap_or(1883),
+            )
+            .qos(
+                env(option_env!("MQTTQOS"))
+                    .and_then(|qos| qos.parse().ok())
+                    .unwrap_or(0),
+            )
+            .retain(option_env!("MQTTRETAIN") == Some("true"));
+        if let Some(interval) = env(option_env!("MQTTINTERVAL")).and_then(|i| i.parse().ok()) {
+            builder = builder.interval(interval);
+        }
+        if let Some(client_id) = env(option_env!("MQTTCLIENTID")) {
+            builder = builder.client_id(client_id);
+        }
+        if let Some(username) = env(option_env!("MQTTUSERNAME")) {
+            builder = builder.username(username);
+        }
+        if let Some(password) = env(option_env!("MQTTPASSWORD")) {
+            builder = builder.password(password);
+        }
+        if let Some(basetopic) = env(option_env!("MQTTBASETOPIC")) {
+            builder = builder.basetopic(basetopic);
+        }
+        Some(builder.build())
+    }
+
     pub fn get_config(&self) -> &MqttConfig {
         self
     }
+    pub fn get_host(&self) -> &str {
+        self.host.as_deref().unwrap_or_default()
+    }
+    pub fn get_port(&self) -> u16 {
+        self.port.unwrap_or_default()
+    }
+    pub fn get_client_id(&self) -> &str {
+        self.client_id.as_deref().unwrap_or_default()
+    }
     pub fn get_username(&self) -> &str {
-        self.username.as_ref().unwrap()
+        self.username.as_deref().unwrap_or_default()
     }
     pub fn get_password(&self) -> &str {
-        self.password.as_ref().unwrap()
+        self.password.as_deref().unwrap_or_default()
     }
     pub fn get_qos(&self) -> u8 {
         self.qos
@@ -232,28 +440,71 @@ impl MqttConfig {
     pub fn get_interval(&self) -> u16 {
         self.interval
     }
+    pub fn get_min_interval(&self) -> u16 {
+        self.min_interval.unwrap_or(1)
+    }
+    pub fn get_deadband(&self) -> Deadband {
+        self.deadband.unwrap_or_default()
+    }
+    pub fn get_version(&self) -> u8 {
+        self.version.unwrap_or(5)
+    }
+    pub fn get_tls(&self) -> bool {
+        self.tls.unwrap_or_default()
+    }
+    /// Identity and decoded key if a PSK is configured
+    pub fn get_psk(&self) -> Option<(&str, Vec<u8>)> {
+        let identity = self.psk_identity.as_deref().filter(|id| !id.is_empty())?;
+        let key = decode_hex(self.psk_key.as_deref()?)?;
+        Some((identity, key))
+    }
     pub fn get_topic(&self) -> &str {
-        self.basetopic.as_ref().unwrap()
+        self.basetopic.as_deref().unwrap_or_default()
     }
-}
-
-impl TryFrom<&MqttConfig> for SocketAddr {
-    type Error = StmError;
-
-    fn try_from(conf: &MqttConfig) -> Result<Self, Self::Error> {
-        if conf.host.is_none() {
-            return Err(StmError::BadMqttIp);
-        };
-        if conf.port.is_none() {
-            return Err(StmError::BadMqttPort);
+    /// Carries the retained birth ("online") and will ("offline") messages
+    pub fn availability_topic(&self) -> String {
+        alloc::format!("{}/availability", self.get_topic())
+    }
+    pub fn validate(&self) -> Result<(), StmError> {
+        match &self.host {
+            Some(host) if !host.trim().is_empty() => (),
+            _ => return Err(StmError::BadMqttIp),
         }
-
-        let ip =
-            Ipv4Addr::from_str(conf.host.as_ref().unwrap()).map_err(|_| StmError::BadMqttIp)?;
-        Ok(SocketAddr::V4(SocketAddrV4::new(ip, conf.port.unwrap())))
+        match self.port {
+            Some(port) if port != 0 => (),
+            _ => return Err(StmError::BadMqttPort),
+        }
+        if self.qos > 2
+            || self.interval == 0
+            || self.get_min_interval() > self.interval
+            || !matches!(self.get_version(), 3 | 5)
+        {
+            return Err(StmError::InvalidMqttConfig);
+        }
+        if self.get_tls() && !cfg!(feature = "mqtt_tls") {
+            return Err(StmError::InvalidMqttConfig);
+        }
+        // a half configured PSK would silently fall back to the CA
+        if self.psk_identity.is_some() != self.psk_key.is_some()
+            || (self.psk_key.is_some() && self.get_psk().is_none())
+        {
+            return Err(StmError::InvalidMqttConfig);
+        }
+        Ok(())
     }
 }
 
+/// Hex PSK of up to 64 bytes
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.is_empty() || hex.len() % 2 != 0 || hex.len() > 128 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
 /*
 impl MqttConfig {
     pub fn new(
@@ -305,12 +556,34 @@ where
 impl JsonTrait for Config {}
 impl JsonTrait for MqttConfig {}
 impl JsonTrait for NetConfig {}
+impl JsonTrait for ProtocolConfig {}
+impl JsonTrait for CanConfig {}
 
 impl Config {
     pub fn pack_volts(&self) -> &MinMax<f32> {
         &self.pack_volts
     }
 
+    /// Rejects inverted ranges and percentages above 100
+    pub fn validate(&self) -> Result<(), StmError> {
+        let ordered = ordered(&self.charge_current)
+            && ordered(&self.discharge_current)
+            && ordered(&self.current_sensor)
+            && ordered(&self.pack_volts)
+            && ordered(&self.cell_temperatures)
+            && ordered(&self.pack_temperatures)
+            && ordered(&self.cells_mv)
+            && ordered(&self.soc)
+            && ordered(&self.dod);
+        if !ordered || *self.soc.maximum() > 100 || *self.dod.maximum() > 100 {
+            return Err(StmError::InvalidConfigRange);
+        }
+        if *self.cells_mv.maximum() > self.cell_millivolt_peak {
+            return Err(StmError::InvalidConfigRange);
+        }
+        Ok(())
+    }
+
     pub fn export_as_bms(&self) -> bms_standard::Config {
         self.into()
     }
@@ -327,6 +600,10 @@ impl Config {
         self.soc = *bms_config.soc();
     }
 }
+fn ordered<T: PartialOrd>(range: &MinMax<T>) -> bool {
+    range.minimum() <= range.maximum()
+}
+
 impl From<&Config> for bms_standard::Config {
     fn from(val: &Config) -> Self {
         bms_standard::Config {
@@ -368,7 +645,7 @@ pub enum State {
     #[default]
     Offline,
 }
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
 pub enum Fault {
     InvFault,
     BmsFault,
@@ -11,6 +11,12 @@ pub enum StmError {
     InvalidDnsDetails,
     BadMqttIp,
     BadMqttPort,
+    InvalidMqttConfig,
+    InvalidConfigRange,
+    InvalidHostname,
+    DnsLookupFailed,
+    StorageUnavailable,
+    Storage(flash_store::StoreError),
 }
 impl core::error::Error for StmError {}
 impl core::fmt::Display for StmError {
@@ -27,6 +33,18 @@ impl core::fmt::Display for StmError {
             StmError::InvalidDnsDetails => write!(f, "InvalidDnsDetails"),
             StmError::BadMqttIp => write!(f, "BadMqttIp"),
             StmError::BadMqttPort => write!(f, "BadMqttPort"),
+            StmError::InvalidMqttConfig => write!(f, "InvalidMqttConfig"),
+            StmError::InvalidConfigRange => write!(f, "InvalidConfigRange"),
+            StmError::InvalidHostname => write!(f, "InvalidHostname"),
+            StmError::DnsLookupFailed => write!(f, "DnsLookupFailed"),
+            StmError::StorageUnavailable => write!(f, "StorageUnavailable"),
+            StmError::Storage(e) => write!(f, "Storage {}", e),
         }
     }
 }
+
+impl From<flash_store::StoreError> for StmError {
+    fn from(e: flash_store::StoreError) -> Self {
+        StmError::Storage(e)
+    }
+}
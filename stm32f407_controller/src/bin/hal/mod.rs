@@ -191,6 +191,27 @@ pub fn can2(p: CAN2, rx: PB5, tx: PB6) -> Can<'static, CAN2> {
     Can::new(p, rx, tx, IrqCAN)
 }
 
+#[cfg(feature = "slcan")]
+pub fn usb(p: USB_OTG_FS, dp: PA12, dm: PA11) -> crate::types::UsbDriver {
+    bind_interrupts!(struct IrqUSB {
+        OTG_FS => embassy_stm32::usb_otg::InterruptHandler<peripherals::USB_OTG_FS>;
+    });
+    static EP_OUT_BUFFER: StaticCell<[u8; 256]> = StaticCell::new();
+    let mut config = embassy_stm32::usb_otg::Config::default();
+    // PA9 is USART1 TX on this board, VBUS is not sensed
+    config.vbus_detection = false;
+    embassy_stm32::usb_otg::Driver::new_fs(p, IrqUSB, dp, dm, EP_OUT_BUFFER.init([0; 256]), config)
+}
+
+/// Hardware RNG, the TLS key exchange needs a cryptographic source
+#[cfg(feature = "mqtt_tls")]
+pub fn tls_rng(p: RNG) -> crate::types::TlsRng {
+    bind_interrupts!(struct IrqRNG {
+        HASH_RNG => rng::InterruptHandler<peripherals::RNG>;
+    });
+    Rng::new(p, IrqRNG)
+}
+
 #[allow(clippy::too_many_arguments)]
 pub async fn get_eth(
     // prng: Rng,
@@ -18,32 +18,24 @@ use embedded_alloc::Heap;
 use hal::*;
 use static_cell::StaticCell;
 
-#[cfg(any(
-    feature = "solax",
-    feature = "foxess",
-    feature = "byd",
-    feature = "pylontech",
-    feature = "goodwe",
-    feature = "forceh2"
-))]
-use crate::tasks::{bms_rx, bms_tx_periodic, inverter_rx};
+use crate::tasks::protocols::{bms_filter, bms_rx, bms_tx_periodic, inverter_rx};
 
 #[cfg(feature = "ntp")]
 use embassy_stm32::rtc::{Rtc, RtcConfig};
 #[cfg(feature = "syslog")]
 use syslog_emb::{SyslogMessage, SyslogSocket};
 
+mod can_bus;
 pub mod config;
 mod errors;
 mod hal;
 mod statics;
+mod storage;
 mod tasks;
 mod types;
 mod utils;
-mod web;
-
-#[cfg(any(feature = "ze40", feature = "ze50", feature = "tesla_m3"))]
 mod wdt;
+mod web;
 
 #[global_allocator]
 static HEAP: Heap = Heap::empty();
@@ -54,7 +46,6 @@ pub const MAC_ADDR: [u8; 6] = [0x00, 0x01, 0xDE, 0xAD, 0xBE, 0xEF]; // prod_devi
 
 #[embassy_executor::main]
 async fn main(spawner: Spawner) -> () {
-    check_compile_features();
     let p = embassy_stm32::init(peripherals_config());
     info!("Init!");
 
@@ -94,6 +85,11 @@ async fn main(spawner: Spawner) -> () {
     let can2 = can2(p.CAN2, p.PB5, p.PB6);
     info!("CAN peripherals initialized");
 
+    // Restore persisted settings before the network stack and tasks read them
+    storage::init(p.FLASH).await;
+    let config_restored = storage::load_all().await;
+    info!("Config store initialized");
+
     let (device, netconfig, seed) = get_eth(
         p.ETH, p.PA1, p.PA2, p.PC1, p.PA7, p.PC4, p.PC5, p.PB12, p.PB13, p.PB11,
     )
@@ -117,8 +113,18 @@ async fn main(spawner: Spawner) -> () {
 
     */
 
+    if config_restored {
+        // persisted config replaces the compiled in defaults
+        let mut bms = crate::statics::BMS.lock().await;
+        let config = crate::statics::CONFIG.lock().await;
+        bms.config = config.export_as_bms();
+        if let Err(e) = bms.set_dod(*config.dod.minimum(), *config.dod.maximum()) {
+            defmt::error!("Stored DoD rejected: {}", defmt::Debug2Format(&e))
+        }
+    }
+
     #[cfg(feature = "v65")]
-    {
+    if !config_restored {
         // modify default config for Bms
         let mut bms = crate::statics::BMS.lock().await;
         bms.config.set_discharge_limts(0.0, 250.0).unwrap();
@@ -133,7 +139,7 @@ async fn main(spawner: Spawner) -> () {
     }
 
     #[cfg(not(feature = "v65"))]
-    {
+    if !config_restored {
         // modify default config for Bms
         let mut bms = crate::statics::BMS.lock().await;
         bms.config.set_discharge_limts(0.0, 35.0).unwrap();
@@ -165,24 +171,37 @@ async fn main(spawner: Spawner) -> () {
         )));
     }
 
-    #[cfg(any(feature = "ze40", feature = "ze50", feature = "tesla_m3"))]
-    defmt::unwrap!(spawner.spawn(bms_rx()));
-    #[cfg(any(
-        feature = "solax",
-        feature = "foxess",
-        feature = "byd",
-        feature = "goodwe",
-        feature = "pylontech",
-        feature = "forceh2"
-    ))]
-    {
-        defmt::unwrap!(spawner.spawn(inverter_rx()));
-        defmt::unwrap!(spawner.spawn(bms_tx_periodic()));
-    }
+    let protocols = *crate::statics::PROTOCOLS.lock().await;
+    info!(
+        "Battery {} inverter {}",
+        protocols.battery, protocols.inverter
+    );
+    defmt::unwrap!(spawner.spawn(bms_rx(protocols.battery)));
+    defmt::unwrap!(spawner.spawn(inverter_rx(protocols.inverter)));
+    defmt::unwrap!(spawner.spawn(bms_tx_periodic(protocols.battery)));
     // always start can 1 first
 
-    defmt::unwrap!(spawner.spawn(crate::tasks::can_interfaces::bms_task(can1, 500_000)));
-    defmt::unwrap!(spawner.spawn(crate::tasks::can_interfaces::inverter_task(can2, 500_000)));
+    let can_config = *crate::statics::CAN_CONFIG.lock().await;
+    info!(
+        "CAN bitrates bms {} inverter {}",
+        can_config.bms, can_config.inverter
+    );
+    defmt::unwrap!(spawner.spawn(crate::tasks::can_interfaces::bms_task(
+        can1,
+        can_config.bms,
+        bms_filter(protocols.battery)
+    )));
+    defmt::unwrap!(spawner.spawn(crate::tasks::can_interfaces::inverter_task(
+        can2,
+        can_config.inverter
+    )));
+
+    #[cfg(feature = "slcan")]
+    unwrap!(spawner.spawn(tasks::usb_slcan::usb_task(hal::usb(
+        p.USB_OTG_FS,
+        p.PA12,
+        p.PA11
+    ))));
 
     // Launch network task
     unwrap!(spawner.spawn(hal::net_task(stack)));
@@ -191,7 +210,7 @@ async fn main(spawner: Spawner) -> () {
     stack.wait_config_up().await;
     info!("Network is up");
 
-    #[cfg(all(not(feature = "tcp_debug"), feature = "http"))]
+    #[cfg(all(not(feature = "gvret"), feature = "http"))]
     unwrap!(spawner.spawn(web::http::http_net_task(stack)));
 
     #[cfg(feature = "ntp")]
@@ -199,8 +218,12 @@ async fn main(spawner: Spawner) -> () {
     #[cfg(feature = "ntp")]
     unwrap!(spawner.spawn(tasks::ntp::ntp_task(stack, rtc)));
 
-    #[cfg(all(feature = "tcp_debug", not(feature = "http")))]
-    unwrap!(spawner.spawn(tasks::tcp_debug::debug_task(stack)));
+    #[cfg(all(feature = "gvret", not(feature = "http")))]
+    unwrap!(spawner.spawn(tasks::gvret::gvret_task(stack)));
+    #[cfg(feature = "mqtt_tls")]
+    {
+        *crate::statics::TLS_RNG.lock().await = Some(tls_rng(p.RNG));
+    }
     #[cfg(feature = "mqtt")]
     unwrap!(spawner.spawn(tasks::mqtt::mqtt_net_task(stack)));
 
@@ -212,18 +235,3 @@ async fn main(spawner: Spawner) -> () {
         embassy_time::Timer::after(embassy_time::Duration::from_secs(10)).await;
     }
 }
-
-fn check_compile_features() {
-    #[cfg(any(
-        all(feature = "ze40", feature = "ze50"),
-        all(feature = "ze40", feature = "tesla_m3"),
-        all(feature = "ze50", feature = "tesla_m3"),
-        all(feature = "solax", feature = "foxess"),
-        all(feature = "solax", feature = "byd"),
-        all(feature = "solax", feature = "pylontech"),
-        all(feature = "foxess", feature = "byd"),
-        all(feature = "foxess", feature = "pylontech"),
-        all(feature = "byd", feature = "pylontech")
-    ))]
-    compile_error!("Only one feature in each group can be enabled at a time.");
-}
@@ -1,29 +1,37 @@
 #[cfg(feature = "mqtt")]
 use crate::tasks::mqtt::MqttFormat;
 use crate::{
-    config::{Config, GlobalState, MqttConfig, NetConfig},
+    can_bus::{Bus, CanBus},
+    config::{CanConfig, Config, GlobalState, MqttConfig, NetConfig, ProtocolConfig},
     types::*,
 };
-use embassy_sync::{channel::Channel, mutex::Mutex, signal::Signal};
+use embassy_sync::{mutex::Mutex, signal::Signal};
 use lazy_static::lazy_static;
 
-pub static INVERTER_CHANNEL_RX: InverterChannelRx = Channel::new();
-pub static INVERTER_CHANNEL_TX: InverterChannelTx = Channel::new();
-pub static BMS_CHANNEL_RX: BmsChannelRx = Channel::new();
-pub static BMS_CHANNEL_TX: BmsChannelTx = Channel::new();
+pub static INVERTER_CAN: CanBus = CanBus::new(Bus::Inverter);
+pub static BMS_CAN: CanBus = CanBus::new(Bus::Bms);
 pub static CAN_READY: Status = Signal::new();
 
-#[cfg(any(feature = "ze40", feature = "ze50", feature = "tesla_m3"))]
 pub static LAST_BMS_MESSAGE: Elapsed = Mutex::new(None);
-#[cfg(any(feature = "ze40", feature = "ze50", feature = "tesla_m3"))]
 pub static WDT: Status = Signal::new();
 pub static CONTACTOR_STATE: Status = Signal::new();
+pub static CONTACTOR_FORCE_OPEN: MutexType<bool> = Mutex::new(false);
 #[cfg(feature = "mqtt")]
 pub static SEND_MQTT: Status = Signal::new();
+// a Signal wakes a single waiter, SEND_MQTT belongs to the UART task
+#[cfg(feature = "mqtt")]
+pub static MQTT_READING: Status = Signal::new();
 pub static LED_COMMAND: LedCommandType = Signal::new();
+pub static MQTT_CONFIG_CHANGED: Status = Signal::new();
+#[cfg(feature = "mqtt_tls")]
+pub static TLS_RNG: MutexType<Option<TlsRng>> = Mutex::new(None);
+pub static CONFIG_STORE: MutexType<Option<crate::storage::ConfigStoreType>> = Mutex::new(None);
 
 #[cfg(feature = "ntp")]
 pub static UTC_NOW: EpochType = Signal::new();
+/// Unix time in seconds at boot (Instant zero), 0 until NTP has answered
+#[cfg(feature = "ntp")]
+pub static BOOT_UNIX_SECS: core::sync::atomic::AtomicU32 = core::sync::atomic::AtomicU32::new(0);
 // #[cfg(any(feature = "ze40"))]
 
 lazy_static! {
@@ -33,6 +41,8 @@ lazy_static! {
 
 
     pub static ref CONFIG: MutexType<Config> = Mutex::new(Config::default());
+    pub static ref PROTOCOLS: MutexType<ProtocolConfig> = Mutex::new(ProtocolConfig::default());
+    pub static ref CAN_CONFIG: MutexType<CanConfig> = Mutex::new(CanConfig::default());
     pub static ref GLOBALSTATE: MutexType<GlobalState> = Mutex::new(GlobalState::default());
     pub static ref BMS: MutexType<bms_standard::Bms> = Mutex::new(bms_standard::Bms::new(bms_standard::Config::default()));
 }
@@ -41,15 +51,6 @@ lazy_static! {
     pub static ref MQTTFMT: MutexType<MqttFormat> = Mutex::new(MqttFormat::default());
 }
 
-#[cfg(any(
-    feature = "solax",
-    feature = "foxess",
-    feature = "byd",
-    feature = "pylontech",
-    feature = "goodwe",
-    feature = "forceh2",
-    feature = "mqtt",
-))]
 pub const LAST_READING_TIMEOUT_SECS: u64 = 10; // move to config
 
 #[macro_export]
@@ -0,0 +1,139 @@
+use crate::config::{CanConfig, Config, JsonTrait, MqttConfig, NetConfig, ProtocolConfig};
+use crate::errors::StmError;
+use crate::statics::{CAN_CONFIG, CONFIG, CONFIG_STORE, MQTTCONFIG, NETCONFIG, PROTOCOLS};
+use defmt::{error, info, warn};
+use embassy_stm32::flash::{Blocking, Flash};
+use embassy_stm32::peripherals::FLASH;
+use flash_store::ConfigStore;
+
+// Sectors 6 and 7 of the STM32F407VE (128KiB each), the firmware image must stay below 256KiB,
+// store.x fails the link otherwise.
+// Erasing a sector stalls the core for ~1s, this only happens when a sector fills up.
+const STORE_SECTORS: [u32; 2] = [0x4_0000, 0x6_0000];
+const STORE_SECTOR_SIZE: u32 = 128 * 1024;
+const RECORD_BUF: usize = 1024;
+
+pub type ConfigStoreType = ConfigStore<Flash<'static, Blocking>>;
+
+/// Flash record key and layout version of a `JsonTrait` type.
+/// Bump `VERSION` when stored JSON from older firmware can no longer be decoded,
+/// the stale record is then ignored and the type's default is used.
+pub trait Persist: JsonTrait {
+    const KEY: u8;
+    const VERSION: u8;
+    const NAME: &'static str;
+}
+
+impl Persist for Config {
+    const KEY: u8 = 0;
+    const VERSION: u8 = 1;
+    const NAME: &'static str = "Config";
+}
+impl Persist for NetConfig {
+    const KEY: u8 = 1;
+    const VERSION: u8 = 1;
+    const NAME: &'static str = "NetConfig";
+}
+impl Persist for MqttConfig {
+    const KEY: u8 = 2;
+    const VERSION: u8 = 1;
+    const NAME: &'static str = "MqttConfig";
+}
+impl Persist for ProtocolConfig {
+    const KEY: u8 = 3;
+    const VERSION: u8 = 1;
+    const NAME: &'static str = "ProtocolConfig";
+}
+impl Persist for CanConfig {
+    const KEY: u8 = 4;
+    const VERSION: u8 = 1;
+    const NAME: &'static str = "CanConfig";
+}
+
+pub async fn init(flash: FLASH) {
+    let flash = Flash::new_blocking(flash);
+    match ConfigStore::new(flash, STORE_SECTORS, STORE_SECTOR_SIZE) {
+        Ok(store) => *CONFIG_STORE.lock().await = Some(store),
+        Err(e) => error!("Config store mount failed: {}", e),
+    }
+}
+
+/// Decodes the stored record into `value`, returns false if there was nothing to load
+pub async fn load<T: Persist>(value: &mut T) -> Result<bool, StmError> {
+    let mut store = CONFIG_STORE.lock().await;
+    let store = store.as_mut().ok_or(StmError::StorageUnavailable)?;
+    let mut buf = [0u8; RECORD_BUF];
+    match store.read(T::KEY, &mut buf)? {
+        None => Ok(false),
+        Some(record) if record.version != T::VERSION => {
+            warn!(
+                "Stored {} is version {}, expected {}",
+                T::NAME,
+                record.version,
+                T::VERSION
+            );
+            Ok(false)
+        }
+        Some(record) => {
+            value.decode_from_json(&buf[..record.len])?;
+            Ok(true)
+        }
+    }
+}
+
+/// Writes `value` to flash. A sector erase blocks the executor for ~1s, every task including
+/// CAN stalls, so callers save before taking the config locks where they can.
+pub async fn save<T: Persist>(value: &T) -> Result<(), StmError> {
+    let json = value.to_json();
+    let mut store = CONFIG_STORE.lock().await;
+    let store = store.as_mut().ok_or(StmError::StorageUnavailable)?;
+    store.write(T::KEY, T::VERSION, json.as_bytes())?;
+    info!("{} saved to flash", T::NAME);
+    Ok(())
+}
+
+/// Restores CONFIG, NETCONFIG, MQTTCONFIG, PROTOCOLS and CAN_CONFIG, returns true if CONFIG was restored
+pub async fn load_all() -> bool {
+    let config = restore(&mut *CONFIG.lock().await).await;
+    restore(&mut *NETCONFIG.lock().await).await;
+    if !restore(&mut *MQTTCONFIG.lock().await).await {
+        migrate_mqtt_config().await;
+    }
+    restore(&mut *PROTOCOLS.lock().await).await;
+    restore(&mut *CAN_CONFIG.lock().await).await;
+    config
+}
+
+/// Devices upgraded from firmware with compile time broker settings have no stored
+/// MqttConfig, keep them publishing by storing the settings this image was built with
+async fn migrate_mqtt_config() {
+    let Some(config) = MqttConfig::from_env() else {
+        return;
+    };
+    if let Err(e) = config.validate() {
+        warn!("Build time MqttConfig is invalid: {}", e);
+        return;
+    }
+    match save(&config).await {
+        Ok(()) => info!("MqttConfig migrated from build time settings"),
+        Err(e) => error!("MqttConfig migration save error {}, used until restart", e),
+    }
+    *MQTTCONFIG.lock().await = config;
+}
+
+async fn restore<T: Persist>(value: &mut T) -> bool {
+    match load(value).await {
+        Ok(true) => {
+            info!("{} restored from flash", T::NAME);
+            true
+        }
+        Ok(false) => {
+            info!("No stored {}, using defaults", T::NAME);
+            false
+        }
+        Err(e) => {
+            error!("{} restore failed: {}", T::NAME, e);
+            false
+        }
+    }
+}
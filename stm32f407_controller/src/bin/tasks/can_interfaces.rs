@@ -1,75 +1,151 @@
 use crate::{
+    can_bus::CanBus,
+    config::Bitrate,
     statics::*,
     tasks::leds::{
         Led::{Led1, Led2},
         LedCommand::Toggle,
     },
-    types::FRAME_BUFFER,
 };
-use defmt::warn;
+use defmt::{info, warn};
 use embassy_futures::select::{select, Either};
 use embassy_stm32::{
-    can::{
-        bxcan::{self, *},
-        Can, CanRx, CanTx,
-    },
+    can::{bxcan::*, Can, CanRx, CanTx, Instance},
+    pac,
     peripherals::*,
 };
-use embassy_sync::{
-    blocking_mutex::raw::CriticalSectionRawMutex,
-    channel::{Receiver, Sender},
-};
-use embassy_time::Duration;
+use embassy_time::{with_timeout, Duration, Instant};
+
+/// Rates tried in auto-baud mode, most common first
+const AUTO_BAUD_RATES: [u32; 4] = [500_000, 250_000, 125_000, 1_000_000];
+/// Listening time at each rate
+const AUTO_BAUD_LISTEN_MS: u64 = 1000;
+/// Valid frames without receive errors needed to lock onto a rate
+const AUTO_BAUD_FRAMES: usize = 3;
+/// Rounds over all rates before giving up
+const AUTO_BAUD_PASSES: usize = 3;
+/// Used when auto-baud finds nothing
+const FALLBACK_BITRATE: u32 = 500_000;
 
 #[embassy_executor::task]
-pub async fn inverter_task(mut can: Can<'static, CAN2>, baud: u32) {
-    let inv_rx = INVERTER_CHANNEL_RX.sender();
-    let inv_tx = INVERTER_CHANNEL_TX.receiver();
-    // Wait for CAN1 to initalise
+pub async fn inverter_task(mut can: Can<'static, CAN2>, bitrate: Bitrate) {
+    // Wait for CAN1 to apply the shared filters, the two buses then detect in parallel
     CAN_READY.wait().await;
     can2_init(&mut can).await;
-    can.set_bitrate(baud);
-    can.enable().await;
+    start(&mut can, bitrate, pac::CAN2, &INVERTER_CAN).await;
 
     warn!("Starting Inverter Can2");
     let (mut tx, mut rx) = can.split();
     loop {
         LED_COMMAND.signal(Toggle(Led2));
-        can_routine::<CAN2, FRAME_BUFFER>(&mut rx, &mut tx, inv_rx, inv_tx).await;
+        can_routine(&mut rx, &mut tx, &INVERTER_CAN).await;
     }
 }
 
 #[embassy_executor::task]
-pub async fn bms_task(mut can: Can<'static, CAN1>, baud: u32) {
-    let bms_rx = BMS_CHANNEL_RX.sender();
-    let bms_tx = BMS_CHANNEL_TX.receiver();
-    can1_init(&mut can).await;
-    can.set_bitrate(baud);
-    can.enable().await;
-    warn!("Starting BMS Can1");
+pub async fn bms_task(mut can: Can<'static, CAN1>, bitrate: Bitrate, filter: filter::Mask32) {
+    can1_init(&mut can, filter).await;
     // Signal to CAN2 that filters have been applied
     CAN_READY.signal(true);
+    start(&mut can, bitrate, pac::CAN1, &BMS_CAN).await;
+    warn!("Starting BMS Can1");
     let (mut tx, mut rx) = can.split();
     loop {
         LED_COMMAND.signal(Toggle(Led1));
-        can_routine::<CAN1, FRAME_BUFFER>(&mut rx, &mut tx, bms_rx, bms_tx).await;
+        can_routine(&mut rx, &mut tx, &BMS_CAN).await;
     }
 }
 
+/// Enables the interface at the configured rate, or the detected one for Bitrate::Auto
+async fn start<C: Instance>(
+    can: &mut Can<'static, C>,
+    bitrate: Bitrate,
+    regs: pac::can::Can,
+    bus: &CanBus,
+) {
+    let rate = match bitrate.bps() {
+        Some(rate) => rate,
+        None => match detect_bitrate(can, regs, bus).await {
+            Some(rate) => {
+                warn!("{} auto-baud locked at {} bit/s", bus.bus(), rate);
+                rate
+            }
+            None => {
+                warn!(
+                    "{} auto-baud found no traffic, using {} bit/s",
+                    bus.bus(),
+                    FALLBACK_BITRATE
+                );
+                bus.set_auto_baud_failed();
+                FALLBACK_BITRATE
+            }
+        },
+    };
+    can.as_mut()
+        .modify_config()
+        .set_silent(false)
+        .leave_disabled();
+    can.set_bitrate(rate);
+    bus.set_bitrate(rate);
+    can.enable().await;
+}
+
+/// Listens in silent mode at each candidate rate. A rate works when frames arrive and
+/// the receive error counter does not rise. Silent mode sends no ACK, so this needs
+/// another node on the bus acknowledging the traffic.
+async fn detect_bitrate<C: Instance>(
+    can: &mut Can<'static, C>,
+    regs: pac::can::Can,
+    bus: &CanBus,
+) -> Option<u32> {
+    for _ in 0..AUTO_BAUD_PASSES {
+        for rate in AUTO_BAUD_RATES {
+            can.as_mut()
+                .modify_config()
+                .set_silent(true)
+                .leave_disabled();
+            can.set_bitrate(rate);
+            can.enable().await;
+
+            let errors_before = regs.esr().read().rec();
+            let deadline = Instant::now() + Duration::from_millis(AUTO_BAUD_LISTEN_MS);
+            let (mut frames, mut bus_errors) = (0, 0);
+            while frames < AUTO_BAUD_FRAMES {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                match with_timeout(remaining, can.read()).await {
+                    Ok(Ok(_)) => frames += 1,
+                    Ok(Err(_)) => bus_errors += 1,
+                    Err(_) => break,
+                }
+            }
+            let errors = regs.esr().read().rec();
+            info!(
+                "{} auto-baud {} bit/s: {} frames, {} bus errors, REC {} -> {}",
+                bus.bus(),
+                rate,
+                frames,
+                bus_errors,
+                errors_before,
+                errors
+            );
+            if frames == AUTO_BAUD_FRAMES && bus_errors == 0 && errors <= errors_before {
+                return Some(rate);
+            }
+        }
+    }
+    None
+}
+
 #[inline]
-async fn can_routine<C, const B: usize>(
-    rx: &mut CanRx<'_, '_, C>,
-    tx: &mut CanTx<'_, '_, C>,
-    ch_rx: Sender<'_, CriticalSectionRawMutex, bxcan::Frame, B>,
-    ch_tx: Receiver<'_, CriticalSectionRawMutex, bxcan::Frame, B>,
-) where
+async fn can_routine<C>(rx: &mut CanRx<'_, '_, C>, tx: &mut CanTx<'_, '_, C>, bus: &CanBus)
+where
     C: embassy_stm32::can::Instance,
 {
     let name = core::any::type_name::<C>();
-    match select(rx.wait_not_empty(), ch_tx.receive()).await {
+    match select(rx.wait_not_empty(), bus.outgoing().receive()).await {
         Either::First(_) => {
             match rx.read().await {
-                Ok(envelope) => ch_rx.send(envelope.frame).await,
+                Ok(envelope) => bus.received(envelope.frame).await,
                 Err(e) => {
                     defmt::error!("{} {}", name, e);
                     embassy_time::Timer::after(Duration::from_millis(50)).await;
@@ -78,28 +154,17 @@ async fn can_routine<C, const B: usize>(
         }
         Either::Second(frame) => {
             tx.write(&frame).await;
+            bus.transmitted(&frame);
         }
     }
 }
 
-async fn can1_init(can: &mut Can<'static, CAN1>) {
+async fn can1_init(can: &mut Can<'static, CAN1>, bms_filter: filter::Mask32) {
     // BMS Filter ============================================
-    #[cfg(feature = "ze50")]
-    can.as_mut().modify_filters().set_split(1).enable_bank(
-        0,
-        Fifo::Fifo1,
-        filter::Mask32::frames_with_ext_id(
-            ExtendedId::new(0x18DAF1DB).unwrap(),
-            ExtendedId::new(0x1ffffff).unwrap(),
-        ),
-    );
-
-    #[cfg(not(feature = "ze50"))]
-    can.as_mut().modify_filters().set_split(1).enable_bank(
-        0,
-        Fifo::Fifo1,
-        filter::Mask32::accept_all(),
-    );
+    can.as_mut()
+        .modify_filters()
+        .set_split(1)
+        .enable_bank(0, Fifo::Fifo1, bms_filter);
 
     // Inverter Filter ============================================
     can.as_mut().modify_filters().slave_filters().enable_bank(
@@ -1,38 +1,52 @@
+use super::protocols::InverterProtocol;
 use crate::statics::*;
+use bms_standard::Bms;
 use defmt::{error, info};
 use defmt::{warn, Debug2Format};
 use embassy_stm32::can::bxcan::Frame;
 const INVERTER_SEND_MS: u64 = 1000;
 
-#[cfg(feature = "byd")]
-use byd_protocol as Inverter;
+pub struct Pylontech;
+pub struct Byd;
+pub struct GoodWe;
 
-#[cfg(feature = "goodwe")]
-use goodwe_protocol as Inverter;
+impl InverterProtocol for Pylontech {
+    const NAME: &'static str = "PylonTech";
 
-#[cfg(feature = "pylontech")]
-const LABEL: &str = "PylonTech";
+    async fn rx() {
+        inverter_rx(Self::NAME, pylontech_protocol::iter::<Frame>).await
+    }
+}
 
-#[cfg(feature = "byd")]
-const LABEL: &str = "BYD";
+impl InverterProtocol for Byd {
+    const NAME: &'static str = "BYD";
+
+    async fn rx() {
+        inverter_rx(Self::NAME, byd_protocol::iter::<Frame>).await
+    }
+}
 
-#[cfg(feature = "goodwe")]
-const LABEL: &str = "GoodWe";
+impl InverterProtocol for GoodWe {
+    const NAME: &'static str = "GoodWe";
 
-#[cfg(feature = "pylontech")]
-use pylontech_protocol as Inverter;
+    async fn rx() {
+        inverter_rx(Self::NAME, goodwe_protocol::iter::<Frame>).await
+    }
+}
 
+/// The inverter is sent the BMS state every second, whatever it sends is only logged
 #[allow(unused_assignments)]
-#[cfg(any(feature = "pylontech", feature = "byd", feature = "goodwe"))]
-#[embassy_executor::task]
-pub async fn inverter_rx() -> ! {
+async fn inverter_rx<I>(label: &'static str, frames: impl Fn(Bms) -> I) -> !
+where
+    I: IntoIterator<Item = Frame>,
+{
     use embassy_time::{Duration, Timer};
-    warn!("Starting {} Inverter Processor", LABEL);
+    warn!("Starting {} Inverter Processor", label);
     let mut inverter_comms_valid = false;
-    let recv = INVERTER_CHANNEL_RX.receiver();
-    let trans = INVERTER_CHANNEL_TX.sender();
+    let mut recv = defmt::unwrap!(INVERTER_CAN.subscribe());
+    let trans = INVERTER_CAN.sender();
     loop {
-        if let Ok(frame) = recv.try_receive() {
+        if let Ok(frame) = recv.try_next_message_pure() {
             warn!("Debug: Inv >> STM {}", Debug2Format(&frame))
         };
         Timer::after(Duration::from_millis(INVERTER_SEND_MS)).await;
@@ -52,12 +66,14 @@ pub async fn inverter_rx() -> ! {
             continue;
         };
         // drops mutex
-        for frame in Inverter::iter::<Frame>(bms) {
-            info!("Sending {} frame {:?}", LABEL, frame.data());
+        for frame in frames(bms) {
+            info!("Sending {} frame {:?}", label, frame.data());
             trans.send(frame).await;
         }
         CONTACTOR_STATE.signal(inverter_comms_valid);
         #[cfg(feature = "mqtt")]
         SEND_MQTT.signal(true);
+        #[cfg(feature = "mqtt")]
+        MQTT_READING.signal(true);
     }
 }
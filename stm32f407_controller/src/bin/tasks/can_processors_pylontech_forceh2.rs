@@ -1,19 +1,27 @@
+use super::protocols::InverterProtocol;
 use crate::statics::*;
 use bms_standard::Bms;
 use defmt::warn;
 use defmt::{error, info};
 use embassy_stm32::can::bxcan;
-use pylontech_force_h2_protocol::ForceH2;
+
+pub struct ForceH2;
+
+impl InverterProtocol for ForceH2 {
+    const NAME: &'static str = "PylonTech Force H2";
+
+    async fn rx() {
+        inverter_rx().await
+    }
+}
 
 #[allow(unused_assignments)]
-#[cfg(feature = "forceh2")]
-#[embassy_executor::task]
-pub async fn inverter_rx() -> ! {
+async fn inverter_rx() -> ! {
     warn!("Starting Force H2 Processor");
-    let mut inverter = ForceH2::default();
+    let mut inverter = pylontech_force_h2_protocol::ForceH2::default();
     let mut inverter_comms_valid = false;
-    let recv = INVERTER_CHANNEL_RX.receiver();
-    let trans = INVERTER_CHANNEL_TX.sender();
+    let mut recv = defmt::unwrap!(INVERTER_CAN.subscribe());
+    let trans = INVERTER_CAN.sender();
     let canid = |frame: &bxcan::Frame| -> Option<u32> {
         match frame.id() {
             bxcan::Id::Standard(_) => None,
@@ -21,7 +29,7 @@ pub async fn inverter_rx() -> ! {
         }
     };
     loop {
-        let frame = recv.receive().await;
+        let frame = recv.next_message_pure().await;
         warn!("Debug: Inv >> STM {}", frame);
         if Some(0x4210) != canid(&frame) {
             continue;
@@ -57,5 +65,7 @@ pub async fn inverter_rx() -> ! {
         CONTACTOR_STATE.signal(inverter_comms_valid);
         #[cfg(feature = "mqtt")]
         SEND_MQTT.signal(true);
+        #[cfg(feature = "mqtt")]
+        MQTT_READING.signal(true);
     }
 }
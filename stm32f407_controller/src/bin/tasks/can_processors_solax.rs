@@ -1,107 +1,122 @@
+use super::protocols::InverterProtocol;
 use crate::statics::*;
 
 use defmt::{error, info, warn};
 use embassy_stm32::can::bxcan::{Frame, Id::*};
+use foxess_protocol::{FoxEssBms, FoxEssError};
+use solax_protocol::{SolaxBms, SolaxError};
 
-#[cfg(feature = "foxess")]
-use foxess_protocol::{FoxEssBms as Inverter, FoxEssError as InverterError};
-#[cfg(feature = "foxess")]
-const LABEL: &str = "FoxESS";
+pub struct Solax;
+pub struct FoxEss;
 
-#[cfg(feature = "solax")]
-const LABEL: &str = "Solax";
-#[cfg(feature = "solax")]
-use solax_protocol::{SolaxBms as Inverter, SolaxError as InverterError};
+/// FoxESS speaks the Solax protocol, only the crate differs
+macro_rules! solax_protocol {
+    ($protocol:ident, $label:literal, $inverter:ident, $error:ident) => {
+        impl InverterProtocol for $protocol {
+            const NAME: &'static str = $label;
 
-#[allow(unused_assignments)]
-#[embassy_executor::task]
-pub async fn inverter_rx() -> ! {
-    warn!("Starting {} Inverter Process", LABEL);
+            async fn rx() {
+                warn!("Starting {} Inverter Process", Self::NAME);
+                let integrated_contactors = PROTOCOLS.lock().await.battery.integrated_contactors();
 
-    let recv = INVERTER_CHANNEL_RX.receiver();
-    let sender = INVERTER_CHANNEL_TX.sender();
+                let mut recv = defmt::unwrap!(INVERTER_CAN.subscribe());
+                let sender = INVERTER_CAN.sender();
 
-    let mut inverter = Inverter::default();
-    let mut initalised = false;
-    loop {
-        let frame: Frame = recv.receive().await;
-        if let Extended(id) = frame.id() {
-            if id.as_raw() != 0x1871 {
-                continue;
-            }
-        }
+                let mut inverter = $inverter::default();
+                let mut initalised = false;
+                loop {
+                    let frame: Frame = recv.next_message_pure().await;
+                    if let Extended(id) = frame.id() {
+                        if id.as_raw() != 0x1871 {
+                            continue;
+                        }
+                    }
 
-        match *LAST_BMS_MESSAGE.lock().await {
-            Some(time) => {
-                if time.elapsed().as_secs() > LAST_READING_TIMEOUT_SECS {
-                    error!("BMS last update timeout, inverter communications stopped");
-                    #[cfg(not(feature = "tesla_m3"))]
-                    CONTACTOR_STATE.signal(false);
-                    continue;
-                }
-            }
-            None => {
-                warn!("Inverter request ignored, BMS not yet seen");
-                #[cfg(not(feature = "tesla_m3"))]
-                CONTACTOR_STATE.signal(false);
-                continue;
-            }
-        };
+                    match *LAST_BMS_MESSAGE.lock().await {
+                        Some(time) => {
+                            if time.elapsed().as_secs() > LAST_READING_TIMEOUT_SECS {
+                                error!("BMS last update timeout, inverter communications stopped");
+                                if !integrated_contactors {
+                                    CONTACTOR_STATE.signal(false);
+                                }
+                                continue;
+                            }
+                        }
+                        None => {
+                            warn!("Inverter request ignored, BMS not yet seen");
+                            if !integrated_contactors {
+                                CONTACTOR_STATE.signal(false);
+                            }
+                            continue;
+                        }
+                    };
 
-        let response = {
-            let bms = BMS.lock().await;
-            if !bms.valid {
-                warn!("Inverter request ignored, BMS data not yet valid");
-            };
-            inverter.parser(frame, &bms, true)
-        };
+                    let response = {
+                        let bms = BMS.lock().await;
+                        if !bms.valid {
+                            warn!("Inverter request ignored, BMS data not yet valid");
+                        };
+                        inverter.parser(frame, &bms, true)
+                    };
 
-        let inverter_comms_valid = match response {
-            Ok(frames) => {
-                info!("Sending to {} inverter", LABEL);
-                for frame in frames {
-                    sender.send(frame).await;
-                }
-                #[cfg(feature = "mqtt")]
-                SEND_MQTT.signal(true);
-                true
-            }
-            Err(e) => {
-                use InverterError::*;
-                match e {
-                    InvalidFrameEncode(id) => {
-                        error!("Critical: frame encoding failed for {:02x}", id);
-                        false // disable contactor
-                    }
-                    BadId(id) => {
-                        error!("Critical: unexpected frame in inverter can data {:02x}", id);
-                        false // disable contactor
+                    let inverter_comms_valid = match response {
+                        Ok(frames) => {
+                            info!("Sending to {} inverter", Self::NAME);
+                            for frame in frames {
+                                sender.send(frame).await;
+                            }
+                            #[cfg(feature = "mqtt")]
+                            SEND_MQTT.signal(true);
+                            #[cfg(feature = "mqtt")]
+                            MQTT_READING.signal(true);
+                            true
+                        }
+                        Err(e) => {
+                            use $error::*;
+                            match e {
+                                InvalidFrameEncode(id) => {
+                                    error!("Critical: frame encoding failed for {:02x}", id);
+                                    false // disable contactor
+                                }
+                                BadId(id) => {
+                                    error!(
+                                        "Critical: unexpected frame in inverter can data {:02x}",
+                                        id
+                                    );
+                                    false // disable contactor
+                                }
+                                TimeStamp(time) => {
+                                    info!(
+                                        "Inverter Time: 20{}-{}-{} {}:{}:{}",
+                                        time[0], time[1], time[2], time[3], time[4], time[5]
+                                    );
+                                    continue;
+                                }
+                                UnwantedFrame => continue,
+                                x => {
+                                    warn!("{} error: {}", Self::NAME, x);
+                                    true
+                                }
+                            }
+                        }
+                    };
+                    if !initalised {
+                        let mut gs = GLOBALSTATE.lock().await;
+                        match inverter_comms_valid {
+                            true => gs.set_fault(crate::config::Fault::None),
+                            false => gs.set_fault(crate::config::Fault::InvFault),
+                        }
                     }
-                    TimeStamp(time) => {
-                        info!(
-                            "Inverter Time: 20{}-{}-{} {}:{}:{}",
-                            time[0], time[1], time[2], time[3], time[4], time[5]
-                        );
-                        continue;
-                    }
-                    UnwantedFrame => continue,
-                    x => {
-                        warn!("Solax error: {}", x);
-                        true
+                    if !integrated_contactors {
+                        CONTACTOR_STATE.signal(inverter_comms_valid && initalised);
                     }
+                    // waits for 2 positive results before activating contactor
+                    initalised = inverter_comms_valid
                 }
             }
-        };
-        if !initalised {
-            let mut gs = GLOBALSTATE.lock().await;
-            match inverter_comms_valid {
-                true => gs.set_fault(crate::config::Fault::None),
-                false => gs.set_fault(crate::config::Fault::InvFault),
-            }
         }
-        #[cfg(not(feature = "tesla_m3"))]
-        CONTACTOR_STATE.signal(inverter_comms_valid && initalised);
-        // waits for 2 positive results before activating contactor
-        initalised = inverter_comms_valid
-    }
+    };
 }
+
+solax_protocol!(Solax, "Solax", SolaxBms, SolaxError);
+solax_protocol!(FoxEss, "FoxESS", FoxEssBms, FoxEssError);
@@ -1,3 +1,4 @@
+use super::protocols::BatteryDriver;
 use crate::statics::*;
 use defmt::{debug, error, info, Debug2Format};
 use embassy_stm32::can::bxcan::Frame;
@@ -16,10 +17,22 @@ Integrated contactors
 
  */
 
-#[cfg(feature = "tesla_m3")]
-#[embassy_executor::task]
-pub async fn bms_tx_periodic() {
-    let tx = BMS_CHANNEL_TX.sender();
+pub struct TeslaM3;
+
+impl BatteryDriver for TeslaM3 {
+    const NAME: &'static str = "Tesla M3";
+
+    async fn rx() {
+        bms_rx().await
+    }
+
+    async fn tx_periodic() {
+        bms_tx_periodic().await
+    }
+}
+
+async fn bms_tx_periodic() {
+    let tx = BMS_CAN.sender();
     let ticker_ms = |ms| Ticker::every(Duration::from_millis(ms));
     let sender = |frame| {
         if let Err(_e) = tx.try_send(frame) {
@@ -126,17 +139,15 @@ pub async fn bms_tx_periodic() {
     }
 }
 
-#[cfg(feature = "tesla_m3")]
-#[embassy_executor::task]
-pub async fn bms_rx() {
+async fn bms_rx() {
     use tesla_m3_bms::HvilState;
 
-    let rx = BMS_CHANNEL_RX.receiver();
+    let mut rx = defmt::unwrap!(BMS_CAN.subscribe());
     let mut data = tesla_m3_bms::Data::default();
     let mut contactor_command = ContactorState::Precharge;
     let mut precharge_triggered: Option<Instant> = None;
     loop {
-        let frame: Frame = rx.receive().await;
+        let frame: Frame = rx.next_message_pure().await;
         let update = match data.decode_frame(frame) {
             Ok(update) => update,
             Err(e) => {
@@ -1,5 +1,6 @@
 #[cfg(feature = "mqtt")]
 use super::mqtt::MqttFormat;
+use super::protocols::BatteryDriver;
 use crate::statics::*;
 
 use defmt::{error, warn};
@@ -14,12 +15,24 @@ static LBC_STATUS: Signal<_Mutex, LbcKey> = Signal::new();
 const PREAMBLE_TIME_MS: u64 = 95;
 const DIAG_TIME_MS: u64 = 5000;
 
-#[cfg(feature = "ze40")]
-#[embassy_executor::task]
-pub async fn bms_tx_periodic() {
+pub struct Ze40;
+
+impl BatteryDriver for Ze40 {
+    const NAME: &'static str = "ZE40";
+
+    async fn rx() {
+        bms_rx().await
+    }
+
+    async fn tx_periodic() {
+        bms_tx_periodic().await
+    }
+}
+
+async fn bms_tx_periodic() {
     use embassy_futures::select::{select, Either};
     use embassy_time::{Duration, Ticker};
-    let tx = BMS_CHANNEL_TX.sender();
+    let tx = BMS_CAN.sender();
     let ticker_ms = |ms| Ticker::every(Duration::from_millis(ms));
     let sender = |frame| {
         if let Err(_e) = tx.try_send(frame) {
@@ -52,9 +65,7 @@ pub async fn bms_tx_periodic() {
 }
 
 #[allow(unused_assignments)]
-#[cfg(feature = "ze40")]
-#[embassy_executor::task]
-pub async fn bms_rx() {
+async fn bms_rx() {
     use bms_standard::BmsError;
     use embassy_stm32::can::bxcan::Id;
     use embassy_stm32::can::bxcan::Id::Standard;
@@ -62,8 +73,8 @@ pub async fn bms_rx() {
 
     let (mut f55, mut faa) = (0u8, 0u8);
 
-    let rx = BMS_CHANNEL_RX.receiver();
-    let tx = BMS_CHANNEL_TX.sender();
+    let mut rx = defmt::unwrap!(BMS_CAN.subscribe());
+    let tx = BMS_CAN.sender();
     let mut data = ze40_bms::Data::new();
     warn!("Starting ZE40 Rx Processor");
     let canid = |frame: &Frame| -> Option<u16> {
@@ -73,7 +84,7 @@ pub async fn bms_rx() {
         }
     };
     loop {
-        let frame = rx.receive().await;
+        let frame = rx.next_message_pure().await;
         // Process 10ms data
         let id = match canid(&frame) {
             Some(id) => id,
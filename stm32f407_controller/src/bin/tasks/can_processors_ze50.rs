@@ -1,6 +1,8 @@
+use super::protocols::BatteryDriver;
 use crate::statics::*;
 use bms_standard::Bms;
 use defmt::{error, info, warn, Debug2Format};
+use embassy_stm32::can::bxcan::{filter::Mask32, ExtendedId};
 use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex as _Mutex;
 use embassy_sync::mutex::Mutex;
 use embassy_time::Instant;
@@ -13,6 +15,28 @@ lazy_static! {
         embassy_sync::mutex::Mutex::new(ze50_bms::Data::default());
 }
 
+pub struct Ze50;
+
+impl BatteryDriver for Ze50 {
+    const NAME: &'static str = "ZE50";
+
+    /// Only the diagnostic responses
+    fn filter() -> Mask32 {
+        Mask32::frames_with_ext_id(
+            ExtendedId::new(0x18DAF1DB).unwrap(),
+            ExtendedId::new(0x1ffffff).unwrap(),
+        )
+    }
+
+    async fn rx() {
+        bms_rx().await
+    }
+
+    async fn tx_periodic() {
+        bms_tx_periodic().await
+    }
+}
+
 async fn update() {
     // push vals to ZE50_BMS struct when reading loop has finished
     let data = ZE50_DATA.lock().await;
@@ -75,9 +99,7 @@ async fn update() {
     };
 }
 
-#[cfg(feature = "ze50")]
-#[embassy_executor::task]
-pub async fn bms_tx_periodic() {
+async fn bms_tx_periodic() {
     use embassy_futures::select::{select, Either};
     use embassy_stm32::can::bxcan::Frame;
     use embedded_hal::can::Frame as _;
@@ -86,7 +108,7 @@ pub async fn bms_tx_periodic() {
     use embassy_time::{Duration, Ticker};
     use ze50_bms::{init_payloads, preamble_payloads};
 
-    let tx = BMS_CHANNEL_TX.sender();
+    let tx = BMS_CAN.sender();
     let ticker_ms = |ms| Ticker::every(Duration::from_millis(ms));
     let sender = |frame| {
         if let Err(_e) = tx.try_send(frame) {
@@ -150,16 +172,14 @@ pub async fn bms_tx_periodic() {
     }
 }
 
-#[cfg(feature = "ze50")]
 #[allow(unused_assignments)]
-#[embassy_executor::task]
-pub async fn bms_rx() {
+async fn bms_rx() {
     use defmt::info;
     use embassy_stm32::can::bxcan::{Frame, Id::Extended};
-    let rx = BMS_CHANNEL_RX.receiver();
+    let mut rx = defmt::unwrap!(BMS_CAN.subscribe());
     warn!("Starting ZE50 RX");
     loop {
-        let frame: Frame = rx.receive().await;
+        let frame: Frame = rx.next_message_pure().await;
         if let Extended(id) = frame.id() {
             if id.as_raw() == !0x18DAF1DB {
                 info!("Unknown Extended ID - RX: {:02x}", id.as_raw());
@@ -0,0 +1,371 @@
+//! GVRET binary protocol server, SavvyCAN's "Network connection" to a GVRET device.
+//!
+//! Streams both buses to the client and writes the frames it sends onto CAN1 (bus 0)
+//! or CAN2 (bus 1). All values are little endian, every message starts with 0xF1.
+use defmt::*;
+use embassy_futures::select::{select3, Either3};
+use embassy_net::{
+    tcp::{TcpSocket, TcpWriter},
+    IpListenEndpoint,
+};
+use embassy_stm32::can::bxcan::{ExtendedId, Frame, Id, StandardId};
+use embassy_sync::pubsub::WaitResult;
+use embassy_time::{Duration, Instant, Timer};
+use embedded_hal::can::Frame as _;
+use embedded_io_async::Write;
+use heapless::Vec;
+
+use crate::{
+    can_bus::{CanBus, TapSubscriber},
+    statics::{BMS_CAN, INVERTER_CAN, LED_COMMAND},
+    types::StackType,
+};
+
+const TCP_TIMEOUT_SECS: u64 = 10;
+const TCP_PORT: u16 = 23;
+
+/// Sent twice by the client to leave the text console
+const BINARY_MODE: u8 = 0xE7;
+const COMMAND: u8 = 0xF1;
+
+const BUILD_CAN_FRAME: u8 = 0x00;
+const TIME_SYNC: u8 = 0x01;
+const GET_DIG_INPUTS: u8 = 0x02;
+const GET_ANALOG_INPUTS: u8 = 0x03;
+const SET_DIG_OUTPUTS: u8 = 0x04;
+const SETUP_CANBUS: u8 = 0x05;
+const GET_CANBUS_PARAMS: u8 = 0x06;
+const GET_DEVICE_INFO: u8 = 0x07;
+const SET_SINGLEWIRE_MODE: u8 = 0x08;
+const KEEP_ALIVE: u8 = 0x09;
+const SET_SYSTEM_TYPE: u8 = 0x0A;
+const ECHO_CAN_FRAME: u8 = 0x0B;
+const GET_NUM_BUSES: u8 = 0x0C;
+const GET_EXT_BUSES: u8 = 0x0D;
+const SET_EXT_BUSES: u8 = 0x0E;
+
+/// Reported as the firmware build, SavvyCAN only displays it
+const BUILD_NUMBER: u16 = 618;
+const EXTENDED_FLAG: u32 = 1 << 31;
+
+/// Largest message either way: frame header, 8 data bytes and checksum
+type Message = Vec<u8, 20>;
+
+enum Request {
+    Frame {
+        bus: u8,
+        frame: Frame,
+    },
+    /// Looped back to the client as if received, never transmitted
+    Echo {
+        bus: u8,
+        frame: Frame,
+    },
+    TimeSync,
+    DigInputs,
+    AnalogInputs,
+    CanbusParams,
+    DeviceInfo,
+    KeepAlive,
+    NumBuses,
+    ExtBuses,
+}
+
+#[derive(Default)]
+enum State {
+    #[default]
+    Idle,
+    Command,
+    /// id (4), bus, length, data, checksum
+    Frame {
+        buf: [u8; 15],
+        len: usize,
+        echo: bool,
+    },
+    /// Arguments of commands that change nothing here
+    Skip(usize),
+}
+
+/// Byte at a time decoder for the client's commands
+#[derive(Default)]
+struct Parser {
+    state: State,
+    binary: bool,
+}
+
+impl Parser {
+    fn feed(&mut self, byte: u8) -> Option<Request> {
+        match core::mem::take(&mut self.state) {
+            State::Idle => match byte {
+                COMMAND => self.state = State::Command,
+                BINARY_MODE => self.binary = true,
+                _ => (),
+            },
+            State::Command => {
+                return match byte {
+                    BUILD_CAN_FRAME | ECHO_CAN_FRAME => {
+                        self.state = State::Frame {
+                            buf: [0; 15],
+                            len: 0,
+                            echo: byte == ECHO_CAN_FRAME,
+                        };
+                        None
+                    }
+                    TIME_SYNC => Some(Request::TimeSync),
+                    GET_DIG_INPUTS => Some(Request::DigInputs),
+                    GET_ANALOG_INPUTS => Some(Request::AnalogInputs),
+                    GET_CANBUS_PARAMS => Some(Request::CanbusParams),
+                    GET_DEVICE_INFO => Some(Request::DeviceInfo),
+                    KEEP_ALIVE => Some(Request::KeepAlive),
+                    GET_NUM_BUSES => Some(Request::NumBuses),
+                    GET_EXT_BUSES => Some(Request::ExtBuses),
+                    SET_DIG_OUTPUTS | SET_SINGLEWIRE_MODE | SET_SYSTEM_TYPE => {
+                        self.state = State::Skip(1);
+                        None
+                    }
+                    SETUP_CANBUS => {
+                        warn!("GVRET bus setup ignored, bitrates are fixed at boot");
+                        self.state = State::Skip(8);
+                        None
+                    }
+                    SET_EXT_BUSES => {
+                        self.state = State::Skip(12);
+                        None
+                    }
+                    x => {
+                        warn!("GVRET unknown command {=u8:#x}", x);
+                        None
+                    }
+                };
+            }
+            State::Frame {
+                mut buf,
+                mut len,
+                echo,
+            } => {
+                buf[len] = byte;
+                len += 1;
+                // length byte is the 6th, then its data and a checksum
+                let complete = len > 6 && len == 7 + (buf[5] & 0x0F).min(8) as usize;
+                if !complete {
+                    self.state = State::Frame { buf, len, echo };
+                    return None;
+                }
+                let raw = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+                let id = match raw & EXTENDED_FLAG {
+                    0 => StandardId::new(raw as u16).map(Id::Standard),
+                    _ => ExtendedId::new(raw & !EXTENDED_FLAG).map(Id::Extended),
+                };
+                let data = &buf[6..len - 1];
+                let bus = buf[4] & 0x03;
+                return match id.and_then(|id| Frame::new(id, data)) {
+                    Some(frame) if echo => Some(Request::Echo { bus, frame }),
+                    Some(frame) => Some(Request::Frame { bus, frame }),
+                    None => {
+                        warn!("GVRET invalid frame id {=u32:#x}", raw);
+                        None
+                    }
+                };
+            }
+            State::Skip(1) => (),
+            State::Skip(n) => self.state = State::Skip(n - 1),
+        }
+        None
+    }
+}
+
+#[embassy_executor::task]
+pub async fn gvret_task(stack: StackType) {
+    loop {
+        if stack.is_link_up() {
+            break;
+        }
+        Timer::after(Duration::from_millis(500)).await;
+    }
+
+    loop {
+        if let Some(_config) = stack.config_v4() {
+            break;
+        }
+        Timer::after(Duration::from_millis(500)).await;
+    }
+    info!("[{}] Spawning GVRET TCP socket", TCP_PORT);
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 2048];
+    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+    socket.set_timeout(Some(Duration::from_secs(TCP_TIMEOUT_SECS)));
+
+    loop {
+        LED_COMMAND.signal(crate::tasks::leds::LedCommand::Off(
+            crate::tasks::leds::Led::Led3,
+        ));
+        match socket.state() {
+            embassy_net::tcp::State::Closed => (),
+            _ => {
+                Timer::after(Duration::from_millis(100)).await;
+                socket.close();
+                info!("[{}] GVRET TCP socket closed", TCP_PORT);
+                Timer::after(Duration::from_millis(100)).await;
+                socket.abort();
+            }
+        };
+        info!("[{}] Wait for connection...", TCP_PORT);
+        let r = socket
+            .accept(IpListenEndpoint {
+                addr: None,
+                port: TCP_PORT,
+            })
+            .await;
+        if let Err(e) = r {
+            info!("[{}] connect error: {:?}", TCP_PORT, e);
+            continue;
+        }
+        if let Some(ip) = socket.remote_endpoint() {
+            info!("Accepted GVRET client: {}:{}", ip.addr, ip.port);
+        }
+        LED_COMMAND.signal(crate::tasks::leds::LedCommand::On(
+            crate::tasks::leds::Led::Led3,
+        ));
+
+        // Tap only while a client is connected so no stale frames queue up in between
+        let (mut bms, mut inverter) = match (BMS_CAN.tap(), INVERTER_CAN.tap()) {
+            (Ok(bms), Ok(inverter)) => (bms, inverter),
+            (Err(e), _) | (_, Err(e)) => {
+                error!("GVRET CAN tap {}", e);
+                continue;
+            }
+        };
+        if let Err(e) = session(&mut socket, &mut bms, &mut inverter).await {
+            info!("[{}] GVRET session ended: {}", TCP_PORT, e);
+        }
+    }
+}
+
+async fn session(
+    socket: &mut TcpSocket<'_>,
+    bms: &mut TapSubscriber,
+    inverter: &mut TapSubscriber,
+) -> Result<(), embassy_net::tcp::Error> {
+    let (mut reader, mut writer) = socket.split();
+    let mut parser = Parser::default();
+    let mut buf = [0u8; 64];
+    loop {
+        let (bus, tapped) = match select3(
+            reader.read(&mut buf),
+            bms.next_message(),
+            inverter.next_message(),
+        )
+        .await
+        {
+            Either3::First(Ok(0)) => return Ok(()),
+            Either3::First(read) => {
+                for &byte in &buf[..read?] {
+                    if let Some(request) = parser.feed(byte) {
+                        respond(&mut writer, request).await?;
+                    }
+                }
+                continue;
+            }
+            Either3::Second(tapped) => (0, tapped),
+            Either3::Third(tapped) => (1, tapped),
+        };
+        let tapped = match tapped {
+            WaitResult::Message(tapped) => tapped,
+            WaitResult::Lagged(n) => {
+                warn!("GVRET bus {} lagged, {} frames dropped", bus, n);
+                continue;
+            }
+        };
+        // the text console is not implemented, wait for the client to switch to binary
+        if parser.binary {
+            writer
+                .write_all(&frame_message(bus, &tapped.frame, tapped.at))
+                .await?;
+        }
+    }
+}
+
+async fn respond(
+    writer: &mut TcpWriter<'_>,
+    request: Request,
+) -> Result<(), embassy_net::tcp::Error> {
+    let mut msg = Message::new();
+    let mut extend = |bytes: &[u8]| {
+        let _ = msg.extend_from_slice(bytes);
+    };
+    match request {
+        Request::Frame { bus, frame } => {
+            let bus = match bus {
+                0 => &BMS_CAN,
+                1 => &INVERTER_CAN,
+                x => {
+                    warn!("GVRET frame for bus {} dropped", x);
+                    return Ok(());
+                }
+            };
+            if bus.sender().try_send(frame).is_err() {
+                warn!("GVRET frame dropped, CAN queue full");
+            }
+            return Ok(());
+        }
+        Request::Echo { bus, frame } => {
+            return writer
+                .write_all(&frame_message(bus, &frame, Instant::now()))
+                .await;
+        }
+        Request::TimeSync => {
+            extend(&[COMMAND, TIME_SYNC]);
+            extend(&timestamp(Instant::now()));
+        }
+        Request::DigInputs => extend(&[COMMAND, GET_DIG_INPUTS, 0, 0]),
+        Request::AnalogInputs => {
+            extend(&[COMMAND, GET_ANALOG_INPUTS]);
+            extend(&[0; 15]);
+        }
+        Request::CanbusParams => {
+            extend(&[COMMAND, GET_CANBUS_PARAMS]);
+            extend(&canbus_params(&BMS_CAN));
+            extend(&canbus_params(&INVERTER_CAN));
+        }
+        Request::DeviceInfo => {
+            extend(&[COMMAND, GET_DEVICE_INFO]);
+            extend(&BUILD_NUMBER.to_le_bytes());
+            // eeprom version, file type, auto start logging, single wire
+            extend(&[0x20, 0, 0, 0]);
+        }
+        Request::KeepAlive => extend(&[COMMAND, KEEP_ALIVE, 0xDE, 0xAD]),
+        Request::NumBuses => extend(&[COMMAND, GET_NUM_BUSES, 2]),
+        Request::ExtBuses => {
+            extend(&[COMMAND, GET_EXT_BUSES]);
+            extend(&[0; 15]);
+        }
+    }
+    writer.write_all(&msg).await
+}
+
+/// Enabled, not listen only, then the bitrate
+fn canbus_params(bus: &CanBus) -> [u8; 5] {
+    let [a, b, c, d] = bus.bitrate().to_le_bytes();
+    [0x01, a, b, c, d]
+}
+
+/// Microseconds, wraps every 71 minutes like the original hardware
+fn timestamp(at: Instant) -> [u8; 4] {
+    (at.as_micros() as u32).to_le_bytes()
+}
+
+fn frame_message(bus: u8, frame: &Frame, at: Instant) -> Message {
+    let id = match frame.id() {
+        Id::Standard(id) => id.as_raw() as u32,
+        Id::Extended(id) => id.as_raw() | EXTENDED_FLAG,
+    };
+    let data = frame.data().map(|d| &d[..]).unwrap_or_default();
+    let mut msg = Message::new();
+    let _ = msg.extend_from_slice(&[COMMAND, BUILD_CAN_FRAME]);
+    let _ = msg.extend_from_slice(&timestamp(at));
+    let _ = msg.extend_from_slice(&id.to_le_bytes());
+    let _ = msg.push(data.len() as u8 | bus << 4);
+    let _ = msg.extend_from_slice(data);
+    let _ = msg.push(0);
+    msg
+}
@@ -10,45 +10,26 @@ use embassy_stm32::timer::simple_pwm::{PwmPin, SimplePwm};
 use embassy_stm32::timer::Channel;
 use embassy_time::{Duration, Timer};
 
-use crate::statics::CONTACTOR_STATE;
+use crate::statics::{CONTACTOR_FORCE_OPEN, CONTACTOR_STATE};
 
 pub mod can_interfaces;
 
 #[cfg(feature = "display")]
 pub mod display;
 
-#[cfg(feature = "tcp_debug")]
-pub mod tcp_debug;
+#[cfg(feature = "gvret")]
+pub mod gvret;
 
-#[cfg(feature = "ze40")]
-pub mod can_processors_ze40;
-#[cfg(feature = "ze40")]
-pub use can_processors_ze40::{bms_rx, bms_tx_periodic};
-
-#[cfg(feature = "ze50")]
-pub mod can_processors_ze50;
-#[cfg(feature = "ze50")]
-pub use can_processors_ze50::{bms_rx, bms_tx_periodic};
-
-#[cfg(feature = "tesla_m3")]
-pub mod can_processors_tesla_m3;
-#[cfg(feature = "tesla_m3")]
-pub use can_processors_tesla_m3::{bms_rx, bms_tx_periodic};
+#[cfg(feature = "slcan")]
+pub mod usb_slcan;
 
-#[cfg(any(feature = "foxess", feature = "solax"))]
-pub mod can_processors_solax;
-#[cfg(any(feature = "foxess", feature = "solax"))]
-pub use can_processors_solax::inverter_rx;
-
-#[cfg(any(feature = "pylontech", feature = "byd", feature = "goodwe"))]
 pub mod can_processors_pylontech;
-#[cfg(any(feature = "pylontech", feature = "byd", feature = "goodwe"))]
-pub use can_processors_pylontech::inverter_rx;
-
-#[cfg(feature = "forceh2")]
 pub mod can_processors_pylontech_forceh2;
-#[cfg(feature = "forceh2")]
-pub use can_processors_pylontech_forceh2::inverter_rx;
+pub mod can_processors_solax;
+pub mod can_processors_tesla_m3;
+pub mod can_processors_ze40;
+pub mod can_processors_ze50;
+pub mod protocols;
 
 pub mod leds;
 
@@ -57,6 +38,19 @@ pub mod modbus;
 
 #[cfg(feature = "mqtt")]
 pub mod mqtt;
+#[cfg(feature = "mqtt_tls")]
+pub mod mqtt_ca {
+    // MQTT_CA from MQTT_CA_DER, generated by build.rs
+    include!(concat!(env!("OUT_DIR"), "/mqtt_ca.rs"));
+}
+#[cfg(feature = "mqtt")]
+pub mod mqtt_commands;
+#[cfg(feature = "mqtt")]
+pub mod mqtt_connection;
+#[cfg(feature = "mqtt")]
+pub mod mqtt_discovery;
+#[cfg(feature = "mqtt")]
+pub mod mqtt_queue;
 
 #[cfg(feature = "ntp")]
 pub mod ntp;
@@ -71,7 +65,7 @@ pub async fn contactor_main_task(main: PA6, timer: TIM3) {
     let max = pwm.get_max_duty() - 1;
     let mut active = false;
     loop {
-        let state = CONTACTOR_STATE.wait().await;
+        let state = CONTACTOR_STATE.wait().await && !*CONTACTOR_FORCE_OPEN.lock().await;
         match (state, active) {
             (false, true) => {
                 warn!("Contactor shutdown");
@@ -117,7 +111,7 @@ pub async fn contactor_both_task(pre: PA4, main: PA6, timer: TIM3) {
     let max = pwm.get_max_duty() - 1;
     let mut active = false;
     loop {
-        let state = CONTACTOR_STATE.wait().await;
+        let state = CONTACTOR_STATE.wait().await && !*CONTACTOR_FORCE_OPEN.lock().await;
         match (state, active) {
             (false, true) => {
                 warn!("Contactors shutdown");
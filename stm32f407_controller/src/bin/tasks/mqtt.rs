@@ -1,11 +1,19 @@
-use crate::config::JsonTrait;
+use crate::config::{Config, Deadband, JsonTrait};
 
 use crate::statics::*;
+use crate::tasks::mqtt_commands::{self, Command, CommandError, RESULT_SUBTOPIC};
+use crate::tasks::mqtt_connection::{BrokerConnector, EmbassyClock, MqttTcpClient, TCP_BUF_SIZE};
+use crate::tasks::mqtt_discovery;
+use crate::tasks::mqtt_queue::OutboundQueue;
 use crate::types::EthDevice;
+use crate::utils::CachedHost;
+use alloc::string::String;
+use embassy_futures::select::{select3, Either, Either3};
 
 use core::fmt::{self, Write};
 use defmt::error;
 use defmt::info;
+use defmt::warn;
 use defmt::Debug2Format;
 
 use embassy_net::{
@@ -15,23 +23,27 @@ use embassy_net::{
 use embassy_stm32::{peripherals::*, usart::Uart};
 use embassy_time::{Duration, Instant, Timer};
 use rust_mqtt::client::client_config::MqttVersion::*;
+use rust_mqtt::client::manager::{ConnectionManager, ManagerEvent};
+use rust_mqtt::client::payload::Formatted;
+use rust_mqtt::client::raw_client::MAX_INBOUND_QOS2;
+use rust_mqtt::packet::v5::property::Property;
+use rust_mqtt::packet::v5::subscription_packet::{RetainHandling, SubscriptionOptions};
 
 use miniserde::{json, Serialize};
-use rust_mqtt::packet::v5::publish_packet::QualityOfService::{self, *};
-use rust_mqtt::{
-    client::{client::MqttClient, client_config::ClientConfig},
-    utils::rng_generator::CountingRng,
-};
+use rust_mqtt::packet::v5::publish_packet::PublishOptions;
+use rust_mqtt::packet::v5::publish_packet::QualityOfService::*;
+use rust_mqtt::packet::v5::reason_codes::ReasonCode;
+use rust_mqtt::{client::client_config::ClientConfig, utils::rng_generator::CountingRng};
 
 const BUF_SIZE: usize = 1500;
-
-#[derive(Clone, Debug)]
-pub struct MqttMessage<'a> {
-    pub topic: &'a str,
-    pub payload: &'a str,
-    pub qos: QualityOfService,
-    pub retain: bool,
-}
+// commands that arrive while a publish waits for its acknowledgement
+const INBOX_SIZE: usize = 512;
+const AVAILABILITY_ONLINE: &[u8] = b"online";
+const AVAILABILITY_OFFLINE: &[u8] = b"offline";
+// seconds, the connection manager sends PINGREQ when nothing else went out for this long
+const KEEP_ALIVE: u16 = 60;
+// missed intervals before the broker drops a retained state, so dashboards see it go stale
+const STALE_INTERVALS: u32 = 3;
 
 #[embassy_executor::task]
 pub async fn mqtt_net_task(stack: &'static Stack<EthDevice>) {
@@ -52,136 +64,323 @@ pub async fn mqtt_net_task(stack: &'static Stack<EthDevice>) {
     }
     info!("Spawning MQTT client");
 
-    use embedded_nal_async::{SocketAddr, TcpConnect};
-    let state: TcpClientState<1, 2048, 2048> = TcpClientState::new();
-    let client = TcpClient::new(stack, &state);
-
-    let mqtt_config = crate::config::MqttConfig::builder()
-        .host(env!("MQTTHOST"))
-        .port(env!("MQTTPORT").parse().expect("Bad MQTT port in env"))
-        .client_id(env!("MQTTCLIENTID"))
-        .username(env!("MQTTUSERNAME"))
-        .password(env!("MQTTPASSWORD"))
-        .basetopic(env!("MQTTBASETOPIC"))
-        .qos(env!("MQTTQOS").parse().expect("Bad QOS number in env"))
-        .retain(env!("MQTTRETAIN") == "true")
-        .interval(
-            env!("MQTTINTERVAL")
-                .parse()
-                .expect("Bad MQTT interval number in env"),
-        )
-        .build();
+    let state: TcpClientState<1, TCP_BUF_SIZE, TCP_BUF_SIZE> = TcpClientState::new();
+    let client: MqttTcpClient = TcpClient::new(stack, &state);
+    let mut broker = CachedHost::new("", 0);
+    // outlives the connection so an outage doesn't leave gaps in the history
+    let mut queue = OutboundQueue::new();
 
     loop {
-        info!("Setting up MQTT connection");
-
-        let addr =
-            SocketAddr::try_from(&mqtt_config).expect("MQTT host details are not valid IPv4");
+        // take a copy so the web and UART handlers can update MQTTCONFIG while connected
+        let mqtt_config = MQTTCONFIG.lock().await.clone();
+        MQTT_CONFIG_CHANGED.reset();
+        if let Err(e) = mqtt_config.validate() {
+            warn!("MQTT config incomplete ({}), waiting for update", e);
+            MQTT_CONFIG_CHANGED.wait().await;
+            continue;
+        }
 
+        info!("Setting up MQTT connection");
+        let max_interval = Duration::from_secs(mqtt_config.get_interval().into());
+        let min_interval = Duration::from_secs(mqtt_config.get_min_interval().into());
+        let deadband = mqtt_config.get_deadband();
         let retain = mqtt_config.get_retain();
+        // MQTTv5 only, the topic itself is replaced by an alias if the broker allows them
+        let state_options = PublishOptions {
+            message_expiry: Some(STALE_INTERVALS * u32::from(mqtt_config.get_interval())),
+            content_type: Some("application/json"),
+        };
         let qos = match mqtt_config.get_qos() {
             1 => QoS1,
             2 => QoS2,
             _ => QoS0,
         };
-        let tcp = match client.connect(addr).await {
-            Ok(t) => t,
-            Err(e) => {
-                error!("MQTT connect error: {}", e);
-                Timer::after(Duration::from_secs(10)).await;
-                continue;
-            }
+        let availability_topic = mqtt_config.availability_topic();
+        let cmd_prefix = alloc::format!("{}/cmd/", mqtt_config.get_topic());
+        let cmd_filter = alloc::format!("{}#", cmd_prefix);
+        let result_topic = alloc::format!("{}{}", cmd_prefix, RESULT_SUBTOPIC);
+        let dump_topic = alloc::format!("{}/dump", mqtt_config.get_topic());
+        let cells_topic = alloc::format!("{}/cells", mqtt_config.get_topic());
+        let queue_topic = alloc::format!("{}/queue", mqtt_config.get_topic());
+        let node_id = mqtt_discovery::node_id(mqtt_config.get_client_id());
+
+        let version = match mqtt_config.get_version() {
+            3 => MQTTv3,
+            _ => MQTTv5,
         };
-        let mut config = ClientConfig::new(MQTTv5, CountingRng(50000));
-        if mqtt_config.get_username().is_empty() {
+        let mut config = ClientConfig::new(version, CountingRng(50000));
+        if !mqtt_config.get_client_id().is_empty() {
+            config.add_client_id(mqtt_config.get_client_id());
+        }
+        if !mqtt_config.get_username().is_empty() {
             config.add_username(mqtt_config.get_username())
         };
-        config.add_username(mqtt_config.get_username());
         if !mqtt_config.get_password().is_empty() {
             config.add_password(mqtt_config.get_password())
         };
-        config.add_will(env!("MQTTWILLTOPIC"), b"Online", mqtt_config.get_retain());
-        config.max_packet_size = 6000;
-        config.keep_alive = 60000;
-        config.max_packet_size = 300;
+        // retained so late subscribers see the current state
+        config.add_will(&availability_topic, AVAILABILITY_OFFLINE, true);
+        config.add_birth(&availability_topic, AVAILABILITY_ONLINE);
+        // commands arrive at the configured QoS and QoS2 ones are never run twice, those
+        // that arrive during a publish wait in the inbox and are dropped once it is full
+        config.add_max_subscribe_qos(qos);
+        config.add_property(Property::ReceiveMaximum(MAX_INBOUND_QOS2 as u16));
+        config.keep_alive = KEEP_ALIVE;
+        // incoming packets have to fit the buffers, outgoing ones are written in chunks
+        config.max_packet_size = BUF_SIZE as u32;
         let mut recv_buffer = [0; BUF_SIZE];
         let mut write_buffer = [0; BUF_SIZE];
+        let mut inbox = [0; INBOX_SIZE];
 
-        let mut client = MqttClient::<_, 5, _>::new(
-            tcp,
+        let connector = BrokerConnector::new(stack, &client, &mut broker, &mqtt_config);
+        // reconnects with backoff and re-subscribes until the config changes
+        let mut manager = ConnectionManager::<_, _, 5, _, 1>::new(
+            connector,
+            EmbassyClock,
             &mut write_buffer,
             BUF_SIZE,
             &mut recv_buffer,
             BUF_SIZE,
+            &mut inbox,
             config,
         );
-
-        match client.connect_to_broker().await {
-            Ok(_) => info!("MQTT connected ok"),
-            Err(e) => {
-                error!("MQTT Failed {}", e);
-                error!("{}", Debug2Format(&mqtt_config));
-                Timer::after(Duration::from_secs(10)).await;
-                continue;
-            }
+        // our own acknowledgements are not echoed back, and a retained command is not
+        // run again after every reconnect
+        let cmd_options = SubscriptionOptions {
+            no_local: true,
+            retain_handling: RetainHandling::DoNotSend,
+            ..Default::default()
         };
+        if let Err(e) = manager
+            .subscribe_with_options(&cmd_filter, cmd_options)
+            .await
+        {
+            error!("MQTT command subscription failed {}", e);
+        }
 
-        'inner: loop {
-            info!("Setting up MQTT message");
-
-            if let Err(e) = client.send_ping().await {
-                error!("No response from MQTT server {}", e);
-                match client.disconnect().await {
-                    Ok(_) => info!("MQTT disconnected ok"),
-                    Err(e) => error!("MQTT disconnect failed {}", e),
-                };
-                match client.connect_to_broker().await {
-                    Ok(_) => info!("MQTT reconnected ok"),
-                    Err(e) => error!("MQTT reconnect failed {}", e),
-                };
-                break 'inner;
-            }
-
-            let p = match MQTTFMT.try_lock() {
-                Ok(p) => p.device_update_msg(),
-                Err(_) => {
-                    defmt::error!("Cannot get mutex lock");
-                    break 'inner;
-                }
-            };
-
-            let message = MqttMessage {
-                topic: mqtt_config.get_topic(),
-                payload: p.as_str(),
-                qos,
-                retain,
-            };
-
-            // let mqtt_data = { *MQTTFMT.lock().await };
-            if let Err(e) = client
-                .send_message(
-                    message.topic,
-                    message.payload.as_bytes(),
-                    message.qos,
-                    message.retain,
-                )
+        // snapshot of the last publish for the deadband, the first is queued right away
+        // and sent once connected
+        let mut state = *MQTTFMT.lock().await;
+        let mut bms = *BMS.lock().await;
+        let mut published = Instant::now();
+        let mut next_publish = published;
+        loop {
+            // the timers only interrupt the manager's waits, never a connect or a packet
+            let wake = match manager
+                .poll_or(select3(
+                    Timer::at(next_publish),
+                    MQTT_CONFIG_CHANGED.wait(),
+                    MQTT_READING.wait(),
+                ))
                 .await
             {
-                error!("MQTT send {}", e);
-                break 'inner;
+                Either::First(ManagerEvent::Connected) => Wake::Connected,
+                Either::First(ManagerEvent::Disconnected(e)) => Wake::Disconnected(e),
+                Either::First(ManagerEvent::Message(topic, payload)) => {
+                    match topic.strip_prefix(cmd_prefix.as_str()) {
+                        Some(RESULT_SUBTOPIC) | None => Wake::Ignored,
+                        Some(name) => Wake::Command(name.into(), Command::parse(name, payload)),
+                    }
+                }
+                Either::First(ManagerEvent::Idle) => Wake::Ignored,
+                Either::Second(Either3::First(_)) => Wake::Publish,
+                Either::Second(Either3::Second(_)) => Wake::ConfigChanged,
+                Either::Second(Either3::Third(_)) => Wake::Reading,
+            };
+            match wake {
+                Wake::Ignored => continue,
+                Wake::Publish => {
+                    // publish again after max_interval, or after min_interval once a reading
+                    // moves past the deadband
+                    state = *MQTTFMT.lock().await;
+                    bms = *BMS.lock().await;
+                    published = Instant::now();
+                    next_publish = published + max_interval;
+                    queue.push(state);
+                    if !manager.is_connected() {
+                        continue;
+                    }
+                    if queue.queued() > 1 {
+                        info!("MQTT replaying {} queued messages", queue.queued());
+                    }
+                    // a failed publish drops the connection, the rest waits for the reconnect
+                    'publish: {
+                        while let Some(payload) = queue.front_payload() {
+                            // returns once acknowledged for QoS1/2
+                            if let Err(e) = manager
+                                .publish_with_options(
+                                    mqtt_config.get_topic(),
+                                    payload.as_bytes(),
+                                    qos,
+                                    retain,
+                                    state_options,
+                                )
+                                .await
+                            {
+                                error!("MQTT send {}", e);
+                                break 'publish;
+                            }
+                            queue.pop();
+                        }
+                        if let Some(dropped) = queue.unreported_drops() {
+                            warn!("MQTT queue overflowed, {} messages dropped", dropped);
+                            let report = alloc::format!(r#"{{"dropped":{}}}"#, dropped);
+                            if let Err(e) = manager
+                                .publish(&queue_topic, report.as_bytes(), QoS0, true)
+                                .await
+                            {
+                                error!("MQTT send {}", e);
+                                break 'publish;
+                            }
+                            queue.mark_reported();
+                        }
+                        // writing numbers cannot fail
+                        if let (true, Ok(mut payload)) = (bms.valid, cell_payload(&bms)) {
+                            if let Err(e) = manager
+                                .publish_streamed(
+                                    &cells_topic,
+                                    &mut payload,
+                                    qos,
+                                    retain,
+                                    state_options,
+                                )
+                                .await
+                            {
+                                error!("MQTT send cells {}", e);
+                                break 'publish;
+                            }
+                        }
+                    }
+                }
+                Wake::Connected => {
+                    info!("MQTT connected ok");
+                    // Home Assistant discovery, retained so HA picks it up after its own restarts
+                    for sensor in mqtt_discovery::sensors() {
+                        let topic = sensor.config_topic(&node_id);
+                        let payload = sensor.config_payload(
+                            &node_id,
+                            mqtt_config.get_topic(),
+                            &availability_topic,
+                        );
+                        if let Err(e) = manager
+                            .publish(&topic, payload.as_bytes(), QoS0, true)
+                            .await
+                        {
+                            error!("MQTT discovery for {} failed {}", sensor.field, e);
+                            break;
+                        }
+                    }
+                    // Timer::at fires immediately, replaying anything queued while offline
+                    next_publish = Instant::now();
+                }
+                Wake::Disconnected(e) => warn!("MQTT not connected {}", e),
+                // offline snapshots are only queued every max_interval
+                Wake::Reading if !manager.is_connected() => continue,
+                Wake::Reading if next_publish > published + min_interval => {
+                    let moved = MQTTFMT.lock().await.exceeds(&state, &deadband);
+                    if moved || cells_exceed(&*BMS.lock().await, &bms, &deadband) {
+                        // Timer::at fires immediately if min_interval has already passed
+                        next_publish = published + min_interval;
+                    }
+                }
+                // already brought forward
+                Wake::Reading => continue,
+                Wake::ConfigChanged => {
+                    info!("MQTT config changed, reconnecting");
+                    if let Err(e) = manager.disconnect().await {
+                        error!("MQTT disconnect failed {}", e)
+                    }
+                    break;
+                }
+                Wake::Command(name, command) => {
+                    info!("MQTT command {}", name.as_str());
+                    let result = match command {
+                        Ok(Command::Dump) => {
+                            let dump = mqtt_commands::dump().await;
+                            manager
+                                .publish(&dump_topic, dump.as_bytes(), QoS0, false)
+                                .await
+                                .map(|_| "Dump published")
+                                .map_err(|_| CommandError::SendFailed)
+                        }
+                        Ok(command) => command.execute().await,
+                        Err(e) => Err(e),
+                    };
+                    let ack = mqtt_commands::ack(&name, result);
+                    if let Err(e) = manager
+                        .publish(&result_topic, ack.as_bytes(), QoS0, false)
+                        .await
+                    {
+                        error!("MQTT send {}", e);
+                    }
+                }
             }
-            // rate limiter
-            embassy_time::Timer::after(Duration::from_secs(mqtt_config.get_interval().into()))
-                .await;
         }
         defmt::warn!("Dropping MQTT client");
     }
 }
 
+/// Per-cell millivolts and balancing flags (0/1) as `{"mv":[..],"bal":[..]}`, rendered in
+/// chunks while it is sent so all cells go in one message without a buffer that holds it
+fn cell_payload(
+    bms: &bms_standard::Bms,
+) -> Result<Formatted<impl Fn(&mut dyn Write) -> fmt::Result + '_>, fmt::Error> {
+    Formatted::new(move |w| {
+        w.write_str(r#"{"mv":["#)?;
+        for (i, mv) in bms.cell_mv.0.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            write!(w, "{}{}", separator, mv)?;
+        }
+        w.write_str(r#"],"bal":["#)?;
+        for (i, &bal) in bms.bal_cells.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            write!(w, "{}{}", separator, bal as u8)?;
+        }
+        w.write_str("]}")
+    })
+}
+
+/// True if any cell voltage moved by at least the deadband or balancing changed
+fn cells_exceed(bms: &bms_standard::Bms, last: &bms_standard::Bms, deadband: &Deadband) -> bool {
+    bms.bal_cells != last.bal_cells
+        || bms
+            .cell_mv
+            .0
+            .iter()
+            .zip(last.cell_mv.0.iter())
+            .any(|(mv, last)| mv.abs_diff(*last) >= deadband.cell_mv)
+}
+
+/// What ended the wait between publishes
+enum Wake {
+    Publish,
+    Reading,
+    ConfigChanged,
+    Connected,
+    Disconnected(ReasonCode),
+    Command(String, Result<Command, CommandError>),
+    Ignored,
+}
+
+/// Applies a MqttConfig received as JSON, returns false if the bytes are not a valid MqttConfig
+async fn update_mqtt_config(json: &[u8]) -> bool {
+    let mut mqtt_config = crate::config::MqttConfig::default();
+    if mqtt_config.decode_from_json(json).is_err() || mqtt_config.validate().is_err() {
+        return false;
+    }
+    if let Err(e) = crate::storage::save(&mqtt_config).await {
+        error!("MqttConfig save error {}", e);
+    }
+    *MQTTCONFIG.lock().await = mqtt_config;
+    MQTT_CONFIG_CHANGED.signal(true);
+    info!("MqttConfig updated from UART");
+    true
+}
+
 #[embassy_executor::task]
 // pub async fn uart_task(uart: Uart<'static, USART3, DMA1_CH2, DMA1_CH3>) {
 pub async fn uart_task(uart: Uart<'static, USART6, DMA2_CH7, DMA2_CH2>) {
-    use embassy_futures::select::{select, Either};
+    use embassy_futures::select::select;
     let mut uart = uart;
     if uart.blocking_flush().is_err() {
         panic!();
@@ -194,8 +393,18 @@ pub async fn uart_task(uart: Uart<'static, USART6, DMA2_CH7, DMA2_CH2>) {
         match select(rx.read_until_idle(&mut buf), SEND_MQTT.wait()).await {
             Either::First(read) => match read {
                 Ok(len) => {
-                    let mut config = CONFIG.lock().await;
-                    if let Err(e) = config.decode_from_json(&buf[..len]) {
+                    if update_mqtt_config(&buf[..len]).await {
+                        let _ = tx
+                            .write(r#"{"message": "MqttConfig updated"}"#.as_bytes())
+                            .await;
+                        buf = [0_u8; 512];
+                        continue;
+                    }
+                    let mut config = Config::default();
+                    if let Err(e) = config
+                        .decode_from_json(&buf[..len])
+                        .and_then(|_| config.validate())
+                    {
                         let message = if let Ok(message) = core::str::from_utf8(&buf[..len]) {
                             message
                         } else {
@@ -210,13 +419,18 @@ pub async fn uart_task(uart: Uart<'static, USART6, DMA2_CH7, DMA2_CH2>) {
                         let _ = tx.write(&buf[..len]).await;
                         let _ = tx.write(r#""}"#.as_bytes()).await;
                         Timer::after(Duration::from_millis(500)).await;
-                        let _ = tx.write(config.to_json().as_bytes()).await;
+                        let json = CONFIG.lock().await.to_json();
+                        let _ = tx.write(json.as_bytes()).await;
                     } else {
+                        // saved before taking the locks, the erase blocks for about a second
+                        if let Err(e) = crate::storage::save(&config).await {
+                            error!("Config save error {}", e);
+                        }
                         info!("Config updated from UART");
                         let _ = tx.write(config.to_json().as_bytes()).await;
                         // update bms
-                        let mut bms = BMS.lock().await;
-                        bms.config = config.export_as_bms();
+                        BMS.lock().await.config = config.export_as_bms();
+                        *CONFIG.lock().await = config;
                     };
                     buf = [0_u8; 512];
                 }
@@ -239,22 +453,6 @@ pub async fn uart_task(uart: Uart<'static, USART6, DMA2_CH7, DMA2_CH2>) {
     }
 }
 
-struct SliceWriter<'a>(&'a mut [u8]);
-
-impl<'a> Write for SliceWriter<'a> {
-    fn write_str(&mut self, s: &str) -> fmt::Result {
-        let bytes = s.as_bytes();
-        if self.0.len() < bytes.len() {
-            Err(fmt::Error)
-        } else {
-            let (head, tail) = core::mem::take(&mut self.0).split_at_mut(bytes.len());
-            head.copy_from_slice(bytes);
-            self.0 = tail;
-            Ok(())
-        }
-    }
-}
-
 #[derive(Clone, Copy, Serialize)]
 pub struct MqttFormat {
     soc: f32,
@@ -318,7 +516,26 @@ impl MqttFormat {
         }
     }
 
-    fn device_update_msg(&self) -> alloc::string::String {
+    pub fn device_update_msg(&self) -> alloc::string::String {
         json::to_string(&self)
     }
+
+    /// True if any value moved by at least its deadband since `last`,
+    /// balancing and validity changes always count
+    fn exceeds(&self, last: &Self, deadband: &Deadband) -> bool {
+        // f32::abs needs std
+        let moved = |now: f32, then: f32, band: f32| now - then >= band || then - now >= band;
+        moved(self.soc, last.soc, deadband.soc)
+            || moved(self.volts, last.volts, deadband.volts)
+            || moved(self.amps, last.amps, deadband.amps)
+            || moved(self.kwh, last.kwh, deadband.kwh)
+            || moved(self.cell_temp_high, last.cell_temp_high, deadband.temp)
+            || moved(self.cell_temp_low, last.cell_temp_low, deadband.temp)
+            || moved(self.charge, last.charge, deadband.limits)
+            || moved(self.discharge, last.discharge, deadband.limits)
+            || self.cell_mv_high.abs_diff(last.cell_mv_high) >= deadband.cell_mv
+            || self.cell_mv_low.abs_diff(last.cell_mv_low) >= deadband.cell_mv
+            || self.bal != last.bal
+            || self.valid != last.valid
+    }
 }
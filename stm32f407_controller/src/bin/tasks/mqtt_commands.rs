@@ -0,0 +1,146 @@
+use crate::statics::{BMS, CONFIG, CONTACTOR_FORCE_OPEN, CONTACTOR_STATE};
+use alloc::string::{String, ToString};
+use bms_standard::BmsSerialise;
+use defmt::{info, warn, Format};
+use miniserde::{json, Deserialize, Serialize};
+
+/// Subtopic of `<basetopic>/cmd/` that acknowledgements are published on, ignored as a command
+pub const RESULT_SUBTOPIC: &str = "result";
+
+#[derive(Debug, Format)]
+pub enum CommandError {
+    UnknownCommand,
+    InvalidPayload,
+    Rejected,
+    SendFailed,
+}
+impl core::error::Error for CommandError {}
+impl core::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::UnknownCommand => write!(f, "Unknown command"),
+            Self::InvalidPayload => write!(f, "Invalid payload"),
+            Self::Rejected => write!(f, "Rejected by BMS"),
+            Self::SendFailed => write!(f, "Publish failed"),
+        }
+    }
+}
+
+/// Charge and discharge current limits in amps, an omitted field is left unchanged
+#[derive(Deserialize)]
+pub struct Limits {
+    charge: Option<f32>,
+    discharge: Option<f32>,
+}
+
+#[derive(Deserialize)]
+pub struct Dod {
+    min: u8,
+    max: u8,
+}
+
+/// `open: true` holds the contactor open until released with `open: false`
+#[derive(Deserialize)]
+pub struct Contactor {
+    open: bool,
+}
+
+/// Commands accepted on `<basetopic>/cmd/<name>`, the payload is JSON
+pub enum Command {
+    Limits(Limits),
+    Dod(Dod),
+    Contactor(Contactor),
+    Dump,
+}
+
+#[derive(Serialize)]
+struct Ack {
+    command: String,
+    ok: bool,
+    message: String,
+}
+
+impl Command {
+    pub fn parse(name: &str, payload: &[u8]) -> Result<Self, CommandError> {
+        fn decode<T: Deserialize>(payload: &[u8]) -> Result<T, CommandError> {
+            core::str::from_utf8(payload)
+                .ok()
+                .and_then(|s| json::from_str(s).ok())
+                .ok_or(CommandError::InvalidPayload)
+        }
+        match name {
+            "limits" => decode(payload).map(Command::Limits),
+            "dod" => decode(payload).map(Command::Dod),
+            "contactor" => decode(payload).map(Command::Contactor),
+            "dump" => Ok(Command::Dump),
+            _ => Err(CommandError::UnknownCommand),
+        }
+    }
+
+    /// Applies the command to the running system, changes are not persisted to flash
+    pub async fn execute(self) -> Result<&'static str, CommandError> {
+        match self {
+            Command::Limits(limits) => {
+                let mut bms = BMS.lock().await;
+                if let Some(max) = limits.charge {
+                    let min = *bms.config.charge_current_limts().minimum();
+                    bms.config
+                        .set_charge_limts(min, max)
+                        .map_err(|_| CommandError::Rejected)?;
+                }
+                if let Some(max) = limits.discharge {
+                    let min = *bms.config.discharge_current_limts().minimum();
+                    bms.config
+                        .set_discharge_limts(min, max)
+                        .map_err(|_| CommandError::Rejected)?;
+                }
+                CONFIG.lock().await.import_from_bms(bms.config);
+                Ok("Limits updated")
+            }
+            Command::Dod(dod) => {
+                BMS.lock()
+                    .await
+                    .set_dod(dod.min, dod.max)
+                    .map_err(|_| CommandError::Rejected)?;
+                CONFIG.lock().await.dod = bms_standard::MinMax::new(dod.min, dod.max);
+                Ok("DoD updated")
+            }
+            Command::Contactor(contactor) => {
+                *CONTACTOR_FORCE_OPEN.lock().await = contactor.open;
+                if contactor.open {
+                    warn!("Contactor forced open over MQTT");
+                    CONTACTOR_STATE.signal(false);
+                    Ok("Contactor held open")
+                } else {
+                    info!("Contactor released over MQTT");
+                    Ok("Contactor released")
+                }
+            }
+            // published by the MQTT task, which owns the client
+            Command::Dump => Ok("Dump queued"),
+        }
+    }
+}
+
+/// JSON acknowledgement for `<basetopic>/cmd/result`
+pub fn ack(command: &str, result: Result<&str, CommandError>) -> String {
+    let ack = match result {
+        Ok(message) => Ack {
+            command: command.into(),
+            ok: true,
+            message: message.into(),
+        },
+        Err(e) => Ack {
+            command: command.into(),
+            ok: false,
+            message: e.to_string(),
+        },
+    };
+    json::to_string(&ack)
+}
+
+/// Full BMS state for the dump command
+pub async fn dump() -> String {
+    let bms: BmsSerialise = (*BMS.lock().await).into();
+    json::to_string(&bms)
+}
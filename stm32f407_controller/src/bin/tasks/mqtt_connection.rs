@@ -0,0 +1,175 @@
+use crate::config::MqttConfig;
+#[cfg(feature = "mqtt_tls")]
+use crate::statics::TLS_RNG;
+use crate::types::{EthDevice, StackType};
+use crate::utils::CachedHost;
+use defmt::error;
+use embassy_net::tcp::client::{TcpClient, TcpConnection};
+use embassy_time::{Instant, Timer};
+use embedded_nal_async::TcpConnect;
+use rust_mqtt::client::manager::{Clock, Connector};
+#[cfg(feature = "mqtt_tls")]
+use rust_mqtt::network::tls::{
+    open_tls, MaybeTls, TlsAuth, TlsClock, TlsOptions, TLS_READ_BUFFER_LEN, TLS_WRITE_BUFFER_LEN,
+};
+use rust_mqtt::packet::v5::reason_codes::ReasonCode;
+
+pub const TCP_BUF_SIZE: usize = 2048;
+
+pub type MqttTcpClient<'d> = TcpClient<'d, EthDevice, 1, TCP_BUF_SIZE, TCP_BUF_SIZE>;
+type MqttTcp<'d> = TcpConnection<'d, 1, TCP_BUF_SIZE, TCP_BUF_SIZE>;
+#[cfg(feature = "mqtt_tls")]
+type Transport<'d> = MaybeTls<'d, MqttTcp<'d>>;
+#[cfg(not(feature = "mqtt_tls"))]
+type Transport<'d> = MqttTcp<'d>;
+
+// TLS record buffers, ~37KiB that is only reserved with the mqtt_tls feature
+#[cfg(feature = "mqtt_tls")]
+static mut TLS_READ: [u8; TLS_READ_BUFFER_LEN] = [0; TLS_READ_BUFFER_LEN];
+#[cfg(feature = "mqtt_tls")]
+static mut TLS_RECORD: [u8; TLS_READ_BUFFER_LEN] = [0; TLS_READ_BUFFER_LEN];
+#[cfg(feature = "mqtt_tls")]
+static mut TLS_WRITE: [u8; TLS_WRITE_BUFFER_LEN] = [0; TLS_WRITE_BUFFER_LEN];
+
+/// Resolves the broker and opens the TCP (and TLS) connection for every
+/// connect of the MQTT connection manager
+pub struct BrokerConnector<'d> {
+    stack: StackType,
+    client: &'d MqttTcpClient<'d>,
+    broker: &'d mut CachedHost,
+    mqtt_config: &'d MqttConfig,
+    attempts: u32,
+}
+
+impl<'d> BrokerConnector<'d> {
+    pub fn new(
+        stack: StackType,
+        client: &'d MqttTcpClient<'d>,
+        broker: &'d mut CachedHost,
+        mqtt_config: &'d MqttConfig,
+    ) -> Self {
+        broker.set(mqtt_config.get_host(), mqtt_config.get_port());
+        Self {
+            stack,
+            client,
+            broker,
+            mqtt_config,
+            attempts: 0,
+        }
+    }
+}
+
+impl<'d> Connector for BrokerConnector<'d> {
+    type Connection = Transport<'d>;
+
+    async fn connect(&mut self) -> Result<Transport<'d>, ReasonCode> {
+        // a reconnect may be down to the broker moving, look it up again
+        if self.attempts > 0 {
+            self.broker.invalidate();
+        }
+        self.attempts = self.attempts.wrapping_add(1);
+        let addr = self.broker.resolve(self.stack).await.map_err(|e| {
+            error!("MQTT broker address error: {}", e);
+            ReasonCode::NetworkError
+        })?;
+        let client = self.client;
+        let tcp = client.connect(addr).await.map_err(|e| {
+            error!("MQTT connect error: {}", e);
+            ReasonCode::NetworkError
+        })?;
+        open_transport(tcp, self.mqtt_config)
+            .await
+            .ok_or(ReasonCode::NetworkError)
+    }
+}
+
+/// embassy-time for the MQTT connection manager
+pub struct EmbassyClock;
+
+impl Clock for EmbassyClock {
+    fn now_ms(&self) -> u64 {
+        Instant::now().as_millis()
+    }
+
+    async fn sleep_until(&mut self, deadline_ms: u64) {
+        Timer::at(Instant::from_millis(deadline_ms)).await
+    }
+}
+
+/// Wall clock from NTP for the broker certificate validity period
+#[cfg(feature = "mqtt_tls")]
+pub struct NtpClock;
+
+#[cfg(feature = "mqtt_tls")]
+impl TlsClock for NtpClock {
+    fn now() -> Option<u64> {
+        use core::sync::atomic::Ordering;
+        match crate::statics::BOOT_UNIX_SECS.load(Ordering::Relaxed) {
+            0 => None,
+            boot => Some(boot as u64 + Instant::now().as_secs()),
+        }
+    }
+}
+
+/// Wraps the TCP connection in TLS when the config asks for it,
+/// the broker is authenticated by the configured PSK or the built in CA
+#[cfg(feature = "mqtt_tls")]
+async fn open_transport<'d>(tcp: MqttTcp<'d>, mqtt_config: &MqttConfig) -> Option<Transport<'d>> {
+    use defmt::{info, Debug2Format};
+
+    if !mqtt_config.get_tls() {
+        return Some(MaybeTls::Plain(tcp));
+    }
+    let psk = mqtt_config.get_psk();
+    let auth = match (&psk, crate::tasks::mqtt_ca::MQTT_CA) {
+        (Some((identity, key)), _) => TlsAuth::Psk {
+            identity: identity.as_bytes(),
+            key,
+        },
+        (None, Some(_)) if NtpClock::now().is_none() => {
+            error!("MQTT TLS waits for NTP, the broker certificate dates can not be checked yet");
+            return None;
+        }
+        (None, Some(ca)) => TlsAuth::Ca(ca),
+        (None, None) => {
+            error!("MQTT TLS needs a PSK or a CA certificate built in with MQTT_CA_DER");
+            return None;
+        }
+    };
+    let options = TlsOptions {
+        server_name: mqtt_config.get_host(),
+        auth,
+    };
+    let mut rng = TLS_RNG.lock().await;
+    let Some(rng) = rng.as_mut() else {
+        error!("MQTT TLS has no RNG");
+        return None;
+    };
+    // SAFETY: only the MQTT task opens connections, and the connection manager drops the
+    // previous connection (the only other user of the buffers) before connecting again
+    let (read_buffer, record_buffer, write_buffer) = unsafe {
+        (
+            &mut *core::ptr::addr_of_mut!(TLS_READ),
+            &mut *core::ptr::addr_of_mut!(TLS_RECORD),
+            &mut *core::ptr::addr_of_mut!(TLS_WRITE),
+        )
+    };
+    match open_tls::<_, _, NtpClock>(tcp, read_buffer, record_buffer, write_buffer, &options, rng)
+        .await
+    {
+        Ok(tls) => {
+            info!("MQTT TLS established");
+            Some(MaybeTls::Tls(tls))
+        }
+        Err(e) => {
+            error!("MQTT TLS handshake failed {}", Debug2Format(&e));
+            None
+        }
+    }
+}
+
+/// `MqttConfig::validate` rejects TLS without the mqtt_tls feature
+#[cfg(not(feature = "mqtt_tls"))]
+async fn open_transport<'d>(tcp: MqttTcp<'d>, _mqtt_config: &MqttConfig) -> Option<Transport<'d>> {
+    Some(tcp)
+}
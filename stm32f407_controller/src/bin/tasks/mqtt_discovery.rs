@@ -0,0 +1,113 @@
+use alloc::string::String;
+use core::fmt::Write;
+use miniserde::json;
+
+const DISCOVERY_PREFIX: &str = "homeassistant";
+
+/// Home Assistant sensor for one field of the `MqttFormat` state message,
+/// an empty device class or unit is left out of the payload
+pub struct Sensor {
+    pub field: &'static str,
+    name: &'static str,
+    device_class: &'static str,
+    unit: &'static str,
+}
+
+// field, name, device_class, unit
+const SENSORS: [(&str, &str, &str, &str); 12] = [
+    ("soc", "SoC", "battery", "%"),
+    ("volts", "Pack voltage", "voltage", "V"),
+    ("amps", "Current", "current", "A"),
+    ("kwh", "Energy remaining", "energy_storage", "kWh"),
+    ("cell_mv_high", "Cell voltage high", "voltage", "mV"),
+    ("cell_mv_low", "Cell voltage low", "voltage", "mV"),
+    (
+        "cell_temp_high",
+        "Cell temperature high",
+        "temperature",
+        "°C",
+    ),
+    ("cell_temp_low", "Cell temperature low", "temperature", "°C"),
+    ("charge", "Charge limit", "current", "A"),
+    ("discharge", "Discharge limit", "current", "A"),
+    ("bal", "Balancing cells", "", ""),
+    ("valid", "Valid", "", ""),
+];
+
+pub fn sensors() -> impl Iterator<Item = Sensor> {
+    SENSORS
+        .iter()
+        .map(|&(field, name, device_class, unit)| Sensor {
+            field,
+            name,
+            device_class,
+            unit,
+        })
+}
+
+/// HA only accepts `[a-zA-Z0-9_-]` in the node id
+pub fn node_id(client_id: &str) -> String {
+    if client_id.is_empty() {
+        return "toucan".into();
+    }
+    client_id
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+impl Sensor {
+    pub fn config_topic(&self, node_id: &str) -> String {
+        alloc::format!(
+            "{}/sensor/{}/{}/config",
+            DISCOVERY_PREFIX,
+            node_id,
+            self.field
+        )
+    }
+
+    /// Retained discovery payload, `state_topic` is where `MqttFormat` is published
+    pub fn config_payload(
+        &self,
+        node_id: &str,
+        state_topic: &str,
+        availability_topic: &str,
+    ) -> String {
+        let mut payload = String::new();
+        // writing to a String cannot fail
+        let _ = write!(
+            payload,
+            r#"{{"name":{},"unique_id":"{}_{}","state_topic":{},"value_template":"{{{{ value_json.{} }}}}","#,
+            json::to_string(self.name),
+            node_id,
+            self.field,
+            json::to_string(state_topic),
+            self.field,
+        );
+        // payload_available/payload_not_available default to "online"/"offline"
+        let _ = write!(
+            payload,
+            r#""availability_topic":{},"#,
+            json::to_string(availability_topic)
+        );
+        if !self.device_class.is_empty() {
+            let _ = write!(payload, r#""device_class":"{}","#, self.device_class);
+        }
+        if !self.unit.is_empty() {
+            let _ = write!(
+                payload,
+                r#""unit_of_measurement":"{}","state_class":"measurement","#,
+                self.unit
+            );
+        }
+        let _ = write!(
+            payload,
+            r#""device":{{"identifiers":["{}"],"name":"{}","manufacturer":"Toucan","model":"STM32F407 controller"}}}}"#,
+            node_id, node_id,
+        );
+        payload
+    }
+}
@@ -0,0 +1,81 @@
+use crate::tasks::mqtt::MqttFormat;
+use alloc::string::String;
+use core::fmt::Write;
+use embassy_time::Instant;
+use heapless::Deque;
+
+// ~3KiB, 10 minutes of snapshots at the default 10s interval
+pub const QUEUE_LEN: usize = 64;
+
+struct Pending {
+    state: MqttFormat,
+    captured: Instant,
+}
+
+/// Outstanding state publications, oldest first.
+/// A message only leaves the queue once the broker has taken it (PUBACK or PUBCOMP for
+/// QoS1/2, a completed write for QoS0), so snapshots queued while the broker is unreachable
+/// are replayed in order after reconnecting. When full the oldest snapshot is dropped and counted.
+pub struct OutboundQueue {
+    pending: Deque<Pending, QUEUE_LEN>,
+    dropped: u32,
+    reported: u32,
+}
+
+impl OutboundQueue {
+    pub const fn new() -> Self {
+        Self {
+            pending: Deque::new(),
+            dropped: 0,
+            reported: 0,
+        }
+    }
+
+    pub fn push(&mut self, state: MqttFormat) {
+        if self.pending.is_full() {
+            self.pending.pop_front();
+            self.dropped = self.dropped.wrapping_add(1);
+        }
+        // cannot fail, there is room after the pop above
+        let _ = self.pending.push_back(Pending {
+            state,
+            captured: Instant::now(),
+        });
+    }
+
+    /// State message for the oldest entry with an `age` field in seconds,
+    /// so replayed messages can be placed in time
+    pub fn front_payload(&self) -> Option<String> {
+        self.pending.front().map(|pending| {
+            let mut payload = pending.state.device_update_msg();
+            // reopen the JSON object
+            payload.pop();
+            // writing to a String cannot fail
+            let _ = write!(
+                payload,
+                r#","age":{}}}"#,
+                pending.captured.elapsed().as_secs()
+            );
+            payload
+        })
+    }
+
+    /// Call once the broker has accepted the message from `front_payload`
+    pub fn pop(&mut self) {
+        self.pending.pop_front();
+    }
+
+    pub fn queued(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Total of dropped messages if it changed since the last `mark_reported`
+    pub fn unreported_drops(&self) -> Option<u32> {
+        (self.dropped != self.reported).then_some(self.dropped)
+    }
+
+    /// Call once the total from `unreported_drops` has been published
+    pub fn mark_reported(&mut self) {
+        self.reported = self.dropped;
+    }
+}
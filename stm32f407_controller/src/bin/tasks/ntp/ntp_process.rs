@@ -1,7 +1,7 @@
 use crate::types::StackType;
+use crate::utils::CachedHost;
 use chrono::{DateTime as ChronoDateTime, NaiveDateTime, TimeZone};
 use chrono_tz::Tz;
-use core::str::FromStr;
 use defmt::{error, info, warn, Debug2Format, Format};
 use embassy_net::{
     udp::{PacketMetadata, UdpSocket},
@@ -13,6 +13,8 @@ use sntpc::{NtpContext, NtpResult, NtpTimestampGenerator};
 
 include!("timezone.rs");
 
+const NTP_ATTEMPTS: u8 = 3;
+
 #[derive(Debug, Format)]
 pub enum NtpError {
     Parse,
@@ -77,48 +79,78 @@ pub async fn ntp_task(stack: StackType, mut rtc: Rtc) {
         warn!("No NTP server configured in .env");
     } else {
         info!("Spawning Network NTP client");
+        // DNS servers arrive with the network config
+        while stack.config_v4().is_none() {
+            embassy_time::Timer::after(embassy_time::Duration::from_millis(500)).await;
+        }
+
+        let (ntp_host, port) = ntp_cfg_ip.rsplit_once(':').expect("Bad NTP host:port");
+        let ntp_port = port.parse::<u16>().expect("Invalid NTP server port");
+        let mut ntp_server = CachedHost::new(ntp_host, ntp_port);
 
         let mut rx_buffer = [0; 512];
         let mut tx_buffer = [0; 512];
         let mut rx_meta = [PacketMetadata::EMPTY; 16];
         let mut tx_meta = [PacketMetadata::EMPTY; 16];
-        let socket = UdpSocket::new(
-            stack,
-            &mut rx_meta,
-            &mut rx_buffer,
-            &mut tx_meta,
-            &mut tx_buffer,
-        );
         let stddt = StdTimestampGen::from(rtc_now);
         warn!("Sending this to NTP: {:?}", Debug2Format(&stddt.datetime));
-        let ntp_context = NtpContext::new(stddt);
-
-        let endpoint = {
-            let (ntp_ip, port) = ntp_cfg_ip.rsplit_once(':').expect("Bad NTP ip:port");
-            let ntp_server_address =
-                Ipv4Address::from_str(ntp_ip).expect("Invalid NTP server IP address");
-            let ntp_port = port.parse::<u16>().expect("Invalid NTP server port");
-
-            IpEndpoint::new(IpAddress::Ipv4(ntp_server_address), ntp_port)
-        };
 
-        let ntpsocket =
-            UdpSocketWrapper(NoStdUdpSocket::bind(socket, endpoint).expect("NTP UDP Bind fail"));
-
-        let result = sntpc::get_time(ntpsocket.0.socketaddress, &ntpsocket, ntp_context).await;
-        let new_rtc_time: Option<NaiveDateTime> = match result {
-            Ok(time) => match Time::try_from(time) {
-                Ok(local_time) => EpochTime::from(local_time).get_datetime(),
-                Err(_) => {
-                    error!("NTP time conversion failed with parse error");
+        let mut new_rtc_time: Option<NaiveDateTime> = None;
+        for attempt in 1..=NTP_ATTEMPTS {
+            if attempt > 1 {
+                embassy_time::Timer::after(embassy_time::Duration::from_secs(5)).await;
+            }
+            let endpoint = match ntp_server.resolve(stack).await {
+                Ok(SocketAddr::V4(addr)) => IpEndpoint::new(
+                    IpAddress::Ipv4(Ipv4Address(addr.ip().octets())),
+                    addr.port(),
+                ),
+                Ok(_) => continue,
+                Err(e) => {
+                    error!("NTP server address error: {}", e);
+                    continue;
+                }
+            };
+            let socket = UdpSocket::new(
+                stack,
+                &mut rx_meta,
+                &mut rx_buffer,
+                &mut tx_meta,
+                &mut tx_buffer,
+            );
+            let ntpsocket = match NoStdUdpSocket::bind(socket, endpoint) {
+                Ok(socket) => UdpSocketWrapper(socket),
+                Err(_) => continue,
+            };
+
+            let ntp_context = NtpContext::new(stddt);
+            let result = sntpc::get_time(ntpsocket.0.socketaddress, &ntpsocket, ntp_context).await;
+            if let Ok(time) = &result {
+                let uptime = embassy_time::Instant::now().as_secs() as u32;
+                crate::statics::BOOT_UNIX_SECS.store(
+                    time.sec().saturating_sub(uptime),
+                    core::sync::atomic::Ordering::Relaxed,
+                );
+            }
+            new_rtc_time = match result {
+                Ok(time) => match Time::try_from(time) {
+                    Ok(local_time) => EpochTime::from(local_time).get_datetime(),
+                    Err(_) => {
+                        error!("NTP time conversion failed with parse error");
+                        None
+                    }
+                },
+                Err(err) => {
+                    error!("NTP update failed with {:?}", Debug2Format(&err));
+                    // the server may have moved, look it up again
+                    ntp_server.invalidate();
                     None
                 }
-            },
-            Err(err) => {
-                error!("NTP update failed with {:?}", Debug2Format(&err));
-                None
+            };
+            if new_rtc_time.is_some() {
+                break;
             }
-        };
+        }
 
         if let Some(ntp_time) = new_rtc_time {
             // let mut rtc = rtc.lock().await;
@@ -0,0 +1,72 @@
+use crate::config::{Battery, Inverter};
+use embassy_stm32::can::bxcan::filter::Mask32;
+
+use super::can_processors_pylontech::{Byd, GoodWe, Pylontech};
+use super::can_processors_pylontech_forceh2::ForceH2;
+use super::can_processors_solax::{FoxEss, Solax};
+use super::can_processors_tesla_m3::TeslaM3;
+use super::can_processors_ze40::Ze40;
+use super::can_processors_ze50::Ze50;
+
+/// Talks to the battery on CAN1 through BMS_CAN and keeps BMS up to date
+#[allow(async_fn_in_trait)]
+pub trait BatteryDriver {
+    const NAME: &'static str;
+
+    /// CAN1 acceptance filter
+    fn filter() -> Mask32 {
+        Mask32::accept_all()
+    }
+
+    /// Decodes battery frames into BMS
+    async fn rx();
+
+    /// Sends the requests the battery needs to keep reporting
+    async fn tx_periodic();
+}
+
+/// Answers the inverter on CAN2 through INVERTER_CAN from BMS
+#[allow(async_fn_in_trait)]
+pub trait InverterProtocol {
+    const NAME: &'static str;
+
+    async fn rx();
+}
+
+pub fn bms_filter(battery: Battery) -> Mask32 {
+    match battery {
+        Battery::Ze40 => Ze40::filter(),
+        Battery::Ze50 => Ze50::filter(),
+        Battery::TeslaM3 => TeslaM3::filter(),
+    }
+}
+
+#[embassy_executor::task]
+pub async fn bms_rx(battery: Battery) {
+    match battery {
+        Battery::Ze40 => Ze40::rx().await,
+        Battery::Ze50 => Ze50::rx().await,
+        Battery::TeslaM3 => TeslaM3::rx().await,
+    }
+}
+
+#[embassy_executor::task]
+pub async fn bms_tx_periodic(battery: Battery) {
+    match battery {
+        Battery::Ze40 => Ze40::tx_periodic().await,
+        Battery::Ze50 => Ze50::tx_periodic().await,
+        Battery::TeslaM3 => TeslaM3::tx_periodic().await,
+    }
+}
+
+#[embassy_executor::task]
+pub async fn inverter_rx(inverter: Inverter) {
+    match inverter {
+        Inverter::Solax => Solax::rx().await,
+        Inverter::FoxEss => FoxEss::rx().await,
+        Inverter::Byd => Byd::rx().await,
+        Inverter::Pylontech => Pylontech::rx().await,
+        Inverter::GoodWe => GoodWe::rx().await,
+        Inverter::ForceH2 => ForceH2::rx().await,
+    }
+}
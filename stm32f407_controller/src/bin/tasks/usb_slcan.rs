@@ -0,0 +1,214 @@
+//! SLCAN adapters on the USB OTG FS port, one CDC-ACM serial port per bus.
+//!
+//! The first port is CAN1 (battery), the second CAN2 (inverter), for `slcand` and
+//! SocketCAN. Opened ports stream both directions of their bus, except the frames
+//! the port sent itself. Bitrates stay as configured on the board, `Sn` is only
+//! accepted when it matches.
+use defmt::*;
+use embassy_futures::join::join3;
+use embassy_futures::select::{select, Either};
+use embassy_stm32::can::bxcan;
+use embassy_sync::pubsub::WaitResult;
+use embassy_usb::{
+    class::cdc_acm::{CdcAcmClass, Receiver, Sender, State},
+    driver::EndpointError,
+    Builder,
+};
+use heapless::Deque;
+use slcan::{Command, Decoder, Response};
+
+use crate::{
+    can_bus::{Bus, CanBus, Direction, TapSubscriber},
+    statics::{BMS_CAN, INVERTER_CAN},
+    types::UsbDriver,
+};
+
+const MAX_PACKET: u16 = 64;
+const HARDWARE_VERSION: u8 = 0x10;
+const SOFTWARE_VERSION: u8 = 0x10;
+/// Frames sent by a session that the tap has not echoed yet
+const PENDING_ECHOES: usize = 16;
+
+#[embassy_executor::task]
+pub async fn usb_task(driver: UsbDriver) {
+    let mut config = embassy_usb::Config::new(0xc0de, 0xcafe);
+    config.manufacturer = Some("Toucan");
+    config.product = Some("Toucan SLCAN");
+    config.serial_number = Some("TOUCAN");
+    config.max_power = 100;
+    config.max_packet_size_0 = 64;
+    // two CDC-ACM functions need interface association descriptors
+    config.device_class = 0xEF;
+    config.device_sub_class = 0x02;
+    config.device_protocol = 0x01;
+    config.composite_with_iads = true;
+
+    let mut device_descriptor = [0; 256];
+    let mut config_descriptor = [0; 256];
+    let mut bos_descriptor = [0; 256];
+    let mut control_buf = [0; 64];
+    let mut bms_state = State::new();
+    let mut inverter_state = State::new();
+    let mut builder = Builder::new(
+        driver,
+        config,
+        &mut device_descriptor,
+        &mut config_descriptor,
+        &mut bos_descriptor,
+        &mut [], // no msos descriptors
+        &mut control_buf,
+    );
+    let bms = CdcAcmClass::new(&mut builder, &mut bms_state, MAX_PACKET);
+    let inverter = CdcAcmClass::new(&mut builder, &mut inverter_state, MAX_PACKET);
+    let mut usb = builder.build();
+
+    warn!("Starting USB SLCAN");
+    join3(
+        usb.run(),
+        port(bms, &BMS_CAN),
+        port(inverter, &INVERTER_CAN),
+    )
+    .await;
+}
+
+async fn port(class: CdcAcmClass<'_, UsbDriver>, bus: &CanBus) {
+    let (mut sender, mut receiver) = class.split();
+    loop {
+        receiver.wait_connection().await;
+        info!("SLCAN {} connected", bus.bus());
+        if let Err(e) = session(&mut sender, &mut receiver, bus).await {
+            info!("SLCAN {} disconnected: {}", bus.bus(), e);
+        }
+    }
+}
+
+async fn session(
+    sender: &mut Sender<'_, UsbDriver>,
+    receiver: &mut Receiver<'_, UsbDriver>,
+    bus: &CanBus,
+) -> Result<(), EndpointError> {
+    let mut decoder = Decoder::new();
+    // Some while the channel is open
+    let mut tap: Option<TapSubscriber> = None;
+    let mut listen_only = false;
+    let mut timestamps = false;
+    // Tx frames are tapped in the order they were queued, so the echoes of this
+    // session's frames arrive in the order they were sent
+    let mut echoes: Deque<bxcan::Frame, PENDING_ECHOES> = Deque::new();
+    let mut buf = [0u8; MAX_PACKET as usize];
+    loop {
+        let event = match tap.as_mut() {
+            Some(tap) => select(receiver.read_packet(&mut buf), tap.next_message()).await,
+            None => Either::First(receiver.read_packet(&mut buf).await),
+        };
+        let tapped = match event {
+            Either::First(read) => {
+                for &byte in &buf[..read?] {
+                    let Some(command) = decoder.push(byte) else {
+                        continue;
+                    };
+                    let response = match command {
+                        Err(e) => {
+                            warn!("SLCAN {} {}", bus.bus(), e);
+                            Response::Error
+                        }
+                        Ok(Command::Open | Command::ListenOnly) if tap.is_some() => Response::Error,
+                        Ok(command @ (Command::Open | Command::ListenOnly)) => match bus.tap() {
+                            Ok(subscriber) => {
+                                tap = Some(subscriber);
+                                listen_only = command == Command::ListenOnly;
+                                Response::Ok
+                            }
+                            Err(e) => {
+                                error!("SLCAN {} tap {}", bus.bus(), e);
+                                Response::Error
+                            }
+                        },
+                        Ok(Command::Close) => {
+                            tap = None;
+                            echoes.clear();
+                            Response::Ok
+                        }
+                        Ok(Command::Bitrate(bitrate)) if bitrate == bus.bitrate() => Response::Ok,
+                        Ok(Command::Bitrate(bitrate)) => {
+                            warn!(
+                                "SLCAN {} bitrate {} rejected, bus runs at {}",
+                                bus.bus(),
+                                bitrate,
+                                bus.bitrate()
+                            );
+                            Response::Error
+                        }
+                        Ok(Command::Transmit(_)) if tap.is_none() || listen_only => Response::Error,
+                        Ok(Command::Transmit(frame)) => match to_bxcan(&frame) {
+                            Some(f) if bus.sender().try_send(f.clone()).is_ok() => {
+                                if echoes.is_full() {
+                                    echoes.pop_front();
+                                }
+                                let _ = echoes.push_back(f);
+                                Response::Sent(frame.id())
+                            }
+                            _ => Response::Error,
+                        },
+                        Ok(Command::Timestamps(on)) => {
+                            timestamps = on;
+                            Response::Ok
+                        }
+                        Ok(Command::Version) => Response::Version {
+                            hardware: HARDWARE_VERSION,
+                            software: SOFTWARE_VERSION,
+                        },
+                        Ok(Command::SerialNumber) => Response::SerialNumber(match bus.bus() {
+                            Bus::Bms => *b"CAN1",
+                            Bus::Inverter => *b"CAN2",
+                        }),
+                        Ok(Command::Status) => Response::Status(0),
+                        Ok(Command::Ignored) => Response::Ok,
+                    };
+                    sender.write_packet(response.encode().as_bytes()).await?;
+                }
+                continue;
+            }
+            Either::Second(WaitResult::Message(tapped)) => tapped,
+            Either::Second(WaitResult::Lagged(n)) => {
+                warn!("SLCAN {} lagged, {} frames dropped", bus.bus(), n);
+                // the dropped frames may include echoes that will never arrive
+                echoes.clear();
+                continue;
+            }
+        };
+        if tapped.direction == Direction::Tx && echoes.front() == Some(&tapped.frame) {
+            echoes.pop_front();
+            continue;
+        }
+        if let Some(frame) = to_slcan(&tapped.frame) {
+            let response = Response::Frame {
+                frame,
+                timestamp: timestamps.then(|| slcan::timestamp(tapped.at.as_millis())),
+            };
+            sender.write_packet(response.encode().as_bytes()).await?;
+        }
+    }
+}
+
+fn to_slcan(frame: &bxcan::Frame) -> Option<slcan::Frame> {
+    let id = match frame.id() {
+        bxcan::Id::Standard(id) => slcan::Id::Standard(id.as_raw()),
+        bxcan::Id::Extended(id) => slcan::Id::Extended(id.as_raw()),
+    };
+    match frame.data() {
+        Some(data) => slcan::Frame::new(id, data),
+        None => slcan::Frame::new_remote(id, frame.dlc()),
+    }
+}
+
+fn to_bxcan(frame: &slcan::Frame) -> Option<bxcan::Frame> {
+    let id = match frame.id() {
+        slcan::Id::Standard(id) => bxcan::Id::Standard(bxcan::StandardId::new(id)?),
+        slcan::Id::Extended(id) => bxcan::Id::Extended(bxcan::ExtendedId::new(id)?),
+    };
+    match frame.is_remote() {
+        true => Some(bxcan::Frame::new_remote(id, frame.dlc())),
+        false => bxcan::Data::new(frame.data()).map(|data| bxcan::Frame::new_data(id, data)),
+    }
+}
@@ -8,21 +8,16 @@ use embassy_stm32::eth::generic_smi::GenericSMI;
 #[cfg(all(feature = "spi", feature = "display"))]
 use embassy_stm32::gpio::{AnyPin, Output};
 
+use embassy_stm32::eth::Ethernet;
 use embassy_stm32::usart::Uart;
-use embassy_stm32::{can::bxcan::Frame, eth::Ethernet};
 use embassy_stm32::{peripherals::*, spi};
 use embassy_sync::{
-    blocking_mutex::raw::CriticalSectionRawMutex as _Mutex, channel::Channel, mutex::Mutex,
-    signal::Signal,
+    blocking_mutex::raw::CriticalSectionRawMutex as _Mutex, mutex::Mutex, signal::Signal,
 };
 
 use embassy_time::Instant;
 pub const FRAME_BUFFER: usize = 10;
 
-pub type InverterChannelRx = Channel<_Mutex, Frame, FRAME_BUFFER>;
-pub type InverterChannelTx = Channel<_Mutex, Frame, FRAME_BUFFER>;
-pub type BmsChannelRx = Channel<_Mutex, Frame, FRAME_BUFFER>;
-pub type BmsChannelTx = Channel<_Mutex, Frame, FRAME_BUFFER>;
 pub type Elapsed = Mutex<_Mutex, Option<Instant>>;
 pub type MutexType<T> = embassy_sync::mutex::Mutex<_Mutex, T>;
 pub type Status = Signal<_Mutex, bool>;
@@ -30,7 +25,11 @@ pub type LedCommandType = Signal<_Mutex, crate::tasks::leds::LedCommand>;
 pub type EpochType = Signal<_Mutex, embassy_stm32::rtc::DateTime>;
 
 pub type EthDevice = Ethernet<'static, ETH, GenericSMI>;
+#[cfg(feature = "mqtt_tls")]
+pub type TlsRng = embassy_stm32::rng::Rng<'static, RNG>;
 pub type StackType = &'static Stack<EthDevice>;
+#[cfg(feature = "slcan")]
+pub type UsbDriver = embassy_stm32::usb_otg::Driver<'static, USB_OTG_FS>;
 
 pub type RS485<'a> = Uart<'a, USART2, DMA1_CH6, DMA1_CH5>;
 pub type RS232<'a> = Uart<'a, USART1, DMA2_CH0, DMA2_CH1>;
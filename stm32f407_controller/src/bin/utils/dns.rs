@@ -0,0 +1,76 @@
+use crate::errors::StmError;
+use crate::types::StackType;
+use alloc::string::String;
+use defmt::{error, info};
+use embassy_net::{dns::DnsQueryType, IpAddress};
+use embassy_time::{Duration, Instant};
+use no_std_net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+
+// embassy-net does not expose the record TTL, refresh hourly instead
+const CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Host name or IPv4 literal resolved through the DNS servers of the stack
+/// (from DHCP, or `NetConfig.dns` with a static address).
+/// The address is cached until it goes stale or the caller reports a failed connection.
+pub struct CachedHost {
+    host: String,
+    port: u16,
+    cached: Option<(SocketAddr, Instant)>,
+}
+
+impl CachedHost {
+    pub fn new(host: &str, port: u16) -> Self {
+        Self {
+            host: host.into(),
+            port,
+            cached: None,
+        }
+    }
+
+    /// Replaces the target, the cache is dropped if it differs
+    pub fn set(&mut self, host: &str, port: u16) {
+        if self.host != host || self.port != port {
+            *self = Self::new(host, port);
+        }
+    }
+
+    /// Forces a fresh lookup on the next `resolve`, call after a connection to the address fails
+    pub fn invalidate(&mut self) {
+        self.cached = None;
+    }
+
+    pub async fn resolve(&mut self, stack: StackType) -> Result<SocketAddr, StmError> {
+        if let Some((addr, resolved)) = self.cached {
+            if resolved.elapsed() < CACHE_TTL {
+                return Ok(addr);
+            }
+        }
+        if self.host.is_empty() {
+            return Err(StmError::InvalidHostname);
+        }
+        // IP literals are returned by embassy-net without a query
+        let addresses = stack
+            .dns_query(&self.host, DnsQueryType::A)
+            .await
+            .map_err(|e| {
+                error!("DNS lookup of {} failed: {}", self.host.as_str(), e);
+                StmError::DnsLookupFailed
+            })?;
+        let ip = addresses
+            .iter()
+            .find_map(|addr| match addr {
+                IpAddress::Ipv4(ip) => Some(Ipv4Addr::from(ip.0)),
+                #[allow(unreachable_patterns)]
+                _ => None,
+            })
+            .ok_or(StmError::DnsLookupFailed)?;
+        let addr = SocketAddr::V4(SocketAddrV4::new(ip, self.port));
+        info!(
+            "Resolved {} to {}",
+            self.host.as_str(),
+            defmt::Debug2Format(&addr)
+        );
+        self.cached = Some((addr, Instant::now()));
+        Ok(addr)
+    }
+}
@@ -1,3 +1,8 @@
 #[allow(unused_imports)]
 pub use bytes_writer::{ByteMutWriter, ByteMutWriterCap};
 mod bytes_writer;
+
+#[cfg(any(feature = "mqtt", feature = "ntp"))]
+mod dns;
+#[cfg(any(feature = "mqtt", feature = "ntp"))]
+pub use dns::CachedHost;
@@ -1,5 +1,12 @@
+use crate::can_bus::capture::{self, Command};
+use crate::config::{CanConfig, Config, JsonTrait, MqttConfig, NetConfig, ProtocolConfig};
 use crate::errors::StmError;
-use crate::statics::{BMS, CONFIG};
+use crate::statics::{
+    BMS, BMS_CAN, CAN_CONFIG, CONFIG, INVERTER_CAN, MQTTCONFIG, MQTT_CONFIG_CHANGED, NETCONFIG,
+    PROTOCOLS,
+};
+use crate::storage;
+use alloc::string::{String, ToString};
 // use crate::types::messagebus::RequestType;
 use crate::types::EthDevice;
 use crate::utils::ByteMutWriter;
@@ -104,15 +111,14 @@ async fn spawned_http(stack: &'static Stack<EthDevice>, num: u8) {
         let mut buffer = [0u8; 1024];
         let mut pos = 0;
         loop {
-            let buf = match socket.read(&mut buffer).await {
+            let buf = match socket.read(&mut buffer[pos..]).await {
                 Ok(0) => {
                     info!("[{}] read EOF", num);
                     break;
                 }
                 Ok(len) => {
-                    let out = &buffer[pos..(pos + len)];
                     pos += len;
-                    out
+                    &buffer[..pos]
                 }
                 Err(e) => {
                     error!("[{}] socket.read {}", num, e);
@@ -124,15 +130,29 @@ async fn spawned_http(stack: &'static Stack<EthDevice>, num: u8) {
             let mut req = httparse::Request::new(&mut headers);
             let res = req.parse(buf);
 
-            let _req_type = HttpRequestType::decode(req.method);
+            let req_type = HttpRequestType::decode(req.method);
 
-            if let Err(e) = res {
-                error!("[{}] parse error {}", num, Debug2Format(&e));
+            let header_len = match res {
+                Err(e) => {
+                    error!("[{}] parse error {}", num, Debug2Format(&e));
+                    break;
+                }
+                Ok(httparse::Status::Partial) if pos < buffer.len() => continue,
+                Ok(httparse::Status::Partial) => {
+                    error!("[{}] request headers exceed buffer", num);
+                    break;
+                }
+                Ok(httparse::Status::Complete(len)) => len,
+            };
+            let body_len = content_length(req.headers);
+            if header_len + body_len > buffer.len() {
+                error!("[{}] request body exceeds buffer", num);
                 break;
             }
-            if res.unwrap().is_partial() {
+            if pos < header_len + body_len {
                 continue;
             }
+            let body = &buf[header_len..header_len + body_len];
             if req.path.is_none() {
                 // respond with index.html
                 match socket.write("FOO".as_bytes()).await {
@@ -155,9 +175,36 @@ async fn spawned_http(stack: &'static Stack<EthDevice>, num: u8) {
             let mut buf = [0u8; 1024 * 12];
             let mut response = ByteMutWriter::new(&mut buf[..]);
 
+            let is_post = matches!(req_type, Ok(HttpRequestType::Post));
             // Set headers for html and json
             match this.path {
                 Some("/favicon.ico") => break,
+                Some(
+                    path @ ("/api/config" | "/api/net" | "/api/mqtt" | "/api/protocols"
+                    | "/api/can" | "/api/capture"),
+                ) if is_post => {
+                    let r = match update_config(path, body).await {
+                        Ok(message) => {
+                            let message = json::to_string(&Message { message });
+                            construct_response(message.as_bytes(), HttpType::Json, &mut response)
+                        }
+                        Err(e) => {
+                            error!("[{}] POST {} rejected: {}", num, path, e);
+                            let (status, message) = error_response(&e);
+                            construct_status_response(
+                                status,
+                                message.as_bytes(),
+                                HttpType::Json,
+                                &mut response,
+                            )
+                        }
+                    };
+                    if let Ok(r) = r {
+                        if let Err(e) = socket.write(&r.buf[..r.cursor()]).await {
+                            error!("[{}] TCP Write error {}", num, e)
+                        }
+                    }
+                }
                 Some("/api/config") => {
                     let config = CONFIG.lock().await;
                     let a = json::to_string(&*config);
@@ -168,6 +215,66 @@ async fn spawned_http(stack: &'static Stack<EthDevice>, num: u8) {
                         }
                     }
                 }
+                Some("/api/protocols") => {
+                    let protocols = PROTOCOLS.lock().await;
+                    let a = json::to_string(&*protocols);
+                    let a = a.as_bytes();
+                    if let Ok(r) = construct_response(a, HttpType::Json, &mut response) {
+                        if let Err(e) = socket.write(&r.buf[..r.cursor()]).await {
+                            error!("[{}] TCP Write error {}", num, e)
+                        }
+                    }
+                }
+                Some("/api/can") => {
+                    let can = CAN_CONFIG.lock().await;
+                    let a = json::to_string(&*can);
+                    let a = a.as_bytes();
+                    if let Ok(r) = construct_response(a, HttpType::Json, &mut response) {
+                        if let Err(e) = socket.write(&r.buf[..r.cursor()]).await {
+                            error!("[{}] TCP Write error {}", num, e)
+                        }
+                    }
+                }
+                Some("/api/can/status") => {
+                    let status = CanStatus {
+                        bms: BMS_CAN.bitrate(),
+                        inverter: INVERTER_CAN.bitrate(),
+                        bms_auto_baud_failed: BMS_CAN.auto_baud_failed(),
+                        inverter_auto_baud_failed: INVERTER_CAN.auto_baud_failed(),
+                    };
+                    let a = json::to_string(&status);
+                    let a = a.as_bytes();
+                    if let Ok(r) = construct_response(a, HttpType::Json, &mut response) {
+                        if let Err(e) = socket.write(&r.buf[..r.cursor()]).await {
+                            error!("[{}] TCP Write error {}", num, e)
+                        }
+                    }
+                }
+                Some("/api/capture") => {
+                    let status = CaptureStatus {
+                        state: match capture::state() {
+                            capture::State::Recording => "Recording",
+                            capture::State::Triggered { .. } => "Triggered",
+                            capture::State::Frozen => "Frozen",
+                        },
+                        frames: {
+                            let window = capture::window();
+                            window.end.wrapping_sub(window.start)
+                        },
+                    };
+                    let a = json::to_string(&status);
+                    let a = a.as_bytes();
+                    if let Ok(r) = construct_response(a, HttpType::Json, &mut response) {
+                        if let Err(e) = socket.write(&r.buf[..r.cursor()]).await {
+                            error!("[{}] TCP Write error {}", num, e)
+                        }
+                    }
+                }
+                Some("/api/capture.log") => {
+                    if let Err(e) = send_capture(&mut socket).await {
+                        error!("[{}] TCP Write error {}", num, e)
+                    }
+                }
                 Some("/api/cells") => {
                     let bms = *BMS.lock().await;
                     let mut buffer = [0u8; 672];
@@ -269,6 +376,156 @@ fn cell_stats(buf: &mut [u8], bms: bms_standard::Bms) -> Result<&[u8], HttpError
     Ok(&buf[..cursor - 1]) // trim trailing comma
 }
 
+/// Streams the capture as a `candump -l` log, it does not fit the response buffer
+async fn send_capture(socket: &mut TcpSocket<'_>) -> Result<(), embassy_net::tcp::Error> {
+    use embedded_io_async::Write;
+    socket
+        .write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\
+            Content-Disposition: attachment; filename=\"toucan.log\"\r\n\
+            Connection: close\r\n\r\n",
+        )
+        .await?;
+    let window = capture::window();
+    let mut line = [0u8; 64];
+    for i in 0..window.end.wrapping_sub(window.start) {
+        // skips frames overwritten since the download started
+        let Some(record) = capture::get(window.start.wrapping_add(i)) else {
+            continue;
+        };
+        let mut writer = ByteMutWriter::new(&mut line);
+        if record.write_candump(&mut writer).is_ok() {
+            socket.write_all(&writer.buf[..writer.cursor()]).await?;
+        }
+    }
+    socket.flush().await
+}
+
+fn content_length(headers: &[httparse::Header]) -> usize {
+    headers
+        .iter()
+        .find(|h| h.name.eq_ignore_ascii_case("content-length"))
+        .and_then(|h| core::str::from_utf8(h.value).ok())
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+#[derive(Deserialize)]
+struct CaptureRequest {
+    command: Command,
+}
+
+/// Bitrates the buses run at, 0 until a bus has started
+#[derive(Serialize)]
+struct CanStatus {
+    bms: u32,
+    inverter: u32,
+    /// True while the bus runs at the fallback rate because auto-baud found nothing
+    bms_auto_baud_failed: bool,
+    inverter_auto_baud_failed: bool,
+}
+
+#[derive(Serialize)]
+struct CaptureStatus {
+    state: &'static str,
+    frames: u32,
+}
+
+/// Decodes and validates a POSTed config, persists it to flash and then applies it, so a
+/// failed save leaves the running config untouched.
+/// `/api/capture` takes a capture command instead and is not persisted
+async fn update_config(path: &str, body: &[u8]) -> Result<&'static str, StmError> {
+    match path {
+        "/api/config" => {
+            let mut config = Config::default();
+            config.decode_from_json(body)?;
+            config.validate()?;
+            // set_dod has its own range checks, run them on a copy before saving
+            let mut bms = *BMS.lock().await;
+            bms.set_dod(*config.dod.minimum(), *config.dod.maximum())
+                .map_err(|_| StmError::InvalidConfigRange)?;
+            storage::save(&config).await?;
+            {
+                let mut bms = BMS.lock().await;
+                let _ = bms.set_dod(*config.dod.minimum(), *config.dod.maximum());
+                bms.config = config.export_as_bms();
+            }
+            *CONFIG.lock().await = config;
+            info!("Config updated from HTTP");
+            Ok("Config saved")
+        }
+        "/api/net" => {
+            let mut netconfig = NetConfig::new(true, None, None, None, None);
+            netconfig.decode_from_json(body)?;
+            netconfig.validate()?;
+            storage::save(&netconfig).await?;
+            *NETCONFIG.lock().await = netconfig;
+            info!("NetConfig updated from HTTP, applied after restart");
+            Ok("NetConfig saved, restart to apply")
+        }
+        "/api/mqtt" => {
+            let mut mqttconfig = MqttConfig::default();
+            mqttconfig.decode_from_json(body)?;
+            mqttconfig.validate()?;
+            storage::save(&mqttconfig).await?;
+            *MQTTCONFIG.lock().await = mqttconfig;
+            MQTT_CONFIG_CHANGED.signal(true);
+            info!("MqttConfig updated from HTTP");
+            Ok("MqttConfig saved")
+        }
+        "/api/can" => {
+            let mut can = CanConfig::default();
+            can.decode_from_json(body)?;
+            storage::save(&can).await?;
+            *CAN_CONFIG.lock().await = can;
+            info!("CanConfig updated from HTTP, applied after restart");
+            Ok("CanConfig saved, restart to apply")
+        }
+        "/api/capture" => {
+            let request: CaptureRequest = json::from_str(
+                core::str::from_utf8(body).map_err(|_e| StmError::InvalidConfigData)?,
+            )
+            .map_err(|_e| StmError::ConfigDeserializeError)?;
+            info!("CAN capture {} from HTTP", request.command);
+            capture::command(request.command);
+            Ok(match request.command {
+                Command::Arm => "Capture armed",
+                Command::Trigger => "Capture triggered",
+                Command::Freeze => "Capture frozen",
+            })
+        }
+        "/api/protocols" => {
+            let mut protocols = ProtocolConfig::default();
+            protocols.decode_from_json(body)?;
+            storage::save(&protocols).await?;
+            *PROTOCOLS.lock().await = protocols;
+            info!("ProtocolConfig updated from HTTP, applied after restart");
+            Ok("ProtocolConfig saved, restart to apply")
+        }
+        _ => Err(StmError::FileNotFound),
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorMessage {
+    status: u16,
+    error: String,
+}
+
+/// HTTP status and JSON body for a failed request
+fn error_response(e: &StmError) -> (u16, String) {
+    let status = match e {
+        StmError::FileNotFound => 404,
+        StmError::StorageUnavailable | StmError::Storage(_) => 500,
+        _ => 400,
+    };
+    let message = ErrorMessage {
+        status,
+        error: e.to_string(),
+    };
+    (status, json::to_string(&message))
+}
+
 // use core::str;
 #[derive(Serialize)]
 struct Message {
@@ -277,15 +534,32 @@ struct Message {
 
 // Function to construct the HTTP response
 fn construct_response<'a>(
-    body: &'a [u8],
+    body: &[u8],
+    h: HttpType,
+    response: &'a mut ByteMutWriter<'a>,
+) -> Result<&'a mut ByteMutWriter<'a>, core::fmt::Error> {
+    construct_status_response(200, body, h, response)
+}
+
+fn construct_status_response<'a>(
+    status: u16,
+    body: &[u8],
     h: HttpType,
     response: &'a mut ByteMutWriter<'a>,
 ) -> Result<&'a mut ByteMutWriter<'a>, core::fmt::Error> {
     use core::fmt::Write;
     use core::str;
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        _ => "Internal Server Error",
+    };
     write!(
         response,
-        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+        status,
+        reason,
         h.as_str(),
         body.len(),
         str::from_utf8(body).unwrap()
@@ -1,2 +1,2 @@
-#[cfg(all(not(feature = "tcp_debug"), feature = "http"))]
+#[cfg(all(not(feature = "gvret"), feature = "http"))]
 pub mod http;
@@ -0,0 +1,4 @@
+/* Flash sectors 6 and 7 (0x08040000 - 0x0807FFFF) hold the config store, see src/bin/storage.
+   The generated memory.x gives the firmware all 512K, so keep the image out of the store here. */
+ASSERT(LOADADDR(.data) + SIZEOF(.data) <= 0x08040000,
+       "firmware image is larger than 256K and overlaps the config store in flash sectors 6 and 7");